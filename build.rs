@@ -6,6 +6,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(feature = "build-frontend")]
     build_web();
 
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/ping_viewer.proto")?;
+
     Ok(())
 }
 
@@ -13,7 +13,7 @@ async fn main() {
     let (manager, handler) = device::manager::DeviceManager::new(10);
 
     let (recordings_manager, recordings_manager_handler) =
-    device::recording::RecordingManager::new(10, "recordings", handler.clone());
+    device::recording::RecordingManager::new(10, cli::manager::recordings_path(), handler.clone());
     tokio::spawn(async move { recordings_manager.run().await });
 
     tokio::spawn(async move { manager.run().await });
@@ -25,9 +25,12 @@ async fn run_tauri_app(handler: device::manager::ManagerActorHandler, recordings
     tauri::Builder::default()
         .setup(|app: &mut tauri::App| {
             let window = app.get_webview_window("main").unwrap();
+            let app_handle = app.handle().clone();
 
             std::thread::spawn(move || {
                 run_from_tauri(&cli::manager::server_address(), handler, recordings_handler).unwrap();
+                // The server only returns after a shutdown, once the recordings are closed
+                app_handle.exit(0);
             });
 
             std::thread::spawn(move || {
@@ -37,6 +40,13 @@ async fn run_tauri_app(handler: device::manager::ManagerActorHandler, recordings
 
             Ok(())
         })
+        .on_window_event(|_window, event| {
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                // Keep the window until the recordings are flushed, the server thread exits the app
+                api.prevent_close();
+                server::shutdown::request();
+            }
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -47,5 +57,6 @@ pub async fn run_from_tauri(
     handler: device::manager::ManagerActorHandler,
     recordings_handler: device::recording::RecordingsManagerHandler
 ) -> std::io::Result<()> {
-    server::manager::run(server_address, handler, recordings_handler).await
+    let listeners = [server::manager::Listener::Tcp(server_address.to_string())];
+    server::manager::run(&listeners, handler, recordings_handler).await
 }
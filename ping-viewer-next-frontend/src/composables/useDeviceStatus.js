@@ -0,0 +1,14 @@
+// Device status arrives as a plain string, like 'Running', except errors that carry
+// their reason as { Error: { reason } }
+export function useDeviceStatus() {
+  const statusName = (status) =>
+    typeof status === 'string' ? status : Object.keys(status ?? {})[0];
+
+  const statusLabel = (status) => {
+    const name = statusName(status);
+    const reason = status?.[name]?.reason;
+    return reason ? `${name}: ${reason}` : name;
+  };
+
+  return { statusName, statusLabel };
+}
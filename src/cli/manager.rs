@@ -1,11 +1,151 @@
 use clap;
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use super::settings;
+
+fn parse_method(value: &str) -> Result<String, String> {
+    let method = value.trim().to_uppercase();
+    method
+        .parse::<actix_web::http::Method>()
+        .map(|_| method.clone())
+        .map_err(|err| format!("Invalid HTTP method {value:?}: {err}"))
+}
+
+fn parse_device(value: &str) -> Result<String, String> {
+    value
+        .parse::<crate::device::manager::CreateStruct>()
+        .map(|_| value.to_string())
+}
+
+fn parse_discovery_rule(value: &str) -> Result<String, String> {
+    value
+        .parse::<crate::device::manager::device_discovery::DiscoveryRule>()
+        .map(|_| value.to_string())
+}
+
+fn parse_listener(value: &str) -> Result<String, String> {
+    value
+        .parse::<crate::server::manager::Listener>()
+        .map(|_| value.to_string())
+}
+
+fn parse_endpoint(value: &str) -> Result<String, String> {
+    settings::validate_endpoint(value).map(|_| value.to_string())
+}
+
+fn parse_log_filter(value: &str) -> Result<String, String> {
+    tracing_subscriber::EnvFilter::builder()
+        .parse(value)
+        .map(|_| value.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// Message dropped when a websocket client queue is full
+#[derive(
+    clap::ValueEnum,
+    serde::Serialize,
+    paperclip::actix::Apiv2Schema,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DropPolicy {
+    /// Keep the latest data, clients see the newest state
+    Oldest,
+    /// Keep what is queued, clients see an uninterrupted run of older data
+    Newest,
+}
+
+/// Layout of the datagrams sent by --udp-output
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UdpOutputFormat {
+    /// Device messages as sent on the websocket, one JSON object per datagram
+    Json,
+    /// Ping protocol frames as received from the device, without the device id
+    Binary,
+}
+
+/// Payload encoding of the samples published by --zenoh-publish
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZenohPublishEncoding {
+    /// The message fields as a JSON object, as in the protocol v2 payloads
+    Json,
+    Cbor,
+    Msgpack,
+    /// Ping protocol frames as received from the device
+    Binary,
+}
+
+/// Content encoding of compressed HTTP responses
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionEncoding {
+    Gzip,
+    Zstd,
+}
+
+impl CompressionEncoding {
+    /// Name in Accept-Encoding and Content-Encoding headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// Output of the discover command
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscoverFormat {
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// The devices as returned by the REST API, for scripts
+    Json,
+}
+
+/// What the binary does, the server unless another command is given
+#[derive(clap::Subcommand, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Run the server, the default
+    Serve,
+    /// Probe the serial ports and the network for devices, print them and exit
+    Discover {
+        #[arg(long, value_enum, default_value_t = DiscoverFormat::Table)]
+        format: DiscoverFormat,
+    },
+    /// Convert MCAP recordings into one CSV file per topic, and classic Ping-Viewer .bin logs into MCAP recordings
+    /// in the recordings directory, then exit
+    Convert {
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<String>,
+        /// Directory of the CSV files, a directory named after each recording next to it by default
+        #[arg(long, value_name = "DIR")]
+        output: Option<String>,
+    },
+    /// Record the discovered devices for a while without the server, then exit
+    Record {
+        /// Length of the recording, Ctrl-C stops it earlier
+        #[arg(long, value_name = "SECONDS")]
+        duration: u64,
+        /// Session label used as the file name prefix
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = env!("CARGO_PKG_DESCRIPTION"))]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Call AutoCreate on DeviceManager during application startup.
     #[arg(long, default_value = "false")]
     enable_auto_create: bool,
@@ -13,9 +153,272 @@ struct Args {
     #[arg(long)]
     reset: bool,
 
-    /// Sets the address for the REST API server
-    #[arg(long, value_name = "IP>:<PORT", default_value = "0.0.0.0:8080")]
-    rest_server: String,
+    /// Devices created at startup, serial:<PATH>[:<BAUDRATE>][:<TYPE>] or udp:<IP>:<PORT>[:<TYPE>] with TYPE ping1d,
+    /// ping360, common or auto, e.g. serial:/dev/ttyUSB0:115200:ping1d. Comma separated or repeated.
+    #[arg(long = "device", value_name = "SPEC", value_delimiter = ',', value_parser = parse_device)]
+    devices: Vec<String>,
+
+    /// Only probe these sources during discovery, serial port paths or IPv4 addresses/ranges (e.g. 192.168.2.0/24).
+    /// Rules only restrict sources of their own kind, comma separated or repeated.
+    #[arg(long, value_name = "PORT|IP[/PREFIX]", value_delimiter = ',', value_parser = parse_discovery_rule)]
+    discovery_allow: Vec<String>,
+
+    /// Never probe these sources during discovery, e.g. the flight controller serial port.
+    #[arg(long, value_name = "PORT|IP[/PREFIX]", value_delimiter = ',', value_parser = parse_discovery_rule)]
+    discovery_deny: Vec<String>,
+
+    /// Seconds between background discovery rounds, 0 disables the background discovery.
+    #[arg(long, value_name = "SECONDS", default_value = "30")]
+    discovery_interval: u64,
+
+    /// Create and start streaming the devices found by the background discovery.
+    #[arg(long)]
+    auto_create_discovered: bool,
+
+    /// Sets the address for the REST API server, repeat it to listen on several addresses. unix:<PATH> listens on a unix socket for local tools.
+    #[arg(long, value_name = "IP>:<PORT", default_value = "0.0.0.0:8080", action = clap::ArgAction::Append, value_parser = parse_listener)]
+    rest_server: Vec<String>,
+
+    /// Encodings offered for REST responses, negotiated with Accept-Encoding, comma separated or repeated.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ENCODING",
+        value_delimiter = ',',
+        default_value = "gzip,zstd"
+    )]
+    compression: Vec<CompressionEncoding>,
+
+    /// Never compress REST responses, e.g. when a proxy in front of the server already does.
+    #[arg(long)]
+    disable_compression: bool,
+
+    /// Origins allowed to call the REST and websocket APIs from a browser, e.g. http://dashboard.local:3000.
+    /// Any origin is allowed when none is given, comma separated or repeated.
+    #[arg(long, value_name = "ORIGIN", value_delimiter = ',')]
+    cors_allowed_origin: Vec<String>,
+
+    /// HTTP methods allowed for cross-origin requests, any method when none is given.
+    #[arg(long, value_name = "METHOD", value_delimiter = ',', value_parser = parse_method)]
+    cors_allowed_method: Vec<String>,
+
+    /// Allow cross-origin requests to send cookies and authorization headers.
+    #[arg(long, value_name = "BOOL", default_value = "true", action = clap::ArgAction::Set)]
+    cors_allow_credentials: bool,
+
+    /// Name advertised over mDNS as <NAME>.local, for the desktop app and other tools to find this instance.
+    #[arg(long, value_name = "NAME", default_value = "ping-viewer-next")]
+    mdns_name: String,
+
+    /// Do not advertise the HTTP server over mDNS.
+    #[arg(long)]
+    disable_mdns: bool,
+
+    /// Serve the Ping1D, Ping360 and VehicleData channels live over the Foxglove WebSocket protocol.
+    #[arg(long, value_name = "IP>:<PORT")]
+    foxglove_server: Option<String>,
+
+    /// Read the vehicle attitude and position from this MAVLink connection instead of the Zenoh bridge,
+    /// e.g. udpin:0.0.0.0:14550, udpout:192.168.2.1:14550, tcpout:127.0.0.1:5760 or serial:/dev/ttyACM0:115200.
+    #[arg(long, value_name = "ADDRESS")]
+    vehicle_mavlink: Option<String>,
+
+    /// Poll the vehicle attitude and position from this mavlink2rest instance instead of the Zenoh bridge,
+    /// e.g. http://blueos.local/mavlink2rest or http://127.0.0.1:6040.
+    #[cfg(feature = "mavlink2rest")]
+    #[arg(long, value_name = "URL", conflicts_with = "vehicle_mavlink")]
+    vehicle_mavlink2rest: Option<String>,
+
+    /// Zenoh endpoint of the vehicle bridge, tcp/127.0.0.1:7447 by default. Takes precedence over the
+    /// vehicle_bridge_endpoint setting, POST /vehicle/bridge/endpoint is then refused.
+    #[arg(long, value_name = "ENDPOINT", value_parser = parse_endpoint)]
+    vehicle_bridge_endpoint: Option<String>,
+
+    /// MAVLink system id of the vehicle, any system by default. GET /vehicle/systems lists the ones seen.
+    #[arg(long, value_name = "ID")]
+    vehicle_system_id: Option<u8>,
+
+    /// Vehicle data older than this many seconds is reported as stale and left out of recordings.
+    #[arg(long, value_name = "SECONDS", default_value = "5")]
+    vehicle_stale_after: f64,
+
+    /// Water density in kg/m³ for the depth from the vehicle pressure sensor, 997 for fresh water.
+    #[arg(long, value_name = "KG/M3", default_value = "1025")]
+    water_density: f64,
+
+    /// MAVLink component id sending the attitude and position, the autopilot by default.
+    #[arg(long, value_name = "ID", default_value = "1")]
+    vehicle_component_id: u8,
+
+    /// MAVLink system id sending the DVL VISION_POSITION_ESTIMATE and VISION_SPEED_ESTIMATE, any system by default.
+    #[arg(long, value_name = "ID")]
+    dvl_system_id: Option<u8>,
+
+    /// MAVLink component id sending the DVL odometry, any component by default. mavlink2rest needs both DVL ids.
+    #[arg(long, value_name = "ID")]
+    dvl_component_id: Option<u8>,
+
+    /// Forward the messages of every streaming device to this UDP destination, e.g. a topside computer.
+    #[arg(long, value_name = "HOST>:<PORT")]
+    udp_output: Option<String>,
+
+    /// Datagram layout of --udp-output.
+    #[arg(long, value_enum, default_value = "json", requires = "udp_output")]
+    udp_output_format: UdpOutputFormat,
+
+    /// Publish the device messages on the Zenoh vehicle bridge as <PREFIX>/<DEVICE_ID>/<MESSAGE>, e.g. pingviewer/<uuid>/profile.
+    #[arg(long)]
+    zenoh_publish: bool,
+
+    /// Key prefix of --zenoh-publish.
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "pingviewer",
+        requires = "zenoh_publish"
+    )]
+    zenoh_publish_prefix: String,
+
+    /// Payload encoding of --zenoh-publish.
+    #[arg(long, value_enum, default_value = "json", requires = "zenoh_publish")]
+    zenoh_publish_encoding: ZenohPublishEncoding,
+
+    /// Serve the Ping1D depths as NMEA 0183 DBT and DPT sentences to TCP clients of this address.
+    #[arg(long, value_name = "IP>:<PORT")]
+    nmea_tcp: Option<String>,
+
+    /// Send the Ping1D depths as NMEA 0183 DBT and DPT sentences to this UDP destination, broadcast addresses included.
+    #[arg(long, value_name = "HOST>:<PORT")]
+    nmea_udp: Option<String>,
+
+    /// Transducer offset in meters sent in DPT, positive to the waterline, negative to the keel.
+    #[arg(
+        long,
+        value_name = "METERS",
+        default_value = "0",
+        allow_negative_numbers = true
+    )]
+    nmea_transducer_offset: f64,
+
+    /// Serve every running device as a raw Ping protocol device on its own TCP and UDP port, from this one up. Only on localhost when API tokens are required.
+    #[arg(long, value_name = "PORT")]
+    proxy_base_port: Option<u16>,
+
+    /// Serve the device manager and recordings APIs over gRPC, see proto/ping_viewer.proto.
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "IP>:<PORT")]
+    grpc_server: Option<String>,
+
+    /// STUN/TURN server offered to WebRTC peers, e.g. `stun:stun.l.google.com:19302`. Repeat for several, none on isolated networks.
+    #[cfg(feature = "webrtc")]
+    #[arg(long, value_name = "URL", action = clap::ArgAction::Append)]
+    webrtc_ice_server: Vec<String>,
+
+    /// Serve in read-only viewing mode, rejecting device commands and any mutating request.
+    #[arg(long)]
+    read_only: bool,
+
+    /// Require this token, as `Authorization: Bearer <TOKEN>` or `?token=<TOKEN>`, on mutating requests and websocket upgrades.
+    #[arg(long, value_name = "TOKEN")]
+    api_token: Option<String>,
+
+    /// File with one accepted API token per line, lines starting with # are ignored.
+    #[arg(long, value_name = "PATH")]
+    api_tokens_file: Option<String>,
+
+    /// Mutating requests allowed per minute from each client IP, e.g. device creation or recording start, unlimited by default.
+    #[arg(long, value_name = "REQUESTS")]
+    rate_limit: Option<u32>,
+
+    /// Mutating requests a client can send at once before the per minute rate applies, defaults to --rate-limit.
+    #[arg(long, value_name = "REQUESTS", requires = "rate_limit")]
+    rate_limit_burst: Option<u32>,
+
+    /// Disable continuous mode of devices without websocket subscribers or active recordings after the given minutes.
+    /// Clients subscribed to all devices keep every device awake.
+    #[arg(long, value_name = "MINUTES")]
+    idle_timeout: Option<u64>,
+
+    /// Also stop the Ping360 motor when a device goes idle.
+    #[arg(long, requires = "idle_timeout")]
+    idle_power_down: bool,
+
+    /// Messages queued for each websocket client before the drop policy applies.
+    #[arg(long, value_name = "MESSAGES", default_value = "256")]
+    ws_queue_size: usize,
+
+    /// Which message is dropped when a slow websocket client queue is full.
+    #[arg(long, value_enum, default_value = "oldest")]
+    ws_drop_policy: DropPolicy,
+
+    /// Seconds between server pings to websocket clients, 0 disables the heartbeat.
+    #[arg(long, value_name = "SECONDS", default_value = "10")]
+    ws_heartbeat_interval: u64,
+
+    /// Seconds without any frame from a websocket client before it is disconnected.
+    #[arg(long, value_name = "SECONDS", default_value = "30")]
+    ws_client_timeout: u64,
+
+    /// Recent messages of each device sent to websocket clients when they connect, 0 disables the replay.
+    #[arg(long, value_name = "MESSAGES", default_value = "10")]
+    ws_replay: usize,
+
+    /// JSON file holding the settings changed through the API, they override the command line.
+    #[arg(long, value_name = "PATH")]
+    settings_file: Option<String>,
+
+    /// Directory where recordings are written and served from, relative paths start from the working directory at startup.
+    #[arg(long, value_name = "PATH", default_value = "recordings")]
+    recordings_path: String,
+
+    /// Write recordings into YYYY/MM/DD subdirectories of the recordings directory.
+    #[arg(long)]
+    recordings_by_date: bool,
+
+    /// Continue recordings in a new numbered file once the active one reaches this size.
+    #[arg(long, value_name = "MEGABYTES")]
+    recording_max_size: Option<u64>,
+
+    /// Continue recordings in a new numbered file after the given minutes.
+    #[arg(long, value_name = "MINUTES")]
+    recording_max_duration: Option<u64>,
+
+    /// Keep the last seconds of every streaming device in memory and write them at the start of its recordings.
+    #[arg(long, value_name = "SECONDS")]
+    recording_pre_trigger: Option<u64>,
+
+    /// Sonar mounting pose on the vehicle, recordings then carry frame transforms for 3D playback.
+    #[arg(long, value_name = "X,Y,Z,ROLL,PITCH,YAW")]
+    sonar_mount: Option<String>,
+
+    /// Write the vehicle pose at this rate during recordings instead of once per sonar message.
+    #[arg(long, value_name = "HZ")]
+    recording_pose_rate: Option<f64>,
+
+    /// MAVLink messages of the vehicle bridge written to recordings as `device_<id>/MAVLink/<MESSAGE>` channels, `*` for all.
+    #[arg(long, value_name = "MESSAGE,...", value_delimiter = ',')]
+    recording_mavlink: Vec<String>,
+
+    /// Remove the oldest recordings once the recordings directory grows over this size.
+    #[arg(long, value_name = "MEGABYTES")]
+    recordings_max_size: Option<u64>,
+
+    /// Remove recordings older than the given days.
+    #[arg(long, value_name = "DAYS")]
+    recordings_max_age: Option<u64>,
+
+    /// Keep at most this many recordings, removing the oldest ones.
+    #[arg(long, value_name = "COUNT")]
+    recordings_max_files: Option<usize>,
+
+    /// JSON5 file describing the S3-compatible or WebDAV target recordings are uploaded to.
+    #[cfg(feature = "upload")]
+    #[arg(long, value_name = "PATH")]
+    upload_config: Option<String>,
+
+    /// Same as the convert command, kept for existing scripts.
+    #[arg(long, value_name = "PATH", num_args = 1..)]
+    import_legacy: Vec<String>,
 
     /// Turns all log categories up to Debug, for more information check RUST_LOG env variable.
     #[arg(short, long)]
@@ -33,14 +436,27 @@ struct Args {
     #[arg(long, default_value = "false")]
     log_include_all_dependencies: bool,
 
+    /// Log levels per module on top of the others, e.g. "ping_viewer_next::device=debug,zenoh=warn".
+    #[arg(long, value_name = "DIRECTIVES", value_parser = parse_log_filter)]
+    log_filter: Option<String>,
+
+    /// Write the process id to this file while the server runs, for init scripts and monitors.
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<String>,
+
     /// Turns on the Tracy tool integration.
     #[arg(long)]
     enable_tracy: bool,
 }
 
+// Every option can also be set as PING_VIEWER_<OPTION>, e.g. PING_VIEWER_REST_SERVER for --rest-server
+const ENV_PREFIX: &str = "PING_VIEWER_";
+
 #[derive(Debug)]
 struct Manager {
     clap_matches: Args,
+    // Options given on the command line or in the environment, they win over the settings file
+    explicit: HashSet<String>,
 }
 
 lazy_static! {
@@ -49,12 +465,63 @@ lazy_static! {
 
 impl Manager {
     fn new() -> Self {
+        let matches = cli_command().get_matches();
+        let explicit = matches
+            .ids()
+            .filter(|id| {
+                matches!(
+                    matches.value_source(id.as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            })
+            .map(|id| id.to_string())
+            .collect();
         Self {
-            clap_matches: Args::parse(),
+            clap_matches: Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit()),
+            explicit,
         }
     }
 }
 
+// Precedence is command line, then environment, then settings file, then default.
+// Options are global so they also follow the subcommand, e.g. `discover --discovery-deny /dev/ttyACM0`.
+fn cli_command() -> clap::Command {
+    Args::command().mut_args(|arg| {
+        let name = env_name(arg.get_id().as_str());
+        arg.env(name).global(true)
+    })
+}
+
+fn env_name(id: &str) -> String {
+    format!("{ENV_PREFIX}{}", id.to_uppercase())
+}
+
+// The settings file only applies to options left to their default
+fn with_settings<T>(id: &str, setting: Option<T>, option: T) -> T {
+    match setting {
+        Some(setting) if !MANAGER.explicit.contains(id) => setting,
+        _ => option,
+    }
+}
+
+/// Command line option that takes precedence over a settings file field, None when the field applies
+pub fn overriding_option(field: &str) -> Option<String> {
+    let id = match field {
+        "recordings_path" => "recordings_path",
+        "recordings_max_size_mb" => "recordings_max_size",
+        "recordings_max_age_days" => "recordings_max_age",
+        "recordings_max_files" => "recordings_max_files",
+        "auto_create" => "enable_auto_create",
+        "vehicle_bridge_endpoint" => "vehicle_bridge_endpoint",
+        // Settings tokens are accepted next to --api-token and --api-tokens-file, nothing overrides them
+        _ => return None,
+    };
+    MANAGER
+        .explicit
+        .contains(id)
+        .then(|| format!("--{} ({})", id.replace('_', "-"), env_name(id)))
+}
+
 // Construct our manager, should be done inside main
 pub fn init() {
     MANAGER.as_ref();
@@ -77,8 +544,249 @@ pub fn is_log_all_dependencies() -> bool {
     MANAGER.clap_matches.log_include_all_dependencies
 }
 
+pub fn log_filter() -> Option<String> {
+    MANAGER.clap_matches.log_filter.clone()
+}
+
 pub fn is_enable_auto_create() -> bool {
-    MANAGER.clap_matches.enable_auto_create
+    with_settings(
+        "enable_auto_create",
+        settings::current().auto_create,
+        MANAGER.clap_matches.enable_auto_create,
+    )
+}
+
+pub fn devices() -> Vec<crate::device::manager::CreateStruct> {
+    MANAGER
+        .clap_matches
+        .devices
+        .iter()
+        .map(|device| {
+            device
+                .parse()
+                .expect("Device specifications are checked while parsing")
+        })
+        .collect()
+}
+
+pub fn discovery_interval() -> Option<std::time::Duration> {
+    Some(MANAGER.clap_matches.discovery_interval)
+        .filter(|seconds| *seconds > 0)
+        .map(std::time::Duration::from_secs)
+}
+
+pub fn is_auto_create_discovered() -> bool {
+    MANAGER.clap_matches.auto_create_discovered
+}
+
+pub fn discovery_allow() -> Vec<String> {
+    MANAGER.clap_matches.discovery_allow.clone()
+}
+
+pub fn discovery_deny() -> Vec<String> {
+    MANAGER.clap_matches.discovery_deny.clone()
+}
+
+pub fn is_read_only() -> bool {
+    MANAGER.clap_matches.read_only
+}
+
+pub fn api_token() -> Option<String> {
+    MANAGER.clap_matches.api_token.clone()
+}
+
+pub fn api_tokens_file() -> Option<String> {
+    MANAGER.clap_matches.api_tokens_file.as_ref().map(|path| {
+        shellexpand::full(path)
+            .expect("Failed to expand path")
+            .to_string()
+    })
+}
+
+pub fn rate_limit() -> Option<u32> {
+    MANAGER.clap_matches.rate_limit.filter(|limit| *limit > 0)
+}
+
+pub fn rate_limit_burst() -> Option<u32> {
+    MANAGER.clap_matches.rate_limit_burst
+}
+
+pub fn idle_timeout() -> Option<std::time::Duration> {
+    MANAGER
+        .clap_matches
+        .idle_timeout
+        .filter(|minutes| *minutes > 0)
+        .map(|minutes| std::time::Duration::from_secs(minutes * 60))
+}
+
+pub fn is_idle_power_down() -> bool {
+    MANAGER.clap_matches.idle_power_down
+}
+
+pub fn ws_queue_size() -> usize {
+    MANAGER.clap_matches.ws_queue_size
+}
+
+pub fn ws_drop_policy() -> DropPolicy {
+    MANAGER.clap_matches.ws_drop_policy
+}
+
+pub fn ws_heartbeat_interval() -> Option<std::time::Duration> {
+    Some(MANAGER.clap_matches.ws_heartbeat_interval)
+        .filter(|seconds| *seconds > 0)
+        .map(std::time::Duration::from_secs)
+}
+
+pub fn ws_client_timeout() -> std::time::Duration {
+    std::time::Duration::from_secs(MANAGER.clap_matches.ws_client_timeout)
+}
+
+pub fn ws_replay_size() -> usize {
+    MANAGER.clap_matches.ws_replay
+}
+
+pub fn settings_file() -> PathBuf {
+    match &MANAGER.clap_matches.settings_file {
+        Some(path) => PathBuf::from(
+            shellexpand::full(path)
+                .expect("Failed to expand path")
+                .to_string(),
+        ),
+        None => dirs::config_dir()
+            .unwrap_or_default()
+            .join(env!("CARGO_PKG_NAME"))
+            .join("settings.json"),
+    }
+}
+
+/// Absolute, so the recording manager and the REST file routes agree whatever the working directory
+pub fn recordings_path() -> String {
+    let path = with_settings(
+        "recordings_path",
+        settings::current().recordings_path,
+        MANAGER.clap_matches.recordings_path.clone(),
+    );
+    let path = shellexpand::full(&path)
+        .expect("Failed to expand path")
+        .to_string();
+    std::path::absolute(&path)
+        .unwrap_or_else(|_| PathBuf::from(&path))
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn is_recordings_by_date() -> bool {
+    MANAGER.clap_matches.recordings_by_date
+}
+
+pub fn recording_max_size() -> Option<u64> {
+    MANAGER
+        .clap_matches
+        .recording_max_size
+        .filter(|megabytes| *megabytes > 0)
+        .map(|megabytes| megabytes * 1024 * 1024)
+}
+
+pub fn recording_max_duration() -> Option<std::time::Duration> {
+    MANAGER
+        .clap_matches
+        .recording_max_duration
+        .filter(|minutes| *minutes > 0)
+        .map(|minutes| std::time::Duration::from_secs(minutes * 60))
+}
+
+pub fn recording_pre_trigger() -> Option<std::time::Duration> {
+    MANAGER
+        .clap_matches
+        .recording_pre_trigger
+        .filter(|seconds| *seconds > 0)
+        .map(std::time::Duration::from_secs)
+}
+
+pub fn recording_pose_period() -> Option<std::time::Duration> {
+    MANAGER
+        .clap_matches
+        .recording_pose_rate
+        .filter(|hertz| *hertz > 0.0)
+        .map(|hertz| std::time::Duration::from_secs_f64(1.0 / hertz))
+}
+
+pub fn recording_mavlink() -> Vec<String> {
+    MANAGER.clap_matches.recording_mavlink.clone()
+}
+
+pub fn sonar_mount() -> Option<String> {
+    MANAGER.clap_matches.sonar_mount.clone()
+}
+
+pub fn recordings_max_size() -> Option<u64> {
+    with_settings(
+        "recordings_max_size",
+        settings::current().recordings_max_size_mb.map(Some),
+        MANAGER.clap_matches.recordings_max_size,
+    )
+    .map(|megabytes| megabytes * 1024 * 1024)
+}
+
+pub fn recordings_max_age() -> Option<std::time::Duration> {
+    with_settings(
+        "recordings_max_age",
+        settings::current().recordings_max_age_days.map(Some),
+        MANAGER.clap_matches.recordings_max_age,
+    )
+    .map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60))
+}
+
+pub fn recordings_max_files() -> Option<usize> {
+    with_settings(
+        "recordings_max_files",
+        settings::current().recordings_max_files.map(Some),
+        MANAGER.clap_matches.recordings_max_files,
+    )
+}
+
+#[cfg(feature = "upload")]
+pub fn upload_config() -> Option<String> {
+    MANAGER.clap_matches.upload_config.as_ref().map(|path| {
+        shellexpand::full(path)
+            .expect("Failed to expand path")
+            .to_string()
+    })
+}
+
+pub fn pid_file() -> Option<String> {
+    MANAGER.clap_matches.pid_file.as_ref().map(|path| {
+        shellexpand::full(path)
+            .expect("Failed to expand path")
+            .to_string()
+    })
+}
+
+pub fn command() -> Command {
+    let expand = |paths: &[String]| {
+        paths
+            .iter()
+            .map(|path| {
+                shellexpand::full(path)
+                    .expect("Failed to expand path")
+                    .to_string()
+            })
+            .collect()
+    };
+    match &MANAGER.clap_matches.command {
+        Some(Command::Convert { paths, output }) => Command::Convert {
+            paths: expand(paths),
+            output: output
+                .as_ref()
+                .map(|output| expand(&[output.clone()]).remove(0)),
+        },
+        Some(command) => command.clone(),
+        None if !MANAGER.clap_matches.import_legacy.is_empty() => Command::Convert {
+            paths: expand(&MANAGER.clap_matches.import_legacy),
+            output: None,
+        },
+        None => Command::Serve,
+    }
 }
 
 pub fn log_path() -> String {
@@ -92,9 +800,159 @@ pub fn log_path() -> String {
         .to_string()
 }
 
-// Return the desired address for the REST API
+// Empty when any origin is allowed
+pub fn compression_encodings() -> Vec<CompressionEncoding> {
+    if MANAGER.clap_matches.disable_compression {
+        return Vec::new();
+    }
+    MANAGER.clap_matches.compression.clone()
+}
+
+pub fn is_compression_enabled() -> bool {
+    !compression_encodings().is_empty()
+}
+
+pub fn cors_allowed_origins() -> Vec<String> {
+    let origins = &MANAGER.clap_matches.cors_allowed_origin;
+    if origins.iter().any(|origin| origin == "*") {
+        return Vec::new();
+    }
+    origins
+        .iter()
+        .map(|origin| origin.trim_end_matches('/').to_string())
+        .collect()
+}
+
+// Empty when any method is allowed
+pub fn cors_allowed_methods() -> Vec<String> {
+    MANAGER.clap_matches.cors_allowed_method.clone()
+}
+
+pub fn is_cors_allow_credentials() -> bool {
+    MANAGER.clap_matches.cors_allow_credentials
+}
+
+// Return the desired address for the REST API, the first network one when several are given
 pub fn server_address() -> String {
-    MANAGER.clap_matches.rest_server.clone()
+    let addresses = &MANAGER.clap_matches.rest_server;
+    addresses
+        .iter()
+        .find(|address| !address.starts_with("unix:"))
+        .or(addresses.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+// Every address the REST API listens on, network addresses and unix:<PATH> sockets
+pub fn server_addresses() -> Vec<String> {
+    MANAGER
+        .clap_matches
+        .rest_server
+        .iter()
+        .map(|address| match address.strip_prefix("unix:") {
+            Some(path) => format!(
+                "unix:{}",
+                shellexpand::full(path).expect("Failed to expand path")
+            ),
+            None => address.clone(),
+        })
+        .collect()
+}
+
+// None when the advertisement is disabled
+pub fn mdns_name() -> Option<String> {
+    (!MANAGER.clap_matches.disable_mdns).then(|| MANAGER.clap_matches.mdns_name.clone())
+}
+
+pub fn foxglove_server_address() -> Option<String> {
+    MANAGER.clap_matches.foxglove_server.clone()
+}
+
+pub fn vehicle_mavlink() -> Option<String> {
+    MANAGER.clap_matches.vehicle_mavlink.clone()
+}
+
+pub fn vehicle_bridge_endpoint() -> Option<String> {
+    with_settings(
+        "vehicle_bridge_endpoint",
+        settings::current().vehicle_bridge_endpoint.map(Some),
+        MANAGER.clap_matches.vehicle_bridge_endpoint.clone(),
+    )
+}
+
+#[cfg(feature = "mavlink2rest")]
+pub fn vehicle_mavlink2rest() -> Option<String> {
+    MANAGER.clap_matches.vehicle_mavlink2rest.clone()
+}
+
+pub fn vehicle_system_id() -> Option<u8> {
+    MANAGER.clap_matches.vehicle_system_id
+}
+
+pub fn vehicle_stale_after() -> std::time::Duration {
+    std::time::Duration::from_secs_f64(MANAGER.clap_matches.vehicle_stale_after)
+}
+
+pub fn water_density() -> f64 {
+    MANAGER.clap_matches.water_density
+}
+
+pub fn vehicle_component_id() -> u8 {
+    MANAGER.clap_matches.vehicle_component_id
+}
+
+pub fn dvl_system_id() -> Option<u8> {
+    MANAGER.clap_matches.dvl_system_id
+}
+
+pub fn dvl_component_id() -> Option<u8> {
+    MANAGER.clap_matches.dvl_component_id
+}
+
+pub fn udp_output() -> Option<String> {
+    MANAGER.clap_matches.udp_output.clone()
+}
+
+pub fn udp_output_format() -> UdpOutputFormat {
+    MANAGER.clap_matches.udp_output_format
+}
+
+pub fn is_zenoh_publish_enabled() -> bool {
+    MANAGER.clap_matches.zenoh_publish
+}
+
+pub fn zenoh_publish_prefix() -> String {
+    MANAGER.clap_matches.zenoh_publish_prefix.clone()
+}
+
+pub fn zenoh_publish_encoding() -> ZenohPublishEncoding {
+    MANAGER.clap_matches.zenoh_publish_encoding
+}
+
+pub fn nmea_tcp() -> Option<String> {
+    MANAGER.clap_matches.nmea_tcp.clone()
+}
+
+pub fn nmea_udp() -> Option<String> {
+    MANAGER.clap_matches.nmea_udp.clone()
+}
+
+pub fn nmea_transducer_offset() -> f64 {
+    MANAGER.clap_matches.nmea_transducer_offset
+}
+
+pub fn proxy_base_port() -> Option<u16> {
+    MANAGER.clap_matches.proxy_base_port
+}
+
+#[cfg(feature = "grpc")]
+pub fn grpc_server_address() -> Option<String> {
+    MANAGER.clap_matches.grpc_server.clone()
+}
+
+#[cfg(feature = "webrtc")]
+pub fn webrtc_ice_servers() -> Vec<String> {
+    MANAGER.clap_matches.webrtc_ice_server.clone()
 }
 
 // Return the command line used to start this application
@@ -114,5 +972,92 @@ mod tests {
     #[test]
     fn default_arguments() {
         assert!(!is_verbose());
+        assert!(cors_allowed_origins().is_empty());
+        assert!(is_cors_allow_credentials());
+        assert!(rate_limit().is_none());
+        assert_eq!(
+            compression_encodings(),
+            vec![CompressionEncoding::Gzip, CompressionEncoding::Zstd]
+        );
+    }
+
+    #[test]
+    fn options_have_environment_variables() {
+        let command = cli_command();
+        let env = |id: &str| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .and_then(|arg| arg.get_env())
+                .map(|env| env.to_string_lossy().to_string())
+        };
+        assert_eq!(
+            env("rest_server").as_deref(),
+            Some("PING_VIEWER_REST_SERVER")
+        );
+        assert_eq!(
+            env("enable_auto_create").as_deref(),
+            Some("PING_VIEWER_ENABLE_AUTO_CREATE")
+        );
+        assert!(overriding_option("api_tokens").is_none());
+    }
+
+    #[test]
+    fn vehicle_bridge_endpoints_are_validated() {
+        assert_eq!(
+            parse_endpoint("tcp/192.168.2.2:7447").unwrap(),
+            "tcp/192.168.2.2:7447"
+        );
+        assert!(parse_endpoint("192.168.2.2:7447").is_err());
+    }
+
+    #[test]
+    fn discovery_rules_are_validated() {
+        assert_eq!(
+            parse_discovery_rule("192.168.2.0/24").unwrap(),
+            "192.168.2.0/24"
+        );
+        assert!(parse_discovery_rule("192.168.2.0/33").is_err());
+        assert!(cli_command()
+            .try_get_matches_from(["ping-viewer-next", "--discovery-deny", "10.0.0.1/40"])
+            .is_err());
+    }
+
+    #[test]
+    fn server_addresses_are_validated() {
+        assert_eq!(parse_listener("0.0.0.0:8080").unwrap(), "0.0.0.0:8080");
+        assert!(parse_listener("unix:").is_err());
+        assert!(cli_command()
+            .try_get_matches_from(["ping-viewer-next", "--rest-server", "8080"])
+            .is_err());
+    }
+
+    #[test]
+    fn background_discovery_options_are_parsed() {
+        let args = |arguments: &[&str]| {
+            let matches = cli_command()
+                .try_get_matches_from(["ping-viewer-next"].iter().chain(arguments))
+                .unwrap();
+            Args::from_arg_matches(&matches).unwrap()
+        };
+        let defaults = args(&[]);
+        assert_eq!(defaults.discovery_interval, 30);
+        assert!(!defaults.auto_create_discovered);
+
+        let args = args(&["--discovery-interval", "0", "--auto-create-discovered"]);
+        assert_eq!(args.discovery_interval, 0);
+        assert!(args.auto_create_discovered);
+    }
+
+    #[test]
+    fn cors_methods_are_validated() {
+        assert_eq!(parse_method("get").unwrap(), "GET");
+        assert!(parse_method("NOT A METHOD").is_err());
+    }
+
+    #[test]
+    fn log_filters_are_validated() {
+        assert!(parse_log_filter("ping_viewer_next::device=debug,zenoh=warn").is_ok());
+        assert!(parse_log_filter("zenoh=loud").is_err());
     }
 }
@@ -0,0 +1,304 @@
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use super::manager;
+
+// Shown instead of the API tokens, they are never sent back
+const REDACTED: &str = "********";
+
+lazy_static! {
+    static ref SETTINGS: watch::Sender<ServerSettings> =
+        watch::channel(ServerSettings::load(&manager::settings_file())).0;
+    // Concurrent patches would each start from the same settings and lose one of the changes
+    static ref UPDATE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+
+/// Options changed at runtime through `PATCH /settings`, kept in the settings file so they survive restarts.
+/// Unset fields keep the default, and options set on the command line or in the environment win over the fields.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Applied on the next start
+    pub recordings_path: Option<String>,
+    pub recordings_max_size_mb: Option<u64>,
+    pub recordings_max_age_days: Option<u64>,
+    pub recordings_max_files: Option<usize>,
+    /// Create the devices found at startup, applied on the next start
+    pub auto_create: Option<bool>,
+    /// Zenoh endpoint of the vehicle bridge, e.g. `tcp/127.0.0.1:7447`
+    pub vehicle_bridge_endpoint: Option<String>,
+    /// Accepted in addition to the command line tokens, only set here: no option overrides them
+    pub api_tokens: Option<Vec<String>>,
+}
+
+/// Answer to `PATCH /settings`
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct SettingsUpdate {
+    pub settings: ServerSettings,
+    /// Changed settings already in effect
+    pub applied: Vec<String>,
+    /// Changed settings that need a restart
+    pub restart_required: Vec<String>,
+    /// Changed settings without effect while the command line or environment sets them, with the option to remove
+    pub overridden: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsError {
+    /// The patch is malformed or a value is out of range
+    Invalid(String),
+    /// The settings file could not be written, nothing was changed
+    Persist(String),
+}
+
+impl ServerSettings {
+    fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => match serde_json::from_str::<Self>(&content) {
+                Ok(settings) => {
+                    info!("Settings: Loaded {path:?}");
+                    settings
+                }
+                // Starting with the defaults would silently drop the saved options, and the next change would erase them
+                Err(err) => panic!("Failed to parse settings file {path:?}: {err}"),
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                warn!("Settings: Failed to read {path:?}, using the command line only: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    fn persist(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("Failed to create settings directory: {err}"))?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        // Written aside then renamed, a crash never leaves a truncated file
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|err| format!("Failed to write settings file {path:?}: {err}"))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self
+            .recordings_path
+            .as_ref()
+            .is_some_and(|path| path.trim().is_empty())
+        {
+            return Err("recordings_path can't be empty".to_string());
+        }
+        if let Some(endpoint) = &self.vehicle_bridge_endpoint {
+            validate_endpoint(endpoint)?;
+        }
+        if let Some(tokens) = &self.api_tokens {
+            if tokens
+                .iter()
+                .any(|token| token.is_empty() || token.chars().any(char::is_whitespace))
+            {
+                return Err("API tokens can't be empty or contain whitespace".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy safe to send to clients
+    pub fn redacted(&self) -> Self {
+        Self {
+            api_tokens: self
+                .api_tokens
+                .as_ref()
+                .map(|tokens| vec![REDACTED.to_string(); tokens.len()]),
+            ..self.clone()
+        }
+    }
+
+    // JSON merge patch: fields set to null return to the command line or default value, missing fields are kept
+    fn patched(&self, patch: &Value) -> Result<Self, String> {
+        let Value::Object(patch) = patch else {
+            return Err("Settings patch must be a JSON object".to_string());
+        };
+        let mut merged = serde_json::to_value(self).map_err(|err| err.to_string())?;
+        if let Value::Object(fields) = &mut merged {
+            for (key, value) in patch {
+                // A settings object read back from GET carries the redacted tokens, they are kept as is
+                if key == "api_tokens"
+                    && value.as_array().is_some_and(|tokens| {
+                        !tokens.is_empty() && tokens.iter().all(|token| token == REDACTED)
+                    })
+                {
+                    continue;
+                }
+                match value {
+                    Value::Null => fields.remove(key),
+                    value => fields.insert(key.clone(), value.clone()),
+                };
+            }
+        }
+        let settings: Self = serde_json::from_value(merged).map_err(|err| err.to_string())?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn changed_fields(&self, other: &Self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        let mut check = |name, differs: bool| {
+            if differs {
+                changed.push(name);
+            }
+        };
+        check(
+            "recordings_path",
+            self.recordings_path != other.recordings_path,
+        );
+        check(
+            "recordings_max_size_mb",
+            self.recordings_max_size_mb != other.recordings_max_size_mb,
+        );
+        check(
+            "recordings_max_age_days",
+            self.recordings_max_age_days != other.recordings_max_age_days,
+        );
+        check(
+            "recordings_max_files",
+            self.recordings_max_files != other.recordings_max_files,
+        );
+        check("auto_create", self.auto_create != other.auto_create);
+        check(
+            "vehicle_bridge_endpoint",
+            self.vehicle_bridge_endpoint != other.vehicle_bridge_endpoint,
+        );
+        check("api_tokens", self.api_tokens != other.api_tokens);
+        changed
+    }
+}
+
+fn needs_restart(field: &str) -> bool {
+    matches!(field, "recordings_path" | "auto_create")
+}
+
+pub fn current() -> ServerSettings {
+    SETTINGS.borrow().clone()
+}
+
+/// Zenoh endpoint as `<protocol>/<address>`, from the settings or the command line
+pub fn validate_endpoint(endpoint: &str) -> Result<(), String> {
+    if !endpoint
+        .split_once('/')
+        .is_some_and(|(protocol, address)| !protocol.is_empty() && !address.is_empty())
+    {
+        return Err(format!(
+            "Invalid vehicle_bridge_endpoint {endpoint:?}, expected e.g. tcp/127.0.0.1:7447"
+        ));
+    }
+    Ok(())
+}
+
+/// Notified on every change, for the parts applying settings live
+pub fn subscribe() -> watch::Receiver<ServerSettings> {
+    SETTINGS.subscribe()
+}
+
+/// Apply a JSON merge patch, the settings file is written before the change is published
+pub fn update(patch: &Value) -> Result<SettingsUpdate, SettingsError> {
+    let _guard = UPDATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let previous = current();
+    let settings = previous.patched(patch).map_err(SettingsError::Invalid)?;
+    let changed = previous.changed_fields(&settings);
+    if !changed.is_empty() {
+        let path = manager::settings_file();
+        if let Err(err) = settings.persist(&path) {
+            error!("Settings: {err}");
+            return Err(SettingsError::Persist(err));
+        }
+        info!("Settings: Changed {changed:?}");
+        SETTINGS.send_replace(settings.clone());
+    }
+
+    let mut overridden = Vec::new();
+    let changed: Vec<_> = changed
+        .into_iter()
+        .filter(|field| match manager::overriding_option(field) {
+            Some(option) => {
+                warn!("Settings: {field} is saved but {option} takes precedence");
+                overridden.push(format!("{field} ({option})"));
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let (restart_required, applied): (Vec<_>, Vec<_>) =
+        changed.into_iter().partition(|field| needs_restart(field));
+    Ok(SettingsUpdate {
+        settings: settings.redacted(),
+        applied: applied.into_iter().map(str::to_string).collect(),
+        restart_required: restart_required.into_iter().map(str::to_string).collect(),
+        overridden,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_patch_merges_and_clears_fields() {
+        let settings = ServerSettings {
+            recordings_max_files: Some(10),
+            auto_create: Some(true),
+            ..Default::default()
+        };
+        let patched = settings
+            .patched(&json!({ "recordings_max_files": null, "recordings_max_age_days": 7 }))
+            .unwrap();
+        assert_eq!(patched.recordings_max_files, None);
+        assert_eq!(patched.recordings_max_age_days, Some(7));
+        assert_eq!(patched.auto_create, Some(true));
+        assert_eq!(
+            settings.changed_fields(&patched),
+            vec!["recordings_max_age_days", "recordings_max_files"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_patches_are_rejected() {
+        let settings = ServerSettings::default();
+        assert!(settings.patched(&json!(["auto_create"])).is_err());
+        assert!(settings.patched(&json!({ "unknown": 1 })).is_err());
+        assert!(settings.patched(&json!({ "auto_create": "yes" })).is_err());
+        assert!(settings
+            .patched(&json!({ "vehicle_bridge_endpoint": "127.0.0.1" }))
+            .is_err());
+        assert!(settings
+            .patched(&json!({ "api_tokens": ["with space"] }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_tokens_are_redacted() {
+        let settings = ServerSettings {
+            api_tokens: Some(vec!["secret".to_string()]),
+            ..Default::default()
+        };
+        let redacted = settings.redacted();
+        assert_eq!(redacted.api_tokens, Some(vec![REDACTED.to_string()]));
+
+        // Sending back what GET returned keeps the real tokens
+        let patched = settings
+            .patched(&serde_json::to_value(&redacted).unwrap())
+            .unwrap();
+        assert_eq!(patched, settings);
+    }
+}
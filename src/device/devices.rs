@@ -306,6 +306,28 @@ pub enum PingRequest {
     Stop,
 }
 
+impl PingRequest {
+    /// Requests that change the device configuration
+    pub fn is_setting(&self) -> bool {
+        matches!(
+            self,
+            PingRequest::Ping1D(
+                Ping1DRequest::SetDeviceId(_)
+                    | Ping1DRequest::SetModeAuto(_)
+                    | Ping1DRequest::SetPingInterval(_)
+                    | Ping1DRequest::SetPingEnable(_)
+                    | Ping1DRequest::SetSpeedOfSound(_)
+                    | Ping1DRequest::SetRange(_)
+                    | Ping1DRequest::SetGainSetting(_)
+            ) | PingRequest::Ping360(
+                Ping360Request::SetDeviceId(_)
+                    | Ping360Request::Transducer(_)
+                    | Ping360Request::AutoTransmit(_)
+            ) | PingRequest::Common(PingCommonRequest::SetDeviceId(_))
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
 pub enum Ping1DRequest {
     DeviceID,
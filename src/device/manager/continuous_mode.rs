@@ -68,7 +68,8 @@ impl DeviceManager {
                 if supports_auto_transmit {
                     match self.get_device_source(device_id) {
                         Ok(source) => match source {
-                            super::SourceSelection::UdpStream(_) => {
+                            super::SourceSelection::UdpStream(_)
+                            | super::SourceSelection::ReplayStream(_) => {
                                 Some(Self::start_ping360_firmware_mode(
                                     self.get_device_manager_handler(),
                                     handler,
@@ -200,7 +201,7 @@ impl DeviceManager {
         device_id: Uuid,
     ) {
         if msg.message_id == <bluerobotics_ping::ping360::AutoDeviceDataStruct as bluerobotics_ping::message::MessageInfo>::id() {
-                if let Ok(bluerobotics_ping::Messages::Ping360(bluerobotics_ping::ping360::Messages::AutoDeviceData(_answer))) = bluerobotics_ping::Messages::try_from(&msg) {
+                if let Ok(bluerobotics_ping::Messages::Ping360(bluerobotics_ping::ping360::Messages::AutoDeviceData(data))) = bluerobotics_ping::Messages::try_from(&msg) {
                     let answer = Answer::DeviceMessage(DeviceAnswer {
                         answer: crate::device::devices::PingAnswer::PingMessage(
                             match  bluerobotics_ping::Messages::try_from(&msg){
@@ -213,12 +214,19 @@ impl DeviceManager {
                         device_id,
                     });
                     crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Some(device_id));
+                    Self::ping360_stabilized_helper(data.angle, device_id);
                 }
             }
     }
 
     // An inner helper focused on Ping360, which uses DeviceData message to plot graphs
     pub fn ping360_continuous_mode_helper(msg: bluerobotics_ping::Messages, device_id: Uuid) {
+        if let bluerobotics_ping::Messages::Ping360(
+            bluerobotics_ping::ping360::Messages::DeviceData(data),
+        ) = &msg
+        {
+            Self::ping360_stabilized_helper(data.angle, device_id);
+        }
         let answer = Answer::DeviceMessage(DeviceAnswer {
             answer: crate::device::devices::PingAnswer::PingMessage(msg),
             device_id,
@@ -226,6 +234,17 @@ impl DeviceManager {
         crate::server::protocols::v1::websocket::send_to_websockets(json!(answer), Some(device_id));
     }
 
+    // Sends the world referenced angle of the beam next to the raw data, while the vehicle pose is fresh
+    fn ping360_stabilized_helper(angle: u16, device_id: Uuid) {
+        let now = chrono::Utc::now().timestamp_millis();
+        if let Some(beam) = crate::device::recording::stabilized::stabilize(device_id, angle, now) {
+            crate::server::protocols::v1::websocket::send_to_websockets(
+                json!({ "StabilizedBeam": beam }),
+                Some(device_id),
+            );
+        }
+    }
+
     // An inner helper that returns error to requester
     pub fn handle_error_continuous_mode(
         error: tokio::sync::broadcast::error::RecvError,
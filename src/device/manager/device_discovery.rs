@@ -1,17 +1,18 @@
 use std::{net::Ipv4Addr, time::Duration};
 
 use tokio::{io::AsyncWriteExt, task::JoinSet, time::timeout};
-use tokio_serial::{available_ports, SerialPort, SerialPortBuilderExt, SerialStream};
+use tokio_serial::{
+    available_ports, SerialPort, SerialPortBuilderExt, SerialPortType, SerialStream,
+};
 use tracing::{debug, error, info, trace, warn};
 
 use crate::device::manager::ManagerError;
 
-use super::{SourceSelection, SourceSerialStruct, SourceUdpStruct};
+use super::{CreateStruct, DeviceSelection, SourceSelection, SourceSerialStruct, SourceUdpStruct};
+use paperclip::actix::Apiv2Schema;
 use regex::Regex;
-use std::collections::HashMap;
-
-#[cfg(feature = "blueos-extension")]
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, PartialEq)]
 pub struct DiscoveryResponse {
@@ -21,6 +22,18 @@ pub struct DiscoveryResponse {
     pub ip_address: Ipv4Addr,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct SerialPortCandidate {
+    pub path: String,
+    pub port_type: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub in_use: bool,
+}
+
 #[cfg(feature = "blueos-extension")]
 #[derive(Debug, Deserialize, Serialize)]
 struct DriverStatus {
@@ -49,20 +62,200 @@ pub struct BluePingDiscoveryResult {
     pub used_ports: Vec<String>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscoveryRule {
+    /// Serial port path, links like /dev/serial/by-id/* match the port they point to
+    SerialPort(String),
+    /// IPv4 address or CIDR range, e.g. 192.168.2.0/24
+    Network { address: Ipv4Addr, prefix: u8 },
+}
+
+impl std::str::FromStr for DiscoveryRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        if rule.is_empty() {
+            return Err("Empty discovery rule".to_string());
+        }
+
+        let (address, prefix) = match rule.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (rule, None),
+        };
+
+        let Ok(address) = address.parse::<Ipv4Addr>() else {
+            return Ok(DiscoveryRule::SerialPort(rule.to_string()));
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= 32)
+                .ok_or_else(|| format!("Invalid network prefix in discovery rule {rule:?}"))?,
+            None => 32,
+        };
+
+        Ok(DiscoveryRule::Network { address, prefix })
+    }
+}
+
+/// Device known in advance, `serial:<path>[:<baudrate>][:<type>]` or `udp:<ip>:<port>[:<type>]`, e.g.
+/// `serial:/dev/ttyUSB0:115200:ping1d` or `udp:192.168.2.2:12345:ping360`.
+/// Without a baudrate it is detected, without a type it is probed.
+impl std::str::FromStr for CreateStruct {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = spec.trim().split_once(':').ok_or_else(|| {
+            format!("Invalid device {spec:?}, expected serial:<path> or udp:<ip>:<port>")
+        })?;
+        let mut parts: Vec<&str> = rest.split(':').collect();
+        let device_selection = match parts.last().map(|part| part.to_lowercase()).as_deref() {
+            Some("ping1d") => Some(DeviceSelection::Ping1D),
+            Some("ping360") => Some(DeviceSelection::Ping360),
+            Some("common") => Some(DeviceSelection::Common),
+            Some("auto") => Some(DeviceSelection::Auto),
+            _ => None,
+        };
+        if device_selection.is_some() {
+            parts.pop();
+        }
+
+        let source = match kind.to_lowercase().as_str() {
+            "serial" => {
+                let baudrate = match parts.last().and_then(|part| part.parse::<u32>().ok()) {
+                    Some(baudrate) if parts.len() > 1 => {
+                        parts.pop();
+                        baudrate
+                    }
+                    _ => 0,
+                };
+                // Windows ports like COM3 have no colon, but keep any other in the path
+                let path = parts.join(":");
+                if path.is_empty() {
+                    return Err(format!("Missing serial port path in device {spec:?}"));
+                }
+                SourceSelection::SerialStream(SourceSerialStruct { path, baudrate })
+            }
+            "udp" => {
+                let [ip, port] = parts.as_slice() else {
+                    return Err(format!("Invalid device {spec:?}, expected udp:<ip>:<port>"));
+                };
+                SourceSelection::UdpStream(SourceUdpStruct {
+                    ip: ip
+                        .parse()
+                        .map_err(|_| format!("Invalid IPv4 address {ip:?} in device {spec:?}"))?,
+                    port: port
+                        .parse()
+                        .map_err(|_| format!("Invalid port {port:?} in device {spec:?}"))?,
+                })
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown source {kind:?} in device {spec:?}, expected serial or udp"
+                ))
+            }
+        };
+
+        Ok(CreateStruct {
+            source,
+            device_selection: device_selection.unwrap_or(DeviceSelection::Auto),
+            negotiate_baudrate: false,
+        })
+    }
+}
+
+impl DiscoveryRule {
+    fn matches(&self, source: &SourceSelection) -> bool {
+        match (self, source) {
+            (DiscoveryRule::SerialPort(path), SourceSelection::SerialStream(serial)) => {
+                same_serial_port(path, &serial.path)
+            }
+            (DiscoveryRule::Network { address, prefix }, SourceSelection::UdpStream(udp)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*address) & mask == u32::from(udp.ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    fn same_kind(&self, source: &SourceSelection) -> bool {
+        matches!(
+            (self, source),
+            (
+                DiscoveryRule::SerialPort(_),
+                SourceSelection::SerialStream(_)
+            ) | (DiscoveryRule::Network { .. }, SourceSelection::UdpStream(_))
+        )
+    }
+}
+
+fn same_serial_port(a: &str, b: &str) -> bool {
+    let canonical =
+        |path: &str| std::fs::canonicalize(path).unwrap_or_else(|_| std::path::PathBuf::from(path));
+    a == b || canonical(a) == canonical(b)
+}
+
+/// Sources that discovery may probe, denied sources are never touched.
+/// The allow-list only restricts the kind of source it has rules for, so allowing a serial port
+/// keeps network discovery untouched.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryFilter {
+    pub allow: Vec<DiscoveryRule>,
+    pub deny: Vec<DiscoveryRule>,
+}
+
+impl DiscoveryFilter {
+    pub fn parse(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            allow: allow
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()?,
+            deny: deny
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn allows(&self, source: &SourceSelection) -> bool {
+        if self.deny.iter().any(|rule| rule.matches(source)) {
+            return false;
+        }
+
+        let mut allow = self
+            .allow
+            .iter()
+            .filter(|rule| rule.same_kind(source))
+            .peekable();
+        allow.peek().is_none() || allow.any(|rule| rule.matches(source))
+    }
+
+    pub fn allows_serial_port(&self, path: &str) -> bool {
+        self.allows(&SourceSelection::SerialStream(SourceSerialStruct {
+            path: path.to_string(),
+            baudrate: 0,
+        }))
+    }
+}
+
 impl DiscoveryResponse {
     /// Decode Ping360 ASCII NetworkDiscovery message using regex
     fn from_response(response: &str) -> Option<Self> {
-        let device_name_part = r"(?P<device_name>.+)";
-        let manufacturer_part = r"(?P<manufacturer>.+)";
+        let device_name_part = r"(?P<device_name>[^\r\n]+)";
+        let manufacturer_part = r"(?P<manufacturer>[^\r\n]+)";
         let mac_address_part = r"MAC\sAddress:-\s(?P<mac_address>[A-Fa-f0-9\-]+)";
         let ip_address_part = r"IP\sAddress:-\s*0{0,2}(?<octet_1>[0-9]{1,3})\.0{0,2}(?<octet_2>[0-9]{1,3})\.0{0,2}(?<octet_3>[0-9]{1,3})\.0{0,2}(?<octet_4>[0-9]{1,3})";
 
+        // Classic Ping-Viewer accepts both CRLF and LF terminated lines
         let regex_pattern = format!(
             r"(?x)
-            ^{device_name_part}\r\n
-            {manufacturer_part}\r\n
-            {mac_address_part}\r\n
-            {ip_address_part}\r\n$
+            ^{device_name_part}\r?\n
+            {manufacturer_part}\r?\n
+            {mac_address_part}\r?\n
+            {ip_address_part}\r?\n\s*$
             "
         );
 
@@ -103,6 +296,39 @@ impl DiscoveryResponse {
     }
 }
 
+// Legacy Ping-Viewer discovery exchange: an ASCII "Discovery" datagram to port 30303, answered by
+// every Ping360 ethernet board with its name, manufacturer, MAC and IP address.
+const LEGACY_DISCOVERY_PORT: u16 = 30303;
+const LEGACY_DISCOVERY_MESSAGE: &str = "Discovery";
+const PING360_ETHERNET_PORT: u16 = 12345;
+
+// Limited broadcast plus the directed broadcast of every IPv4 interface, like classic Ping-Viewer does,
+// so hosts with several interfaces reach sonars outside the default route.
+fn legacy_discovery_targets() -> Vec<Ipv4Addr> {
+    let mut targets = vec![Ipv4Addr::BROADCAST];
+
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => {
+            for interface in interfaces {
+                if interface.is_loopback() {
+                    continue;
+                }
+                if let if_addrs::IfAddr::V4(address) = interface.addr {
+                    let broadcast = address.broadcast.unwrap_or_else(|| {
+                        Ipv4Addr::from(u32::from(address.ip) | !u32::from(address.netmask))
+                    });
+                    if !targets.contains(&broadcast) {
+                        targets.push(broadcast);
+                    }
+                }
+            }
+        }
+        Err(err) => warn!("auto_create: network: Failed to list network interfaces: {err}"),
+    }
+
+    targets
+}
+
 pub fn network_discovery() -> Option<Vec<SourceSelection>> {
     let socket = match std::net::UdpSocket::bind("0.0.0.0:0") {
         Ok(s) => s,
@@ -117,11 +343,20 @@ pub fn network_discovery() -> Option<Vec<SourceSelection>> {
         return None;
     }
 
-    let broadcast_addr = "255.255.255.255:30303";
-    let discovery_message = "Discovery";
-
-    if let Err(err) = socket.send_to(discovery_message.as_bytes(), broadcast_addr) {
-        warn!("auto_create: network: Failed to send discovery message: {err}");
+    let mut sent = false;
+    for target in legacy_discovery_targets() {
+        match socket.send_to(
+            LEGACY_DISCOVERY_MESSAGE.as_bytes(),
+            (target, LEGACY_DISCOVERY_PORT),
+        ) {
+            Ok(_) => sent = true,
+            Err(err) => {
+                debug!("auto_create: network: Failed to send discovery message to {target}: {err}")
+            }
+        }
+    }
+    if !sent {
+        warn!("auto_create: network: Failed to send discovery message");
         return None;
     }
 
@@ -131,7 +366,7 @@ pub fn network_discovery() -> Option<Vec<SourceSelection>> {
     }
 
     let mut buf = [0; 1024];
-    let mut responses = Vec::new();
+    let mut responses: Vec<DiscoveryResponse> = Vec::new();
 
     loop {
         match socket.recv_from(&mut buf) {
@@ -145,6 +380,20 @@ pub fn network_discovery() -> Option<Vec<SourceSelection>> {
                 };
 
                 if let Some(discovery_response) = DiscoveryResponse::from_response(response) {
+                    // The same board answers once per broadcast address it is reachable from
+                    if responses
+                        .iter()
+                        .any(|known| known.ip_address == discovery_response.ip_address)
+                    {
+                        continue;
+                    }
+                    info!(
+                        "auto_create: network: Found {} ({}) at {}, MAC: {}",
+                        discovery_response.device_name,
+                        discovery_response.manufacturer,
+                        discovery_response.ip_address,
+                        discovery_response.mac_address
+                    );
                     responses.push(discovery_response);
                 } else {
                     warn!(
@@ -168,7 +417,7 @@ pub fn network_discovery() -> Option<Vec<SourceSelection>> {
     for device in responses {
         let source = SourceSelection::UdpStream(SourceUdpStruct {
             ip: device.ip_address,
-            port: 12345,
+            port: PING360_ETHERNET_PORT,
         });
 
         available_sources.push(source);
@@ -226,7 +475,53 @@ pub async fn blueos_ping_discovery() -> Option<BluePingDiscoveryResult> {
     })
 }
 
-pub async fn serial_discovery(skip_ports: Option<&[String]>) -> Option<Vec<SourceSelection>> {
+// List the serial ports available on the host, with USB metadata when the port provides it
+pub fn list_serial_ports(used_ports: &[String]) -> Result<Vec<SerialPortCandidate>, ManagerError> {
+    let ports = available_ports().map_err(|err| {
+        warn!("list_serial_ports: Unable to list serial ports, details: {err}");
+        ManagerError::DeviceSourceError(err.to_string())
+    })?;
+
+    let candidates = ports
+        .into_iter()
+        .map(|port_info| {
+            let in_use = used_ports.contains(&port_info.port_name);
+            let mut candidate = SerialPortCandidate {
+                path: port_info.port_name,
+                port_type: "unknown".to_string(),
+                vid: None,
+                pid: None,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+                in_use,
+            };
+
+            match port_info.port_type {
+                SerialPortType::UsbPort(usb) => {
+                    candidate.port_type = "usb".to_string();
+                    candidate.vid = Some(usb.vid);
+                    candidate.pid = Some(usb.pid);
+                    candidate.serial_number = usb.serial_number;
+                    candidate.manufacturer = usb.manufacturer;
+                    candidate.product = usb.product;
+                }
+                SerialPortType::PciPort => candidate.port_type = "pci".to_string(),
+                SerialPortType::BluetoothPort => candidate.port_type = "bluetooth".to_string(),
+                SerialPortType::Unknown => {}
+            }
+
+            candidate
+        })
+        .collect();
+
+    Ok(candidates)
+}
+
+pub async fn serial_discovery(
+    skip_ports: Option<&[String]>,
+    filter: &DiscoveryFilter,
+) -> Option<Vec<SourceSelection>> {
     match available_ports() {
         Ok(serial_ports) => {
             debug!("serial_discovery: Found {serial_ports:?}");
@@ -239,12 +534,23 @@ pub async fn serial_discovery(skip_ports: Option<&[String]>) -> Option<Vec<Sourc
                 .filter(|port_info| match skip_ports {
                     Some(skip_list) => !skip_list.contains(&port_info.port_name),
                     None => true,
+                })
+                .filter(|port_info| {
+                    let allowed = filter.allows_serial_port(&port_info.port_name);
+                    if !allowed {
+                        debug!(
+                            "serial_discovery: Skipping filtered port {}",
+                            port_info.port_name
+                        );
+                    }
+                    allowed
                 });
 
             filtered_ports.for_each(|port_info| {
                 let path = port_info.port_name.clone();
                 set.spawn(async move {
-                    let baud_rate = auto_detect_baudrate(path.clone()).await?;
+                    let baud_rate =
+                        auto_detect_baudrate(path.clone(), &NEGOTIATION_BAUDRATES).await?;
 
                     Ok(SourceSelection::SerialStream(SourceSerialStruct {
                         path,
@@ -283,16 +589,50 @@ pub async fn serial_discovery(skip_ports: Option<&[String]>) -> Option<Vec<Sourc
     }
 }
 
-async fn auto_detect_baudrate(path: String) -> Result<u32, ManagerError> {
+// Highest rates first, the first rate with a clean quality check is the fastest usable link
+const NEGOTIATION_BAUDRATES: [u32; 8] = [
+    2500000, 2000000, 1843200, 921600, 460800, 230400, 115200, 9600,
+];
+
+// Common Ping baudrates, factory default first, used when only a responsive rate is needed
+const COMMON_BAUDRATES: [u32; 5] = [115200, 9600, 230400, 460800, 921600];
+
+// Resolve the baudrate of a serial source when it was not specified (0), keeping other sources untouched
+pub async fn resolve_source_baudrate(
+    source: SourceSelection,
+    negotiate_higher: bool,
+) -> Result<SourceSelection, ManagerError> {
+    match source {
+        SourceSelection::SerialStream(serial) if serial.baudrate == 0 => {
+            let baudrate = detect_baudrate(&serial.path, negotiate_higher).await?;
+            info!(
+                "Baudrate detection: {} responded at {baudrate} (negotiated: {negotiate_higher})",
+                serial.path
+            );
+            Ok(SourceSelection::SerialStream(SourceSerialStruct {
+                path: serial.path,
+                baudrate,
+            }))
+        }
+        source => Ok(source),
+    }
+}
+
+pub async fn detect_baudrate(path: &str, negotiate_higher: bool) -> Result<u32, ManagerError> {
+    if negotiate_higher {
+        auto_detect_baudrate(path.to_string(), &NEGOTIATION_BAUDRATES).await
+    } else {
+        auto_detect_baudrate(path.to_string(), &COMMON_BAUDRATES).await
+    }
+}
+
+async fn auto_detect_baudrate(path: String, baud_rates: &[u32]) -> Result<u32, ManagerError> {
     const BAUDRATE_CHECK_MESSAGES: usize = 10;
     const TOTAL_CHECK_TIMEOUT_MS: u64 = 2000;
 
-    let baud_rates = [
-        2500000, 2000000, 1843200, 921600, 460800, 230400, 115200, 9600,
-    ];
     let mut baudrate_results: HashMap<u32, BaudrateCheckResult> = HashMap::new();
 
-    for &rate in &baud_rates {
+    for &rate in baud_rates {
         debug!("auto_detect_baudrate: Testing baud rate: {rate} for {path}");
 
         let mut serial_stream = match tokio_serial::new(path.clone(), rate).open_native_async() {
@@ -534,6 +874,79 @@ mod tests {
         assert_eq!(parsed, Some(expected));
     }
 
+    #[test]
+    fn test_discovery_response_parsing_with_lf_endings() {
+        let response = "SONAR PING360\n\
+                        Blue Robotics\n\
+                        MAC Address:- 54-10-EC-79-7D-D1\n\
+                        IP Address:- 192.168.002.002\n\n";
+
+        let parsed = DiscoveryResponse::from_response(response).unwrap();
+        assert_eq!(parsed.device_name, "SONAR PING360");
+        assert_eq!(parsed.ip_address, Ipv4Addr::new(192, 168, 2, 2));
+    }
+
+    #[test]
+    fn test_device_specifications() {
+        let device: CreateStruct = "serial:/dev/ttyUSB0:115200:ping1d".parse().unwrap();
+        assert_eq!(
+            device.source,
+            SourceSelection::SerialStream(SourceSerialStruct {
+                path: "/dev/ttyUSB0".to_string(),
+                baudrate: 115200,
+            })
+        );
+        assert_eq!(device.device_selection, DeviceSelection::Ping1D);
+
+        let device: CreateStruct = "serial:/dev/ttyACM0".parse().unwrap();
+        assert_eq!(
+            device.source,
+            SourceSelection::SerialStream(SourceSerialStruct {
+                path: "/dev/ttyACM0".to_string(),
+                baudrate: 0,
+            })
+        );
+        assert_eq!(device.device_selection, DeviceSelection::Auto);
+
+        let device: CreateStruct = "udp:192.168.2.2:12345:ping360".parse().unwrap();
+        assert_eq!(
+            device.source,
+            SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::new(192, 168, 2, 2),
+                port: 12345,
+            })
+        );
+        assert_eq!(device.device_selection, DeviceSelection::Ping360);
+
+        assert!("udp:192.168.2.2".parse::<CreateStruct>().is_err());
+        assert!("serial:".parse::<CreateStruct>().is_err());
+        assert!("tcp:192.168.2.2:9000".parse::<CreateStruct>().is_err());
+        assert!("/dev/ttyUSB0".parse::<CreateStruct>().is_err());
+    }
+
+    #[test]
+    fn test_discovery_filter() {
+        let filter = DiscoveryFilter::parse(
+            &["192.168.2.0/24".to_string()],
+            &["/dev/ttyACM0".to_string(), "192.168.2.1".to_string()],
+        )
+        .unwrap();
+
+        let udp = |ip: [u8; 4]| {
+            SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::from(ip),
+                port: 12345,
+            })
+        };
+
+        assert!(filter.allows(&udp([192, 168, 2, 2])));
+        assert!(!filter.allows(&udp([192, 168, 2, 1])));
+        assert!(!filter.allows(&udp([10, 0, 0, 2])));
+        assert!(!filter.allows_serial_port("/dev/ttyACM0"));
+        assert!(filter.allows_serial_port("/dev/ttyUSB0"));
+        assert!(DiscoveryFilter::parse(&["10.0.0.0/33".to_string()], &[]).is_err());
+    }
+
     #[test]
     fn test_invalid_response_parsing() {
         let invalid_response = "INVALID RESPONSE FORMAT";
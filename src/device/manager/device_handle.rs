@@ -17,6 +17,26 @@ impl DeviceManager {
         Err(ManagerError::DeviceNotExist(device_id))
     }
 
+    pub fn check_source_conflict(&self, source: &SourceSelection) -> Result<(), ManagerError> {
+        let Some(device) = self
+            .device
+            .values()
+            .find(|device| device.source.conflicts_with(source))
+        else {
+            return Ok(());
+        };
+
+        if &device.source == source {
+            trace!("Device creation error: Device already exist for provided SourceSelection, details: {source:?}");
+            return Err(ManagerError::DeviceAlreadyExist(device.id));
+        }
+        error!(
+            "Device creation error: source {source:?} is already used by device {:?}",
+            device.id
+        );
+        Err(ManagerError::SourceInUse(device.source.clone(), device.id))
+    }
+
     pub fn get_device(&self, device_id: Uuid) -> Result<&Device, ManagerError> {
         let device = self
             .device
@@ -0,0 +1,232 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::manager::{
+    metrics::ThroughputMetrics, Answer, DeviceManager, DeviceSelection, ManagerError,
+};
+
+// Ping360 head angles are in gradians, a full turn is 400 steps
+const FULL_TURN: u16 = 400;
+// A step taking this many times the mean interval is reported as a stall
+const STALL_FACTOR: f64 = 5.0;
+// Coefficient of variation above which step timing is considered irregular
+const JITTER_THRESHOLD: f64 = 0.5;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MotorDiagnostics {
+    pub steps_observed: u64,
+    pub mean_step_interval_ms: f64,
+    pub max_step_interval_ms: f64,
+    pub step_interval_jitter_ms: f64,
+    pub missed_steps: u64,
+    pub unexpected_jumps: u64,
+    pub stalls: u64,
+    pub last_angle: Option<u16>,
+    /// The Ping360 protocol has no temperature message, kept for heads that may report it
+    pub head_temperature_c: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceHealth {
+    /// 100 is a healthy device, every anomaly lowers the score
+    pub score: f64,
+    pub anomalies: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ping360Diagnostics {
+    pub device_id: Uuid,
+    pub motor: MotorDiagnostics,
+    pub health: DeviceHealth,
+}
+
+#[derive(Debug, Default)]
+pub struct MotorTracker {
+    data: MotorDiagnostics,
+    last_step: Option<Instant>,
+    // Welford accumulator for the step interval variance
+    interval_m2: f64,
+}
+
+pub struct StepSample {
+    pub angle: u16,
+    /// Expected step size, None when the device is polled instead of auto transmitting
+    pub num_steps: Option<u8>,
+    pub start_angle: u16,
+    pub stop_angle: u16,
+}
+
+impl MotorTracker {
+    pub fn snapshot(&self) -> MotorDiagnostics {
+        self.data.clone()
+    }
+
+    pub fn feed(&mut self, sample: StepSample, now: Instant) {
+        if let Some(last_step) = self.last_step.replace(now) {
+            self.record_interval(now.duration_since(last_step).as_secs_f64() * 1000.0);
+        }
+
+        if let (Some(last_angle), Some(num_steps)) = (self.data.last_angle, sample.num_steps) {
+            self.check_step(last_angle, num_steps.max(1) as u16, &sample);
+        }
+
+        self.data.steps_observed += 1;
+        self.data.last_angle = Some(sample.angle);
+    }
+
+    fn record_interval(&mut self, interval_ms: f64) {
+        let data = &mut self.data;
+        if data.mean_step_interval_ms > 0.0
+            && interval_ms > data.mean_step_interval_ms * STALL_FACTOR
+        {
+            data.stalls += 1;
+        }
+
+        let count = data.steps_observed as f64;
+        let delta = interval_ms - data.mean_step_interval_ms;
+        data.mean_step_interval_ms += delta / count;
+        self.interval_m2 += delta * (interval_ms - data.mean_step_interval_ms);
+        data.step_interval_jitter_ms = (self.interval_m2 / count).sqrt();
+        data.max_step_interval_ms = data.max_step_interval_ms.max(interval_ms);
+    }
+
+    fn check_step(&mut self, last_angle: u16, num_steps: u16, sample: &StepSample) {
+        let forward = (sample.angle + FULL_TURN - last_angle) % FULL_TURN;
+        let backward = (last_angle + FULL_TURN - sample.angle) % FULL_TURN;
+
+        // Regular step, or a reversal/wrap at the sector limits
+        if forward == num_steps
+            || backward == num_steps
+            || sample.angle.abs_diff(sample.start_angle) < num_steps
+            || sample.angle.abs_diff(sample.stop_angle) < num_steps
+        {
+            return;
+        }
+
+        if forward > num_steps && forward < FULL_TURN / 2 && forward % num_steps == 0 {
+            self.data.missed_steps += (forward / num_steps - 1) as u64;
+        } else {
+            self.data.unexpected_jumps += 1;
+        }
+    }
+}
+
+pub fn health_score(
+    throughput: Option<&ThroughputMetrics>,
+    motor: &MotorDiagnostics,
+) -> DeviceHealth {
+    let mut score = 100.0;
+    let mut anomalies = Vec::new();
+
+    if let Some(throughput) = throughput {
+        if throughput.total_messages > 0 && throughput.unknown_messages > 0 {
+            let ratio = throughput.unknown_messages as f64 / throughput.total_messages as f64;
+            score -= (ratio * 100.0).min(30.0);
+            anomalies.push(format!("{} unknown messages", throughput.unknown_messages));
+        }
+    }
+
+    if motor.steps_observed > 0 && motor.missed_steps > 0 {
+        let ratio = motor.missed_steps as f64 / motor.steps_observed as f64;
+        score -= (ratio * 200.0).min(30.0);
+        anomalies.push(format!("{} missed motor steps", motor.missed_steps));
+    }
+
+    if motor.unexpected_jumps > 0 {
+        score -= (motor.unexpected_jumps as f64 * 2.0).min(15.0);
+        anomalies.push(format!("{} unexpected head jumps", motor.unexpected_jumps));
+    }
+
+    if motor.stalls > 0 {
+        score -= (motor.stalls as f64 * 5.0).min(20.0);
+        anomalies.push(format!("{} motor stalls", motor.stalls));
+    }
+
+    if motor.mean_step_interval_ms > 0.0
+        && motor.step_interval_jitter_ms / motor.mean_step_interval_ms > JITTER_THRESHOLD
+    {
+        score -= 10.0;
+        anomalies.push(format!(
+            "Irregular step timing, jitter {:.1} ms over {:.1} ms mean",
+            motor.step_interval_jitter_ms, motor.mean_step_interval_ms
+        ));
+    }
+
+    DeviceHealth {
+        score: f64::max(score, 0.0),
+        anomalies,
+    }
+}
+
+impl DeviceManager {
+    pub fn get_ping360_diagnostics(&self, device_id: Uuid) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+        if device.device_type != DeviceSelection::Ping360 {
+            return Err(ManagerError::Other(format!(
+                "Motor diagnostics are only available for Ping360 devices, device: {device_id}"
+            )));
+        }
+
+        let metrics = device.metrics.as_ref().ok_or_else(|| {
+            ManagerError::Other(format!("No diagnostics available for device: {device_id}"))
+        })?;
+        let throughput = metrics.snapshot();
+        let motor = metrics.motor_snapshot();
+
+        Ok(Answer::Ping360Diagnostics(Ping360Diagnostics {
+            device_id,
+            health: health_score(Some(&throughput), &motor),
+            motor,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn feed_angles(tracker: &mut MotorTracker, angles: &[u16], num_steps: u8) {
+        let start = Instant::now();
+        for (index, angle) in angles.iter().enumerate() {
+            tracker.feed(
+                StepSample {
+                    angle: *angle,
+                    num_steps: Some(num_steps),
+                    start_angle: 0,
+                    stop_angle: 399,
+                },
+                start + Duration::from_millis(20 * index as u64),
+            );
+        }
+    }
+
+    #[test]
+    fn test_regular_sweep_is_healthy() {
+        let mut tracker = MotorTracker::default();
+        let angles: Vec<u16> = (0..800).map(|step| (step * 2 % 400) as u16).collect();
+        feed_angles(&mut tracker, &angles, 2);
+
+        let motor = tracker.snapshot();
+        assert_eq!(motor.missed_steps, 0);
+        assert_eq!(motor.unexpected_jumps, 0);
+        assert!((motor.mean_step_interval_ms - 20.0).abs() < 1e-6);
+
+        let health = health_score(None, &motor);
+        assert_eq!(health.score, 100.0);
+        assert!(health.anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_missed_steps_lower_health() {
+        let mut tracker = MotorTracker::default();
+        feed_angles(&mut tracker, &[10, 11, 12, 15, 16, 300], 1);
+
+        let motor = tracker.snapshot();
+        assert_eq!(motor.missed_steps, 2);
+        assert_eq!(motor.unexpected_jumps, 1);
+        assert!(health_score(None, &motor).score < 100.0);
+    }
+}
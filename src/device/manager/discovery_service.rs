@@ -9,18 +9,15 @@ use tokio::time::sleep;
 use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
 use tracing::{debug, error, info, trace, warn};
 use udp_stream::UdpStream;
-use uuid::Uuid;
 
 use crate::device::devices::{DeviceActor, DeviceType, PingAnswer, UpgradeResult};
 use crate::device::manager::ManagerError;
 
 use super::{
-    device_discovery, DeviceInfo, DeviceSelection, DeviceStatus, SourceSelection, SourceType,
+    device_discovery::{self, DiscoveryFilter},
+    DeviceInfo, DeviceSelection, DeviceStatus, SourceSelection, SourceType,
 };
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-
 pub struct DeviceFactory;
 
 impl DeviceFactory {
@@ -29,6 +26,11 @@ impl DeviceFactory {
         mut device_type: DeviceSelection,
     ) -> Result<DeviceInfo, ManagerError> {
         let port = match &source {
+            SourceSelection::ReplayStream(_) => {
+                return Err(ManagerError::DeviceSourceError(
+                    "Replay devices are created from recordings".to_string(),
+                ))
+            }
             SourceSelection::UdpStream(source_udp_struct) => {
                 let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
 
@@ -127,9 +129,7 @@ impl DeviceFactory {
             }
         }
 
-        let mut hasher = DefaultHasher::new();
-        source.hash(&mut hasher);
-        let id = Uuid::from_u128(hasher.finish() as u128);
+        let id = super::identity::device_uuid(&source);
 
         let device = DeviceInfo {
             id,
@@ -147,8 +147,12 @@ pub struct DeviceDiscoveryManager {
     tx: broadcast::Sender<DeviceInfo>,
     handle: Option<tokio::task::JoinHandle<()>>,
     known_devices_rx: broadcast::Receiver<Vec<DeviceInfo>>,
+    filter: DiscoveryFilter,
+    interval: Option<Duration>,
 }
 
+pub const DEFAULT_DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
 impl DeviceDiscoveryManager {
     pub fn new(
         known_devices_rx: broadcast::Receiver<Vec<DeviceInfo>>,
@@ -159,29 +163,28 @@ impl DeviceDiscoveryManager {
                 tx,
                 handle: None,
                 known_devices_rx,
+                filter: DiscoveryFilter::default(),
+                interval: Some(DEFAULT_DISCOVERY_INTERVAL),
             },
             rx,
         )
     }
 
     pub fn start_discovery(&mut self) {
+        let Some(interval) = self.interval else {
+            info!("DeviceDiscovery service is disabled");
+            return;
+        };
         let tx = self.tx.clone();
         let mut known_devices_rx = self.known_devices_rx.resubscribe();
+        let filter = self.filter.clone();
 
         let handle = tokio::spawn(async move {
             let mut known_devices = Vec::new();
-            let mut device_keys = HashSet::new();
 
             loop {
                 match known_devices_rx.try_recv() {
-                    Ok(devices) => {
-                        known_devices = devices;
-                        device_keys.clear();
-                        for device in &known_devices {
-                            let key = get_device_key(&device.source);
-                            device_keys.insert(key);
-                        }
-                    }
+                    Ok(devices) => known_devices = devices,
                     Err(tokio::sync::broadcast::error::TryRecvError::Empty) => {}
                     Err(e) => {
                         error!("Error receiving known devices update: {e}");
@@ -189,49 +192,7 @@ impl DeviceDiscoveryManager {
                     }
                 }
 
-                let mut available_sources = Vec::new();
-
-                #[cfg(feature = "blueos-extension")]
-                if let Some(discovery_result) = device_discovery::blueos_ping_discovery().await {
-                    for source in discovery_result.sources {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
-
-                if let Some(result) = device_discovery::network_discovery() {
-                    for source in result {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
-
-                #[cfg(not(feature = "blueos-extension"))]
-                let used_ports: Vec<String> = known_devices
-                    .iter()
-                    .filter_map(|device| {
-                        if let SourceSelection::SerialStream(serial) = &device.source {
-                            Some(serial.path.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                // Add serial devices, skipping used ports
-                #[cfg(not(feature = "blueos-extension"))]
-                if let Some(result) = device_discovery::serial_discovery(Some(&used_ports)).await {
-                    for source in result {
-                        let key = get_device_key(&source);
-                        if !device_keys.contains(&key) {
-                            available_sources.push(source);
-                        }
-                    }
-                }
+                let available_sources = discover_sources(&filter, &known_devices).await;
 
                 // Process discovered sources
                 for source in available_sources {
@@ -250,13 +211,21 @@ impl DeviceDiscoveryManager {
                     }
                 }
 
-                tokio::time::sleep(Duration::from_secs(30)).await;
+                tokio::time::sleep(interval).await;
             }
         });
 
         self.handle = Some(handle);
     }
 
+    pub fn set_filter(&mut self, filter: DiscoveryFilter) {
+        self.filter = filter;
+    }
+
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+    }
+
     pub fn stop_discovery(&mut self) {
         if let Some(handle) = self.handle.take() {
             handle.abort();
@@ -264,13 +233,128 @@ impl DeviceDiscoveryManager {
     }
 }
 
+/// Sources answering a discovery round now, without the known devices and the filtered sources
+pub async fn discover_sources(
+    filter: &DiscoveryFilter,
+    known_devices: &[DeviceInfo],
+) -> Vec<SourceSelection> {
+    let device_keys: HashSet<String> = known_devices
+        .iter()
+        .map(|device| get_device_key(&device.source))
+        .collect();
+    let mut available_sources = Vec::new();
+
+    #[cfg(feature = "blueos-extension")]
+    if let Some(discovery_result) = device_discovery::blueos_ping_discovery().await {
+        for source in discovery_result.sources {
+            let key = get_device_key(&source);
+            if !device_keys.contains(&key) && filter.allows(&source) {
+                available_sources.push(source);
+            }
+        }
+    }
+
+    if let Some(result) = device_discovery::network_discovery() {
+        for source in result {
+            let key = get_device_key(&source);
+            if !device_keys.contains(&key) && filter.allows(&source) {
+                available_sources.push(source);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "blueos-extension"))]
+    let used_ports: Vec<String> = known_devices
+        .iter()
+        .filter_map(|device| {
+            if let SourceSelection::SerialStream(serial) = &device.source {
+                Some(serial.path.clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Add serial devices, skipping used ports
+    #[cfg(not(feature = "blueos-extension"))]
+    if let Some(result) = device_discovery::serial_discovery(Some(&used_ports), filter).await {
+        for source in result {
+            let key = get_device_key(&source);
+            if !device_keys.contains(&key) && filter.allows(&source) {
+                available_sources.push(source);
+            }
+        }
+    }
+
+    available_sources
+}
+
 fn get_device_key(source: &SourceSelection) -> String {
     match source {
         SourceSelection::SerialStream(serial) => serial.path.clone(),
         SourceSelection::UdpStream(udp) => format!("{}:{}", udp.ip, udp.port),
+        SourceSelection::ReplayStream(replay) => format!("replay:{}", replay.file_name),
     }
 }
 
+/// Discovered devices in aligned columns, sources written as --device takes them
+pub fn table(devices: &[DeviceInfo]) -> String {
+    let header = ["ID", "TYPE", "SOURCE", "FIRMWARE"].map(String::from);
+    let rows: Vec<[String; 4]> = devices
+        .iter()
+        .map(|device| {
+            let source = match &device.source {
+                SourceSelection::SerialStream(serial) => {
+                    format!("serial:{}:{}", serial.path, serial.baudrate)
+                }
+                SourceSelection::UdpStream(udp) => format!("udp:{}:{}", udp.ip, udp.port),
+                SourceSelection::ReplayStream(replay) => format!("replay:{}", replay.file_name),
+            };
+            let firmware = match &device.properties {
+                Some(super::DeviceProperties::Common(common)) => Some(common),
+                Some(super::DeviceProperties::Ping1D(properties)) => Some(&properties.common),
+                Some(super::DeviceProperties::Ping360(properties)) => Some(&properties.common),
+                None => None,
+            }
+            .map(|common| {
+                let information = &common.device_information;
+                format!(
+                    "{}.{}.{}",
+                    information.firmware_version_major,
+                    information.firmware_version_minor,
+                    information.firmware_version_patch
+                )
+            })
+            .unwrap_or_else(|| "-".to_string());
+            [
+                device.id.to_string(),
+                format!("{:?}", device.device_type),
+                source,
+                firmware,
+            ]
+        })
+        .collect();
+
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
 impl Drop for DeviceDiscoveryManager {
     fn drop(&mut self) {
         self.stop_discovery();
@@ -303,7 +387,19 @@ impl DiscoveryComponent {
 
     pub fn start_discovery(&mut self) {
         self.manager.start_discovery();
-        info!("DeviceDiscovery service is running");
+        if self.manager.handle.is_some() {
+            info!("DeviceDiscovery service is running");
+        }
+    }
+
+    /// Applied on the next start of the discovery service
+    pub fn set_filter(&mut self, filter: DiscoveryFilter) {
+        self.manager.set_filter(filter);
+    }
+
+    /// Period between discovery rounds, `None` disables the background discovery
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.manager.set_interval(interval);
     }
 
     pub fn stop_discovery(&mut self) {
@@ -319,3 +415,60 @@ impl DiscoveryComponent {
         self.rx.resubscribe()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::manager::{
+        CommonProperties, DeviceProperties, Ping1DProperties, SourceSerialStruct, SourceUdpStruct,
+    };
+
+    #[test]
+    fn test_table() {
+        let id = uuid::Uuid::nil();
+        let common = CommonProperties {
+            device_information: bluerobotics_ping::common::DeviceInformationStruct {
+                device_type: 1,
+                device_revision: 1,
+                firmware_version_major: 3,
+                firmware_version_minor: 29,
+                firmware_version_patch: 0,
+                reserved: 0,
+            },
+            protocol_version: bluerobotics_ping::common::ProtocolVersionStruct {
+                version_major: 1,
+                version_minor: 0,
+                version_patch: 0,
+                reserved: 0,
+            },
+        };
+        let devices = vec![
+            DeviceInfo {
+                id,
+                source: SourceSelection::SerialStream(SourceSerialStruct {
+                    path: "/dev/ttyUSB0".to_string(),
+                    baudrate: 115200,
+                }),
+                status: DeviceStatus::Running,
+                device_type: DeviceSelection::Ping1D,
+                properties: Some(DeviceProperties::Ping1D(Ping1DProperties { common })),
+            },
+            DeviceInfo {
+                id,
+                source: SourceSelection::UdpStream(SourceUdpStruct {
+                    ip: "192.168.2.2".parse().unwrap(),
+                    port: 12345,
+                }),
+                status: DeviceStatus::Running,
+                device_type: DeviceSelection::Ping360,
+                properties: None,
+            },
+        ];
+        assert_eq!(
+            table(&devices),
+            "ID                                    TYPE     SOURCE                      FIRMWARE\n\
+             00000000-0000-0000-0000-000000000000  Ping1D   serial:/dev/ttyUSB0:115200  3.29.0\n\
+             00000000-0000-0000-0000-000000000000  Ping360  udp:192.168.2.2:12345       -\n"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use tokio_serial::{available_ports, SerialPortType};
+use tracing::{debug, trace};
+use uuid::Uuid;
+
+use crate::device::manager::SourceSelection;
+
+// Namespace of every device UUID, changing it changes the id of every device
+const DEVICE_NAMESPACE: Uuid = Uuid::from_u128(0x6b1e_55c2_8f0a_4e4f_9c3d_2a71_0d5e_b360);
+
+/// Stable identity of the physical device behind a source, only from what is known when it is created.
+/// USB serial number when available, otherwise the source without its baudrate.
+/// Ethernet devices keep their address: a MAC address learned later by discovery would change the id
+/// of a device already running.
+pub fn device_identity(source: &SourceSelection) -> String {
+    match source {
+        SourceSelection::SerialStream(serial) => match usb_identity(&serial.path) {
+            Some(identity) => identity,
+            None => format!("serial:{}", serial.path),
+        },
+        SourceSelection::UdpStream(udp) => format!("udp:{}:{}", udp.ip, udp.port),
+        SourceSelection::ReplayStream(replay) => format!("replay:{}", replay.file_name),
+    }
+}
+
+/// UUIDv5 of the device identity, the same across restarts and builds.
+/// Ids from versions hashing the whole source are not carried over: devices created by them get a
+/// new id once, and sessions or settings stored under the old one must be attached again.
+pub fn device_uuid(source: &SourceSelection) -> Uuid {
+    let identity = device_identity(source);
+    let id = Uuid::new_v5(&DEVICE_NAMESPACE, identity.as_bytes());
+    trace!("Device identity {identity:?} -> {id}");
+    id
+}
+
+fn usb_identity(path: &str) -> Option<String> {
+    let canonical =
+        |path: &str| std::fs::canonicalize(path).unwrap_or_else(|_| std::path::PathBuf::from(path));
+    let target = canonical(path);
+
+    let ports = match available_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            debug!("Device identity: Unable to list serial ports, details: {err}");
+            return None;
+        }
+    };
+
+    ports
+        .into_iter()
+        .find(|port| port.port_name == path || canonical(&port.port_name) == target)
+        .and_then(|port| match port.port_type {
+            SerialPortType::UsbPort(usb) => usb
+                .serial_number
+                .filter(|serial_number| !serial_number.trim().is_empty())
+                .map(|serial_number| {
+                    format!(
+                        "usb:{:04x}:{:04x}:{}",
+                        usb.vid,
+                        usb.pid,
+                        serial_number.trim()
+                    )
+                }),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::device::manager::{SourceSerialStruct, SourceUdpStruct};
+
+    #[test]
+    fn test_uuid_ignores_baudrate() {
+        let source = |baudrate| {
+            SourceSelection::SerialStream(SourceSerialStruct {
+                path: "/dev/ping-test-port".to_string(),
+                baudrate,
+            })
+        };
+        assert_eq!(device_uuid(&source(115200)), device_uuid(&source(0)));
+    }
+
+    #[test]
+    fn test_udp_uuid_is_the_same_before_and_after_discovery() {
+        let source = SourceSelection::UdpStream(SourceUdpStruct {
+            ip: Ipv4Addr::new(192, 168, 2, 2),
+            port: 12345,
+        });
+        // Only the address is hashed, discovery answering with the MAC address later changes nothing
+        assert_eq!(
+            device_uuid(&source),
+            Uuid::new_v5(&DEVICE_NAMESPACE, b"udp:192.168.2.2:12345")
+        );
+    }
+}
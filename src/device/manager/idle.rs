@@ -0,0 +1,157 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{Ping360Request, PingRequest},
+    manager::{Answer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError},
+};
+
+pub const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct KeepAwake {
+    pub uuid: Uuid,
+    /// Hold the device awake while true, e.g. during a recording
+    pub hold: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct IdlePolicy {
+    timeout: Option<Duration>,
+    power_down: bool,
+    last_active: HashMap<Uuid, Instant>,
+    sleeping: HashSet<Uuid>,
+    keep_awake: HashSet<Uuid>,
+}
+
+impl IdlePolicy {
+    /// A `None` timeout disables auto-sleep
+    pub fn new(timeout: Option<Duration>, power_down: bool) -> Self {
+        Self {
+            timeout,
+            power_down,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeout.is_some()
+    }
+}
+
+impl DeviceManager {
+    pub fn set_idle_policy(&mut self, policy: IdlePolicy) {
+        if let Some(timeout) = policy.timeout {
+            info!(
+                "DeviceManager: Idle devices will sleep after {}s, power down: {}",
+                timeout.as_secs(),
+                policy.power_down
+            );
+        }
+        self.idle_policy = policy;
+    }
+
+    pub async fn keep_awake(&mut self, request: KeepAwake) -> Result<Answer, ManagerError> {
+        self.check_device_uuid(request.uuid)?;
+
+        if request.hold {
+            self.idle_policy.keep_awake.insert(request.uuid);
+            self.wake_device(request.uuid).await?;
+        } else {
+            self.idle_policy.keep_awake.remove(&request.uuid);
+            self.idle_policy
+                .last_active
+                .insert(request.uuid, Instant::now());
+        }
+
+        Ok(Answer::DeviceInfo(vec![self
+            .get_device(request.uuid)?
+            .info()]))
+    }
+
+    async fn wake_device(&mut self, device_id: Uuid) -> Result<(), ManagerError> {
+        self.idle_policy
+            .last_active
+            .insert(device_id, Instant::now());
+        if !self.idle_policy.sleeping.remove(&device_id) {
+            return Ok(());
+        }
+
+        info!("DeviceManager: Waking up idle device: {device_id}");
+        self.continuous_mode(device_id).await?;
+        Ok(())
+    }
+
+    async fn sleep_device(&mut self, device_id: Uuid) -> Result<(), ManagerError> {
+        info!("DeviceManager: Device {device_id} is idle, disabling continuous mode");
+        self.continuous_mode_off(device_id).await?;
+
+        if self.idle_policy.power_down
+            && self.get_device_type(device_id)? == DeviceSelection::Ping360
+        {
+            let handler = self.extract_handler(self.get_device_handler(device_id).await?)?;
+            if let Err(err) = handler
+                .send(PingRequest::Ping360(Ping360Request::MotorOff))
+                .await
+            {
+                warn!("DeviceManager: Failed to power down idle Ping360 {device_id}: {err:?}");
+            }
+        }
+
+        self.idle_policy.sleeping.insert(device_id);
+        Ok(())
+    }
+
+    pub async fn check_idle_devices(&mut self) {
+        let Some(timeout) = self.idle_policy.timeout else {
+            return;
+        };
+
+        let now = Instant::now();
+        self.idle_policy
+            .last_active
+            .retain(|device_id, _| self.device.contains_key(device_id));
+        self.idle_policy
+            .sleeping
+            .retain(|device_id| self.device.contains_key(device_id));
+
+        let devices: Vec<(Uuid, DeviceStatus)> = self
+            .device
+            .values()
+            .map(|device| (device.id, device.status.clone()))
+            .collect();
+
+        for (device_id, status) in devices {
+            let watched = crate::server::protocols::v1::websocket::subscribers(device_id) > 0
+                || self.idle_policy.keep_awake.contains(&device_id);
+
+            if self.idle_policy.sleeping.contains(&device_id) {
+                if watched {
+                    if let Err(err) = self.wake_device(device_id).await {
+                        warn!("DeviceManager: Failed to wake device {device_id}: {err:?}");
+                    }
+                }
+                continue;
+            }
+
+            if watched || status != DeviceStatus::ContinuousMode {
+                self.idle_policy.last_active.insert(device_id, now);
+                continue;
+            }
+
+            let last_active = *self.idle_policy.last_active.entry(device_id).or_insert(now);
+            if now.duration_since(last_active) >= timeout {
+                if let Err(err) = self.sleep_device(device_id).await {
+                    warn!("DeviceManager: Failed to put device {device_id} to sleep: {err:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::device::manager::{Answer, DeviceManager, ManagerError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatestSample {
+    pub device_id: Uuid,
+    /// Same layout as the device messages sent on the websocket
+    pub message: bluerobotics_ping::Messages,
+    pub received_at: String,
+    /// Time since the message was received, a stale sample means the device stopped sending
+    pub age_ms: u64,
+}
+
+#[derive(Debug)]
+struct Sample {
+    message: bluerobotics_ping::Messages,
+    received_at: DateTime<Utc>,
+    instant: Instant,
+}
+
+/// Most recent Profile or DeviceData of a device, fed by its metrics task
+#[derive(Debug, Clone, Default)]
+pub struct LatestCache(Arc<RwLock<Option<Sample>>>);
+
+impl LatestCache {
+    pub fn update(&self, message: bluerobotics_ping::Messages) {
+        if !is_sample(&message) {
+            return;
+        }
+        match self.0.write() {
+            Ok(mut latest) => {
+                *latest = Some(Sample {
+                    message,
+                    received_at: Utc::now(),
+                    instant: Instant::now(),
+                })
+            }
+            Err(err) => error!("Failed to update latest device sample: {err:?}"),
+        }
+    }
+
+    pub fn snapshot(&self, device_id: Uuid) -> Option<LatestSample> {
+        let latest = self.0.read().ok()?;
+        let sample = latest.as_ref()?;
+        Some(LatestSample {
+            device_id,
+            message: sample.message.clone(),
+            received_at: sample.received_at.to_rfc3339(),
+            age_ms: sample.instant.elapsed().as_millis() as u64,
+        })
+    }
+}
+
+// Measurements only, configuration and acknowledge messages are not what pollers look for
+fn is_sample(message: &bluerobotics_ping::Messages) -> bool {
+    matches!(
+        message,
+        bluerobotics_ping::Messages::Ping1D(bluerobotics_ping::ping1d::Messages::Profile(_))
+            | bluerobotics_ping::Messages::Ping360(
+                bluerobotics_ping::ping360::Messages::DeviceData(_)
+                    | bluerobotics_ping::ping360::Messages::AutoDeviceData(_)
+            )
+    )
+}
+
+impl DeviceManager {
+    pub fn get_latest_sample(&self, device_id: Uuid) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+        device
+            .metrics
+            .as_ref()
+            .and_then(|metrics| metrics.latest.snapshot(device_id))
+            .map(Answer::LatestSample)
+            .ok_or_else(|| {
+                ManagerError::Other(format!("No sample received yet for device: {device_id}"))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_data() -> bluerobotics_ping::Messages {
+        bluerobotics_ping::Messages::Ping360(bluerobotics_ping::ping360::Messages::AutoDeviceData(
+            bluerobotics_ping::ping360::AutoDeviceDataStruct {
+                mode: 1,
+                gain_setting: 0,
+                angle: 100,
+                transmit_duration: 32,
+                sample_period: 80,
+                transmit_frequency: 740,
+                start_angle: 0,
+                stop_angle: 399,
+                num_steps: 1,
+                delay: 0,
+                number_of_samples: 2,
+                data_length: 2,
+                data: vec![0, 255],
+            },
+        ))
+    }
+
+    #[test]
+    fn test_only_measurements_are_cached() {
+        let cache = LatestCache::default();
+        let device_id = Uuid::new_v4();
+        assert!(cache.snapshot(device_id).is_none());
+
+        cache.update(device_data());
+        let sample = cache.snapshot(device_id).unwrap();
+        assert_eq!(sample.device_id, device_id);
+
+        cache.update(bluerobotics_ping::Messages::Common(
+            bluerobotics_ping::common::Messages::ProtocolVersion(
+                bluerobotics_ping::common::ProtocolVersionStruct {
+                    version_major: 1,
+                    version_minor: 0,
+                    version_patch: 0,
+                    reserved: 0,
+                },
+            ),
+        ));
+        assert!(matches!(
+            cache.snapshot(device_id).unwrap().message,
+            bluerobotics_ping::Messages::Ping360(
+                bluerobotics_ping::ping360::Messages::AutoDeviceData(_)
+            )
+        ));
+    }
+}
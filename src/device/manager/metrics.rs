@@ -0,0 +1,215 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{error, trace, warn};
+use uuid::Uuid;
+
+use bluerobotics_ping::message::ProtocolMessage;
+
+use crate::device::manager::{
+    diagnostics::{MotorDiagnostics, MotorTracker, StepSample},
+    latest::LatestCache,
+    Answer, DeviceManager, ManagerError,
+};
+
+// Ping protocol frame overhead: 8 bytes of header plus 2 bytes of checksum
+const FRAME_OVERHEAD_BYTES: u64 = 10;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const WEBSOCKET_REPORT_WINDOWS: u64 = 5;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThroughputMetrics {
+    pub device_id: Uuid,
+    pub messages_per_second: f64,
+    pub bytes_per_second: f64,
+    pub total_messages: u64,
+    pub total_bytes: u64,
+    /// Messages with valid framing and checksum that could not be decoded, e.g. unknown ids. Frames with a
+    /// bad checksum are dropped by the driver and never reach this counter
+    pub unknown_messages: u64,
+    pub lagged_messages: u64,
+}
+
+#[derive(Debug)]
+pub struct MetricsHandle {
+    pub data: Arc<RwLock<ThroughputMetrics>>,
+    pub motor: Arc<RwLock<MotorTracker>>,
+    pub latest: LatestCache,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MetricsHandle {
+    pub fn snapshot(&self) -> ThroughputMetrics {
+        match self.data.read() {
+            Ok(data) => data.clone(),
+            Err(err) => {
+                error!("Failed to read device metrics: {err:?}");
+                ThroughputMetrics::default()
+            }
+        }
+    }
+
+    pub fn motor_snapshot(&self) -> MotorDiagnostics {
+        match self.motor.read() {
+            Ok(motor) => motor.snapshot(),
+            Err(err) => {
+                error!("Failed to read motor diagnostics: {err:?}");
+                MotorDiagnostics::default()
+            }
+        }
+    }
+}
+
+impl Drop for MetricsHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl DeviceManager {
+    pub async fn start_metrics(&mut self, device_id: Uuid) -> Result<(), ManagerError> {
+        let subscriber = self.get_subscriber(device_id).await?;
+
+        let data = Arc::new(RwLock::new(ThroughputMetrics {
+            device_id,
+            ..Default::default()
+        }));
+        let motor = Arc::new(RwLock::new(MotorTracker::default()));
+        let latest = LatestCache::default();
+        let task = tokio::spawn(metrics_task(
+            subscriber,
+            data.clone(),
+            motor.clone(),
+            latest.clone(),
+            device_id,
+        ));
+
+        self.get_mut_device(device_id)?.metrics = Some(MetricsHandle {
+            data,
+            motor,
+            latest,
+            task,
+        });
+        trace!("Throughput metrics started for device: {device_id:?}");
+        Ok(())
+    }
+
+    pub fn get_metrics(&self) -> Result<Answer, ManagerError> {
+        let metrics = self
+            .device
+            .values()
+            .filter_map(|device| device.metrics.as_ref().map(MetricsHandle::snapshot))
+            .collect();
+        Ok(Answer::DeviceMetrics(metrics))
+    }
+
+    pub fn get_device_metrics(&self, device_id: Uuid) -> Result<Answer, ManagerError> {
+        let device = self.get_device(device_id)?;
+        let metrics = device.metrics.as_ref().ok_or_else(|| {
+            ManagerError::Other(format!("No metrics available for device: {device_id}"))
+        })?;
+        Ok(Answer::DeviceMetrics(vec![metrics.snapshot()]))
+    }
+}
+
+async fn metrics_task(
+    mut subscriber: Receiver<ProtocolMessage>,
+    data: Arc<RwLock<ThroughputMetrics>>,
+    motor: Arc<RwLock<MotorTracker>>,
+    latest: LatestCache,
+    device_id: Uuid,
+) {
+    let mut interval = tokio::time::interval(RATE_WINDOW);
+    let mut window_start = Instant::now();
+    let mut window_messages: u64 = 0;
+    let mut window_bytes: u64 = 0;
+    let mut windows: u64 = 0;
+
+    loop {
+        tokio::select! {
+            result = subscriber.recv() => match result {
+                Ok(msg) => {
+                    window_messages += 1;
+                    window_bytes += FRAME_OVERHEAD_BYTES + msg.payload.len() as u64;
+
+                    match bluerobotics_ping::Messages::try_from(&msg) {
+                        Ok(bluerobotics_ping::Messages::Ping360(message)) => {
+                            if let Some(sample) = step_sample(&message) {
+                                if let Ok(mut motor) = motor.write() {
+                                    motor.feed(sample, Instant::now());
+                                }
+                            }
+                            latest.update(bluerobotics_ping::Messages::Ping360(message));
+                        }
+                        Ok(message) => latest.update(message),
+                        Err(_) => {
+                            if let Ok(mut data) = data.write() {
+                                data.unknown_messages += 1;
+                            }
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Device metrics subscriber lagged by {count} messages, device: {device_id}");
+                    if let Ok(mut data) = data.write() {
+                        data.lagged_messages += count;
+                    }
+                }
+                Err(RecvError::Closed) => {
+                    trace!("Device metrics subscriber closed, device: {device_id}");
+                    break;
+                }
+            },
+            _ = interval.tick() => {
+                let elapsed = window_start.elapsed().as_secs_f64().max(f64::EPSILON);
+                window_start = Instant::now();
+
+                let snapshot = match data.write() {
+                    Ok(mut data) => {
+                        data.messages_per_second = window_messages as f64 / elapsed;
+                        data.bytes_per_second = window_bytes as f64 / elapsed;
+                        data.total_messages += window_messages;
+                        data.total_bytes += window_bytes;
+                        data.clone()
+                    }
+                    Err(err) => {
+                        error!("Failed to update device metrics: {err:?}, device: {device_id}");
+                        break;
+                    }
+                };
+                window_messages = 0;
+                window_bytes = 0;
+
+                windows += 1;
+                if windows % WEBSOCKET_REPORT_WINDOWS == 0 {
+                    let answer = Answer::DeviceMetrics(vec![snapshot]);
+                    crate::server::protocols::v1::websocket::send_to_websockets(
+                        json!(answer),
+                        Some(device_id),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn step_sample(message: &bluerobotics_ping::ping360::Messages) -> Option<StepSample> {
+    match message {
+        bluerobotics_ping::ping360::Messages::AutoDeviceData(data) => Some(StepSample {
+            angle: data.angle,
+            num_steps: Some(data.num_steps),
+            start_angle: data.start_angle,
+            stop_angle: data.stop_angle,
+        }),
+        bluerobotics_ping::ping360::Messages::DeviceData(data) => Some(StepSample {
+            angle: data.angle,
+            num_steps: None,
+            start_angle: 0,
+            stop_angle: 399,
+        }),
+        _ => None,
+    }
+}
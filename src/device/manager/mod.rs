@@ -4,14 +4,29 @@ pub mod continuous_mode;
 pub mod device_discovery;
 /// Specially for continuous_mode methods, startup, shutdown, handle and errors routines for each device type
 pub mod device_handle;
+/// Specially for Ping360 motor diagnostics and device health scoring
+pub mod diagnostics;
 /// Specially for DeviceManager, allow discovery service to run on background
 pub mod discovery_service;
+/// Specially for deterministic device UUIDs, derived from the hardware identity
+pub mod identity;
+/// Specially for idle auto-sleep, disabling continuous mode of devices nobody is watching
+pub mod idle;
+/// Specially for the most recent measurement of each device, for clients polling instead of streaming
+pub mod latest;
+/// Specially for throughput metrics, messages and bytes rates and undecodable messages for each device
+pub mod metrics;
+/// Specially for named device settings presets, stored on disk and applied to compatible devices
+pub mod presets;
+/// Specially for request/response devices, opt-in weighted round-robin polling shared fairly across devices
+pub mod scheduler;
+/// Specially for exporting, comparing and applying device settings between instances
+pub mod settings_sync;
 
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    hash::{Hash, Hasher},
+    collections::HashMap,
     net::{Ipv4Addr, SocketAddrV4, UdpSocket},
     ops::Deref,
     sync::{Arc, RwLock},
@@ -23,17 +38,22 @@ use tokio::{
 };
 
 use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, trace, warn, Instrument};
 use udp_stream::UdpStream;
 use uuid::Uuid;
 
 use super::devices::{DeviceActor, DeviceActorHandler, DeviceType, PingAnswer};
+use super::recording::events::{self as recording_events, EventKind};
 use bluerobotics_ping::{
     common::{DeviceInformationStruct, ProtocolVersionStruct},
     device::{Ping1D, Ping360},
     message::ProtocolMessage,
 };
 use discovery_service::DiscoveryComponent;
+use idle::IdlePolicy;
+use presets::PresetStore;
+use scheduler::PollScheduler;
+use settings_sync::{ManagerState, SettingsDiff, SettingsSnapshot};
 #[derive(Debug)]
 pub struct Device {
     pub id: Uuid,
@@ -44,6 +64,7 @@ pub struct Device {
     pub status: DeviceStatus,
     pub device_type: DeviceSelection,
     pub properties: Option<DeviceProperties>,
+    pub metrics: Option<metrics::MetricsHandle>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -93,6 +114,14 @@ pub struct DeviceInfo {
     pub properties: Option<DeviceProperties>,
 }
 impl Device {
+    pub fn set_status(&mut self, status: DeviceStatus) {
+        if self.status == status {
+            return;
+        }
+        broadcast_status_change(self.id, Some(&self.status), &status);
+        self.status = status;
+    }
+
     pub fn info(&self) -> DeviceInfo {
         DeviceInfo {
             id: self.id,
@@ -132,6 +161,30 @@ pub enum DeviceSelection {
 pub enum SourceSelection {
     UdpStream(SourceUdpStruct),
     SerialStream(SourceSerialStruct),
+    /// Virtual device replaying a recording, created through the recordings replay API
+    ReplayStream(SourceReplayStruct),
+}
+
+impl SourceSelection {
+    /// Whether both sources would open the same port, regardless of the serial baudrate
+    pub fn conflicts_with(&self, other: &SourceSelection) -> bool {
+        match (self, other) {
+            (SourceSelection::UdpStream(a), SourceSelection::UdpStream(b)) => {
+                a.ip == b.ip && a.port == b.port
+            }
+            (SourceSelection::ReplayStream(a), SourceSelection::ReplayStream(b)) => {
+                a.file_name == b.file_name
+            }
+            (SourceSelection::SerialStream(a), SourceSelection::SerialStream(b)) => {
+                // Resolve links like /dev/serial/by-id/* to the real port
+                let canonical = |path: &str| {
+                    std::fs::canonicalize(path).unwrap_or_else(|_| std::path::PathBuf::from(path))
+                };
+                a.path == b.path || canonical(&a.path) == canonical(&b.path)
+            }
+            _ => false,
+        }
+    }
 }
 
 enum SourceType {
@@ -148,21 +201,78 @@ pub struct SourceUdpStruct {
 #[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
 pub struct SourceSerialStruct {
     pub path: String,
+    /// A baudrate of 0 (or omitted) requests automatic detection during device creation.
+    #[serde(default)]
     pub baudrate: u32,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, Hash, Apiv2Schema, PartialEq)]
+pub struct SourceReplayStruct {
+    pub file_name: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DeviceStatus {
+    /// Discovered, not opened yet
     Available,
+    /// Source opened, identifying the device
+    Probing,
     Running,
-    Error,
     ContinuousMode,
+    /// The link to the device was lost
+    Disconnected,
+    Error {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceStatusChange {
+    pub device_id: Uuid,
+    pub previous: Option<DeviceStatus>,
+    pub status: DeviceStatus,
+}
+
+pub fn broadcast_status_change(
+    device_id: Uuid,
+    previous: Option<&DeviceStatus>,
+    status: &DeviceStatus,
+) {
+    trace!("Device {device_id:?} status changed: {previous:?} -> {status:?}");
+    let answer = Answer::DeviceStatusChange(DeviceStatusChange {
+        device_id,
+        previous: previous.cloned(),
+        status: status.clone(),
+    });
+    crate::server::protocols::v1::websocket::send_to_websockets(
+        serde_json::json!(answer),
+        Some(device_id),
+    );
+
+    let reconnected = matches!(
+        previous,
+        Some(DeviceStatus::Disconnected | DeviceStatus::Error { .. })
+    ) && matches!(status, DeviceStatus::Running | DeviceStatus::ContinuousMode);
+    let kind = if reconnected {
+        EventKind::Reconnected
+    } else {
+        EventKind::StatusChanged
+    };
+    recording_events::publish(
+        device_id,
+        kind,
+        serde_json::json!({"previous": previous, "status": status}).to_string(),
+    );
 }
 
 pub struct DeviceManager {
     receiver: mpsc::Receiver<ManagerActorRequest>,
     pub device: HashMap<Uuid, Device>,
     discovery_service: DiscoveryComponent,
+    poll_scheduler: PollScheduler,
+    presets: PresetStore,
+    idle_policy: IdlePolicy,
+    auto_create_discovered: bool,
     pub manager_handler: ManagerActorHandler,
 }
 
@@ -170,6 +280,8 @@ pub struct DeviceManager {
 pub struct ManagerActorRequest {
     pub request: Request,
     pub respond_to: oneshot::Sender<Result<Answer, ManagerError>>,
+    /// Span of the caller, keeps its request id on the logs of the manager
+    pub span: tracing::Span,
 }
 #[derive(Clone)]
 pub struct ManagerActorHandler {
@@ -182,13 +294,26 @@ pub enum Answer {
     #[serde(skip)]
     InnerDeviceHandler(DeviceActorHandler),
     DeviceInfo(Vec<DeviceInfo>),
+    DeviceStatusChange(DeviceStatusChange),
+    DeviceDiscovered(DeviceInfo),
     DeviceConfig(ModifyDeviceResult),
+    Settings(SettingsSnapshot),
+    SettingsDiff(SettingsDiff),
+    State(ManagerState),
+    DeviceMetrics(Vec<metrics::ThroughputMetrics>),
+    SerialPorts(Vec<device_discovery::SerialPortCandidate>),
+    PollSchedulerStats(scheduler::PollSchedulerStats),
+    Ping360Diagnostics(diagnostics::Ping360Diagnostics),
+    Presets(Vec<presets::Preset>),
+    LatestSample(latest::LatestSample),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ManagerError {
     DeviceNotExist(Uuid),
     DeviceAlreadyExist(Uuid),
+    /// The source is already opened by the device with this id
+    SourceInUse(SourceSelection, Uuid),
     DeviceStatus(DeviceStatus, Uuid),
     DeviceError(super::devices::DeviceError),
     DeviceSourceError(String),
@@ -219,8 +344,27 @@ pub enum Request {
     ModifyDevice(ModifyDevice),
     EnableContinuousMode(UuidWrapper),
     DisableContinuousMode(UuidWrapper),
+    ExportSettings,
+    DiffSettings(SettingsSnapshot),
+    ApplySettings(SettingsSnapshot),
+    ExportState,
+    ImportState(ManagerState),
+    GetMetrics,
+    GetDeviceMetrics(UuidWrapper),
+    GetPing360Diagnostics(UuidWrapper),
+    GetLatestSample(UuidWrapper),
+    ListSerialPorts,
+    SetPollWeight(scheduler::PollWeight),
+    GetPollSchedulerStats,
+    ListPresets,
+    SavePreset(presets::Preset),
+    DeletePreset(presets::PresetName),
+    ApplyPreset(presets::ApplyPreset),
+    KeepAwake(idle::KeepAwake),
     #[serde(skip)]
     SpecialTurnOffContinuousMode(UuidWrapper),
+    #[serde(skip)]
+    RegisterVirtualDevice(VirtualDevice),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -242,6 +386,26 @@ pub struct ModifyDevice {
     pub modify: ModifyDeviceCommand,
 }
 
+impl Request {
+    /// Device the request is about, answers are broadcast to its subscribers
+    pub fn device_id(&self) -> Option<Uuid> {
+        match self {
+            Request::ModifyDevice(modify) => Some(modify.uuid),
+            Request::Ping(device_request) => Some(device_request.uuid),
+            Request::Delete(uuid_wrapper)
+            | Request::Info(uuid_wrapper)
+            | Request::EnableContinuousMode(uuid_wrapper)
+            | Request::DisableContinuousMode(uuid_wrapper)
+            | Request::GetDeviceMetrics(uuid_wrapper)
+            | Request::GetPing360Diagnostics(uuid_wrapper)
+            | Request::GetLatestSample(uuid_wrapper) => Some(uuid_wrapper.uuid),
+            Request::SetPollWeight(poll_weight) => Some(poll_weight.uuid),
+            Request::ApplyPreset(apply_preset) => Some(apply_preset.uuid),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
 pub struct UuidWrapper {
     pub uuid: Uuid,
@@ -258,6 +422,9 @@ impl Deref for UuidWrapper {
 pub struct CreateStruct {
     pub source: SourceSelection,
     pub device_selection: DeviceSelection,
+    /// When detecting the baudrate, prefer the highest rate with a clean link over the first responsive one.
+    #[serde(default)]
+    pub negotiate_baudrate: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -277,7 +444,13 @@ impl DeviceManager {
                 }
             }
             Request::Create(request) => {
-                let result = self.create(request.source, request.device_selection).await;
+                let result = self
+                    .create(
+                        request.source,
+                        request.device_selection,
+                        request.negotiate_baudrate,
+                    )
+                    .await;
                 if let Err(e) = actor_request.respond_to.send(result) {
                     error!("DeviceManager: Failed to return Create response: {e:?}");
                 }
@@ -308,12 +481,18 @@ impl DeviceManager {
             }
             Request::EnableContinuousMode(uuid) => {
                 let result = self.continuous_mode(*uuid).await;
+                if result.is_ok() {
+                    recording_events::publish(*uuid, EventKind::ContinuousModeStarted, "");
+                }
                 if let Err(e) = actor_request.respond_to.send(result) {
                     error!("DeviceManager: Failed to return EnableContinuousMode response: {e:?}");
                 }
             }
             Request::DisableContinuousMode(uuid) => {
                 let result = self.continuous_mode_off(*uuid).await;
+                if result.is_ok() {
+                    recording_events::publish(*uuid, EventKind::ContinuousModeStopped, "");
+                }
                 if let Err(e) = actor_request.respond_to.send(result) {
                     error!("DeviceManager: Failed to return DisableContinuousMode response: {e:?}");
                 }
@@ -330,6 +509,120 @@ impl DeviceManager {
                     error!("DeviceManager: Failed to return ModifyDevice response: {err:?}");
                 }
             }
+            Request::ExportSettings => {
+                let answer = self.export_settings();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ExportSettings response: {err:?}");
+                }
+            }
+            Request::DiffSettings(snapshot) => {
+                let answer = self.diff_settings(snapshot).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return DiffSettings response: {err:?}");
+                }
+            }
+            Request::ApplySettings(snapshot) => {
+                let answer = self.apply_settings(snapshot).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ApplySettings response: {err:?}");
+                }
+            }
+            Request::ExportState => {
+                let answer = self.export_state();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ExportState response: {err:?}");
+                }
+            }
+            Request::ImportState(state) => {
+                let answer = self.import_state(state).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ImportState response: {err:?}");
+                }
+            }
+            Request::GetMetrics => {
+                let answer = self.get_metrics();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return GetMetrics response: {err:?}");
+                }
+            }
+            Request::GetDeviceMetrics(uuid) => {
+                let answer = self.get_device_metrics(*uuid);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return GetDeviceMetrics response: {err:?}");
+                }
+            }
+            Request::GetPing360Diagnostics(uuid) => {
+                let answer = self.get_ping360_diagnostics(*uuid);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!(
+                        "DeviceManager: Failed to return GetPing360Diagnostics response: {err:?}"
+                    );
+                }
+            }
+            Request::GetLatestSample(uuid) => {
+                let answer = self.get_latest_sample(*uuid);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return GetLatestSample response: {err:?}");
+                }
+            }
+            Request::ListSerialPorts => {
+                let answer = self.list_serial_ports();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ListSerialPorts response: {err:?}");
+                }
+            }
+            Request::SetPollWeight(request) => {
+                let answer = self.set_poll_weight(request);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return SetPollWeight response: {err:?}");
+                }
+            }
+            Request::GetPollSchedulerStats => {
+                let answer = self.get_poll_scheduler_stats();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!(
+                        "DeviceManager: Failed to return GetPollSchedulerStats response: {err:?}"
+                    );
+                }
+            }
+            Request::ListPresets => {
+                let answer = self.list_presets();
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ListPresets response: {err:?}");
+                }
+            }
+            Request::SavePreset(preset) => {
+                let answer = self.save_preset(preset);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return SavePreset response: {err:?}");
+                }
+            }
+            Request::DeletePreset(request) => {
+                let answer = self.delete_preset(&request.name);
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return DeletePreset response: {err:?}");
+                }
+            }
+            Request::ApplyPreset(request) => {
+                let answer = self.apply_preset(request).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return ApplyPreset response: {err:?}");
+                }
+            }
+            Request::RegisterVirtualDevice(virtual_device) => {
+                let answer = self.register_virtual_device(virtual_device).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!(
+                        "DeviceManager: Failed to return RegisterVirtualDevice response: {err:?}"
+                    );
+                }
+            }
+            Request::KeepAwake(request) => {
+                let answer = self.keep_awake(request).await;
+                if let Err(err) = actor_request.respond_to.send(answer) {
+                    error!("DeviceManager: Failed to return KeepAwake response: {err:?}");
+                }
+            }
             _ => {
                 if let Err(e) = actor_request
                     .respond_to
@@ -349,6 +642,10 @@ impl DeviceManager {
             receiver,
             device: HashMap::new(),
             discovery_service: DiscoveryComponent::new(),
+            poll_scheduler: PollScheduler::new(),
+            presets: PresetStore::default(),
+            idle_policy: IdlePolicy::default(),
+            auto_create_discovered: false,
             manager_handler: actor_handler.clone(),
         };
 
@@ -356,6 +653,19 @@ impl DeviceManager {
         (actor, actor_handler)
     }
 
+    /// Restrict which sources the background discovery probes, must be set before `run`
+    pub fn set_discovery_filter(&mut self, filter: device_discovery::DiscoveryFilter) {
+        info!("DeviceManager: Discovery filter: {filter:?}");
+        self.discovery_service.set_filter(filter);
+    }
+
+    /// Re-run discovery every `interval` (`None` disables it), optionally creating the devices it finds.
+    /// Must be set before `run`.
+    pub fn set_background_discovery(&mut self, interval: Option<Duration>, auto_create: bool) {
+        self.discovery_service.set_interval(interval);
+        self.auto_create_discovered = auto_create && interval.is_some();
+    }
+
     pub fn get_device_manager_handler(&self) -> ManagerActorHandler {
         self.manager_handler.clone()
     }
@@ -364,6 +674,7 @@ impl DeviceManager {
         info!("DeviceManager is running");
 
         self.discovery_service.start_discovery();
+        self.poll_scheduler.start();
 
         if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
             self.discovery_service.broadcast_known_devices(&inner);
@@ -372,26 +683,24 @@ impl DeviceManager {
         let mut discovery_rx = self.discovery_service.get_discovery_rx();
 
         let mut status_check_interval = tokio::time::interval(std::time::Duration::from_secs(30));
+        let mut idle_check_interval = tokio::time::interval(idle::IDLE_CHECK_PERIOD);
 
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
-                    self.handle_message(msg).await;
+                    let span = msg.span.clone();
+                    self.handle_message(msg).instrument(span).await;
                 }
                 Ok(device_info) = discovery_rx.recv() => {
-                    match self.register_device(device_info.clone()).await {
-                        Ok(_) => {
-                            info!("New device available, registered with id {:?} : device_type: {:?}", device_info.id, device_info.device_type);
-                        }
-                        Err(err) => {
-                            error!("Failed to register discovered device: {err:?}");
-                        }
-                    }
+                    self.handle_discovered_device(device_info).await;
                 }
                 _ = status_check_interval.tick() => {
                     debug!("Running scheduled device status check");
                     self.update_devices_status().await;
                 }
+                _ = idle_check_interval.tick(), if self.idle_policy.is_enabled() => {
+                    self.check_idle_devices().await;
+                }
                 else => break,
             }
         }
@@ -399,6 +708,49 @@ impl DeviceManager {
         error!("DeviceManager has stopped please check your application");
     }
 
+    async fn handle_discovered_device(&mut self, device_info: DeviceInfo) {
+        let device_id = device_info.id;
+        // A rediscovered source keeps its device, even when the discovered id differs
+        if let Some(device) = self
+            .device
+            .values()
+            .find(|device| device.source.conflicts_with(&device_info.source))
+        {
+            trace!(
+                "Discovered source {:?} is already managed by device {:?}",
+                device_info.source,
+                device.id
+            );
+            return;
+        }
+        if let Err(err) = self.register_device(device_info.clone()).await {
+            error!("Failed to register discovered device: {err:?}");
+            return;
+        }
+        info!(
+            "New device available, registered with id {device_id:?} : device_type: {:?}",
+            device_info.device_type
+        );
+
+        let mut device_info = device_info;
+        if self.auto_create_discovered {
+            match self.auto_create_device(device_id).await {
+                Ok(_) => {
+                    info!("Discovered device {device_id:?} created automatically");
+                    if let Ok(device) = self.get_device(device_id) {
+                        device_info = device.info();
+                    }
+                }
+                Err(err) => error!("Failed to create discovered device {device_id:?}: {err:?}"),
+            }
+        }
+
+        crate::server::protocols::v1::websocket::send_to_websockets(
+            serde_json::json!(Answer::DeviceDiscovered(device_info)),
+            Some(device_id),
+        );
+    }
+
     pub async fn update_devices_status(&mut self) {
         let device_info = match self.list().await {
             Ok(Answer::DeviceInfo(answer)) => answer,
@@ -406,7 +758,18 @@ impl DeviceManager {
         };
 
         for device in device_info {
-            if matches!(device.status, DeviceStatus::Error | DeviceStatus::Available) {
+            if matches!(
+                device.status,
+                DeviceStatus::Error { .. }
+                    | DeviceStatus::Available
+                    | DeviceStatus::Probing
+                    | DeviceStatus::Disconnected
+            ) {
+                continue;
+            }
+
+            // Replays can be paused at will, silence is not a failure
+            if matches!(device.source, SourceSelection::ReplayStream(_)) {
                 continue;
             }
 
@@ -431,10 +794,10 @@ impl DeviceManager {
             if let Some(handle) = &device_entry.actor {
                 if handle.is_finished() {
                     error!(
-                        "Device Actor main task finished, marking device with error. Device id: {:?}",
+                        "Device Actor main task finished, marking device as disconnected. Device id: {:?}",
                         device.id
                     );
-                    device_entry.status = DeviceStatus::Error;
+                    device_entry.set_status(DeviceStatus::Disconnected);
                     continue;
                 }
             }
@@ -462,13 +825,17 @@ impl DeviceManager {
     ) {
         let Some(broadcast) = &device_entry.broadcast else {
             error!("Device actor broadcast service finished, marking device with error. Device id: {:?}", device_id);
-            device_entry.status = DeviceStatus::Error;
+            device_entry.set_status(DeviceStatus::Error {
+                reason: "Continuous mode broadcast service is missing".to_string(),
+            });
             return;
         };
 
         if broadcast.is_finished() {
             error!("Device actor broadcast service finished, marking device with error. Device id: {:?}", device_id);
-            device_entry.status = DeviceStatus::Error;
+            device_entry.set_status(DeviceStatus::Error {
+                reason: "Continuous mode broadcast service finished".to_string(),
+            });
             return;
         }
 
@@ -481,15 +848,17 @@ impl DeviceManager {
                         error!(
                             "Device connection timeout, marking with error. Device id: {device_id:?}",
                         );
-                        device_entry.status = DeviceStatus::Error;
+                        device_entry.set_status(DeviceStatus::Error {
+                            reason: "No data received in continuous mode".to_string(),
+                        });
                     }
                     Ok(Err(err)) => match err {
                         tokio::sync::broadcast::error::RecvError::Lagged(_) => error!(
                             "Device connection error. Device id: {device_id:?}, Error: {err:?}"
                         ),
                         tokio::sync::broadcast::error::RecvError::Closed => {
-                            error!("Device connection error, marking with error. Device id: {device_id:?}, Error: {err:?}");
-                            device_entry.status = DeviceStatus::Error;
+                            error!("Device connection closed, marking as disconnected. Device id: {device_id:?}, Error: {err:?}");
+                            device_entry.set_status(DeviceStatus::Disconnected);
                         }
                     },
                     Ok(Ok(_ok)) => {
@@ -523,14 +892,18 @@ impl DeviceManager {
                     "Device connection timeout, marking with error. Device id: {:?}",
                     device_id
                 );
-                device_entry.status = DeviceStatus::Error;
+                device_entry.set_status(DeviceStatus::Error {
+                    reason: "Device information request timed out".to_string(),
+                });
             }
             Ok(Err(err)) => {
                 error!(
                     "Device connection error, marking with error. Device id: {:?}, Error: {:?}",
                     device_id, err
                 );
-                device_entry.status = DeviceStatus::Error;
+                device_entry.set_status(DeviceStatus::Error {
+                    reason: format!("{err:?}"),
+                });
             }
             Ok(Ok(_answer)) => {
                 debug!("Device still responsive. Device id: {:?}", device_id);
@@ -541,18 +914,78 @@ impl DeviceManager {
     pub async fn create(
         &mut self,
         source: SourceSelection,
-        mut device_selection: DeviceSelection,
+        device_selection: DeviceSelection,
+        negotiate_baudrate: bool,
     ) -> Result<Answer, ManagerError> {
-        let mut hasher = DefaultHasher::new();
-        source.hash(&mut hasher);
-        let hash = Uuid::from_u128(hasher.finish().into());
+        // Check before negotiating the baudrate, that would already open the port
+        self.check_source_conflict(&source)?;
+        let source = device_discovery::resolve_source_baudrate(source, negotiate_baudrate).await?;
+
+        let hash = identity::device_uuid(&source);
 
         if self.device.contains_key(&hash) {
             trace!("Device creation error: Device already exist for provided SourceSelection, details: {source:?}");
             return Err(ManagerError::DeviceAlreadyExist(hash));
         }
 
+        broadcast_status_change(hash, None, &DeviceStatus::Probing);
+        let (device, handler, device_selection) =
+            match Self::probe_source(&source, device_selection).await {
+                Ok(probed) => probed,
+                Err(err) => {
+                    broadcast_status_change(
+                        hash,
+                        Some(&DeviceStatus::Probing),
+                        &DeviceStatus::Error {
+                            reason: format!("{err:?}"),
+                        },
+                    );
+                    return Err(err);
+                }
+            };
+
+        let actor = tokio::spawn(async move { device.run().await });
+
+        let device = Device {
+            id: hash,
+            source,
+            handler: Some(handler),
+            actor: Some(actor),
+            status: DeviceStatus::Probing,
+            broadcast: None,
+            device_type: device_selection,
+            properties: None,
+            metrics: None,
+        };
+
+        self.device.insert(hash, device);
+        self.get_mut_device(hash)?.set_status(DeviceStatus::Running);
+
+        if let Err(err) = self.start_metrics(hash).await {
+            warn!("Device metrics unavailable for: {hash:?}, details: {err:?}");
+        }
+
+        trace!("Updating device properties for: {:?}", hash);
+        self.update_device_properties(hash).await?;
+
+        trace!("Device broadcast enable by default for: {hash:?}");
+        let device_info = self.continuous_mode(hash).await?;
+
+        info!("New device created and available, details: {device_info:?}");
+        Ok(device_info)
+    }
+
+    // Open the source and, for Auto selection, find out which device answers on it
+    async fn probe_source(
+        source: &SourceSelection,
+        mut device_selection: DeviceSelection,
+    ) -> Result<(DeviceActor, DeviceActorHandler, DeviceSelection), ManagerError> {
         let port = match &source {
+            SourceSelection::ReplayStream(_) => {
+                return Err(ManagerError::DeviceSourceError(
+                    "Replay devices are created from recordings".to_string(),
+                ))
+            }
             SourceSelection::UdpStream(source_udp_struct) => {
                 let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
 
@@ -661,29 +1094,7 @@ impl DeviceManager {
             }
         }
 
-        let actor = tokio::spawn(async move { device.run().await });
-
-        let device = Device {
-            id: hash,
-            source,
-            handler: Some(handler),
-            actor: Some(actor),
-            status: DeviceStatus::Running,
-            broadcast: None,
-            device_type: device_selection,
-            properties: None,
-        };
-
-        self.device.insert(hash, device);
-
-        trace!("Updating device properties for: {:?}", hash);
-        self.update_device_properties(hash).await?;
-
-        trace!("Device broadcast enable by default for: {hash:?}");
-        let device_info = self.continuous_mode(hash).await?;
-
-        info!("New device created and available, details: {device_info:?}");
-        Ok(device_info)
+        Ok((device, handler, device_selection))
     }
 
     pub async fn auto_create(&mut self) -> Result<Answer, ManagerError> {
@@ -750,6 +1161,11 @@ impl DeviceManager {
         device_type: DeviceSelection,
     ) -> Result<DeviceInfo, ManagerError> {
         let port = match &source {
+            SourceSelection::ReplayStream(_) => {
+                return Err(ManagerError::DeviceSourceError(
+                    "Replay devices are created from recordings".to_string(),
+                ))
+            }
             SourceSelection::UdpStream(source_udp_struct) => {
                 let socket_addr = SocketAddrV4::new(source_udp_struct.ip, source_udp_struct.port);
 
@@ -801,11 +1217,15 @@ impl DeviceManager {
         if let Some(device) = self.device.get_mut(&device_id) {
             device.handler = Some(handler.clone());
             device.actor = Some(actor);
-            device.status = DeviceStatus::Running;
+            device.set_status(DeviceStatus::Running);
         } else {
             return Err(ManagerError::DeviceNotExist(device_id));
         }
 
+        if let Err(err) = self.start_metrics(device_id).await {
+            warn!("Device metrics unavailable for: {device_id:?}, details: {err:?}");
+        }
+
         match self.continuous_mode(device_id).await {
             Ok(_) => {
                 trace!(
@@ -842,6 +1262,20 @@ impl DeviceManager {
         Ok(Answer::DeviceInfo(list))
     }
 
+    pub fn list_serial_ports(&self) -> Result<Answer, ManagerError> {
+        let used_ports: Vec<String> = self
+            .device
+            .values()
+            .filter_map(|device| match &device.source {
+                SourceSelection::SerialStream(serial) => Some(serial.path.clone()),
+                SourceSelection::UdpStream(_) | SourceSelection::ReplayStream(_) => None,
+            })
+            .collect();
+
+        let ports = device_discovery::list_serial_ports(&used_ports)?;
+        Ok(Answer::SerialPorts(ports))
+    }
+
     pub async fn info(&self, device_id: Uuid) -> Result<Answer, ManagerError> {
         self.check_device_uuid(device_id)?;
         Ok(Answer::DeviceInfo(vec![self.get_device(device_id)?.info()]))
@@ -866,11 +1300,13 @@ impl DeviceManager {
             broadcast: None,
             device_type: device_info.device_type,
             properties: device_info.properties,
+            metrics: None,
         };
 
         let info = device.info();
 
         self.device.insert(id, device);
+        broadcast_status_change(id, None, &info.status);
 
         if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
             self.discovery_service.broadcast_known_devices(&inner);
@@ -879,6 +1315,45 @@ impl DeviceManager {
         Ok(Answer::DeviceInfo(vec![info]))
     }
 
+    pub async fn register_virtual_device(
+        &mut self,
+        virtual_device: VirtualDevice,
+    ) -> Result<Answer, ManagerError> {
+        let id = virtual_device.id;
+        self.check_source_conflict(&virtual_device.source)?;
+        if self.device.contains_key(&id) {
+            return Err(ManagerError::DeviceAlreadyExist(id));
+        }
+
+        let device = Device {
+            id,
+            source: virtual_device.source,
+            handler: Some(virtual_device.handler),
+            actor: None,
+            status: DeviceStatus::Probing,
+            broadcast: None,
+            device_type: virtual_device.device_type,
+            properties: None,
+            metrics: None,
+        };
+        broadcast_status_change(id, None, &DeviceStatus::Probing);
+        self.device.insert(id, device);
+        self.get_mut_device(id)?.set_status(DeviceStatus::Running);
+
+        if let Err(err) = self.start_metrics(id).await {
+            warn!("Device metrics unavailable for: {id:?}, details: {err:?}");
+        }
+        self.update_device_properties(id).await?;
+        let device_info = self.continuous_mode(id).await?;
+
+        if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
+            self.discovery_service.broadcast_known_devices(&inner);
+        }
+
+        info!("Virtual device registered, details: {device_info:?}");
+        Ok(device_info)
+    }
+
     pub async fn turnoff_device_on_continuous_mode(
         &mut self,
         device_id: Uuid,
@@ -905,6 +1380,9 @@ impl DeviceManager {
             .ok_or(ManagerError::DeviceNotExist(id))?;
         let device_info = device.info();
 
+        self.poll_scheduler.unregister(id);
+        recording_events::publish(id, EventKind::Deleted, "");
+
         if let Ok(Answer::DeviceInfo(inner)) = self.list().await {
             self.discovery_service.broadcast_known_devices(&inner);
         }
@@ -956,9 +1434,11 @@ impl DeviceManager {
                 self.continuous_mode_startup_routine(device_id, device_type)
                     .await?;
 
+                self.poll_scheduler.unregister(device_id);
+
                 let device = self.get_mut_device(device_id)?;
                 device.broadcast = broadcast_handle;
-                device.status = DeviceStatus::ContinuousMode;
+                device.set_status(DeviceStatus::ContinuousMode);
 
                 let updated_device_info = self.get_device(device_id)?.info();
 
@@ -981,10 +1461,15 @@ impl DeviceManager {
             broadcast.abort_handle().abort();
         }
 
-        device.status = DeviceStatus::Running;
+        device.set_status(DeviceStatus::Running);
 
         let updated_device_info = device.info();
 
+        if let Some(handler) = device.handler.clone() {
+            self.poll_scheduler
+                .register(device_id, handler, &updated_device_info.device_type);
+        }
+
         Ok(Answer::DeviceInfo(vec![updated_device_info]))
     }
 
@@ -1176,6 +1661,11 @@ impl DeviceManager {
             }
             ModifyDeviceCommand::SetPing360Config(config) => {
                 self.update_ping360_config(request.uuid, config).await?;
+                recording_events::publish(
+                    request.uuid,
+                    EventKind::SettingChanged,
+                    serde_json::to_string(&config).unwrap_or_default(),
+                );
                 Ok(Answer::DeviceConfig(ModifyDeviceResult::ConfigAcknowledge(
                     request,
                 )))
@@ -1205,6 +1695,11 @@ impl DeviceManager {
 }
 
 impl ManagerActorHandler {
+    /// Requests waiting for the manager, a growing value means it cannot keep up
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
     pub async fn send(&self, request: Request) -> Result<Answer, ManagerError> {
         let (result_sender, result_receiver) = oneshot::channel();
 
@@ -1220,6 +1715,7 @@ impl ManagerActorHandler {
                 let manager_request = ManagerActorRequest {
                     request: handler_request,
                     respond_to: result_sender,
+                    span: tracing::Span::current(),
                 };
                 self.sender
                     .send(manager_request)
@@ -1245,6 +1741,14 @@ impl ManagerActorHandler {
                         match result {
                             Ok(result) => {
                                 info!("Handling Ping request: {request:?}: Success");
+                                if request.device_request.is_setting() {
+                                    recording_events::publish(
+                                        request.uuid,
+                                        EventKind::SettingChanged,
+                                        serde_json::to_string(&request.device_request)
+                                            .unwrap_or_default(),
+                                    );
+                                }
                                 Ok(Answer::DeviceMessage(DeviceAnswer {
                                     answer: result,
                                     device_id: request.uuid,
@@ -1265,6 +1769,7 @@ impl ManagerActorHandler {
                 let device_request = ManagerActorRequest {
                     request: request.clone(),
                     respond_to: result_sender,
+                    span: tracing::Span::current(),
                 };
 
                 self.sender
@@ -1292,8 +1797,23 @@ impl ManagerActorHandler {
     }
 }
 
+/// Device backed by an actor of this application instead of a serial/UDP port, like recording replays
+#[derive(Debug, Clone)]
+pub struct VirtualDevice {
+    pub id: Uuid,
+    pub source: SourceSelection,
+    pub device_type: DeviceSelection,
+    pub handler: DeviceActorHandler,
+}
+
+// Only created internally, never part of the API schema
+impl paperclip::v2::schema::Apiv2Schema for VirtualDevice {}
+
 pub async fn turnoff_device_continuous_mode(source: &SourceSelection) -> Result<(), ManagerError> {
     match source {
+        SourceSelection::ReplayStream(_) => {
+            trace!("Replay device stops its stream on request, nothing to turn off");
+        }
         SourceSelection::SerialStream(serial_config) => {
             debug!(
                 "Sending break line to serial device at {} for 1 second",
@@ -1336,3 +1856,176 @@ pub async fn turnoff_device_continuous_mode(source: &SourceSelection) -> Result<
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(status: DeviceStatus) -> Device {
+        Device {
+            id: Uuid::new_v4(),
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::LOCALHOST,
+                port: 12345,
+            }),
+            handler: None,
+            actor: None,
+            broadcast: None,
+            status,
+            device_type: DeviceSelection::Ping360,
+            properties: None,
+            metrics: None,
+        }
+    }
+
+    #[test]
+    fn test_every_status_transition_is_published_once() {
+        let mut events = recording_events::subscribe();
+        let mut device = device(DeviceStatus::Available);
+        for status in [
+            DeviceStatus::Probing,
+            DeviceStatus::Running,
+            DeviceStatus::Running,
+            DeviceStatus::Disconnected,
+            DeviceStatus::Running,
+            DeviceStatus::ContinuousMode,
+        ] {
+            device.set_status(status);
+        }
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.device_id == device.id {
+                kinds.push(event.kind);
+            }
+        }
+        assert_eq!(
+            kinds,
+            [
+                EventKind::StatusChanged,
+                EventKind::StatusChanged,
+                EventKind::StatusChanged,
+                EventKind::Reconnected,
+                EventKind::StatusChanged,
+            ]
+        );
+        assert_eq!(device.info().status, DeviceStatus::ContinuousMode);
+    }
+
+    fn udp(port: u16) -> SourceSelection {
+        SourceSelection::UdpStream(SourceUdpStruct {
+            ip: Ipv4Addr::new(192, 168, 2, 2),
+            port,
+        })
+    }
+
+    fn serial(path: &std::path::Path, baudrate: u32) -> SourceSelection {
+        SourceSelection::SerialStream(SourceSerialStruct {
+            path: path.to_string_lossy().to_string(),
+            baudrate,
+        })
+    }
+
+    #[test]
+    fn test_sources_opening_the_same_port_conflict() {
+        assert!(udp(9090).conflicts_with(&udp(9090)));
+        assert!(!udp(9090).conflicts_with(&udp(9092)));
+
+        let directory = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let port = directory.join("ttyUSB0");
+        std::fs::write(&port, b"").unwrap();
+        // The same port through another path, as /dev/serial/by-id links are
+        let other_path = directory
+            .join("..")
+            .join(directory.file_name().unwrap())
+            .join("ttyUSB0");
+        assert!(serial(&port, 115200).conflicts_with(&serial(&other_path, 0)));
+        assert!(!serial(&port, 115200).conflicts_with(&serial(&directory.join("ttyUSB1"), 0)));
+        assert!(!serial(&port, 115200).conflicts_with(&udp(9090)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_source_in_use_carries_the_existing_device() {
+        let (mut manager, _handler) = DeviceManager::new(1);
+        let mut existing = device(DeviceStatus::Running);
+        existing.source = SourceSelection::SerialStream(SourceSerialStruct {
+            path: "/dev/ttyUSB0".to_string(),
+            baudrate: 115200,
+        });
+        let existing_id = existing.id;
+        let existing_source = existing.source.clone();
+        manager.device.insert(existing_id, existing);
+
+        assert!(matches!(
+            manager.check_source_conflict(&existing_source),
+            Err(ManagerError::DeviceAlreadyExist(id)) if id == existing_id
+        ));
+        let other_baudrate = SourceSelection::SerialStream(SourceSerialStruct {
+            path: "/dev/ttyUSB0".to_string(),
+            baudrate: 0,
+        });
+        assert!(matches!(
+            manager.check_source_conflict(&other_baudrate),
+            Err(ManagerError::SourceInUse(source, id)) if id == existing_id && source == existing_source
+        ));
+        assert!(manager.check_source_conflict(&udp(9090)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rediscovered_source_is_not_created_twice() {
+        let (mut manager, _handler) = DeviceManager::new(1);
+        manager.set_background_discovery(Some(Duration::from_secs(30)), true);
+        let existing = device(DeviceStatus::Running);
+        let existing_id = existing.id;
+        let source = existing.source.clone();
+        manager.device.insert(existing_id, existing);
+
+        for id in [existing_id, Uuid::new_v4()] {
+            manager
+                .handle_discovered_device(DeviceInfo {
+                    id,
+                    source: source.clone(),
+                    status: DeviceStatus::Available,
+                    device_type: DeviceSelection::Ping360,
+                    properties: None,
+                })
+                .await;
+        }
+        assert_eq!(manager.device.len(), 1);
+        assert_eq!(
+            manager.get_device(existing_id).unwrap().status,
+            DeviceStatus::Running
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_discovered_source_is_registered() {
+        let (mut manager, _handler) = DeviceManager::new(1);
+        manager.set_background_discovery(Some(Duration::from_secs(30)), false);
+        let id = Uuid::new_v4();
+        manager
+            .handle_discovered_device(DeviceInfo {
+                id,
+                source: udp(9090),
+                status: DeviceStatus::Running,
+                device_type: DeviceSelection::Ping360,
+                properties: None,
+            })
+            .await;
+        assert_eq!(
+            manager.get_device(id).unwrap().status,
+            DeviceStatus::Available
+        );
+    }
+
+    #[test]
+    fn test_devices_are_only_created_by_a_background_discovery() {
+        let (mut manager, _handler) = DeviceManager::new(1);
+        manager.set_background_discovery(None, true);
+        assert!(!manager.auto_create_discovered);
+        manager.set_background_discovery(Some(Duration::from_secs(30)), true);
+        assert!(manager.auto_create_discovered);
+    }
+}
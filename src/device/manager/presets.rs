@@ -0,0 +1,320 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, trace, warn};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{Ping1DRequest, PingRequest},
+    manager::{Answer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError, Ping360Config},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct Preset {
+    pub name: String,
+    pub parameters: PresetParameters,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub enum PresetParameters {
+    Ping1D(Ping1DPreset),
+    Ping360(Ping360Config),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub struct Ping1DPreset {
+    /// When enabled the device picks gain and range on its own, only the ping interval is applied.
+    pub mode_auto: bool,
+    pub gain_setting: u8,
+    /// Scan start in millimeters
+    pub scan_start: u32,
+    /// Scan length in millimeters
+    pub scan_length: u32,
+    /// Interval between pings in milliseconds
+    pub ping_interval: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct PresetName {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ApplyPreset {
+    pub uuid: Uuid,
+    pub name: String,
+}
+
+impl PresetParameters {
+    pub fn is_compatible(&self, device_type: &DeviceSelection) -> bool {
+        matches!(
+            (self, device_type),
+            (PresetParameters::Ping1D(_), DeviceSelection::Ping1D)
+                | (PresetParameters::Ping360(_), DeviceSelection::Ping360)
+        )
+    }
+}
+
+pub struct PresetStore {
+    path: PathBuf,
+    presets: BTreeMap<String, Preset>,
+}
+
+impl Default for PresetStore {
+    fn default() -> Self {
+        let path = dirs::config_dir()
+            .unwrap_or_default()
+            .join(env!("CARGO_PKG_NAME"))
+            .join("presets.json");
+        Self::load(path)
+    }
+}
+
+impl PresetStore {
+    pub fn load(path: PathBuf) -> Self {
+        let presets = match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Vec<Preset>>(&content) {
+                Ok(presets) => presets
+                    .into_iter()
+                    .map(|preset| (preset.name.clone(), preset))
+                    .collect(),
+                Err(err) => {
+                    error!("PresetStore: Failed to parse {path:?}, details: {err:?}");
+                    BTreeMap::new()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                warn!("PresetStore: Failed to read {path:?}, details: {err:?}");
+                BTreeMap::new()
+            }
+        };
+
+        info!(
+            "PresetStore: Loaded {} presets from {path:?}",
+            presets.len()
+        );
+        Self { path, presets }
+    }
+
+    fn persist(&self) -> Result<(), ManagerError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| {
+                ManagerError::Other(format!("Failed to create presets directory: {err}"))
+            })?;
+        }
+        let content = serde_json::to_string_pretty(&self.list())
+            .map_err(|err| ManagerError::Other(err.to_string()))?;
+        std::fs::write(&self.path, content)
+            .map_err(|err| ManagerError::Other(format!("Failed to write presets: {err}")))
+    }
+
+    pub fn list(&self) -> Vec<Preset> {
+        self.presets.values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Preset, ManagerError> {
+        self.presets
+            .get(name)
+            .ok_or_else(|| ManagerError::Other(format!("Preset {name:?} doesn't exist")))
+    }
+
+    pub fn save(&mut self, preset: Preset) -> Result<(), ManagerError> {
+        if preset.name.trim().is_empty() {
+            return Err(ManagerError::Other(
+                "Preset name can't be empty".to_string(),
+            ));
+        }
+        self.presets.insert(preset.name.clone(), preset);
+        self.persist()
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<Preset, ManagerError> {
+        let preset = self
+            .presets
+            .remove(name)
+            .ok_or_else(|| ManagerError::Other(format!("Preset {name:?} doesn't exist")))?;
+        self.persist()?;
+        Ok(preset)
+    }
+}
+
+impl DeviceManager {
+    pub fn list_presets(&self) -> Result<Answer, ManagerError> {
+        Ok(Answer::Presets(self.presets.list()))
+    }
+
+    pub fn save_preset(&mut self, preset: Preset) -> Result<Answer, ManagerError> {
+        trace!("Saving preset: {preset:?}");
+        self.presets.save(preset.clone())?;
+        Ok(Answer::Presets(vec![preset]))
+    }
+
+    pub fn delete_preset(&mut self, name: &str) -> Result<Answer, ManagerError> {
+        let preset = self.presets.delete(name)?;
+        Ok(Answer::Presets(vec![preset]))
+    }
+
+    pub async fn apply_preset(&mut self, request: ApplyPreset) -> Result<Answer, ManagerError> {
+        let device_id = request.uuid;
+        let preset = self.presets.get(&request.name)?.clone();
+        let device_type = self.get_device(device_id)?.device_type.clone();
+
+        if !preset.parameters.is_compatible(&device_type) {
+            return Err(ManagerError::Other(format!(
+                "Preset {:?} is not compatible with {device_type:?} device: {device_id}",
+                preset.name
+            )));
+        }
+
+        match preset.parameters {
+            PresetParameters::Ping360(config) => {
+                // Ping360 settings live in the continuous mode properties, make sure they exist
+                if self.get_device_status(device_id)? == DeviceStatus::Available {
+                    self.continuous_mode(device_id).await?;
+                }
+                self.update_ping360_config(device_id, config).await?;
+            }
+            PresetParameters::Ping1D(parameters) => {
+                let handler = self.extract_handler(self.get_device_handler(device_id).await?)?;
+
+                let mut requests = vec![Ping1DRequest::SetModeAuto(
+                    bluerobotics_ping::ping1d::SetModeAutoStruct {
+                        mode_auto: parameters.mode_auto as u8,
+                    },
+                )];
+                if !parameters.mode_auto {
+                    requests.push(Ping1DRequest::SetRange(
+                        bluerobotics_ping::ping1d::SetRangeStruct {
+                            scan_start: parameters.scan_start,
+                            scan_length: parameters.scan_length,
+                        },
+                    ));
+                    requests.push(Ping1DRequest::SetGainSetting(
+                        bluerobotics_ping::ping1d::SetGainSettingStruct {
+                            gain_setting: parameters.gain_setting,
+                        },
+                    ));
+                }
+                requests.push(Ping1DRequest::SetPingInterval(
+                    bluerobotics_ping::ping1d::SetPingIntervalStruct {
+                        ping_interval: parameters.ping_interval,
+                    },
+                ));
+
+                for request in requests {
+                    handler
+                        .send(PingRequest::Ping1D(request))
+                        .await
+                        .map_err(|err| {
+                            error!("Failed to apply preset {:?}, details: {err:?}", preset.name);
+                            ManagerError::DeviceError(err)
+                        })?;
+                }
+            }
+        }
+
+        info!("Preset {:?} applied to device: {device_id}", preset.name);
+        Ok(Answer::DeviceInfo(vec![self.get_device(device_id)?.info()]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::device::manager::{Device, SourceSelection, SourceUdpStruct};
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("ping-viewer-{}", Uuid::new_v4()))
+            .join("presets.json")
+    }
+
+    fn ping1d_preset(name: &str, ping_interval: u16) -> Preset {
+        Preset {
+            name: name.to_string(),
+            parameters: PresetParameters::Ping1D(Ping1DPreset {
+                mode_auto: false,
+                gain_setting: 2,
+                scan_start: 0,
+                scan_length: 20000,
+                ping_interval,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_presets_round_trip_through_the_file() {
+        let path = temp_path();
+        let mut store = PresetStore::load(path.clone());
+        assert!(store.list().is_empty());
+
+        store.save(ping1d_preset("shallow", 100)).unwrap();
+        store.save(ping1d_preset("deep", 200)).unwrap();
+        // Saving under the same name overwrites it
+        store.save(ping1d_preset("shallow", 50)).unwrap();
+        assert!(store.save(ping1d_preset(" ", 50)).is_err());
+
+        let store = PresetStore::load(path.clone());
+        let names: Vec<String> = store.list().into_iter().map(|preset| preset.name).collect();
+        assert_eq!(names, ["deep", "shallow"]);
+        assert_eq!(
+            store.get("shallow").unwrap().parameters,
+            ping1d_preset("shallow", 50).parameters
+        );
+
+        let mut store = store;
+        store.delete("deep").unwrap();
+        assert!(store.delete("deep").is_err());
+        let store = PresetStore::load(path.clone());
+        assert!(store.get("deep").is_err());
+        assert_eq!(store.list().len(), 1);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_preset_is_rejected() {
+        let path = temp_path();
+        let (mut manager, _handler) = DeviceManager::new(1);
+        manager.presets = PresetStore::load(path.clone());
+        manager.save_preset(ping1d_preset("shallow", 100)).unwrap();
+
+        let device_id = Uuid::new_v4();
+        manager.device.insert(
+            device_id,
+            Device {
+                id: device_id,
+                source: SourceSelection::UdpStream(SourceUdpStruct {
+                    ip: Ipv4Addr::LOCALHOST,
+                    port: 12345,
+                }),
+                handler: None,
+                actor: None,
+                broadcast: None,
+                status: DeviceStatus::Running,
+                device_type: DeviceSelection::Ping360,
+                properties: None,
+                metrics: None,
+            },
+        );
+
+        let result = manager
+            .apply_preset(ApplyPreset {
+                uuid: device_id,
+                name: "shallow".to_string(),
+            })
+            .await;
+        assert!(
+            matches!(result, Err(ManagerError::Other(message)) if message.contains("not compatible"))
+        );
+        let parameters = ping1d_preset("shallow", 100).parameters;
+        assert!(parameters.is_compatible(&DeviceSelection::Ping1D));
+        assert!(!parameters.is_compatible(&DeviceSelection::Common));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, error, info, trace};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{self, DeviceActorHandler, PingRequest},
+    manager::{Answer, DeviceAnswer, DeviceManager, DeviceSelection, DeviceStatus, ManagerError},
+};
+
+const SLOT_PERIOD: Duration = Duration::from_millis(50);
+const SLOT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct PollWeight {
+    pub uuid: Uuid,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PollStats {
+    pub device_id: Uuid,
+    pub weight: u32,
+    pub slots_granted: u64,
+    pub polls_succeeded: u64,
+    pub polls_failed: u64,
+    pub average_latency_ms: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PollSchedulerStats {
+    pub devices: Vec<PollStats>,
+    /// Jain's fairness index over slots granted per unit of weight, 1.0 means perfectly fair
+    pub fairness_index: f64,
+}
+
+#[derive(Debug)]
+struct PollEntry {
+    handler: DeviceActorHandler,
+    request: PingRequest,
+    current_weight: i64,
+    // A device is not polled again before its previous poll answers or times out
+    in_flight: bool,
+    stats: PollStats,
+}
+
+impl PollEntry {
+    fn is_ready(&self) -> bool {
+        self.stats.weight > 0 && !self.in_flight
+    }
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    entries: HashMap<Uuid, PollEntry>,
+    weights: HashMap<Uuid, u32>,
+}
+
+impl SchedulerState {
+    // Smooth weighted round-robin: every pick raises each entry by its weight and lowers the
+    // selected one by the total, so devices interleave instead of running in bursts.
+    fn next(&mut self) -> Option<(Uuid, DeviceActorHandler, PingRequest)> {
+        let total: i64 = self
+            .entries
+            .values()
+            .filter(|entry| entry.is_ready())
+            .map(|entry| entry.stats.weight as i64)
+            .sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut selected: Option<(&Uuid, &mut PollEntry)> = None;
+        for (id, entry) in self.entries.iter_mut() {
+            if !entry.is_ready() {
+                continue;
+            }
+            entry.current_weight += entry.stats.weight as i64;
+            let is_better = match &selected {
+                Some((selected_id, selected_entry)) => {
+                    entry.current_weight > selected_entry.current_weight
+                        || (entry.current_weight == selected_entry.current_weight
+                            && id < *selected_id)
+                }
+                None => true,
+            };
+            if is_better {
+                selected = Some((id, entry));
+            }
+        }
+
+        let (id, entry) = selected?;
+        entry.current_weight -= total;
+        entry.in_flight = true;
+        entry.stats.slots_granted += 1;
+        Some((*id, entry.handler.clone(), entry.request.clone()))
+    }
+
+    fn record(&mut self, device_id: Uuid, success: bool, latency: Duration) {
+        let Some(entry) = self.entries.get_mut(&device_id) else {
+            return;
+        };
+        entry.in_flight = false;
+        let stats = &mut entry.stats;
+        if success {
+            stats.polls_succeeded += 1;
+        } else {
+            stats.polls_failed += 1;
+        }
+        let polls = (stats.polls_succeeded + stats.polls_failed) as f64;
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        stats.average_latency_ms += (latency_ms - stats.average_latency_ms) / polls;
+    }
+
+    fn stats(&self) -> PollSchedulerStats {
+        let mut devices: Vec<PollStats> = self
+            .entries
+            .values()
+            .map(|entry| entry.stats.clone())
+            .collect();
+        devices.sort_by_key(|stats| stats.device_id);
+
+        PollSchedulerStats {
+            fairness_index: fairness_index(&devices),
+            devices,
+        }
+    }
+}
+
+fn fairness_index(devices: &[PollStats]) -> f64 {
+    let shares: Vec<f64> = devices
+        .iter()
+        .filter(|stats| stats.weight > 0)
+        .map(|stats| stats.slots_granted as f64 / stats.weight as f64)
+        .collect();
+
+    let sum: f64 = shares.iter().sum();
+    let sum_of_squares: f64 = shares.iter().map(|share| share * share).sum();
+    if sum_of_squares == 0.0 {
+        return 1.0;
+    }
+    (sum * sum) / (shares.len() as f64 * sum_of_squares)
+}
+
+pub struct PollScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+    handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Default for PollScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PollScheduler {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SchedulerState::default())),
+            handle: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        let state = self.state.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SLOT_PERIOD);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let next = match state.lock() {
+                    Ok(mut state) => state.next(),
+                    Err(err) => {
+                        error!("PollScheduler: Failed to lock state: {err:?}");
+                        break;
+                    }
+                };
+                let Some((device_id, handler, request)) = next else {
+                    continue;
+                };
+
+                // Polls run concurrently, a slow or absent device only delays itself
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let start = Instant::now();
+                    let result = tokio::time::timeout(SLOT_TIMEOUT, handler.send(request)).await;
+                    let latency = start.elapsed();
+
+                    let success = match result {
+                        Ok(Ok(answer)) => {
+                            let answer = Answer::DeviceMessage(DeviceAnswer { answer, device_id });
+                            crate::server::protocols::v1::websocket::send_to_websockets(
+                                json!(answer),
+                                Some(device_id),
+                            );
+                            true
+                        }
+                        Ok(Err(err)) => {
+                            debug!(
+                                "PollScheduler: Poll failed for device: {device_id}, details: {err:?}"
+                            );
+                            false
+                        }
+                        Err(_) => {
+                            debug!("PollScheduler: Poll timed out for device: {device_id}");
+                            false
+                        }
+                    };
+
+                    if let Ok(mut state) = state.lock() {
+                        state.record(device_id, success, latency);
+                    }
+                });
+            }
+        });
+
+        self.handle = Some(handle);
+        info!("PollScheduler service is running");
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+
+    /// Devices are only polled once given a weight, a registered device keeps its statistics
+    pub fn register(
+        &self,
+        device_id: Uuid,
+        handler: DeviceActorHandler,
+        device_type: &DeviceSelection,
+    ) {
+        let request = match device_type {
+            DeviceSelection::Ping1D => PingRequest::Ping1D(devices::Ping1DRequest::Profile),
+            DeviceSelection::Ping360 => PingRequest::Ping360(devices::Ping360Request::DeviceData),
+            DeviceSelection::Common | DeviceSelection::Auto => {
+                PingRequest::Common(devices::PingCommonRequest::DeviceInformation)
+            }
+        };
+
+        if let Ok(mut state) = self.state.lock() {
+            if state.entries.contains_key(&device_id) {
+                return;
+            }
+            let weight = state.weights.get(&device_id).copied().unwrap_or_default();
+            state.entries.insert(
+                device_id,
+                PollEntry {
+                    handler,
+                    request,
+                    current_weight: 0,
+                    in_flight: false,
+                    stats: PollStats {
+                        device_id,
+                        weight,
+                        ..Default::default()
+                    },
+                },
+            );
+            trace!("PollScheduler: Registered device: {device_id:?}");
+        }
+    }
+
+    pub fn unregister(&self, device_id: Uuid) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.entries.remove(&device_id).is_some() {
+                trace!("PollScheduler: Unregistered device: {device_id:?}");
+            }
+        }
+    }
+
+    pub fn set_weight(&self, device_id: Uuid, weight: u32) -> Result<(), ManagerError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|err| ManagerError::Other(err.to_string()))?;
+        state.weights.insert(device_id, weight);
+        if let Some(entry) = state.entries.get_mut(&device_id) {
+            entry.stats.weight = weight;
+            entry.current_weight = 0;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<PollSchedulerStats, ManagerError> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|err| ManagerError::Other(err.to_string()))?;
+        Ok(state.stats())
+    }
+}
+
+impl Drop for PollScheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl DeviceManager {
+    pub fn set_poll_weight(&mut self, request: PollWeight) -> Result<Answer, ManagerError> {
+        self.check_device_uuid(request.uuid)?;
+        self.poll_scheduler
+            .set_weight(request.uuid, request.weight)?;
+        // Polling is opt-in, a running device joins the scheduler once it gets a weight
+        let device = self.get_device(request.uuid)?;
+        if device.status == DeviceStatus::Running {
+            if let Some(handler) = device.handler.clone() {
+                self.poll_scheduler
+                    .register(request.uuid, handler, &device.info().device_type);
+            }
+        }
+        Ok(Answer::PollSchedulerStats(self.poll_scheduler.stats()?))
+    }
+
+    pub fn get_poll_scheduler_stats(&self) -> Result<Answer, ManagerError> {
+        Ok(Answer::PollSchedulerStats(self.poll_scheduler.stats()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with_weights(weights: &[u32]) -> (SchedulerState, Vec<Uuid>) {
+        let mut state = SchedulerState::default();
+        let mut ids = Vec::new();
+        for (index, weight) in weights.iter().enumerate() {
+            let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+            let device_id = Uuid::from_u128(index as u128 + 1);
+            state.entries.insert(
+                device_id,
+                PollEntry {
+                    handler: DeviceActorHandler { sender },
+                    request: PingRequest::GetSubscriber,
+                    current_weight: 0,
+                    in_flight: false,
+                    stats: PollStats {
+                        device_id,
+                        weight: *weight,
+                        ..Default::default()
+                    },
+                },
+            );
+            ids.push(device_id);
+        }
+        (state, ids)
+    }
+
+    // One slot whose poll answers right away
+    fn poll(state: &mut SchedulerState) -> Option<Uuid> {
+        let (device_id, _, _) = state.next()?;
+        state.record(device_id, true, Duration::ZERO);
+        Some(device_id)
+    }
+
+    #[test]
+    fn test_slots_are_proportional_to_weights() {
+        let (mut state, ids) = state_with_weights(&[1, 2, 5]);
+        for _ in 0..800 {
+            poll(&mut state);
+        }
+
+        let slots: Vec<u64> = ids
+            .iter()
+            .map(|id| state.entries[id].stats.slots_granted)
+            .collect();
+        assert_eq!(slots, vec![100, 200, 500]);
+        assert!((state.stats().fairness_index - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_zero_weight_devices_are_skipped() {
+        let (mut state, ids) = state_with_weights(&[0, 1]);
+        for _ in 0..10 {
+            assert_eq!(poll(&mut state), Some(ids[1]));
+        }
+
+        let (mut state, _) = state_with_weights(&[0, 0]);
+        assert!(state.next().is_none());
+    }
+
+    #[test]
+    fn test_equal_weights_never_starve() {
+        let (mut state, ids) = state_with_weights(&[1, 1, 1, 1]);
+        let mut picks = Vec::new();
+        for _ in 0..8 {
+            picks.push(poll(&mut state).unwrap());
+        }
+        for id in ids {
+            assert_eq!(picks.iter().filter(|pick| **pick == id).count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_devices_in_flight_are_skipped() {
+        let (mut state, ids) = state_with_weights(&[5, 1]);
+        let (slow, _, _) = state.next().unwrap();
+        assert_eq!(slow, ids[0]);
+        // The other device keeps its slots while the first poll is pending
+        for _ in 0..3 {
+            assert_eq!(poll(&mut state), Some(ids[1]));
+        }
+
+        state.record(slow, false, SLOT_TIMEOUT);
+        assert_eq!(poll(&mut state), Some(ids[0]));
+    }
+
+    #[test]
+    fn test_devices_are_not_polled_without_weight() {
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let scheduler = PollScheduler::new();
+        let device_id = Uuid::from_u128(1);
+        scheduler.register(
+            device_id,
+            DeviceActorHandler { sender },
+            &DeviceSelection::Ping1D,
+        );
+        assert!(scheduler.state.lock().unwrap().next().is_none());
+
+        scheduler.set_weight(device_id, 1).unwrap();
+        assert!(scheduler.state.lock().unwrap().next().is_some());
+    }
+}
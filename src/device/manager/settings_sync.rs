@@ -0,0 +1,449 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace, warn};
+use uuid::Uuid;
+
+use crate::device::manager::{
+    presets::Preset, Answer, DeviceManager, DeviceProperties, DeviceSelection, DeviceStatus,
+    ManagerError, Ping360Config, SourceSelection,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct SettingsSnapshot {
+    pub version: String,
+    pub devices: Vec<DeviceSettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct DeviceSettings {
+    pub id: Uuid,
+    pub source: SourceSelection,
+    pub device_type: DeviceSelection,
+    pub ping360_config: Option<Ping360Config>,
+}
+
+/// Full DeviceManager state, used for backup/restore and to copy configurations between vehicles
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ManagerState {
+    pub settings: SettingsSnapshot,
+    /// Devices that were streaming in continuous mode when the state was exported
+    #[serde(default)]
+    pub continuous_mode: Vec<Uuid>,
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsDiff {
+    pub changes: Vec<SettingsChange>,
+    pub unchanged: Vec<Uuid>,
+    /// Changes that could not be applied, the others are applied regardless
+    #[serde(default)]
+    pub failed: Vec<FailedChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedChange {
+    pub change: SettingsChange,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SettingsChange {
+    CreateDevice(DeviceSettings),
+    UpdatePing360Config {
+        id: Uuid,
+        current: Option<Ping360Config>,
+        incoming: Ping360Config,
+        fields: Vec<FieldChange>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub current: serde_json::Value,
+    pub incoming: serde_json::Value,
+}
+
+impl DeviceManager {
+    pub fn export_settings(&self) -> Result<Answer, ManagerError> {
+        Ok(Answer::Settings(self.settings_snapshot()?))
+    }
+
+    pub fn settings_snapshot(&self) -> Result<SettingsSnapshot, ManagerError> {
+        let mut devices = Vec::new();
+        for device in self.device.values() {
+            devices.push(DeviceSettings {
+                id: device.id,
+                source: device.source.clone(),
+                device_type: device.device_type.clone(),
+                ping360_config: self.current_ping360_config(device.id)?,
+            });
+        }
+        devices.sort_by_key(|device| device.id);
+
+        Ok(SettingsSnapshot {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            devices,
+        })
+    }
+
+    pub async fn diff_settings(&self, snapshot: SettingsSnapshot) -> Result<Answer, ManagerError> {
+        Ok(Answer::SettingsDiff(self.compute_settings_diff(&snapshot)?))
+    }
+
+    pub async fn apply_settings(
+        &mut self,
+        snapshot: SettingsSnapshot,
+    ) -> Result<Answer, ManagerError> {
+        let diff = self.compute_settings_diff(&snapshot)?;
+        let mut applied = Vec::new();
+        let mut failed = Vec::new();
+
+        // One device failing, e.g. absent on this vehicle, does not keep the others from being configured
+        for change in diff.changes {
+            match self.apply_settings_change(&change).await {
+                Ok(()) => applied.push(change),
+                Err(err) => {
+                    warn!("Settings: failed to apply {change:?}, details: {err:?}");
+                    failed.push(FailedChange {
+                        change,
+                        error: format!("{err:?}"),
+                    });
+                }
+            }
+        }
+
+        info!(
+            "Settings from version {} applied, {} changes, {} failed",
+            snapshot.version,
+            applied.len(),
+            failed.len()
+        );
+
+        Ok(Answer::SettingsDiff(SettingsDiff {
+            changes: applied,
+            unchanged: diff.unchanged,
+            failed,
+        }))
+    }
+
+    async fn apply_settings_change(&mut self, change: &SettingsChange) -> Result<(), ManagerError> {
+        match change {
+            SettingsChange::CreateDevice(settings) => {
+                trace!("Applying settings: creating device {:?}", settings.id);
+                let id = match self
+                    .create(settings.source.clone(), settings.device_type.clone(), false)
+                    .await?
+                {
+                    Answer::DeviceInfo(info) => info.first().map(|info| info.id),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    ManagerError::Other(format!(
+                        "apply_settings: Failed to create device {}",
+                        settings.id
+                    ))
+                })?;
+
+                if let Some(config) = settings.ping360_config {
+                    self.update_ping360_config(id, config).await?;
+                }
+            }
+            SettingsChange::UpdatePing360Config { id, incoming, .. } => {
+                trace!("Applying settings: updating Ping360Config for device {id:?}");
+                if self.get_device_status(*id)? == DeviceStatus::Available {
+                    self.continuous_mode(*id).await?;
+                }
+                self.update_ping360_config(*id, *incoming).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn export_state(&self) -> Result<Answer, ManagerError> {
+        Ok(Answer::State(self.manager_state()?))
+    }
+
+    pub fn manager_state(&self) -> Result<ManagerState, ManagerError> {
+        let mut continuous_mode: Vec<Uuid> = self
+            .device
+            .values()
+            .filter(|device| device.status == DeviceStatus::ContinuousMode)
+            .map(|device| device.id)
+            .collect();
+        continuous_mode.sort();
+
+        Ok(ManagerState {
+            settings: self.settings_snapshot()?,
+            continuous_mode,
+            presets: self.presets.list(),
+        })
+    }
+
+    pub async fn import_state(&mut self, state: ManagerState) -> Result<Answer, ManagerError> {
+        for preset in state.presets {
+            self.presets.save(preset)?;
+        }
+
+        let exported = state.settings.devices.clone();
+        self.apply_settings(state.settings).await?;
+
+        let sources = self
+            .device
+            .values()
+            .map(|device| (device.id, &device.source));
+        let continuous_mode = local_device_ids(sources, &exported, &state.continuous_mode);
+        for device_id in continuous_mode {
+            match self.get_device_status(device_id) {
+                Ok(DeviceStatus::Available | DeviceStatus::Running) => {
+                    if let Err(err) = self.continuous_mode(device_id).await {
+                        warn!("Import state: failed to restore continuous mode for device {device_id}, details: {err:?}");
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("Import state: device {device_id} is not available, details: {err:?}");
+                }
+            }
+        }
+
+        info!("DeviceManager state imported");
+        self.export_state()
+    }
+
+    fn compute_settings_diff(
+        &self,
+        snapshot: &SettingsSnapshot,
+    ) -> Result<SettingsDiff, ManagerError> {
+        let mut changes = Vec::new();
+        let mut unchanged = Vec::new();
+
+        for incoming in &snapshot.devices {
+            let sources = self
+                .device
+                .values()
+                .map(|device| (device.id, &device.source));
+            let Some(id) = matching_device(sources, incoming) else {
+                changes.push(SettingsChange::CreateDevice(incoming.clone()));
+                continue;
+            };
+
+            if self.get_device(id)?.source != incoming.source {
+                warn!(
+                    "Settings diff: device {} has a different source, details: {:?}",
+                    incoming.id, incoming.source
+                );
+            }
+
+            let Some(incoming_config) = incoming.ping360_config else {
+                unchanged.push(id);
+                continue;
+            };
+
+            let current = self.current_ping360_config(id)?;
+            if current == Some(incoming_config) {
+                unchanged.push(id);
+                continue;
+            }
+
+            changes.push(SettingsChange::UpdatePing360Config {
+                id,
+                current,
+                incoming: incoming_config,
+                fields: field_changes(&current, &incoming_config),
+            });
+        }
+
+        Ok(SettingsDiff {
+            changes,
+            unchanged,
+            failed: Vec::new(),
+        })
+    }
+
+    fn current_ping360_config(
+        &self,
+        device_id: Uuid,
+    ) -> Result<Option<Ping360Config>, ManagerError> {
+        match &self.get_device(device_id)?.properties {
+            Some(DeviceProperties::Ping360(properties)) => Ok(Some(
+                *properties.continuous_mode_settings.read().map_err(|err| {
+                    ManagerError::Other(format!(
+                        "current_ping360_config: {err}, device: {device_id}"
+                    ))
+                })?,
+            )),
+            _ => Ok(None),
+        }
+    }
+}
+
+// The local device with the same id, or the one opened from the same port, since ids derived from the
+// hardware differ between vehicles
+fn matching_device<'a>(
+    mut devices: impl Iterator<Item = (Uuid, &'a SourceSelection)> + Clone,
+    incoming: &DeviceSettings,
+) -> Option<Uuid> {
+    devices
+        .clone()
+        .find(|(id, _)| *id == incoming.id)
+        .or_else(|| devices.find(|(_, source)| source.conflicts_with(&incoming.source)))
+        .map(|(id, _)| id)
+}
+
+// Exported ids mapped to the devices the settings were applied to, which differ between vehicles
+fn local_device_ids<'a>(
+    devices: impl Iterator<Item = (Uuid, &'a SourceSelection)> + Clone,
+    exported: &[DeviceSettings],
+    exported_ids: &[Uuid],
+) -> Vec<Uuid> {
+    exported_ids
+        .iter()
+        .map(|exported_id| {
+            exported
+                .iter()
+                .find(|settings| settings.id == *exported_id)
+                .and_then(|settings| matching_device(devices.clone(), settings))
+                .unwrap_or(*exported_id)
+        })
+        .collect()
+}
+
+// Compare two serializable values field by field, reporting each top-level field that differs
+fn field_changes<C: Serialize, I: Serialize>(current: &C, incoming: &I) -> Vec<FieldChange> {
+    let current = serde_json::to_value(current).unwrap_or_default();
+    let incoming = serde_json::to_value(incoming).unwrap_or_default();
+
+    let serde_json::Value::Object(incoming) = incoming else {
+        return Vec::new();
+    };
+
+    incoming
+        .into_iter()
+        .filter_map(|(field, incoming)| {
+            let current = current.get(&field).cloned().unwrap_or_default();
+            (current != incoming).then_some(FieldChange {
+                field,
+                current,
+                incoming,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Ping360Config {
+        Ping360Config {
+            mode: 1,
+            gain_setting: 0,
+            transmit_duration: 32,
+            sample_period: 80,
+            transmit_frequency: 740,
+            number_of_samples: 1200,
+            start_angle: 0,
+            stop_angle: 399,
+            num_steps: 1,
+            delay: 0,
+        }
+    }
+
+    #[test]
+    fn test_field_changes_reports_only_different_fields() {
+        let current = config();
+        let incoming = Ping360Config {
+            gain_setting: 2,
+            stop_angle: 200,
+            ..current
+        };
+
+        let changes = field_changes(&Some(current), &incoming);
+        let fields: Vec<&str> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields.len(), 2);
+        assert!(fields.contains(&"gain_setting"));
+        assert!(fields.contains(&"stop_angle"));
+    }
+
+    #[test]
+    fn test_matching_device_by_id_then_source() {
+        use crate::device::manager::SourceUdpStruct;
+
+        let udp = |port| {
+            SourceSelection::UdpStream(SourceUdpStruct {
+                ip: "192.168.2.2".parse().unwrap(),
+                port,
+            })
+        };
+        let (first, second) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let sources = [(first, udp(9090)), (second, udp(9092))];
+        let devices = || sources.iter().map(|(id, source)| (*id, source));
+        let incoming = |id, source| DeviceSettings {
+            id,
+            source,
+            device_type: DeviceSelection::Ping360,
+            ping360_config: None,
+        };
+
+        assert_eq!(
+            matching_device(devices(), &incoming(second, udp(9090))),
+            Some(second)
+        );
+        // Exported from another vehicle
+        assert_eq!(
+            matching_device(devices(), &incoming(Uuid::from_u128(3), udp(9092))),
+            Some(second)
+        );
+        assert_eq!(
+            matching_device(devices(), &incoming(Uuid::from_u128(3), udp(9093))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_continuous_mode_is_restored_on_local_devices() {
+        use crate::device::manager::SourceUdpStruct;
+
+        let udp = |port| {
+            SourceSelection::UdpStream(SourceUdpStruct {
+                ip: "192.168.2.2".parse().unwrap(),
+                port,
+            })
+        };
+        // Ids of the other vehicle, derived from its hardware
+        let exported = |id: u128, port| DeviceSettings {
+            id: Uuid::from_u128(id),
+            source: udp(port),
+            device_type: DeviceSelection::Ping360,
+            ping360_config: None,
+        };
+        let state = ManagerState {
+            settings: SettingsSnapshot {
+                version: "0.0.0".to_string(),
+                devices: vec![exported(10, 9090), exported(11, 9092)],
+            },
+            continuous_mode: vec![Uuid::from_u128(11)],
+            presets: Vec::new(),
+        };
+        let sources = [
+            (Uuid::from_u128(1), udp(9090)),
+            (Uuid::from_u128(2), udp(9092)),
+        ];
+        let devices = sources.iter().map(|(id, source)| (*id, source));
+
+        assert_eq!(
+            local_device_ids(devices, &state.settings.devices, &state.continuous_mode),
+            [Uuid::from_u128(2)]
+        );
+    }
+
+    #[test]
+    fn test_field_changes_without_current_value() {
+        let changes = field_changes(&None::<Ping360Config>, &config());
+        assert_eq!(changes.len(), 10);
+        assert!(changes.iter().all(|c| c.current.is_null()));
+    }
+}
@@ -0,0 +1,330 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use tokio::{io::AsyncReadExt, sync::mpsc};
+use tracing::{debug, warn};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// Entries are stored as is, MCAP chunks are already compressed
+const METHOD_STORED: u16 = 0;
+// Sizes and CRC follow the data, names are UTF-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+pub struct ArchiveEntry {
+    /// Name inside the archive, forward slash separated
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+struct WrittenEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    modified: DateTime<Utc>,
+}
+
+impl WrittenEntry {
+    fn is_zip64(&self) -> bool {
+        self.size >= u32::MAX as u64 || self.offset >= u32::MAX as u64
+    }
+}
+
+/// Zip of the entries built while it is sent, memory stays at one chunk per archive
+pub fn stream(
+    entries: Vec<ArchiveEntry>,
+) -> impl futures::Stream<Item = Result<actix_web::web::Bytes, std::io::Error>> {
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(err) = write_archive(entries, &sender).await {
+            // A closed receiver means the client went away, nothing left to report
+            if !sender.is_closed() {
+                warn!("Archive: Failed to build archive: {err}");
+                let _ = sender.send(Err(err)).await;
+            }
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    })
+}
+
+type ArchiveSender = mpsc::Sender<Result<actix_web::web::Bytes, std::io::Error>>;
+
+async fn send(sender: &ArchiveSender, data: Vec<u8>, offset: &mut u64) -> std::io::Result<()> {
+    *offset += data.len() as u64;
+    sender
+        .send(Ok(data.into()))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Client disconnected"))
+}
+
+async fn write_archive(entries: Vec<ArchiveEntry>, sender: &ArchiveSender) -> std::io::Result<()> {
+    let mut offset = 0;
+    let mut written = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let zip64 = entry.size >= u32::MAX as u64;
+        let entry_offset = offset;
+        send(sender, local_header(&entry, zip64), &mut offset).await?;
+
+        let mut file = tokio::fs::File::open(&entry.path).await?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+            send(sender, buffer[..read].to_vec(), &mut offset).await?;
+        }
+        if size != entry.size {
+            return Err(std::io::Error::other(format!(
+                "{} changed size while archiving",
+                entry.name
+            )));
+        }
+
+        let crc = hasher.finalize();
+        send(sender, data_descriptor(crc, size, zip64), &mut offset).await?;
+        debug!("Archive: Added {} ({size} bytes)", entry.name);
+        written.push(WrittenEntry {
+            name: entry.name,
+            crc,
+            size,
+            offset: entry_offset,
+            modified: entry.modified,
+        });
+    }
+
+    let directory_offset = offset;
+    for entry in &written {
+        send(sender, central_header(entry), &mut offset).await?;
+    }
+    let directory_size = offset - directory_offset;
+    send(
+        sender,
+        end_of_directory(written.len() as u64, directory_size, directory_offset),
+        &mut offset,
+    )
+    .await
+}
+
+fn dos_time(time: &DateTime<Utc>) -> (u16, u16) {
+    let date = ((time.year().clamp(1980, 2107) - 1980) as u16) << 9
+        | (time.month() as u16) << 5
+        | time.day() as u16;
+    let time =
+        (time.hour() as u16) << 11 | (time.minute() as u16) << 5 | (time.second() / 2) as u16;
+    (time, date)
+}
+
+fn local_header(entry: &ArchiveEntry, zip64: bool) -> Vec<u8> {
+    let (time, date) = dos_time(&entry.modified);
+    let mut header = Vec::new();
+    header.extend(0x04034b50u32.to_le_bytes());
+    header.extend(if zip64 { VERSION_ZIP64 } else { VERSION }.to_le_bytes());
+    header.extend(FLAGS.to_le_bytes());
+    header.extend(METHOD_STORED.to_le_bytes());
+    header.extend(time.to_le_bytes());
+    header.extend(date.to_le_bytes());
+    header.extend(0u32.to_le_bytes()); // CRC, in the data descriptor
+    let size = if zip64 { u32::MAX } else { 0 };
+    header.extend(size.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend((entry.name.len() as u16).to_le_bytes());
+    header.extend((if zip64 { 20u16 } else { 0 }).to_le_bytes());
+    header.extend(entry.name.as_bytes());
+    if zip64 {
+        header.extend(ZIP64_EXTRA_ID.to_le_bytes());
+        header.extend(16u16.to_le_bytes());
+        header.extend([0; 16]);
+    }
+    header
+}
+
+fn data_descriptor(crc: u32, size: u64, zip64: bool) -> Vec<u8> {
+    let mut descriptor = Vec::new();
+    descriptor.extend(0x08074b50u32.to_le_bytes());
+    descriptor.extend(crc.to_le_bytes());
+    for _ in 0..2 {
+        if zip64 {
+            descriptor.extend(size.to_le_bytes());
+        } else {
+            descriptor.extend((size as u32).to_le_bytes());
+        }
+    }
+    descriptor
+}
+
+fn central_header(entry: &WrittenEntry) -> Vec<u8> {
+    let (time, date) = dos_time(&entry.modified);
+    let large_size = entry.size >= u32::MAX as u64;
+    let large_offset = entry.offset >= u32::MAX as u64;
+    let mut extra = Vec::new();
+    if large_size {
+        extra.extend(entry.size.to_le_bytes());
+        extra.extend(entry.size.to_le_bytes());
+    }
+    if large_offset {
+        extra.extend(entry.offset.to_le_bytes());
+    }
+
+    let version = if entry.is_zip64() {
+        VERSION_ZIP64
+    } else {
+        VERSION
+    };
+    let size = if large_size {
+        u32::MAX
+    } else {
+        entry.size as u32
+    };
+    let mut header = Vec::new();
+    header.extend(0x02014b50u32.to_le_bytes());
+    header.extend(version.to_le_bytes()); // Made by
+    header.extend(version.to_le_bytes()); // Needed to extract
+    header.extend(FLAGS.to_le_bytes());
+    header.extend(METHOD_STORED.to_le_bytes());
+    header.extend(time.to_le_bytes());
+    header.extend(date.to_le_bytes());
+    header.extend(entry.crc.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend((entry.name.len() as u16).to_le_bytes());
+    let extra_length = if extra.is_empty() { 0 } else { extra.len() + 4 };
+    header.extend((extra_length as u16).to_le_bytes());
+    header.extend(0u16.to_le_bytes()); // Comment
+    header.extend(0u16.to_le_bytes()); // Disk
+    header.extend(0u16.to_le_bytes()); // Internal attributes
+    header.extend(0u32.to_le_bytes()); // External attributes
+    let offset = if large_offset {
+        u32::MAX
+    } else {
+        entry.offset as u32
+    };
+    header.extend(offset.to_le_bytes());
+    header.extend(entry.name.as_bytes());
+    if !extra.is_empty() {
+        header.extend(ZIP64_EXTRA_ID.to_le_bytes());
+        header.extend((extra.len() as u16).to_le_bytes());
+        header.extend(extra);
+    }
+    header
+}
+
+fn end_of_directory(entries: u64, size: u64, offset: u64) -> Vec<u8> {
+    let zip64 = entries >= u16::MAX as u64 || size >= u32::MAX as u64 || offset >= u32::MAX as u64;
+    let mut end = Vec::new();
+    if zip64 {
+        let record_offset = offset + size;
+        end.extend(0x06064b50u32.to_le_bytes());
+        end.extend(44u64.to_le_bytes()); // Size of the rest of the record
+        end.extend(VERSION_ZIP64.to_le_bytes());
+        end.extend(VERSION_ZIP64.to_le_bytes());
+        end.extend(0u32.to_le_bytes()); // Disk
+        end.extend(0u32.to_le_bytes()); // Disk with the directory
+        end.extend(entries.to_le_bytes());
+        end.extend(entries.to_le_bytes());
+        end.extend(size.to_le_bytes());
+        end.extend(offset.to_le_bytes());
+
+        end.extend(0x07064b50u32.to_le_bytes());
+        end.extend(0u32.to_le_bytes());
+        end.extend(record_offset.to_le_bytes());
+        end.extend(1u32.to_le_bytes()); // Total disks
+    }
+
+    end.extend(0x06054b50u32.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    let count = entries.min(u16::MAX as u64) as u16;
+    end.extend(count.to_le_bytes());
+    end.extend(count.to_le_bytes());
+    end.extend((size.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend((offset.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend(0u16.to_le_bytes()); // Comment
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_streamed_archive_is_read_back() {
+        let directory = std::env::temp_dir().join(format!("ping-viewer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let files = [
+            ("device_1.mcap", vec![1u8; CHUNK_SIZE * 2 + 10]),
+            ("2025/01/31/empty.mcap", Vec::new()),
+        ];
+        let entries = files
+            .iter()
+            .enumerate()
+            .map(|(index, (name, data))| {
+                let path = directory.join(index.to_string());
+                std::fs::write(&path, data).unwrap();
+                ArchiveEntry {
+                    name: name.to_string(),
+                    path,
+                    size: data.len() as u64,
+                    modified: Utc::now(),
+                }
+            })
+            .collect();
+
+        let mut data = Vec::new();
+        let mut archive = std::pin::pin!(stream(entries));
+        while let Some(chunk) = archive.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), files.len());
+        for (name, expected) in &files {
+            let mut content = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut content)
+                .unwrap();
+            assert_eq!(&content, expected);
+        }
+    }
+
+    #[test]
+    fn test_small_archive_has_no_zip64_records() {
+        let end = end_of_directory(2, 100, 1000);
+        assert_eq!(end.len(), 22);
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+
+        let end = end_of_directory(2, 100, u32::MAX as u64 + 1);
+        assert_eq!(end.len(), 56 + 20 + 22);
+        assert_eq!(&end[..4], &0x06064b50u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_dos_time() {
+        let time = DateTime::parse_from_rfc3339("2024-03-05T10:20:31Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            dos_time(&time),
+            (10 << 11 | 20 << 5 | 15, 44 << 9 | 3 << 5 | 5)
+        );
+    }
+}
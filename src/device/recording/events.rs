@@ -0,0 +1,57 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::trace;
+use uuid::Uuid;
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<RecordingEvent> = broadcast::channel(100).0;
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Apiv2Schema, schemars::JsonSchema,
+)]
+pub enum EventKind {
+    RecordingStarted,
+    RecordingStopped,
+    SettingChanged,
+    ContinuousModeStarted,
+    ContinuousModeStopped,
+    StatusChanged,
+    Reconnected,
+    /// The device was removed from the DeviceManager, its recordings are stopped
+    Deleted,
+}
+
+/// Device event written on the `device_<id>/Events` channel of its recordings
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema, schemars::JsonSchema)]
+pub struct RecordingEvent {
+    pub device_id: Uuid,
+    pub kind: EventKind,
+    /// The request or state behind the event, usually as JSON
+    pub details: String,
+    pub time: String,
+}
+
+impl RecordingEvent {
+    pub fn new(device_id: Uuid, kind: EventKind, details: impl Into<String>) -> Self {
+        Self {
+            device_id,
+            kind,
+            details: details.into(),
+            time: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Publish a device event to the active recordings of that device, dropped when none listen
+pub fn publish(device_id: Uuid, kind: EventKind, details: impl Into<String>) {
+    let event = RecordingEvent::new(device_id, kind, details);
+    trace!("Recording event: {event:?}");
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<RecordingEvent> {
+    EVENTS.subscribe()
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::device::manager::ManagerError;
+
+use super::reader::RecordingFile;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::JsonLines => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Apiv2Schema)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Full topic or its last segment, e.g. `Ping1D`. Required for CSV when the file has several topics
+    pub topic: Option<String>,
+}
+
+pub struct Export {
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+struct Row {
+    topic: String,
+    log_time: u64,
+    data: serde_json::Value,
+}
+
+fn topic_matches(topic: &str, selection: &str) -> bool {
+    topic == selection
+        || topic
+            .rsplit_once('/')
+            .is_some_and(|(_, name)| name == selection)
+}
+
+pub fn export_with_progress(
+    path: &Path,
+    options: &ExportOptions,
+    progress: impl FnMut(f64),
+) -> Result<Export, ManagerError> {
+    let (rows, topics) = read_rows(path, options.topic.as_deref(), progress)?;
+
+    if let Some(selection) = &options.topic {
+        if !topics.iter().any(|topic| topic_matches(topic, selection)) {
+            return Err(ManagerError::Other(format!(
+                "Topic {selection:?} not found, available topics: {topics:?}"
+            )));
+        }
+    } else if options.format == ExportFormat::Csv && topics.len() > 1 {
+        return Err(ManagerError::Other(format!(
+            "CSV export needs a topic, available topics: {topics:?}"
+        )));
+    }
+
+    let data = match options.format {
+        ExportFormat::Csv => to_csv(&rows),
+        ExportFormat::JsonLines => to_json_lines(&rows)?,
+    };
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match &options.topic {
+        Some(topic) => format!(
+            "{stem}_{}.{}",
+            topic.rsplit('/').next().unwrap_or(topic),
+            options.format.extension()
+        ),
+        None => format!("{stem}.{}", options.format.extension()),
+    };
+
+    Ok(Export { file_name, data })
+}
+
+/// Every topic of a recording as its own CSV file, named after the topic, e.g. `device_<id>/Ping1D.csv`
+pub fn export_topics_csv(path: &Path) -> Result<Vec<Export>, ManagerError> {
+    let (rows, _) = read_rows(path, None, |_| {})?;
+    Ok(topics_csv(rows))
+}
+
+fn topics_csv(rows: Vec<Row>) -> Vec<Export> {
+    let mut topics: BTreeMap<String, Vec<Row>> = BTreeMap::new();
+    for row in rows {
+        topics.entry(row.topic.clone()).or_default().push(row);
+    }
+    topics
+        .into_iter()
+        .map(|(topic, rows)| Export {
+            // ROS 2 topics start with a slash, the files stay under the output directory
+            file_name: format!(
+                "{}.{}",
+                topic.trim_start_matches('/'),
+                ExportFormat::Csv.extension()
+            ),
+            data: to_csv(&rows),
+        })
+        .collect()
+}
+
+// Messages of the selected topic, or all of them, and every topic of the file
+fn read_rows(
+    path: &Path,
+    selection: Option<&str>,
+    mut progress: impl FnMut(f64),
+) -> Result<(Vec<Row>, BTreeSet<String>), ManagerError> {
+    let data = RecordingFile::open(path)?;
+    let stream = mcap::MessageStream::new(&data)
+        .map_err(|err| ManagerError::Other(format!("Invalid MCAP file {path:?}: {err}")))?;
+    let total_messages = data.message_count();
+
+    let mut rows = Vec::new();
+    let mut topics = BTreeSet::new();
+    for (index, message) in stream.enumerate() {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                // Files still being written have no footer, export everything read so far
+                debug!("Export: stopped reading {path:?}: {err}");
+                break;
+            }
+        };
+        if let Some(total) = total_messages {
+            progress((index + 1) as f64 / total as f64);
+        }
+
+        let topic = message.channel.topic.as_str();
+        topics.insert(topic.to_string());
+        if let Some(selection) = selection {
+            if !topic_matches(topic, selection) {
+                continue;
+            }
+        }
+
+        match serde_json::from_slice::<serde_json::Value>(&message.data) {
+            Ok(data) => rows.push(Row {
+                topic: topic.to_string(),
+                log_time: message.log_time,
+                data,
+            }),
+            Err(err) => warn!("Export: failed to decode message on {topic}: {err}"),
+        }
+    }
+    Ok((rows, topics))
+}
+
+fn to_json_lines(rows: &[Row]) -> Result<Vec<u8>, ManagerError> {
+    let mut output = Vec::new();
+    for row in rows {
+        let line = serde_json::json!({
+            "topic": row.topic,
+            "log_time": row.log_time,
+            "data": row.data,
+        });
+        serde_json::to_writer(&mut output, &line)
+            .map_err(|err| ManagerError::Other(format!("Failed to write JSON line: {err}")))?;
+        output.push(b'\n');
+    }
+    Ok(output)
+}
+
+// One column per top level field, arrays such as the sonar samples are kept as JSON
+fn to_csv(rows: &[Row]) -> Vec<u8> {
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows {
+        if let serde_json::Value::Object(fields) = &row.data {
+            for key in fields.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let header: Vec<String> = ["log_time_ns", "time"]
+        .into_iter()
+        .map(String::from)
+        .chain(columns.iter().cloned())
+        .map(|column| csv_field(&column))
+        .collect();
+    output.push_str(&header.join(","));
+    output.push('\n');
+
+    for row in rows {
+        let mut fields = vec![
+            row.log_time.to_string(),
+            chrono::DateTime::from_timestamp_nanos(row.log_time as i64).to_rfc3339(),
+        ];
+        for column in &columns {
+            let value = match row.data.get(column) {
+                None | Some(serde_json::Value::Null) => String::new(),
+                Some(serde_json::Value::String(text)) => text.clone(),
+                Some(value) => value.to_string(),
+            };
+            fields.push(csv_field(&value));
+        }
+        output.push_str(&fields.join(","));
+        output.push('\n');
+    }
+
+    output.into_bytes()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_keeps_arrays_in_one_field() {
+        let rows = vec![Row {
+            topic: "device_1/Ping1D".to_string(),
+            log_time: 0,
+            data: serde_json::json!({"distance": 1500, "profile_data": [1, 2, 3]}),
+        }];
+        let csv = String::from_utf8(to_csv(&rows)).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "log_time_ns,time,distance,profile_data"
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            "0,1970-01-01T00:00:00+00:00,1500,\"[1,2,3]\""
+        );
+    }
+
+    #[test]
+    fn test_topics_get_their_own_csv() {
+        let row = |topic: &str, log_time| Row {
+            topic: topic.to_string(),
+            log_time,
+            data: serde_json::json!({"value": log_time}),
+        };
+        let exports = topics_csv(vec![
+            row("device_1/Ping1D", 1),
+            row("/device_1/range", 2),
+            row("device_1/Ping1D", 3),
+        ]);
+        let names: Vec<&str> = exports
+            .iter()
+            .map(|export| export.file_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["device_1/range.csv", "device_1/Ping1D.csv"]);
+        let ping1d = String::from_utf8(exports[1].data.clone()).unwrap();
+        assert_eq!(ping1d.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_topic_matches_last_segment() {
+        assert!(topic_matches("device_1/Ping360", "Ping360"));
+        assert!(topic_matches("device_1/Ping360", "device_1/Ping360"));
+        assert!(!topic_matches("device_1/Ping360", "Ping1D"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use notify::{
+    event::{AccessKind, AccessMode, CreateKind, ModifyKind, RemoveKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{debug, info, trace, warn};
+
+lazy_static! {
+    static ref CHANGES: broadcast::Sender<FileChange> = broadcast::channel(100).0;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Apiv2Schema)]
+pub enum FileChangeKind {
+    Created,
+    /// The writer closed the file, e.g. a stopped or rotated recording
+    Finished,
+    Renamed,
+    Deleted,
+}
+
+/// Change in the recordings directory, sent on `ws/files`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub struct FileChange {
+    pub kind: FileChangeKind,
+    /// Relative to the recordings directory, as in `/recordings/list`
+    pub file_name: String,
+    /// Name before a rename
+    pub previous_name: Option<String>,
+    /// Bytes on disk, None for deleted files
+    pub size: Option<u64>,
+    pub time: String,
+}
+
+pub fn subscribe() -> broadcast::Receiver<FileChange> {
+    CHANGES.subscribe()
+}
+
+/// Watch the recordings directory, changes are published until the watcher is dropped
+pub fn watch(base_path: &Path) -> Option<RecommendedWatcher> {
+    if let Err(err) = std::fs::create_dir_all(base_path) {
+        warn!("Files: Failed to create {base_path:?}, file changes are not reported: {err}");
+        return None;
+    }
+    // Events carry absolute paths, the base must match them to be stripped
+    let base = base_path
+        .canonicalize()
+        .unwrap_or_else(|_| base_path.to_path_buf());
+
+    let watched = base.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) => {
+            for change in changes(&watched, &event) {
+                trace!("Files: {change:?}");
+                let _ = CHANGES.send(change);
+            }
+        }
+        Err(err) => warn!("Files: Watcher error: {err}"),
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Files: Failed to create watcher, file changes are not reported: {err}");
+            return None;
+        }
+    };
+    if let Err(err) = watcher.watch(&base, RecursiveMode::Recursive) {
+        warn!("Files: Failed to watch {base:?}, file changes are not reported: {err}");
+        return None;
+    }
+    info!("Files: Watching {base:?} for changes");
+    Some(watcher)
+}
+
+fn changes(base: &Path, event: &Event) -> Vec<FileChange> {
+    let change = |kind, path: &PathBuf, previous: Option<&PathBuf>| {
+        let file_name = relative_name(base, path)?;
+        let previous_name = match previous {
+            Some(previous) => Some(relative_name(base, previous)?),
+            None => None,
+        };
+        let size = match kind {
+            FileChangeKind::Deleted => None,
+            _ => Some(std::fs::metadata(path).ok().filter(|m| m.is_file())?.len()),
+        };
+        Some(FileChange {
+            kind,
+            file_name,
+            previous_name,
+            size,
+            time: chrono::Utc::now().to_rfc3339(),
+        })
+    };
+
+    let kind = match event.kind {
+        EventKind::Create(CreateKind::File | CreateKind::Any) => FileChangeKind::Created,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => FileChangeKind::Finished,
+        EventKind::Remove(RemoveKind::File | RemoveKind::Any) => FileChangeKind::Deleted,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let [from, to] = &event.paths[..] else {
+                return Vec::new();
+            };
+            // A recording renamed away from a temporary name, e.g. after recovery, is a new file
+            return match relative_name(base, from) {
+                Some(_) => change(FileChangeKind::Renamed, to, Some(from)),
+                None => change(FileChangeKind::Created, to, None),
+            }
+            .into_iter()
+            .collect();
+        }
+        // Moves across the watched boundary only show one side
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => FileChangeKind::Deleted,
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => FileChangeKind::Created,
+        _ => return Vec::new(),
+    };
+    debug!("Files: {kind:?} {:?}", event.paths);
+    event
+        .paths
+        .iter()
+        .filter_map(|path| change(kind, path, None))
+        .collect()
+}
+
+// Hidden and temporary files, e.g. `.mcap.recovering`, are not recordings
+fn relative_name(base: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+    let file_name = relative.file_name()?.to_string_lossy();
+    if file_name.starts_with('.') || file_name.ends_with(".recovering") {
+        return None;
+    }
+    Some(
+        relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_names() {
+        let base = Path::new("/data/recordings");
+        assert_eq!(
+            relative_name(base, Path::new("/data/recordings/2024-03-05/dive.mcap")).as_deref(),
+            Some("2024-03-05/dive.mcap")
+        );
+        assert_eq!(
+            relative_name(base, Path::new("/data/recordings/dive.mcap.recovering")),
+            None
+        );
+        assert_eq!(
+            relative_name(base, Path::new("/data/recordings/.hidden")),
+            None
+        );
+        assert_eq!(relative_name(base, Path::new("/tmp/dive.mcap")), None);
+    }
+
+    #[test]
+    fn test_deleted_files_are_reported() {
+        let base = Path::new("/data/recordings");
+        let event = Event::new(EventKind::Remove(RemoveKind::File))
+            .add_path(PathBuf::from("/data/recordings/dive.mcap"));
+        let reported = changes(base, &event);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].kind, FileChangeKind::Deleted);
+        assert_eq!(reported[0].file_name, "dive.mcap");
+        assert_eq!(reported[0].size, None);
+
+        let directory = Event::new(EventKind::Remove(RemoveKind::Folder))
+            .add_path(PathBuf::from("/data/recordings/2024-03-05"));
+        assert!(changes(base, &directory).is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+use std::{collections::HashMap, time::Duration};
+
+use bluerobotics_ping::message::ProtocolMessage;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::device::manager::ManagerActorHandler;
+use crate::vehicle::{history, VehicleData};
+
+use super::{
+    decode_sonar_message, device_subscriber, report::ping360_range, udp::streaming_devices,
+    SonarData,
+};
+
+// How often the devices to georeference are refreshed from the DeviceManager
+const GEOREFERENCE_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+// Mean earth radius of WGS84, enough for the few meters a sonar sees
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+// Ping360 beams without an echo this strong have nothing to locate
+const PING360_MIN_INTENSITY: u8 = 100;
+
+lazy_static! {
+    static ref SAMPLES: broadcast::Sender<GeoreferencedSample> = broadcast::channel(100).0;
+}
+
+/// Position of a sonar return, from the sample and the vehicle pose at its time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema, schemars::JsonSchema)]
+pub struct GeoreferencedSample {
+    pub device_id: Uuid,
+    #[schemars(description = "Unix time in milliseconds of the sample")]
+    pub timestamp_ms: i64,
+    #[schemars(description = "Latitude of the return in decimal degrees")]
+    pub lat: f64,
+    #[schemars(description = "Longitude of the return in decimal degrees")]
+    pub lon: f64,
+    #[schemars(description = "Depth of the return below the surface in meters")]
+    pub depth: f64,
+    #[schemars(description = "Ping1D confidence in percent or Ping360 echo intensity, 0 to 255")]
+    pub strength: u16,
+    #[schemars(description = "Ping360 beam bearing from north in degrees, none for Ping1D")]
+    pub bearing: Option<f64>,
+}
+
+/// Georeferenced samples of every streaming device, for the `ws/georeferenced` websocket
+pub fn subscribe() -> broadcast::Receiver<GeoreferencedSample> {
+    SAMPLES.subscribe()
+}
+
+/// Locate a sonar message with the vehicle pose at the given time, None without a fresh pose
+pub fn georeference(
+    device_id: Uuid,
+    msg: &ProtocolMessage,
+    timestamp_ms: i64,
+) -> Option<GeoreferencedSample> {
+    let sonar = decode_sonar_message(msg)?;
+    let pose = history::pose_at(timestamp_ms)?;
+    locate(device_id, &sonar, &pose)
+}
+
+fn locate(device_id: Uuid, sonar: &SonarData, pose: &VehicleData) -> Option<GeoreferencedSample> {
+    // Vehicle without a position yet, see location_fix
+    if pose.lat == 0.0 && pose.lon == 0.0 {
+        return None;
+    }
+    // Beam in the vehicle frame, x forward, y starboard and z down, assuming the default mounting
+    let (beam, strength) = match sonar {
+        SonarData::Ping1D(profile) => {
+            let range = profile.distance as f64 / 1000.0;
+            ([0.0, 0.0, range], u16::from(profile.confidence))
+        }
+        SonarData::Ping360(data) => {
+            let (index, intensity) = data
+                .data
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, intensity)| **intensity)?;
+            if *intensity < PING360_MIN_INTENSITY {
+                return None;
+            }
+            let range = ping360_range(data.sample_period, index + 1);
+            let angle = data.angle as f64 * std::f64::consts::TAU / 400.0;
+            (
+                [range * angle.cos(), range * angle.sin(), 0.0],
+                u16::from(*intensity),
+            )
+        }
+    };
+
+    let [north, east, down] = body_to_ned(beam, pose);
+    let lat = pose.lat + (north / EARTH_RADIUS_M).to_degrees();
+    let lon = pose.lon + (east / (EARTH_RADIUS_M * pose.lat.to_radians().cos())).to_degrees();
+    let bearing = matches!(sonar, SonarData::Ping360(_))
+        .then(|| east.atan2(north).to_degrees().rem_euclid(360.0));
+
+    Some(GeoreferencedSample {
+        device_id,
+        timestamp_ms: pose.timestamp_ms,
+        lat,
+        lon,
+        depth: pose.depth.unwrap_or_default() + down,
+        strength,
+        bearing,
+    })
+}
+
+// Rotation of the vehicle frame to north, east and down, with the autopilot roll, pitch and yaw
+pub(super) fn body_to_ned([x, y, z]: [f64; 3], pose: &VehicleData) -> [f64; 3] {
+    let (sr, cr) = (pose.roll as f64).sin_cos();
+    let (sp, cp) = (pose.pitch as f64).sin_cos();
+    let (sy, cy) = (pose.yaw as f64).sin_cos();
+    [
+        cp * cy * x + (sr * sp * cy - cr * sy) * y + (cr * sp * cy + sr * sy) * z,
+        cp * sy * x + (sr * sp * sy + cr * cy) * y + (cr * sp * sy - sr * cy) * z,
+        -sp * x + sr * cp * y + cr * cp * z,
+    ]
+}
+
+/// Georeferences the messages of every streaming device for the websocket subscribers
+pub struct Georeferencer {
+    devices_manager_handler: ManagerActorHandler,
+    readers: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl Georeferencer {
+    pub fn new(devices_manager_handler: ManagerActorHandler) -> Self {
+        Self {
+            devices_manager_handler,
+            readers: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut refresh_interval = tokio::time::interval(GEOREFERENCE_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_readers().await;
+        }
+    }
+
+    async fn refresh_readers(&mut self) {
+        let streaming = streaming_devices(&self.devices_manager_handler).await;
+
+        self.readers.retain(|device_id, reader| {
+            let keep = streaming.contains(device_id) && !reader.is_finished();
+            if !keep {
+                reader.abort();
+            }
+            keep
+        });
+
+        for device_id in streaming {
+            if self.readers.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver =
+                match device_subscriber(&self.devices_manager_handler, device_id).await {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        warn!("Georeferencer: Failed to subscribe to device {device_id}: {err:?}");
+                        continue;
+                    }
+                };
+
+            let reader = tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            // Nothing to compute while no websocket client listens
+                            if SAMPLES.receiver_count() == 0 {
+                                continue;
+                            }
+                            let now = chrono::Utc::now().timestamp_millis();
+                            if let Some(sample) = georeference(device_id, &msg, now) {
+                                let _ = SAMPLES.send(sample);
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("Georeferencer: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            self.readers.insert(device_id, reader);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bluerobotics_ping::ping1d::ProfileStruct;
+
+    fn pose(roll: f32, yaw: f32) -> VehicleData {
+        VehicleData {
+            roll,
+            pitch: 0.0,
+            yaw,
+            alt: 0.0,
+            lat: -27.0,
+            lon: -48.0,
+            heading: None,
+            groundspeed: None,
+            depth: Some(2.0),
+            local_position: None,
+            velocity: None,
+            timestamp_ms: 1,
+        }
+    }
+
+    fn profile(distance: u32) -> SonarData {
+        SonarData::Ping1D(ProfileStruct {
+            distance,
+            confidence: 100,
+            transmit_duration: 0,
+            ping_number: 0,
+            scan_start: 0,
+            scan_length: 0,
+            gain_setting: 0,
+            profile_data_length: 0,
+            profile_data: vec![],
+        })
+    }
+
+    #[test]
+    fn test_level_ping1d_is_below_the_vehicle() {
+        let device_id = Uuid::new_v4();
+        let sample = locate(device_id, &profile(10_000), &pose(0.0, 1.0)).unwrap();
+        assert_eq!((sample.lat, sample.lon), (-27.0, -48.0));
+        assert!((sample.depth - 12.0).abs() < 1e-9);
+        assert_eq!(sample.bearing, None);
+    }
+
+    #[test]
+    fn test_rolled_ping1d_points_to_port() {
+        // Rolled to starboard, the down looking beam hits the bottom on the port side, west when heading north
+        let sample = locate(
+            Uuid::new_v4(),
+            &profile(10_000),
+            &pose(std::f32::consts::FRAC_PI_6, 0.0),
+        )
+        .unwrap();
+        assert!(sample.lon < -48.0);
+        assert!((sample.lat - -27.0).abs() < 1e-9);
+        assert!((sample.depth - (2.0 + 10.0 * 3f64.sqrt() / 2.0)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::device::manager::ManagerError;
+
+use super::reader::RecordingFile;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct RecordingInfo {
+    pub file_size: u64,
+    pub profile: String,
+    pub library: String,
+    /// False for files still being written or left open by a crash, counted by reading every message
+    pub finalized: bool,
+    pub message_count: u64,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub duration_s: f64,
+    pub topics: Vec<TopicInfo>,
+    pub schemas: Vec<SchemaInfo>,
+    /// Names of the metadata records, e.g. `software`, `device` and `settings`
+    pub metadata: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct TopicInfo {
+    pub topic: String,
+    pub message_encoding: String,
+    pub schema_name: Option<String>,
+    pub message_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct SchemaInfo {
+    pub name: String,
+    pub encoding: String,
+    pub data: String,
+}
+
+#[derive(Default)]
+struct Counts {
+    messages: u64,
+    start: Option<u64>,
+    end: Option<u64>,
+    // topic -> (encoding, schema name, count)
+    topics: BTreeMap<String, (String, Option<String>, u64)>,
+    schemas: BTreeMap<String, SchemaInfo>,
+}
+
+impl Counts {
+    fn add_channel(&mut self, channel: &mcap::Channel, count: u64) {
+        let entry = self.topics.entry(channel.topic.clone()).or_insert((
+            channel.message_encoding.clone(),
+            channel.schema.as_ref().map(|schema| schema.name.clone()),
+            0,
+        ));
+        entry.2 += count;
+        if let Some(schema) = &channel.schema {
+            self.schemas
+                .entry(schema.name.clone())
+                .or_insert_with(|| SchemaInfo {
+                    name: schema.name.clone(),
+                    encoding: schema.encoding.clone(),
+                    data: String::from_utf8_lossy(&schema.data).to_string(),
+                });
+        }
+    }
+}
+
+// Metadata is written right after the header, the start of the file is enough to find it
+const METADATA_SCAN_SIZE: u64 = 64 * 1024;
+
+// Files remembered by `cached_device_ids` before the ones deleted since are forgotten
+const MAX_CACHED_DEVICE_IDS: usize = 4096;
+
+lazy_static! {
+    // Devices of each file at its modification time, listings filter on them at every request
+    static ref DEVICE_IDS: Mutex<HashMap<PathBuf, (SystemTime, Vec<uuid::Uuid>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// `device_ids`, only read again when the file was modified since the last call
+pub fn cached_device_ids(path: &Path, modified: SystemTime) -> Vec<uuid::Uuid> {
+    if let Some((cached, devices)) = DEVICE_IDS.lock().unwrap().get(path) {
+        if *cached == modified {
+            return devices.clone();
+        }
+    }
+
+    let devices = device_ids(path);
+    let mut cache = DEVICE_IDS.lock().unwrap();
+    if cache.len() >= MAX_CACHED_DEVICE_IDS {
+        cache.retain(|path, _| path.exists());
+    }
+    cache.insert(path.to_path_buf(), (modified, devices.clone()));
+    devices
+}
+
+/// Devices recorded in a file, from the `device` metadata records
+pub fn device_ids(path: &Path) -> Vec<uuid::Uuid> {
+    let mut head = Vec::new();
+    let read = std::fs::File::open(path)
+        .and_then(|file| file.take(METADATA_SCAN_SIZE).read_to_end(&mut head));
+    if let Err(err) = read {
+        debug!("Info: failed to read {path:?}: {err}");
+        return Vec::new();
+    }
+    let Ok(reader) = mcap::read::LinearReader::new_with_options(
+        &head,
+        mcap::read::Options::IgnoreEndMagic.into(),
+    ) else {
+        return Vec::new();
+    };
+
+    let mut devices = Vec::new();
+    for record in reader {
+        match record {
+            Ok(mcap::records::Record::Metadata(record)) if record.name.starts_with("device") => {
+                if let Some(id) = record.metadata.get("id").and_then(|id| id.parse().ok()) {
+                    devices.push(id);
+                }
+            }
+            Ok(mcap::records::Record::Chunk { .. }) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    devices
+}
+
+pub fn read_info(path: &Path) -> Result<RecordingInfo, ManagerError> {
+    let data = RecordingFile::open(path)?;
+
+    let mut profile = String::new();
+    let mut library = String::new();
+    let mut metadata = Vec::new();
+    let reader = mcap::read::LinearReader::new(&data)
+        .map_err(|err| ManagerError::Other(format!("Invalid MCAP file {path:?}: {err}")))?;
+    // Header and metadata come first, stop at the first chunk instead of decompressing the file
+    for record in reader {
+        match record {
+            Ok(mcap::records::Record::Header(header)) => {
+                profile = header.profile;
+                library = header.library;
+            }
+            Ok(mcap::records::Record::Metadata(record)) => metadata.push(record.name),
+            Ok(mcap::records::Record::Chunk { .. }) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+
+    let summary = mcap::Summary::read(&data).ok().flatten();
+    let finalized = summary
+        .as_ref()
+        .is_some_and(|summary| summary.stats.is_some());
+
+    let mut counts = Counts::default();
+    match summary.and_then(|summary| summary.stats.map(|stats| (summary.channels, stats))) {
+        Some((channels, stats)) => {
+            counts.messages = stats.message_count;
+            counts.start = Some(stats.message_start_time);
+            counts.end = Some(stats.message_end_time);
+            for (id, channel) in &channels {
+                let count = stats.channel_message_counts.get(id).copied().unwrap_or(0);
+                counts.add_channel(channel, count);
+            }
+        }
+        None => {
+            let stream = mcap::MessageStream::new(&data)
+                .map_err(|err| ManagerError::Other(format!("Invalid MCAP file {path:?}: {err}")))?;
+            for message in stream {
+                let message = match message {
+                    Ok(message) => message,
+                    Err(err) => {
+                        debug!("Info: stopped reading {path:?}: {err}");
+                        break;
+                    }
+                };
+                counts.messages += 1;
+                counts.start = Some(
+                    counts
+                        .start
+                        .map_or(message.log_time, |start| start.min(message.log_time)),
+                );
+                counts.end = Some(
+                    counts
+                        .end
+                        .map_or(message.log_time, |end| end.max(message.log_time)),
+                );
+                counts.add_channel(&message.channel, 1);
+            }
+        }
+    }
+
+    let time = |nanos: u64| chrono::DateTime::from_timestamp_nanos(nanos as i64).to_rfc3339();
+    let (start, end) = match (counts.start, counts.end) {
+        (Some(start), Some(end)) if counts.messages > 0 => (Some(start), Some(end)),
+        _ => (None, None),
+    };
+
+    Ok(RecordingInfo {
+        file_size: data.len() as u64,
+        profile,
+        library,
+        finalized,
+        message_count: counts.messages,
+        start_time: start.map(time),
+        end_time: end.map(time),
+        duration_s: match (start, end) {
+            (Some(start), Some(end)) => end.saturating_sub(start) as f64 / 1e9,
+            _ => 0.0,
+        },
+        topics: counts
+            .topics
+            .into_iter()
+            .map(
+                |(topic, (message_encoding, schema_name, message_count))| TopicInfo {
+                    topic,
+                    message_encoding,
+                    schema_name,
+                    message_count,
+                },
+            )
+            .collect(),
+        schemas: counts.schemas.into_values().collect(),
+        metadata,
+    })
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, oneshot, Semaphore};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::device::manager::ManagerError;
+
+// Finished jobs kept around so late subscribers can still see how they ended
+const MAX_FINISHED_JOBS: usize = 100;
+// Only broadcast progress when it moves by at least this fraction
+const PROGRESS_STEP: f64 = 0.01;
+// Below the temporary directory, every process writes the outputs of its jobs in its own subdirectory
+const OUTPUTS_DIRECTORY: &str = "ping-viewer-next-jobs";
+// Held by the process owning the subdirectory, until it exits
+const OUTPUTS_LOCK: &str = ".lock";
+
+lazy_static! {
+    pub static ref JOBS: JobManager = JobManager::new(default_workers());
+}
+
+fn default_workers() -> usize {
+    // Leave room for the sonar streams and the actix workers
+    std::thread::available_parallelism()
+        .map(|cores| (cores.get() / 2).max(1))
+        .unwrap_or(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub enum JobKind {
+    SurveyReport,
+    ReplayLoad,
+    Export,
+    Recovery,
+    Import,
+    Upload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(tag = "state", content = "reason")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed(String),
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: JobKind,
+    pub file_name: String,
+    pub status: JobStatus,
+    /// Fraction of the work done, from 0 to 1
+    pub progress: f64,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub finished: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Output of a background job, as produced by its work
+#[derive(Debug, Clone)]
+pub struct JobArtifact {
+    pub file_name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Output of a finished job, written to a temporary file until the job is pruned
+#[derive(Debug, Clone)]
+pub struct StoredArtifact {
+    pub file_name: String,
+    pub content_type: String,
+    pub path: PathBuf,
+}
+
+impl StoredArtifact {
+    fn write(directory: &Path, id: Uuid, artifact: JobArtifact) -> Result<Self, ManagerError> {
+        let path = directory.join(id.to_string());
+        std::fs::create_dir_all(directory)
+            .and_then(|_| std::fs::write(&path, &artifact.data))
+            .map_err(|err| {
+                ManagerError::Other(format!("Failed to store job output {path:?}: {err}"))
+            })?;
+        Ok(Self {
+            file_name: artifact.file_name,
+            content_type: artifact.content_type,
+            path,
+        })
+    }
+
+    fn remove(&self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "JobManager: Failed to remove job output {:?}: {err}",
+                self.path
+            );
+        }
+    }
+}
+
+/// Bounded pool for long conversions of recordings, every job update is broadcast to `ws/jobs`
+pub struct JobManager {
+    workers: Arc<Semaphore>,
+    jobs: Arc<RwLock<HashMap<Uuid, Job>>>,
+    artifacts: Arc<RwLock<HashMap<Uuid, StoredArtifact>>>,
+    events: broadcast::Sender<Job>,
+    outputs: PathBuf,
+    _outputs_lock: Option<File>,
+}
+
+/// Handed to the job work to report how far it got
+#[derive(Clone)]
+pub struct JobProgress {
+    id: Uuid,
+    jobs: Arc<RwLock<HashMap<Uuid, Job>>>,
+    events: broadcast::Sender<Job>,
+    last_sent: f64,
+}
+
+impl JobProgress {
+    pub fn set(&mut self, progress: f64) {
+        let progress = progress.clamp(0.0, 1.0);
+        if progress - self.last_sent < PROGRESS_STEP {
+            return;
+        }
+        self.last_sent = progress;
+        update_job(&self.jobs, &self.events, self.id, |job| {
+            job.progress = progress
+        });
+    }
+}
+
+fn update_job(
+    jobs: &RwLock<HashMap<Uuid, Job>>,
+    events: &broadcast::Sender<Job>,
+    id: Uuid,
+    update: impl FnOnce(&mut Job),
+) {
+    let job = {
+        let mut jobs = jobs.write().unwrap();
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+        update(job);
+        job.clone()
+    };
+    // No subscribers is not an error, jobs still run without anyone watching
+    let _ = events.send(job);
+}
+
+impl JobManager {
+    pub fn new(workers: usize) -> Self {
+        Self::with_outputs(workers, &std::env::temp_dir().join(OUTPUTS_DIRECTORY))
+    }
+
+    /// Outputs left below `root` by processes that are gone are removed first
+    pub fn with_outputs(workers: usize, root: &Path) -> Self {
+        info!("JobManager: Running recording jobs with {workers} workers");
+        sweep_outputs(root);
+        let outputs = root.join(Uuid::new_v4().to_string());
+        let outputs_lock = lock_outputs(&outputs)
+            .inspect_err(|err| warn!("JobManager: Failed to lock job outputs {outputs:?}: {err}"))
+            .ok();
+        let (events, _) = broadcast::channel(100);
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            jobs: Arc::new(RwLock::new(HashMap::new())),
+            artifacts: Arc::new(RwLock::new(HashMap::new())),
+            events,
+            outputs,
+            _outputs_lock: outputs_lock,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Job> {
+        self.events.subscribe()
+    }
+
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.read().unwrap().values().cloned().collect();
+        jobs.sort_by_key(|job| job.created);
+        jobs
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.jobs.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn artifact(&self, id: Uuid) -> Option<StoredArtifact> {
+        self.artifacts.read().unwrap().get(&id).cloned()
+    }
+
+    /// Queue the work without a waiting requester, its output is fetched later by job id
+    pub fn submit_detached<F>(&self, kind: JobKind, file_name: &str, work: F) -> Job
+    where
+        F: FnOnce(&mut JobProgress) -> Result<JobArtifact, ManagerError> + Send + 'static,
+    {
+        let artifacts = self.artifacts.clone();
+        let outputs = self.outputs.clone();
+        // Stored before the job reports completion, so a finished job always has its output,
+        // on disk since up to MAX_FINISHED_JOBS outputs are kept
+        let (id, _) = self.submit(kind, file_name, move |progress| {
+            let artifact = StoredArtifact::write(&outputs, progress.id, work(progress)?)?;
+            artifacts.write().unwrap().insert(progress.id, artifact);
+            Ok(())
+        });
+        self.get(id).expect("Job was just inserted")
+    }
+
+    /// Queue the work on the pool, the result is delivered on the returned receiver
+    pub fn submit<T, F>(
+        &self,
+        kind: JobKind,
+        file_name: &str,
+        work: F,
+    ) -> (Uuid, oneshot::Receiver<Result<T, ManagerError>>)
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut JobProgress) -> Result<T, ManagerError> + Send + 'static,
+    {
+        let job = Job {
+            id: Uuid::new_v4(),
+            kind,
+            file_name: file_name.to_string(),
+            status: JobStatus::Queued,
+            progress: 0.0,
+            created: chrono::Utc::now(),
+            finished: None,
+        };
+        let id = job.id;
+        debug!("JobManager: Queued job {job:?}");
+
+        {
+            let mut jobs = self.jobs.write().unwrap();
+            for pruned in prune_finished(&mut jobs) {
+                if let Some(artifact) = self.artifacts.write().unwrap().remove(&pruned) {
+                    artifact.remove();
+                }
+            }
+            jobs.insert(id, job.clone());
+        }
+        let _ = self.events.send(job);
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        let workers = self.workers.clone();
+        let jobs = self.jobs.clone();
+        let events = self.events.clone();
+
+        tokio::spawn(async move {
+            let Ok(_permit) = workers.acquire_owned().await else {
+                error!("JobManager: Worker pool is closed, dropping job {id}");
+                return;
+            };
+            update_job(&jobs, &events, id, |job| job.status = JobStatus::Running);
+
+            let mut progress = JobProgress {
+                id,
+                jobs: jobs.clone(),
+                events: events.clone(),
+                last_sent: 0.0,
+            };
+            let result = match tokio::task::spawn_blocking(move || work(&mut progress)).await {
+                Ok(result) => result,
+                Err(err) => Err(ManagerError::Other(format!("Job {id} panicked: {err}"))),
+            };
+
+            update_job(&jobs, &events, id, |job| {
+                job.finished = Some(chrono::Utc::now());
+                match &result {
+                    Ok(_) => {
+                        job.progress = 1.0;
+                        job.status = JobStatus::Completed;
+                    }
+                    Err(err) => job.status = JobStatus::Failed(format!("{err:?}")),
+                }
+            });
+
+            if result_sender.send(result).is_err() {
+                debug!("JobManager: Job {id} finished after its requester went away");
+            }
+        });
+
+        (id, result_receiver)
+    }
+}
+
+fn lock_outputs(directory: &Path) -> std::io::Result<File> {
+    std::fs::create_dir_all(directory)?;
+    let lock = File::create(directory.join(OUTPUTS_LOCK))?;
+    lock.try_lock()?;
+    Ok(lock)
+}
+
+// Jobs do not survive a restart, the outputs of a process are removed once its lock is free. Directories of
+// running processes stay locked, as well as ones just created and not locked yet
+fn sweep_outputs(root: &Path) {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let removed = if path.is_dir() {
+            let Ok(lock) = File::open(path.join(OUTPUTS_LOCK)) else {
+                continue;
+            };
+            if lock.try_lock().is_err() {
+                continue;
+            }
+            drop(lock);
+            std::fs::remove_dir_all(&path)
+        } else {
+            // Outputs of versions writing every process into the same directory
+            std::fs::remove_file(&path)
+        };
+        match removed {
+            Ok(()) => debug!("JobManager: Removed job outputs {path:?} of a previous run"),
+            Err(err) => warn!("JobManager: Failed to remove job outputs {path:?}: {err}"),
+        }
+    }
+}
+
+// Returns the removed jobs so their artifacts go with them
+fn prune_finished(jobs: &mut HashMap<Uuid, Job>) -> Vec<Uuid> {
+    let mut finished: Vec<(chrono::DateTime<chrono::Utc>, Uuid)> = jobs
+        .values()
+        .filter_map(|job| job.finished.map(|finished| (finished, job.id)))
+        .collect();
+    if finished.len() < MAX_FINISHED_JOBS {
+        return Vec::new();
+    }
+    finished.sort();
+    let pruned: Vec<Uuid> = finished
+        .iter()
+        .take(finished.len() + 1 - MAX_FINISHED_JOBS)
+        .map(|(_, id)| *id)
+        .collect();
+    for id in &pruned {
+        jobs.remove(id);
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_reports_progress_and_result() {
+        let manager = JobManager::new(1);
+        let mut events = manager.subscribe();
+
+        let (id, result) = manager.submit(JobKind::SurveyReport, "test.mcap", |progress| {
+            progress.set(0.5);
+            Ok(42)
+        });
+        assert_eq!(result.await.unwrap().unwrap(), 42);
+
+        let mut states = Vec::new();
+        while let Ok(job) = events.try_recv() {
+            assert_eq!(job.id, id);
+            states.push((job.status, job.progress));
+        }
+        assert_eq!(states.first().unwrap().0, JobStatus::Queued);
+        assert!(states.contains(&(JobStatus::Running, 0.5)));
+        assert_eq!(states.last().unwrap(), &(JobStatus::Completed, 1.0));
+    }
+
+    #[tokio::test]
+    async fn test_detached_job_keeps_its_artifact() {
+        let manager = JobManager::new(1);
+        let mut events = manager.subscribe();
+
+        let job = manager.submit_detached(JobKind::Export, "test.mcap", |_| {
+            Ok(JobArtifact {
+                file_name: "test.jsonl".to_string(),
+                content_type: "application/x-ndjson".to_string(),
+                data: b"{}".to_vec(),
+            })
+        });
+        while let Ok(update) = events.recv().await {
+            if update.status.is_finished() {
+                assert_eq!(update.status, JobStatus::Completed);
+                break;
+            }
+        }
+        let artifact = manager.artifact(job.id).unwrap();
+        assert_eq!(artifact.file_name, "test.jsonl");
+        assert_eq!(std::fs::read(&artifact.path).unwrap(), b"{}");
+        artifact.remove();
+    }
+
+    #[test]
+    fn test_outputs_of_previous_runs_are_removed() {
+        let root = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        let stale = root.join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&stale).unwrap();
+        std::fs::write(stale.join(OUTPUTS_LOCK), b"").unwrap();
+        std::fs::write(stale.join(Uuid::new_v4().to_string()), b"{}").unwrap();
+        let flat = root.join(Uuid::new_v4().to_string());
+        std::fs::write(&flat, b"{}").unwrap();
+
+        let running = JobManager::with_outputs(1, &root);
+        assert!(!stale.exists());
+        assert!(!flat.exists());
+        assert!(running.outputs.exists());
+
+        // A second process leaves the outputs of the running one alone
+        let second = JobManager::with_outputs(1, &root);
+        assert!(running.outputs.exists());
+        assert!(second.outputs.exists());
+
+        drop(running);
+        drop(second);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
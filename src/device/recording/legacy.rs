@@ -0,0 +1,416 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use bluerobotics_ping::decoder::{Decoder, DecoderResult};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use foxglove::{schemas::Timestamp, Context};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::device::manager::ManagerError;
+
+use super::{
+    decode_sonar_message, writer::McapFileWriter, RecordingChannels, RecordingFormat, VehicleScope,
+};
+
+const LOG_TITLE: &str = "PingViewer sensor log file";
+// QDataStream marks null strings and byte arrays with this length
+const NULL_LENGTH: u32 = u32::MAX;
+// Packs are stamped with the time of day only, `hh:mm:ss.zzz`
+const PACK_TIME_FORMAT: &str = "%H:%M:%S%.3f";
+// Data kept after the current offset, far more than a pack, so a damaged pack is not mistaken for one
+// that was not read yet
+const LOG_WINDOW_MARGIN: usize = 512 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ImportReport {
+    /// The MCAP file written next to the source, relative to its directory
+    pub file_name: String,
+    pub device_id: Uuid,
+    pub messages: u64,
+    pub skipped_bytes: u64,
+}
+
+/// Header written by the classic Ping-Viewer before the sensor packs
+#[derive(Debug, Default)]
+struct LegacyHeader {
+    version: i32,
+    hash: String,
+    date: String,
+    tag: String,
+}
+
+/// Big endian QDataStream reader, enough for the strings, integers and byte arrays of the log
+struct QtReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> QtReader<'a> {
+    fn i32(&mut self) -> Option<i32> {
+        let bytes = self.data.get(self.offset..self.offset + 4)?;
+        self.offset += 4;
+        Some(i32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn byte_array(&mut self) -> Option<&'a [u8]> {
+        let length = self.i32()? as u32;
+        if length == NULL_LENGTH {
+            return Some(&[]);
+        }
+        let bytes = self.data.get(self.offset..self.offset + length as usize)?;
+        self.offset += length as usize;
+        Some(bytes)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let bytes = self.byte_array()?;
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect();
+        String::from_utf16(&units).ok()
+    }
+
+    // One `(time, ping-protocol bytes)` pack, None leaves the offset untouched
+    fn pack(&mut self) -> Option<(NaiveTime, &'a [u8])> {
+        let start = self.offset;
+        let pack = self.read_pack();
+        if pack.is_none() {
+            self.offset = start;
+        }
+        pack
+    }
+
+    fn read_pack(&mut self) -> Option<(NaiveTime, &'a [u8])> {
+        let time = NaiveTime::parse_from_str(&self.string()?, PACK_TIME_FORMAT).ok()?;
+        let data = self.byte_array()?;
+        data.starts_with(b"BR").then_some((time, data))
+    }
+}
+
+/// Reads the log in windows, packs are small and the log of a long dive does not fit in memory
+struct LogWindow<R> {
+    source: R,
+    data: Vec<u8>,
+    offset: usize,
+    // Bytes dropped from the start of the window
+    discarded: u64,
+    finished: bool,
+    skipped: u64,
+}
+
+impl<R: Read> LogWindow<R> {
+    fn new(source: R) -> Self {
+        Self {
+            source,
+            data: Vec::new(),
+            offset: 0,
+            discarded: 0,
+            finished: false,
+            skipped: 0,
+        }
+    }
+
+    // Keep at least `LOG_WINDOW_MARGIN` bytes after the offset, unless the log ends first
+    fn fill(&mut self) -> std::io::Result<()> {
+        if self.finished || self.data.len() - self.offset >= LOG_WINDOW_MARGIN {
+            return Ok(());
+        }
+        self.data.drain(..self.offset);
+        self.discarded += self.offset as u64;
+        self.offset = 0;
+
+        let wanted = 2 * LOG_WINDOW_MARGIN - self.data.len();
+        let read = (&mut self.source)
+            .take(wanted as u64)
+            .read_to_end(&mut self.data)?;
+        self.finished = read < wanted;
+        Ok(())
+    }
+
+    /// Bytes of the log read so far
+    fn position(&self) -> u64 {
+        self.discarded + self.offset as u64
+    }
+
+    fn header(&mut self) -> Result<LegacyHeader, ManagerError> {
+        self.fill()
+            .map_err(|err| ManagerError::Other(format!("Failed to read log: {err}")))?;
+        let mut reader = QtReader {
+            data: &self.data,
+            offset: self.offset,
+        };
+        let header = read_header(&mut reader)?;
+        self.offset = reader.offset;
+        Ok(header)
+    }
+
+    /// Next pack, resynchronizing byte by byte after unknown or damaged data. None at the end of the log
+    fn next_pack(&mut self) -> std::io::Result<Option<(NaiveTime, Vec<u8>)>> {
+        loop {
+            self.fill()?;
+            if self.offset >= self.data.len() {
+                return Ok(None);
+            }
+            let mut reader = QtReader {
+                data: &self.data,
+                offset: self.offset,
+            };
+            match reader.pack() {
+                Some((time, frames)) => {
+                    let frames = frames.to_vec();
+                    self.offset = reader.offset;
+                    return Ok(Some((time, frames)));
+                }
+                None => {
+                    self.offset += 1;
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+fn read_header(reader: &mut QtReader) -> Result<LegacyHeader, ManagerError> {
+    if reader.string().as_deref() != Some(LOG_TITLE) {
+        return Err(ManagerError::Other(
+            "Not a Ping-Viewer sensor log".to_string(),
+        ));
+    }
+    // Newer versions append sensor details, packs are found by resynchronizing after these
+    Ok(read_header_fields(reader).unwrap_or_default())
+}
+
+fn read_header_fields(reader: &mut QtReader) -> Option<LegacyHeader> {
+    Some(LegacyHeader {
+        version: reader.i32()?,
+        hash: reader.string()?,
+        date: reader.string()?,
+        tag: reader.string()?,
+    })
+}
+
+// The header date is written by Qt, fall back to the file time when it cannot be read
+fn log_date(header: &LegacyHeader, source: &Path) -> NaiveDate {
+    DateTime::parse_from_rfc3339(&header.date)
+        .map(|date| date.date_naive())
+        .or_else(|_| {
+            chrono::NaiveDateTime::parse_from_str(&header.date, "%Y-%m-%dT%H:%M:%S")
+                .map(|date| date.date())
+        })
+        .unwrap_or_else(|_| {
+            std::fs::metadata(source)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| DateTime::<Utc>::from(modified).date_naive())
+                .unwrap_or_else(|_| Utc::now().date_naive())
+        })
+}
+
+/// Path of the MCAP written for a legacy log, `survey.bin` becomes `survey.mcap`
+pub fn output_path(source: &Path, directory: &Path) -> PathBuf {
+    let stem = source
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "legacy".to_string());
+    directory.join(format!("{stem}.mcap"))
+}
+
+/// Convert a classic Ping-Viewer `.bin` log into an MCAP with the live recording channel layout
+pub fn import(
+    source: &Path,
+    directory: &Path,
+    mut progress: impl FnMut(f64),
+) -> Result<ImportReport, ManagerError> {
+    let read_error =
+        |err: std::io::Error| ManagerError::Other(format!("Failed to read {source:?}: {err}"));
+    let file = std::fs::File::open(source).map_err(read_error)?;
+    let size = file.metadata().map_err(read_error)?.len();
+    let mut log = LogWindow::new(file);
+    let header = log.header()?;
+    debug!("Import: {source:?} has header {header:?}");
+
+    // Stable id, importing the same log twice lands on the same topics
+    let file_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let device_id = Uuid::new_v5(&Uuid::NAMESPACE_OID, file_name.as_bytes());
+    let output = output_path(source, directory);
+
+    let ctx = Context::new();
+    let writer = McapFileWriter::create(&ctx, &output, foxglove::McapWriteOptions::default())
+        .map_err(|err| ManagerError::Other(format!("Failed to create MCAP file: {err}")))?;
+
+    let import = BTreeMap::from([
+        ("source".to_string(), file_name.clone()),
+        ("legacy_version".to_string(), header.version.to_string()),
+        ("legacy_hash".to_string(), header.hash.clone()),
+        ("legacy_date".to_string(), header.date.clone()),
+        ("legacy_tag".to_string(), header.tag.clone()),
+        (
+            "software".to_string(),
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+    ]);
+    if let Err(err) = writer.write_metadata("import", import) {
+        warn!("Import: Failed to write metadata for {source:?}: {err:?}");
+    }
+
+    let channels = match RecordingChannels::new(
+        &ctx,
+        device_id,
+        RecordingFormat::Mcap,
+        false,
+        Some(VehicleScope::Device),
+    ) {
+        Ok(channels) => channels,
+        Err(err) => {
+            let _ = writer.close();
+            return Err(err);
+        }
+    };
+
+    let mut date = log_date(&header, source);
+    let mut previous_time: Option<NaiveTime> = None;
+    let mut messages = 0;
+    while let Some((time, frames)) = log.next_pack().map_err(read_error)? {
+        progress(log.position() as f64 / size.max(1) as f64);
+
+        // Only the time of day is stored, a jump backwards means the log went past midnight
+        if previous_time
+            .is_some_and(|previous| previous.signed_duration_since(time) > TimeDelta::hours(12))
+        {
+            date = date.succ_opt().unwrap_or(date);
+        }
+        previous_time = Some(time);
+        let time = date.and_time(time).and_utc();
+        let timestamp = Timestamp::new(time.timestamp() as u32, time.timestamp_subsec_nanos());
+
+        let mut decoder = Decoder::new();
+        for byte in &frames {
+            if let DecoderResult::Success(message) = decoder.parse_byte(*byte) {
+                if decode_sonar_message(&message).is_some() {
+                    channels.log_message(&message, timestamp);
+                    messages += 1;
+                }
+            }
+        }
+    }
+
+    writer
+        .close()
+        .map_err(|err| ManagerError::Other(format!("Failed to close MCAP writer: {err}")))?;
+    info!("Import: {source:?} converted into {output:?} with {messages} messages");
+
+    Ok(ImportReport {
+        file_name: output
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        device_id,
+        messages,
+        skipped_bytes: log.skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qt_string(value: &str) -> Vec<u8> {
+        let units: Vec<u8> = value.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut bytes = (units.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(units);
+        bytes
+    }
+
+    fn qt_bytes(value: &[u8]) -> Vec<u8> {
+        let mut bytes = (value.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(value);
+        bytes
+    }
+
+    #[test]
+    fn test_read_header_and_resync_on_packs() {
+        let mut data = qt_string(LOG_TITLE);
+        data.extend(2i32.to_be_bytes());
+        for field in ["abc123", "2020-05-04T10:00:00", "dive"] {
+            data.extend(qt_string(field));
+        }
+        // Sensor details of newer versions, unknown to the reader
+        data.extend([0, 0, 0, 1, 0, 0, 0, 2, 7]);
+        data.extend(qt_string("10:00:01.250"));
+        data.extend(qt_bytes(b"BRtest"));
+
+        let mut reader = QtReader {
+            data: &data,
+            offset: 0,
+        };
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.date, "2020-05-04T10:00:00");
+
+        let mut skipped = 0;
+        let pack = loop {
+            if let Some(pack) = reader.pack() {
+                break pack;
+            }
+            reader.offset += 1;
+            skipped += 1;
+        };
+        assert_eq!(skipped, 9);
+        assert_eq!(
+            pack.0,
+            NaiveTime::from_hms_milli_opt(10, 0, 1, 250).unwrap()
+        );
+        assert_eq!(pack.1, b"BRtest");
+    }
+
+    #[test]
+    fn test_packs_are_read_across_windows() {
+        let mut data = qt_string(LOG_TITLE);
+        data.extend(2i32.to_be_bytes());
+        for field in ["abc123", "2020-05-04T10:00:00", "dive"] {
+            data.extend(qt_string(field));
+        }
+        let mut frames = b"BR".to_vec();
+        frames.resize(1000, 0);
+        let packs = 3 * LOG_WINDOW_MARGIN / frames.len();
+        for _ in 0..packs {
+            data.extend(qt_string("10:00:01.250"));
+            data.extend(qt_bytes(&frames));
+        }
+        // A pack cut by the end of the log
+        data.extend(qt_string("10:00:02.000"));
+
+        let mut log = LogWindow::new(std::io::Cursor::new(&data));
+        assert_eq!(log.header().unwrap().tag, "dive");
+        let mut read = 0;
+        while let Some((_, pack)) = log.next_pack().unwrap() {
+            assert_eq!(pack, frames);
+            read += 1;
+        }
+        assert_eq!(read, packs);
+        assert_eq!(log.skipped, qt_string("10:00:02.000").len() as u64);
+        assert_eq!(log.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let data = qt_string("Something else");
+        let mut reader = QtReader {
+            data: &data,
+            offset: 0,
+        };
+        assert!(read_header(&mut reader).is_err());
+    }
+}
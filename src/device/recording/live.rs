@@ -0,0 +1,131 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use foxglove::Context;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::device::manager::{DeviceStatus, ManagerActorHandler, ManagerError};
+use crate::vehicle::history;
+
+use super::{device_subscriber, timestamp_of, RecordingChannels, RecordingFormat, VehicleScope};
+
+// How often the devices to publish are refreshed from the DeviceManager
+const LIVE_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+
+/// Publishes the recording channels of every streaming device over the Foxglove WebSocket protocol
+pub struct LiveServer {
+    ctx: Arc<Context>,
+    devices_manager_handler: ManagerActorHandler,
+    publishers: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl LiveServer {
+    pub fn new(devices_manager_handler: ManagerActorHandler) -> Self {
+        Self {
+            ctx: Context::new(),
+            devices_manager_handler,
+            publishers: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self, address: SocketAddr) -> Result<(), ManagerError> {
+        // Dropping the handle stops the server, it lives as long as this loop
+        let _server = foxglove::WebSocketServer::new()
+            .name(env!("CARGO_PKG_NAME"))
+            .bind(address.ip().to_string(), address.port())
+            .context(&self.ctx)
+            .start()
+            .await
+            .map_err(|err| {
+                ManagerError::Other(format!(
+                    "Failed to start Foxglove WebSocket server on {address}: {err}"
+                ))
+            })?;
+        info!("LiveServer: Foxglove WebSocket server listening on ws://{address}");
+
+        let mut refresh_interval = tokio::time::interval(LIVE_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_publishers().await;
+        }
+    }
+
+    async fn refresh_publishers(&mut self) {
+        let streaming: Vec<Uuid> = match self
+            .devices_manager_handler
+            .send(crate::device::manager::Request::List)
+            .await
+        {
+            Ok(crate::device::manager::Answer::DeviceInfo(devices)) => devices
+                .into_iter()
+                .filter(|device| {
+                    matches!(
+                        device.status,
+                        DeviceStatus::Running | DeviceStatus::ContinuousMode
+                    )
+                })
+                .map(|device| device.id)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        self.publishers.retain(|device_id, publisher| {
+            let keep = streaming.contains(device_id) && !publisher.is_finished();
+            if !keep {
+                publisher.abort();
+            }
+            keep
+        });
+
+        for device_id in streaming {
+            if self.publishers.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver =
+                match device_subscriber(&self.devices_manager_handler, device_id).await {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        warn!("LiveServer: Failed to subscribe to device {device_id}: {err:?}");
+                        continue;
+                    }
+                };
+            let channels = match RecordingChannels::new(
+                &self.ctx,
+                device_id,
+                RecordingFormat::Mcap,
+                false,
+                Some(VehicleScope::Device),
+            ) {
+                Ok(channels) => channels,
+                Err(err) => {
+                    error!("LiveServer: Failed to create channels of {device_id}: {err:?}");
+                    continue;
+                }
+            };
+
+            let publisher = tokio::spawn(async move {
+                debug!("LiveServer: Publishing device {device_id}");
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            let now = chrono::Utc::now();
+                            let timestamp = timestamp_of(now);
+                            channels.log_message(&msg, timestamp);
+                            if let Some(vehicle) = history::pose_at(now.timestamp_millis()) {
+                                channels.log_vehicle(&vehicle, timestamp);
+                            }
+                        }
+                        // Live viewers prefer fresh data over a complete history
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("LiveServer: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+                debug!("LiveServer: Stopped publishing device {device_id}");
+            });
+            self.publishers.insert(device_id, publisher);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::{collections::HashMap, sync::Arc};
+
+use foxglove::{Context, RawChannel};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::warn;
+
+use crate::vehicle::stream::{self, MavlinkSample};
+
+/// Next message of the stream, never ready without one
+pub async fn recv(
+    stream: &mut Option<Receiver<MavlinkSample>>,
+) -> Result<MavlinkSample, RecvError> {
+    match stream {
+        Some(stream) => stream.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Vehicle telemetry of a session, one schemaless JSON channel per MAVLink message under `<prefix>/MAVLink/`
+pub struct MavlinkChannels {
+    ctx: Arc<Context>,
+    prefix: String,
+    topics: Vec<String>,
+    // Created on the first message, so topics the vehicle never sends leave no empty channels
+    channels: HashMap<String, Arc<RawChannel>>,
+}
+
+impl MavlinkChannels {
+    /// `device_<id>` for a single device recording, `vehicle` for a group file
+    pub fn new(ctx: &Arc<Context>, prefix: String, topics: Vec<String>) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            prefix,
+            topics,
+            channels: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    pub fn log(&mut self, sample: &MavlinkSample) {
+        if !stream::matches(&self.topics, &sample.name) {
+            return;
+        }
+        let channel = match self.channels.get(&sample.name) {
+            Some(channel) => channel.clone(),
+            None => {
+                let topic = format!("{}/MAVLink/{}", self.prefix, sample.name);
+                match self
+                    .ctx
+                    .channel_builder(&topic)
+                    .message_encoding("json")
+                    .build_raw()
+                {
+                    Ok(channel) => {
+                        self.channels.insert(sample.name.clone(), channel.clone());
+                        channel
+                    }
+                    Err(err) => {
+                        warn!("Failed to create MAVLink channel {topic}: {err}");
+                        // Only tried once, the vehicle keeps sending the message
+                        self.topics
+                            .retain(|topic| !topic.eq_ignore_ascii_case(&sample.name));
+                        return;
+                    }
+                }
+            }
+        };
+        let payload = match serde_json::to_vec(&sample.message) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Failed to encode MAVLink {}: {err}", sample.name);
+                return;
+            }
+        };
+        channel.log_with_meta(
+            &payload,
+            foxglove::PartialMetadata::with_log_time(sample.timestamp_ms as u64 * 1_000_000),
+        );
+    }
+}
@@ -1,43 +1,440 @@
 use bluerobotics_ping::{ping1d::ProfileStruct, ping360::AutoDeviceDataStruct};
 use foxglove::Context;
-use foxglove::McapWriterHandle;
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::BufWriter;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::sync::oneshot;
 use tokio::sync::{
     broadcast::{self, Receiver},
     mpsc, RwLock,
 };
-use tracing::{error, info, trace, warn};
+use tracing::{debug, error, info, trace, warn, Instrument};
 use uuid::Uuid;
 
 use crate::device::{
-    devices::DeviceActorHandler,
-    manager::{DeviceSelection, ManagerError},
+    devices::{DeviceActorHandler, Ping1DRequest, PingRequest},
+    manager::{DeviceInfo, DeviceSelection, DeviceStatus, ManagerError},
 };
-use crate::vehicle::VehicleData;
+use crate::vehicle::{clock::VehicleClock, history, VehicleData};
 
 use super::manager::{ManagerActorHandler, UuidWrapper};
+use writer::McapFileWriter;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Specially for downloading several recordings as one zip
+pub mod archive;
+/// Specially for device events written into recordings
+pub mod events;
+/// Specially for exporting recordings to CSV and JSON Lines
+pub mod export;
+/// Specially for notifying clients of changes in the recordings directory
+pub mod files;
+/// Specially for locating the sonar returns with the vehicle pose
+pub mod georeference;
+/// Specially for summarizing recordings without downloading them
+pub mod info;
+/// Specially for long running conversions of recordings, bounded worker pool with progress events
+pub mod jobs;
+/// Specially for importing classic Ping-Viewer `.bin` logs
+pub mod legacy;
+/// Specially for live visualization of the recording channels in Foxglove
+pub mod live;
+/// Specially for complete vehicle telemetry next to the sonar data, with the raw MAVLink messages of the bridge
+pub mod mavlink;
+/// Specially for the sonar mounting pose and the frame transforms of recordings
+pub mod mounting;
+/// Specially for chartplotters, serving the Ping1D depths as NMEA 0183 sentences
+pub mod nmea;
+/// Specially for keeping the seconds before a recording starts
+pub mod pre_trigger;
+/// Specially for reading recordings without loading them in memory
+pub mod reader;
+/// Specially for finalizing recordings left open by a crash
+pub mod recovery;
+/// Specially for replaying recordings through virtual devices
+pub mod replay;
+/// Specially for survey report generation from recorded sessions
+pub mod report;
+/// Specially for keeping the recordings directory within its disk budget
+pub mod retention;
+/// Specially for writing sessions as ROS 2 bags
+pub mod ros2;
+/// Specially for Ping360 displays that stay still while the vehicle turns, with world referenced beam angles
+pub mod stabilized;
+/// Specially for forwarding live device messages to a UDP destination
+pub mod udp;
+/// Specially for pushing recordings to S3-compatible or WebDAV storage
+#[cfg(feature = "upload")]
+pub mod upload;
+/// Specially for MCAP files with metadata records, bookmarks and session details
+pub mod writer;
+/// Specially for publishing live device messages onto the vehicle Zenoh bridge
+pub mod zenoh_output;
+
+pub use ros2::RecordingFormat;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
 pub struct RecordingSession {
     pub device_id: Uuid,
     pub file_path: PathBuf,
     pub is_active: bool,
     pub start_time: chrono::DateTime<chrono::Utc>,
     pub device_type: DeviceSelection,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub format: RecordingFormat,
+    /// Shared by the sessions recorded into the same file, see `StartRecordingGroup`
+    #[serde(default)]
+    pub group_id: Option<Uuid>,
+    /// Points of interest marked while recording, RFC 3339 times
+    #[serde(default)]
+    pub bookmarks: Vec<String>,
 }
 
+/// One file can hold several devices, it is closed by whichever of them stops first
+pub type SharedWriter = Arc<std::sync::Mutex<Option<McapFileWriter>>>;
+
 pub struct SessionGuard {
     pub session: RecordingSession,
-    pub writer: Option<McapWriterHandle<BufWriter<File>>>,
+    pub writer: SharedWriter,
+    /// Written again at the start of every rotated file
+    pub metadata: Vec<(String, BTreeMap<String, String>)>,
+    pub annotations: NoteChannel<Annotation>,
+    pub events: NoteChannel<events::RecordingEvent>,
+}
+
+/// Notes written next to the device data, JSON channels or `std_msgs/String` in ROS 2 recordings
+pub enum NoteChannel<T: foxglove::Encode> {
+    Json(foxglove::Channel<T>),
+    Ros2(Arc<foxglove::RawChannel>),
+}
+
+impl<T: foxglove::Encode> NoteChannel<T> {
+    fn new(
+        ctx: &Arc<Context>,
+        device_id: Uuid,
+        format: RecordingFormat,
+        name: &str,
+    ) -> Result<Self, ManagerError> {
+        Ok(match format {
+            RecordingFormat::Mcap => NoteChannel::Json(
+                ctx.channel_builder(&format!("device_{}/{}", device_id, name))
+                    .build::<T>(),
+            ),
+            RecordingFormat::Ros2 => {
+                NoteChannel::Ros2(ros2::string_channel(ctx, device_id, &name.to_lowercase())?)
+            }
+        })
+    }
+
+    fn log(&self, note: &T, text: &str, timestamp: foxglove::schemas::Timestamp) {
+        match self {
+            NoteChannel::Json(channel) => channel.log_with_time(note, timestamp),
+            NoteChannel::Ros2(channel) => ros2::log_string(channel, text, timestamp),
+        }
+    }
+}
+
+/// Which session of a file logs the vehicle channels, one vehicle is shared by every device of a group
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VehicleScope {
+    /// Under the `device_<id>/` topics of a single device recording
+    Device,
+    /// Once under the `vehicle/` topics of a group file
+    Group,
+}
+
+impl VehicleScope {
+    // First device of a group logs the vehicle for all of them
+    fn of(group: bool, index: usize) -> Option<Self> {
+        match (group, index) {
+            (false, _) => Some(VehicleScope::Device),
+            (true, 0) => Some(VehicleScope::Group),
+            (true, _) => None,
+        }
+    }
+
+    fn prefix(self, device_id: Uuid, format: RecordingFormat) -> String {
+        match (self, format) {
+            (VehicleScope::Group, _) => mounting::VEHICLE_FRAME.to_string(),
+            (VehicleScope::Device, RecordingFormat::Mcap) => format!("device_{device_id}"),
+            (VehicleScope::Device, RecordingFormat::Ros2) => ros2::frame_id(device_id),
+        }
+    }
+}
+
+struct VehicleChannels {
+    vehicle: foxglove::Channel<VehicleData>,
+    location: foxglove::Channel<foxglove::schemas::LocationFix>,
+    clock: foxglove::Channel<VehicleClock>,
+}
+
+// Channels a session writes the device stream to, in the layout of its format
+enum RecordingChannels {
+    Json {
+        ping1d: foxglove::Channel<ProfileStruct>,
+        ping360: foxglove::Channel<AutoDeviceDataStruct>,
+        vehicle: Option<VehicleChannels>,
+        georeferenced: foxglove::Channel<georeference::GeoreferencedSample>,
+        stabilized: foxglove::Channel<stabilized::StabilizedBeam>,
+        raw: Option<foxglove::Channel<RawFrame>>,
+    },
+    Ros2(ros2::Ros2Channels),
+}
+
+impl RecordingChannels {
+    fn new(
+        ctx: &Arc<Context>,
+        device_id: Uuid,
+        format: RecordingFormat,
+        raw_frames: bool,
+        vehicle: Option<VehicleScope>,
+    ) -> Result<Self, ManagerError> {
+        let vehicle_prefix = vehicle.map(|scope| scope.prefix(device_id, format));
+        match format {
+            RecordingFormat::Mcap => Ok(RecordingChannels::Json {
+                ping1d: ctx
+                    .channel_builder(&format!("device_{}/Ping1D", device_id))
+                    .build::<ProfileStruct>(),
+                ping360: ctx
+                    .channel_builder(&format!("device_{}/Ping360", device_id))
+                    .build::<AutoDeviceDataStruct>(),
+                vehicle: vehicle_prefix.map(|prefix| VehicleChannels {
+                    vehicle: ctx
+                        .channel_builder(&format!("{prefix}/VehicleData"))
+                        .build::<VehicleData>(),
+                    location: ctx
+                        .channel_builder(&format!("{prefix}/Location"))
+                        .build::<foxglove::schemas::LocationFix>(),
+                    clock: ctx
+                        .channel_builder(&format!("{prefix}/VehicleClock"))
+                        .build::<VehicleClock>(),
+                }),
+                georeferenced: ctx
+                    .channel_builder(&format!("device_{}/Georeferenced", device_id))
+                    .build::<georeference::GeoreferencedSample>(),
+                stabilized: ctx
+                    .channel_builder(&format!("device_{}/Ping360Stabilized", device_id))
+                    .build::<stabilized::StabilizedBeam>(),
+                raw: raw_frames.then(|| {
+                    ctx.channel_builder(&format!("device_{}/Raw", device_id))
+                        .build::<RawFrame>()
+                }),
+            }),
+            RecordingFormat::Ros2 => {
+                if raw_frames {
+                    warn!("Raw frames are not written to ROS 2 recordings of {device_id}");
+                }
+                Ok(RecordingChannels::Ros2(ros2::Ros2Channels::new(
+                    ctx,
+                    device_id,
+                    vehicle_prefix.as_deref(),
+                )?))
+            }
+        }
+    }
+
+    fn log_message(
+        &self,
+        msg: &bluerobotics_ping::message::ProtocolMessage,
+        timestamp: foxglove::schemas::Timestamp,
+    ) {
+        match self {
+            RecordingChannels::Json {
+                ping1d,
+                ping360,
+                raw,
+                ..
+            } => {
+                if let Some(raw) = raw {
+                    log_raw_frame(raw, msg, timestamp);
+                }
+                match decode_sonar_message(msg) {
+                    Some(SonarData::Ping1D(profile)) => ping1d.log_with_time(&profile, timestamp),
+                    Some(SonarData::Ping360(data)) => ping360.log_with_time(&data, timestamp),
+                    None => {}
+                }
+            }
+            RecordingChannels::Ros2(channels) => match decode_sonar_message(msg) {
+                Some(SonarData::Ping1D(profile)) => channels.log_ping1d(&profile, timestamp),
+                Some(SonarData::Ping360(data)) => channels.log_ping360(&data, timestamp),
+                None => {}
+            },
+        }
+    }
+
+    fn log_vehicle(&self, vehicle_data: &VehicleData, timestamp: foxglove::schemas::Timestamp) {
+        match self {
+            RecordingChannels::Json {
+                vehicle: Some(channels),
+                ..
+            } => {
+                channels.vehicle.log_with_time(vehicle_data, timestamp);
+                if let Some(fix) = location_fix(vehicle_data, timestamp) {
+                    channels.location.log_with_time(&fix, timestamp);
+                }
+            }
+            RecordingChannels::Json { vehicle: None, .. } => {}
+            RecordingChannels::Ros2(channels) => channels.log_vehicle(vehicle_data, timestamp),
+        }
+    }
+
+    fn log_georeferenced(
+        &self,
+        sample: &georeference::GeoreferencedSample,
+        timestamp: foxglove::schemas::Timestamp,
+    ) {
+        match self {
+            RecordingChannels::Json { georeferenced, .. } => {
+                georeferenced.log_with_time(sample, timestamp)
+            }
+            // No standard ROS 2 message fits, the NavSatFix of the vehicle is written instead
+            RecordingChannels::Ros2(_) => {}
+        }
+    }
+
+    fn log_stabilized(
+        &self,
+        beam: &stabilized::StabilizedBeam,
+        timestamp: foxglove::schemas::Timestamp,
+    ) {
+        match self {
+            RecordingChannels::Json { stabilized, .. } => stabilized.log_with_time(beam, timestamp),
+            // The attitude is in the recorded odometry, ROS 2 tools stabilize with the transforms
+            RecordingChannels::Ros2(_) => {}
+        }
+    }
+
+    fn log_clock(&self, vehicle_clock: &VehicleClock, timestamp: foxglove::schemas::Timestamp) {
+        match self {
+            RecordingChannels::Json {
+                vehicle: Some(channels),
+                ..
+            } => channels.clock.log_with_time(vehicle_clock, timestamp),
+            RecordingChannels::Json { vehicle: None, .. } => {}
+            // ROS 2 messages are stamped with the host time, the vehicle_clock metadata maps it
+            RecordingChannels::Ros2(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct StartRecordingOptions {
+    pub uuid: Uuid,
+    /// Also log the undecoded ping-protocol frames on the `device_<id>/Raw` channel
+    #[serde(default)]
+    pub raw_frames: bool,
+    /// Session label used as the file name prefix, e.g. `survey-pierA`
+    #[serde(default)]
+    pub name: Option<String>,
+    /// `Ros2` writes a bag readable by `ros2 bag` instead of the Foxglove JSON channels
+    #[serde(default)]
+    pub format: RecordingFormat,
+    /// MAVLink messages logged on `device_<id>/MAVLink/<MESSAGE>`, e.g. `ATTITUDE`, the --recording-mavlink ones when unset
+    #[serde(default)]
+    pub mavlink_topics: Option<Vec<String>>,
+}
+
+impl From<Uuid> for StartRecordingOptions {
+    fn from(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            raw_frames: false,
+            name: None,
+            format: RecordingFormat::default(),
+            mavlink_topics: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct GroupRecordingOptions {
+    /// Devices recorded together, each under its own `device_<id>/` topics, the vehicle once under `vehicle/`
+    pub devices: Vec<Uuid>,
+    #[serde(default)]
+    pub raw_frames: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub format: RecordingFormat,
+    #[serde(default)]
+    pub mavlink_topics: Option<Vec<String>>,
+}
+
+const MAX_SESSION_NAME_LENGTH: usize = 64;
+
+// Only keep characters that are safe in a file name on every platform, no separators or dots
+pub fn sanitize_session_name(name: &str) -> Option<String> {
+    let sanitized: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_SESSION_NAME_LENGTH)
+        .collect();
+    let sanitized = sanitized.trim_matches('-').to_string();
+    (!sanitized.is_empty()).then_some(sanitized)
+}
+
+/// Ping-protocol frame as received, to re-decode recordings with fixed parsers
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RawFrame {
+    pub message_id: u16,
+    pub frame: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct AnnotationRequest {
+    pub device_id: Uuid,
+    pub text: String,
+}
+
+/// Operator note written on the `device_<id>/Annotations` channel of the recording
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema, schemars::JsonSchema)]
+pub struct Annotation {
+    pub device_id: Uuid,
+    pub text: String,
+    pub time: String,
+}
+
+// How often the size of the active file is checked against the rotation policy
+const ROTATION_CHECK_PERIOD: Duration = Duration::from_secs(1);
+// How often the vehicle clock mapping is written on the VehicleClock channel
+const VEHICLE_CLOCK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Thresholds to close the active file and continue the session in a new one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RotationPolicy {
+    pub max_size: Option<u64>,
+    pub max_duration: Option<Duration>,
+}
+
+impl RotationPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_duration.is_some()
+    }
+
+    fn is_due(&self, file_path: &Path, file_start: Instant) -> bool {
+        if self
+            .max_duration
+            .is_some_and(|max_duration| file_start.elapsed() >= max_duration)
+        {
+            return true;
+        }
+        self.max_size.is_some_and(|max_size| {
+            std::fs::metadata(file_path).is_ok_and(|metadata| metadata.len() >= max_size)
+        })
+    }
 }
 
 pub struct RecordingManager {
@@ -46,28 +443,54 @@ pub struct RecordingManager {
     base_path: PathBuf,
     status_broadcast: broadcast::Sender<RecordingSession>,
     devices_manager_handler: ManagerActorHandler,
-    vehicle_data: Arc<RwLock<Option<VehicleData>>>,
+    replays: Arc<RwLock<HashMap<Uuid, replay::ReplayHandle>>>,
+    rotation: RotationPolicy,
+    pre_trigger: Option<Duration>,
+    pre_trigger_buffers: HashMap<Uuid, pre_trigger::PreTriggerBuffer>,
+    retention: retention::RetentionPolicy,
+    pose_period: Option<Duration>,
+    mavlink_topics: Vec<String>,
+    date_directories: bool,
+    default_mount: Option<mounting::MountingPose>,
+    mounts: HashMap<Uuid, mounting::MountingPose>,
+    directory_lock: Option<std::fs::File>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
 #[serde(tag = "command", content = "payload")]
 pub enum RecordingManagerCommand {
-    StartRecording(UuidWrapper),
+    StartRecording(StartRecordingOptions),
+    StartRecordingGroup(GroupRecordingOptions),
     StopRecording(UuidWrapper),
     GetRecordingStatus(UuidWrapper),
     GetAllRecordingStatus,
     GetSubscriber,
+    GetDiskUsage,
+    Annotate(AnnotationRequest),
+    Bookmark(UuidWrapper),
+    SetMounting(mounting::MountingRequest),
+    StartRecordingAll,
+    StopRecordingAll,
+    StartReplay(replay::ReplayOptions),
+    ControlReplay(replay::ReplayControl),
+    StopReplay(UuidWrapper),
+    ListReplays,
+    #[serde(skip)]
+    SetRetention(retention::RetentionPolicy),
 }
 
 #[derive(Clone)]
 pub struct RecordingsManagerHandler {
     sender: mpsc::Sender<ManagerActorRequest>,
+    base_path: Arc<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct ManagerActorRequest {
     pub request: RecordingManagerCommand,
     pub respond_to: oneshot::Sender<Result<Answer, ManagerError>>,
+    /// Span of the caller, keeps its request id on the logs of the manager
+    pub span: tracing::Span,
 }
 
 #[derive(Debug, Serialize, Deserialize, Apiv2Schema)]
@@ -75,19 +498,26 @@ pub enum Answer {
     RecordingSession(RecordingSession),
     RecordingStatus(Option<RecordingSession>),
     AllRecordingStatus(Vec<RecordingSession>),
+    ReplayStatus(replay::ReplayStatus),
+    Replays(Vec<replay::ReplayStatus>),
+    DiskUsage(retention::DiskUsage),
+    Annotation(Annotation),
+    Mounting(Option<mounting::MountingPose>),
     #[serde(skip)]
     RecordingManager(Receiver<RecordingSession>),
 }
 
 impl RecordingManager {
-    pub fn new_with_pose(
+    pub fn new(
         size: usize,
         base_path: impl AsRef<Path>,
         device_manager: ManagerActorHandler,
-        vehicle_data: Arc<RwLock<Option<VehicleData>>>,
     ) -> (Self, RecordingsManagerHandler) {
         let (sender, receiver) = mpsc::channel(size);
-        let actor_handler: RecordingsManagerHandler = RecordingsManagerHandler { sender };
+        let actor_handler: RecordingsManagerHandler = RecordingsManagerHandler {
+            sender,
+            base_path: Arc::new(base_path.as_ref().to_path_buf()),
+        };
         let (status_broadcast, _) = broadcast::channel(100);
         let actor = RecordingManager {
             sessions: Arc::new(RwLock::new(HashMap::new())),
@@ -95,27 +525,142 @@ impl RecordingManager {
             status_broadcast,
             receiver,
             devices_manager_handler: device_manager,
-            vehicle_data,
+            replays: Arc::new(RwLock::new(HashMap::new())),
+            rotation: RotationPolicy::default(),
+            pre_trigger: None,
+            pre_trigger_buffers: HashMap::new(),
+            retention: retention::RetentionPolicy::default(),
+            pose_period: None,
+            mavlink_topics: Vec::new(),
+            date_directories: false,
+            default_mount: None,
+            mounts: HashMap::new(),
+            directory_lock: None,
         };
         (actor, actor_handler)
     }
 
-    pub fn new(
-        size: usize,
-        base_path: impl AsRef<Path>,
-        device_manager: ManagerActorHandler,
-    ) -> (Self, RecordingsManagerHandler) {
-        Self::new_with_pose(size, base_path, device_manager, Arc::new(RwLock::new(None)))
+    pub fn set_rotation(&mut self, rotation: RotationPolicy) {
+        if rotation.is_enabled() {
+            info!("RecordingsManager: Rotating recordings with {rotation:?}");
+        }
+        self.rotation = rotation;
+    }
+
+    /// Keep the given window of every streaming device in memory, written at the start of its recordings
+    pub fn set_pre_trigger(&mut self, window: Option<Duration>) {
+        if let Some(window) = window {
+            info!(
+                "RecordingsManager: Recordings start with the previous {}s of data",
+                window.as_secs()
+            );
+        }
+        self.pre_trigger = window;
+    }
+
+    /// Log the vehicle pose on its own timer, otherwise it is only written along sonar messages
+    pub fn set_pose_period(&mut self, period: Option<Duration>) {
+        if let Some(period) = period {
+            info!("RecordingsManager: Recording vehicle pose every {period:?}");
+        }
+        self.pose_period = period;
+    }
+
+    /// MAVLink messages of the vehicle bridge written to recordings that do not choose their own
+    pub fn set_mavlink_topics(&mut self, topics: Vec<String>) {
+        if !topics.is_empty() {
+            info!("RecordingsManager: Recording the MAVLink messages {topics:?}");
+        }
+        self.mavlink_topics = topics;
+    }
+
+    /// Write new recordings into `YYYY/MM/DD` subdirectories of the base path
+    pub fn set_date_directories(&mut self, enabled: bool) {
+        if enabled {
+            info!("RecordingsManager: Organizing recordings by date");
+        }
+        self.date_directories = enabled;
+    }
+
+    /// Mounting pose of devices without their own, recordings then carry frame transforms
+    pub fn set_default_mount(&mut self, mount: Option<mounting::MountingPose>) {
+        if let Some(mount) = mount {
+            info!("RecordingsManager: Sonars are mounted at {mount:?}");
+        }
+        self.default_mount = mount;
+    }
+
+    /// Pose used by the next recordings of the device
+    pub fn set_mounting(
+        &mut self,
+        request: mounting::MountingRequest,
+    ) -> Option<mounting::MountingPose> {
+        match request.pose {
+            Some(pose) => {
+                self.mounts.insert(request.device_id, pose);
+            }
+            None => {
+                self.mounts.remove(&request.device_id);
+            }
+        }
+        self.mounting(request.device_id)
+    }
+
+    fn mounting(&self, device_id: Uuid) -> Option<mounting::MountingPose> {
+        self.mounts.get(&device_id).copied().or(self.default_mount)
+    }
+
+    pub fn set_retention(&mut self, retention: retention::RetentionPolicy) {
+        if retention.is_enabled() {
+            info!("RecordingsManager: Pruning recordings with {retention:?}");
+        }
+        self.retention = retention;
     }
 
     pub async fn run(mut self) {
         info!("RecordingsManager is running");
 
+        // Another process recording into the same directory keeps its files open, they are not crashed
+        self.directory_lock = recovery::lock_directory(&self.base_path);
+        match self.directory_lock {
+            Some(_) => self.recover_unfinished_recordings(),
+            None => warn!(
+                "RecordingsManager: {:?} is used by another process, skipping recovery",
+                self.base_path
+            ),
+        }
+
+        let mut retention_interval = tokio::time::interval(retention::RETENTION_CHECK_PERIOD);
+
+        let mut pre_trigger_interval =
+            tokio::time::interval(pre_trigger::PRE_TRIGGER_REFRESH_PERIOD);
+
+        let mut device_events = events::subscribe();
+
         loop {
             tokio::select! {
                 Some(msg) = self.receiver.recv() => {
-                    self.handle_message(msg).await;
+                    let span = msg.span.clone();
+                    self.handle_message(msg).instrument(span).await;
+                }
+                _ = pre_trigger_interval.tick(), if self.pre_trigger.is_some() => {
+                    self.refresh_pre_trigger_buffers().await;
+                }
+                _ = retention_interval.tick(), if self.retention.is_enabled() => {
+                    self.apply_retention().await;
                 }
+                event = device_events.recv() => match event {
+                    Ok(event) if event.kind == events::EventKind::Deleted => {
+                        self.stop_deleted_device(event.device_id).await;
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("RecordingsManager: Missed {skipped} device events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        device_events = events::subscribe();
+                    }
+                },
                 else => break,
             }
         }
@@ -127,10 +672,14 @@ impl RecordingManager {
         trace!("RecordingsManager: Received a request, details: {actor_request:?}");
 
         let result = match actor_request.request {
-            RecordingManagerCommand::StartRecording(uuid_wrapper) => self
-                .start_recording(*uuid_wrapper)
+            RecordingManagerCommand::StartRecording(options) => self
+                .start_recording(options)
                 .await
                 .map(Answer::RecordingSession),
+            RecordingManagerCommand::StartRecordingGroup(options) => self
+                .start_recording_group(options)
+                .await
+                .map(Answer::AllRecordingStatus),
             RecordingManagerCommand::StopRecording(uuid_wrapper) => self
                 .stop_recording(*uuid_wrapper)
                 .await
@@ -146,6 +695,68 @@ impl RecordingManager {
             RecordingManagerCommand::GetSubscriber => {
                 Ok(Answer::RecordingManager(self.subscribe()))
             }
+            RecordingManagerCommand::GetDiskUsage => Ok(Answer::DiskUsage(retention::disk_usage(
+                &self.base_path,
+                self.retention,
+            ))),
+            RecordingManagerCommand::SetRetention(retention) => {
+                self.set_retention(retention);
+                if retention.is_enabled() {
+                    self.apply_retention().await;
+                }
+                Ok(Answer::DiskUsage(retention::disk_usage(
+                    &self.base_path,
+                    self.retention,
+                )))
+            }
+            RecordingManagerCommand::Annotate(request) => {
+                self.annotate(request).await.map(Answer::Annotation)
+            }
+            RecordingManagerCommand::Bookmark(uuid_wrapper) => self
+                .bookmark(*uuid_wrapper)
+                .await
+                .map(Answer::RecordingSession),
+            RecordingManagerCommand::SetMounting(request) => {
+                Ok(Answer::Mounting(self.set_mounting(request)))
+            }
+            RecordingManagerCommand::StartRecordingAll => self
+                .start_recording_all()
+                .await
+                .map(Answer::AllRecordingStatus),
+            RecordingManagerCommand::StopRecordingAll => self
+                .stop_recording_all()
+                .await
+                .map(Answer::AllRecordingStatus),
+            RecordingManagerCommand::StartReplay(options) => {
+                // Loading a long recording takes a while, answer from a task to keep the actor free
+                let replays = self.replays.clone();
+                let devices_manager_handler = self.devices_manager_handler.clone();
+                let base_path = self.base_path.clone();
+                let respond_to = actor_request.respond_to;
+                tokio::spawn(async move {
+                    let result =
+                        replay::start_replay(replays, devices_manager_handler, base_path, options)
+                            .await
+                            .map(Answer::ReplayStatus);
+                    if let Err(e) = respond_to.send(result) {
+                        error!("RecordingsManager: Failed to return response: {e:?}");
+                    }
+                });
+                return;
+            }
+            RecordingManagerCommand::ControlReplay(control) => {
+                replay::control_replay(&self.replays, control)
+                    .await
+                    .map(Answer::ReplayStatus)
+            }
+            RecordingManagerCommand::StopReplay(uuid_wrapper) => {
+                replay::stop_replay(&self.replays, &self.devices_manager_handler, *uuid_wrapper)
+                    .await
+                    .map(Answer::ReplayStatus)
+            }
+            RecordingManagerCommand::ListReplays => {
+                Ok(Answer::Replays(replay::list_replays(&self.replays).await))
+            }
         };
 
         if let Err(e) = actor_request.respond_to.send(result) {
@@ -161,106 +772,659 @@ impl RecordingManager {
         let _ = self.status_broadcast.send(session.clone());
     }
 
-    pub async fn start_recording(&self, device_id: Uuid) -> Result<RecordingSession, ManagerError> {
-        if self.sessions.read().await.contains_key(&device_id) {
-            return Err(ManagerError::Other(format!(
-                "Device {} is already recording",
-                device_id
-            )));
+    pub async fn start_recording(
+        &self,
+        options: StartRecordingOptions,
+    ) -> Result<RecordingSession, ManagerError> {
+        self.start_sessions(
+            &[options.uuid],
+            options.name.as_deref(),
+            options.format,
+            options.raw_frames,
+            options.mavlink_topics,
+        )
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ManagerError::NoDevices)
+    }
+
+    /// Record several devices into one file, the session starts and stops as a whole
+    pub async fn start_recording_group(
+        &self,
+        options: GroupRecordingOptions,
+    ) -> Result<Vec<RecordingSession>, ManagerError> {
+        let mut devices = options.devices.clone();
+        devices.sort();
+        devices.dedup();
+        if devices.len() < 2 {
+            return Err(ManagerError::Other(
+                "A group recording needs at least two devices".to_string(),
+            ));
         }
+        self.start_sessions(
+            &devices,
+            options.name.as_deref(),
+            options.format,
+            options.raw_frames,
+            options.mavlink_topics,
+        )
+        .await
+    }
 
-        tokio::fs::create_dir_all(&self.base_path)
-            .await
-            .map_err(|e| {
-                ManagerError::Other(format!("Failed to create recording directory: {}", e))
-            })?;
+    async fn start_sessions(
+        &self,
+        devices: &[Uuid],
+        name: Option<&str>,
+        format: RecordingFormat,
+        raw_frames: bool,
+        mavlink_topics: Option<Vec<String>>,
+    ) -> Result<Vec<RecordingSession>, ManagerError> {
+        {
+            let sessions = self.sessions.read().await;
+            if let Some(device_id) = devices.iter().find(|id| sessions.contains_key(id)) {
+                return Err(ManagerError::Other(format!(
+                    "Device {} is already recording",
+                    device_id
+                )));
+            }
+        }
 
         let timestamp = chrono::Utc::now();
-        let filename = format!(
-            "device_{}_{}.mcap",
-            device_id,
-            timestamp.format("%Y%m%d_%H%M%S")
-        );
-        let file_path = self.base_path.join(filename);
+        let directory = if self.date_directories {
+            self.base_path
+                .join(timestamp.format("%Y/%m/%d").to_string())
+        } else {
+            self.base_path.clone()
+        };
+        tokio::fs::create_dir_all(&directory).await.map_err(|e| {
+            ManagerError::Other(format!("Failed to create recording directory: {}", e))
+        })?;
 
-        let request = self
-            .devices_manager_handler
-            .send(crate::device::manager::Request::Info(
-                crate::device::manager::UuidWrapper { uuid: device_id },
-            ))
-            .await?;
+        if self.retention.is_enabled() {
+            self.apply_retention().await;
+        }
+
+        let name = match name {
+            Some(name) => Some(sanitize_session_name(name).ok_or_else(|| {
+                ManagerError::Other(format!("Invalid recording session name: {name:?}"))
+            })?),
+            None => None,
+        };
 
-        let device_info = match request {
-            crate::device::manager::Answer::DeviceInfo(h) => h.first().unwrap().clone(),
-            _ => return Err(ManagerError::Other("Invalid device handler".to_string())),
+        let mavlink_topics = mavlink_topics.unwrap_or_else(|| self.mavlink_topics.clone());
+        if format == RecordingFormat::Ros2 && !mavlink_topics.is_empty() {
+            warn!("MAVLink messages are not written to ROS 2 recordings");
+        }
+
+        let group_id = (devices.len() > 1).then(Uuid::new_v4);
+        let time = timestamp.format("%Y%m%d_%H%M%S");
+        let filename = match (&name, group_id) {
+            (Some(name), _) => format!("{}_{}.mcap", name, time),
+            (None, Some(_)) => format!("group_{}.mcap", time),
+            (None, None) => format!("device_{}_{}.mcap", devices[0], time),
         };
+        let file_path = directory.join(filename);
+
+        // Resolve every device before creating the file, so a missing one leaves nothing behind
+        let mut targets = Vec::new();
+        for &device_id in devices {
+            let device_info = self.device_info(device_id).await?;
+            let handler = self.device_handler(device_id).await?;
+            targets.push((device_info, handler));
+        }
 
         let ctx = Context::new();
-        let mcap_writer: McapWriterHandle<BufWriter<File>> = ctx
-            .mcap_writer()
-            .create_new_buffered_file(&file_path)
+        let mcap_writer = McapFileWriter::create(&ctx, &file_path, format.write_options())
             .map_err(|e| ManagerError::Other(format!("Failed to create MCAP file: {}", e)))?;
 
-        let session = RecordingSession {
-            device_id,
-            file_path: file_path.clone(),
-            is_active: true,
-            start_time: timestamp,
-            device_type: device_info.device_type.clone(),
-        };
+        let mut written_metadata = std::collections::HashSet::new();
+        let mut guards = Vec::new();
+        for (device_info, handler) in targets {
+            let device_id = device_info.id;
+            let mut metadata = self.recording_metadata(&device_info, timestamp).await;
+            // Devices share the file in a group, their records are told apart by the id
+            if group_id.is_some() {
+                for (name, _) in metadata.iter_mut().filter(|(name, _)| name != "software") {
+                    *name = format!("{name}_{device_id}");
+                }
+            }
+            for (name, metadata) in &metadata {
+                if !written_metadata.insert(name.clone()) {
+                    continue;
+                }
+                if let Err(err) = mcap_writer.write_metadata(name, metadata.clone()) {
+                    warn!("Failed to write {name} metadata for device {device_id}: {err:?}");
+                }
+            }
 
-        let session_guard = SessionGuard {
-            session: session.clone(),
-            writer: Some(mcap_writer),
-        };
+            let notes =
+                NoteChannel::new(&ctx, device_id, format, "Annotations").and_then(|annotations| {
+                    let events = NoteChannel::new(&ctx, device_id, format, "Events")?;
+                    Ok((annotations, events))
+                });
+            let (annotations, events) = match notes {
+                Ok(notes) => notes,
+                Err(err) => {
+                    let _ = mcap_writer.close();
+                    return Err(err);
+                }
+            };
 
-        self.sessions.write().await.insert(device_id, session_guard);
-        self.broadcast_status(&session).await;
+            let session = RecordingSession {
+                device_id,
+                file_path: file_path.clone(),
+                is_active: true,
+                start_time: timestamp,
+                device_type: device_info.device_type.clone(),
+                name: name.clone(),
+                format,
+                group_id,
+                bookmarks: Vec::new(),
+            };
+            guards.push((
+                SessionGuard {
+                    session,
+                    writer: Arc::new(std::sync::Mutex::new(None)),
+                    metadata,
+                    annotations,
+                    events,
+                },
+                handler,
+            ));
+        }
+
+        let writer = Arc::new(std::sync::Mutex::new(Some(mcap_writer)));
+        let mut started = Vec::new();
+        for (index, (mut session_guard, handler)) in guards.into_iter().enumerate() {
+            session_guard.writer = writer.clone();
+            let session = session_guard.session.clone();
+            let device_id = session.device_id;
+            log_event(
+                &session_guard.events,
+                &events::RecordingEvent::new(
+                    device_id,
+                    events::EventKind::RecordingStarted,
+                    serde_json::to_string(&session).unwrap_or_default(),
+                ),
+            );
+
+            self.sessions.write().await.insert(device_id, session_guard);
+            self.broadcast_status(&session).await;
+
+            hold_awake(&self.devices_manager_handler, device_id, true).await;
+
+            let sessions = self.sessions.clone();
+            let devices_manager_handler = self.devices_manager_handler.clone();
+            // Rotating a shared file would need every device of the group to switch together
+            let rotation = if group_id.is_some() {
+                RotationPolicy::default()
+            } else {
+                self.rotation
+            };
+            let pose_period = self.pose_period;
+            let mount = self.mounting(device_id);
+            let status_broadcast = self.status_broadcast.clone();
+            let pre_trigger = self
+                .pre_trigger_buffers
+                .get(&device_id)
+                .map(pre_trigger::PreTriggerBuffer::history);
+            let file_path = file_path.clone();
+            let ctx = ctx.clone();
+            let mavlink_topics = mavlink_topics.clone();
+            let vehicle = VehicleScope::of(group_id.is_some(), index);
+
+            tokio::spawn(async move {
+                if let Err(e) = Self::recording_task(
+                    handler,
+                    file_path,
+                    sessions,
+                    device_id,
+                    ctx,
+                    rotation,
+                    status_broadcast,
+                    pre_trigger,
+                    raw_frames,
+                    format,
+                    pose_period,
+                    mount,
+                    mavlink_topics,
+                    vehicle,
+                )
+                .await
+                {
+                    error!("Recording task failed for device {}: {:?}", device_id, e);
+                }
+                hold_awake(&devices_manager_handler, device_id, false).await;
+            });
 
-        let sessions = self.sessions.clone();
-        let devices_manager_handler = self.devices_manager_handler.clone();
-        let vehicle_data = self.vehicle_data.clone();
+            started.push(session);
+        }
+
+        if let Some(group_id) = group_id {
+            info!(
+                "Recording group {group_id} of {} devices into {file_path:?}",
+                started.len()
+            );
+        }
+        Ok(started)
+    }
 
-        let device_handler = devices_manager_handler
+    async fn device_info(&self, device_id: Uuid) -> Result<DeviceInfo, ManagerError> {
+        match self
+            .devices_manager_handler
+            .send(crate::device::manager::Request::Info(
+                crate::device::manager::UuidWrapper { uuid: device_id },
+            ))
+            .await?
+        {
+            crate::device::manager::Answer::DeviceInfo(info) => {
+                info.into_iter().next().ok_or(ManagerError::NoDevices)
+            }
+            _ => Err(ManagerError::Other("Invalid device handler".to_string())),
+        }
+    }
+
+    async fn device_handler(&self, device_id: Uuid) -> Result<DeviceActorHandler, ManagerError> {
+        match self
+            .devices_manager_handler
             .send(crate::device::manager::Request::GetDeviceHandler(
                 crate::device::manager::UuidWrapper { uuid: device_id },
             ))
-            .await?;
+            .await?
+        {
+            crate::device::manager::Answer::InnerDeviceHandler(handler) => Ok(handler),
+            _ => Err(ManagerError::Other("Invalid device handler".to_string())),
+        }
+    }
 
-        let handler = match device_handler {
-            crate::device::manager::Answer::InnerDeviceHandler(h) => h,
-            _ => return Err(ManagerError::Other("Invalid device handler".to_string())),
-        };
+    // Self-describing recordings: software build and the full device configuration at start
+    async fn recording_metadata(
+        &self,
+        device_info: &DeviceInfo,
+        start_time: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<(String, BTreeMap<String, String>)> {
+        let software = BTreeMap::from([
+            ("name".to_string(), env!("CARGO_PKG_NAME").to_string()),
+            ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+            ("git_sha".to_string(), env!("VERGEN_GIT_SHA").to_string()),
+            (
+                "build_date".to_string(),
+                env!("VERGEN_BUILD_DATE").to_string(),
+            ),
+        ]);
 
-        tokio::spawn(async move {
-            if let Err(e) =
-                Self::recording_task(handler, file_path, sessions, device_id, ctx, vehicle_data)
-                    .await
-            {
-                error!("Recording task failed for device {}: {:?}", device_id, e);
+        let mut device = BTreeMap::from([
+            ("id".to_string(), device_info.id.to_string()),
+            (
+                "device_type".to_string(),
+                format!("{:?}", device_info.device_type),
+            ),
+            (
+                "source".to_string(),
+                serde_json::to_string(&device_info.source).unwrap_or_default(),
+            ),
+            (
+                "properties".to_string(),
+                serde_json::to_string(&device_info.properties).unwrap_or_default(),
+            ),
+            ("recording_start".to_string(), start_time.to_rfc3339()),
+        ]);
+
+        // Flat view of the configuration for quick inspection, e.g. in the Foxglove metadata panel
+        let mut settings = BTreeMap::new();
+        if let Some(properties) = &device_info.properties {
+            flatten_settings(
+                &serde_json::to_value(properties).unwrap_or_default(),
+                &mut settings,
+            );
+        }
+
+        if device_info.device_type == DeviceSelection::Ping1D {
+            for (key, request) in [
+                ("general_info", Ping1DRequest::GeneralInfo),
+                ("range", Ping1DRequest::Range),
+                ("speed_of_sound", Ping1DRequest::SpeedOfSound),
+                ("transmit_duration", Ping1DRequest::TransmitDuration),
+            ] {
+                let request = crate::device::manager::Request::Ping(
+                    crate::device::manager::DeviceRequestStruct {
+                        uuid: device_info.id,
+                        device_request: PingRequest::Ping1D(request),
+                    },
+                );
+                match self.devices_manager_handler.send(request).await {
+                    Ok(crate::device::manager::Answer::DeviceMessage(answer)) => {
+                        let answer = serde_json::to_value(&answer.answer).unwrap_or_default();
+                        flatten_settings(&answer, &mut settings);
+                        device.insert(key.to_string(), answer.to_string());
+                    }
+                    Ok(answer) => warn!("Unexpected answer while reading {key}: {answer:?}"),
+                    Err(err) => warn!("Failed to read {key} for recording metadata: {err:?}"),
+                }
             }
-        });
+        }
 
-        Ok(session)
+        derive_settings(&mut settings);
+
+        let mut metadata = vec![
+            ("software".to_string(), software),
+            ("device".to_string(), device),
+            ("settings".to_string(), settings),
+        ];
+        if let Some(clock) = crate::vehicle::clock::current() {
+            metadata.push(("vehicle_clock".to_string(), clock.metadata()));
+        }
+        metadata
     }
 
+    /// Stopping any device of a group stops the whole group, they share the file
     pub async fn stop_recording(&self, device_id: Uuid) -> Result<RecordingSession, ManagerError> {
+        self.stop_sessions(device_id)
+            .await?
+            .into_iter()
+            .find(|session| session.device_id == device_id)
+            .ok_or(ManagerError::NoDevices)
+    }
+
+    async fn stop_sessions(&self, device_id: Uuid) -> Result<Vec<RecordingSession>, ManagerError> {
         let mut sessions = self.sessions.write().await;
-        let session_guard = sessions.get_mut(&device_id).ok_or_else(|| {
-            ManagerError::Other(format!("No recording session for device {}", device_id))
-        })?;
+        let group_id = sessions
+            .get(&device_id)
+            .ok_or_else(|| {
+                ManagerError::Other(format!("No recording session for device {}", device_id))
+            })?
+            .session
+            .group_id;
+        let members: Vec<Uuid> = match group_id {
+            Some(group_id) => sessions
+                .values()
+                .filter(|guard| guard.session.group_id == Some(group_id))
+                .map(|guard| guard.session.device_id)
+                .collect(),
+            None => vec![device_id],
+        };
 
-        session_guard.session.is_active = false;
-        if let Some(writer) = session_guard.writer.take() {
+        let mut stopped = Vec::new();
+        let mut writer = None;
+        for member in members {
+            let Some(session_guard) = sessions.get_mut(&member) else {
+                continue;
+            };
+            session_guard.session.is_active = false;
+            log_event(
+                &session_guard.events,
+                &events::RecordingEvent::new(member, events::EventKind::RecordingStopped, ""),
+            );
+            writer = writer.or_else(|| session_guard.writer.lock().unwrap().take());
+            stopped.push(session_guard.session.clone());
+        }
+        drop(sessions);
+
+        if let Some(writer) = writer {
             writer
                 .close()
                 .map_err(|e| ManagerError::Other(format!("Failed to close MCAP writer: {}", e)))?;
         }
+        for session in &stopped {
+            self.broadcast_status(session).await;
+            hold_awake(&self.devices_manager_handler, session.device_id, false).await;
+        }
+        Ok(stopped)
+    }
+
+    /// Start a session for every device with an open source, already recording ones are kept
+    pub async fn start_recording_all(&self) -> Result<Vec<RecordingSession>, ManagerError> {
+        let devices = match self
+            .devices_manager_handler
+            .send(crate::device::manager::Request::List)
+            .await?
+        {
+            crate::device::manager::Answer::DeviceInfo(devices) => devices,
+            answer => {
+                return Err(ManagerError::Other(format!(
+                    "Unexpected answer while listing devices: {answer:?}"
+                )))
+            }
+        };
+
+        let mut started = Vec::new();
+        for device in devices {
+            if !matches!(
+                device.status,
+                DeviceStatus::Running | DeviceStatus::ContinuousMode
+            ) {
+                trace!(
+                    "Not recording device {} in status {:?}",
+                    device.id,
+                    device.status
+                );
+                continue;
+            }
+            if self.sessions.read().await.contains_key(&device.id) {
+                continue;
+            }
+            match self.start_recording(device.id.into()).await {
+                Ok(session) => started.push(session),
+                Err(err) => warn!("Failed to start recording device {}: {err:?}", device.id),
+            }
+        }
+
+        if started.is_empty() && self.sessions.read().await.is_empty() {
+            return Err(ManagerError::NoDevices);
+        }
+        info!("Started recording {} devices", started.len());
+        self.get_all_recording_status().await
+    }
+
+    // Must run before any new session starts, files being written look unfinished too
+    fn recover_unfinished_recordings(&self) {
+        for path in recovery::unfinished_recordings(&self.base_path) {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            warn!("RecordingsManager: Recovering unfinished recording {file_name}");
+
+            let (job_id, result) =
+                jobs::JOBS.submit(jobs::JobKind::Recovery, &file_name, move |progress| {
+                    recovery::recover(&path, |fraction| progress.set(fraction))
+                });
+            tokio::spawn(async move {
+                match result.await {
+                    Ok(Ok(recovered)) => info!(
+                        "RecordingsManager: Recovered {} messages of {file_name}",
+                        recovered.messages
+                    ),
+                    Ok(Err(err)) => {
+                        error!("RecordingsManager: Failed to recover {file_name}: {err:?}")
+                    }
+                    Err(err) => error!("RecordingsManager: Recovery job {job_id} failed: {err:?}"),
+                }
+            });
+        }
+    }
+
+    // The recording task would otherwise wait forever on a device that no longer streams
+    async fn stop_deleted_device(&mut self, device_id: Uuid) {
+        self.pre_trigger_buffers.remove(&device_id);
+
+        let is_active = self
+            .sessions
+            .read()
+            .await
+            .get(&device_id)
+            .is_some_and(|guard| guard.session.is_active);
+        if !is_active {
+            return;
+        }
+
+        match self.stop_recording(device_id).await {
+            Ok(session) => info!(
+                "Stopped recording of deleted device {device_id} into {:?}",
+                session.file_path
+            ),
+            Err(err) => warn!("Failed to stop recording of deleted device {device_id}: {err:?}"),
+        }
+    }
+
+    async fn apply_retention(&self) {
+        let active: std::collections::HashSet<PathBuf> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .map(|guard| guard.session.file_path.clone())
+            .collect();
+        let base_path = self.base_path.clone();
+        let policy = self.retention;
+
+        match tokio::task::spawn_blocking(move || retention::prune(&base_path, &policy, &active))
+            .await
+        {
+            Ok(0) => {}
+            Ok(removed) => info!("RecordingsManager: Retention removed {removed} recordings"),
+            Err(err) => error!("RecordingsManager: Retention task failed: {err:?}"),
+        }
+    }
+
+    async fn refresh_pre_trigger_buffers(&mut self) {
+        let Some(window) = self.pre_trigger else {
+            return;
+        };
+
+        let streaming: Vec<Uuid> = match self
+            .devices_manager_handler
+            .send(crate::device::manager::Request::List)
+            .await
+        {
+            Ok(crate::device::manager::Answer::DeviceInfo(devices)) => devices
+                .into_iter()
+                .filter(|device| {
+                    matches!(
+                        device.status,
+                        DeviceStatus::Running | DeviceStatus::ContinuousMode
+                    )
+                })
+                .map(|device| device.id)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        self.pre_trigger_buffers
+            .retain(|device_id, buffer| streaming.contains(device_id) && !buffer.is_finished());
+
+        for device_id in streaming {
+            if self.pre_trigger_buffers.contains_key(&device_id) {
+                continue;
+            }
+            match device_subscriber(&self.devices_manager_handler, device_id).await {
+                Ok(receiver) => {
+                    self.pre_trigger_buffers.insert(
+                        device_id,
+                        pre_trigger::PreTriggerBuffer::spawn(device_id, window, receiver),
+                    );
+                }
+                Err(err) => {
+                    warn!("Failed to buffer pre-trigger data of device {device_id}: {err:?}")
+                }
+            }
+        }
+    }
+
+    pub async fn stop_recording_all(&self) -> Result<Vec<RecordingSession>, ManagerError> {
+        let active: Vec<Uuid> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .filter(|guard| guard.session.is_active)
+            .map(|guard| guard.session.device_id)
+            .collect();
+
+        let mut stopped = Vec::new();
+        for device_id in active {
+            // Already stopped together with an earlier device of its group
+            if stopped
+                .iter()
+                .any(|session: &RecordingSession| session.device_id == device_id)
+            {
+                continue;
+            }
+            match self.stop_sessions(device_id).await {
+                Ok(sessions) => stopped.extend(sessions),
+                Err(err) => warn!("Failed to stop recording device {device_id}: {err:?}"),
+            }
+        }
+        info!("Stopped recording {} devices", stopped.len());
+        Ok(stopped)
+    }
+
+    /// Mark the current time of an active session, written as a `bookmark_<n>` metadata record
+    pub async fn bookmark(&self, device_id: Uuid) -> Result<RecordingSession, ManagerError> {
+        let mut sessions = self.sessions.write().await;
+        let session_guard = sessions
+            .get_mut(&device_id)
+            .filter(|guard| guard.session.is_active)
+            .ok_or_else(|| {
+                ManagerError::Other(format!(
+                    "No active recording session for device {device_id}"
+                ))
+            })?;
+
+        let time = chrono::Utc::now().to_rfc3339();
+        let index = session_guard.session.bookmarks.len() + 1;
+        let metadata = BTreeMap::from([
+            ("device_id".to_string(), device_id.to_string()),
+            ("time".to_string(), time.clone()),
+        ]);
+        if let Some(writer) = session_guard.writer.lock().unwrap().as_ref() {
+            writer
+                .write_metadata(&format!("bookmark_{index}"), metadata)
+                .map_err(|e| ManagerError::Other(format!("Failed to write bookmark: {}", e)))?;
+        }
+        session_guard.session.bookmarks.push(time);
         let session = session_guard.session.clone();
+        drop(sessions);
+
         self.broadcast_status(&session).await;
         Ok(session)
     }
 
+    pub async fn annotate(&self, request: AnnotationRequest) -> Result<Annotation, ManagerError> {
+        let text = request.text.trim();
+        if text.is_empty() {
+            return Err(ManagerError::Other("Annotation text is empty".to_string()));
+        }
+
+        let sessions = self.sessions.read().await;
+        let session_guard = sessions
+            .get(&request.device_id)
+            .filter(|guard| guard.session.is_active)
+            .ok_or_else(|| {
+                ManagerError::Other(format!(
+                    "No recording session for device {}",
+                    request.device_id
+                ))
+            })?;
+
+        let annotation = Annotation {
+            device_id: request.device_id,
+            text: text.to_string(),
+            time: chrono::Utc::now().to_rfc3339(),
+        };
+        session_guard.annotations.log(
+            &annotation,
+            &annotation.text,
+            foxglove::schemas::Timestamp::now(),
+        );
+        info!(
+            "Annotated recording of {}: {:?}",
+            request.device_id, annotation.text
+        );
+        Ok(annotation)
+    }
+
     pub async fn get_recording_status(
         &self,
         device_id: Uuid,
@@ -283,13 +1447,22 @@ impl RecordingManager {
             .collect())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn recording_task(
         handler: DeviceActorHandler,
-        _file_path: PathBuf,
+        file_path: PathBuf,
         sessions: Arc<RwLock<HashMap<Uuid, SessionGuard>>>,
         device_id: Uuid,
         ctx: Arc<Context>,
-        vehicle_data: Arc<RwLock<Option<VehicleData>>>,
+        rotation: RotationPolicy,
+        status_broadcast: broadcast::Sender<RecordingSession>,
+        pre_trigger: Option<pre_trigger::PreTriggerHistory>,
+        raw_frames: bool,
+        format: RecordingFormat,
+        pose_period: Option<Duration>,
+        mount: Option<mounting::MountingPose>,
+        mavlink_topics: Vec<String>,
+        vehicle: Option<VehicleScope>,
     ) -> Result<(), ManagerError> {
         let subscriber = handler
             .send(super::devices::PingRequest::GetSubscriber)
@@ -306,18 +1479,49 @@ impl RecordingManager {
                 return Err(ManagerError::NoDevices);
             }
         };
+        let subscribed = Instant::now();
 
-        // Define topic strings
-        let ping1d_topic = format!("device_{}/Ping1D", device_id);
-        let ping360_topic = format!("device_{}/Ping360", device_id);
-        let vehicle_topic = format!("device_{}/VehicleData", device_id);
+        let channels = RecordingChannels::new(&ctx, device_id, format, raw_frames, vehicle)?;
+        let transforms = mount
+            .map(|mount| mounting::TransformChannel::new(&ctx, device_id, format, mount))
+            .transpose()?;
+        if let Some(transforms) = &transforms {
+            transforms.log(None, foxglove::schemas::Timestamp::now());
+        }
+        let mut device_events = events::subscribe();
+        // The ROS 2 profile only has typed CDR channels, the JSON messages have no place there
+        let mut mavlink_channels = match (format, vehicle) {
+            (RecordingFormat::Mcap, Some(scope)) => {
+                mavlink::MavlinkChannels::new(&ctx, scope.prefix(device_id, format), mavlink_topics)
+            }
+            _ => mavlink::MavlinkChannels::new(&ctx, String::new(), Vec::new()),
+        };
+        // Bridges only parse the stream while someone listens
+        let mut mavlink_stream =
+            (!mavlink_channels.is_empty()).then(crate::vehicle::stream::subscribe);
 
-        // Create device-specific channels with proper schema
-        let ping1d_channel = ctx.channel_builder(&ping1d_topic).build::<ProfileStruct>();
-        let ping360_channel = ctx
-            .channel_builder(&ping360_topic)
-            .build::<AutoDeviceDataStruct>();
-        let vehicle_channel = ctx.channel_builder(&vehicle_topic).build::<VehicleData>();
+        // Data from before the start, anything newer is still waiting on the subscriber
+        if let Some(pre_trigger) = pre_trigger {
+            let buffered = pre_trigger.take_before(subscribed);
+            info!(
+                "Writing {} pre-trigger messages for device {device_id}",
+                buffered.len()
+            );
+            for buffered in buffered {
+                channels.log_message(&buffered.message, buffered.timestamp);
+            }
+        }
+
+        let mut active_path = file_path.clone();
+        let mut file_start = Instant::now();
+        let mut last_rotation_check = Instant::now();
+        let mut part = 1;
+        // Keeps the pose flowing while the device stalls, the period is unused when disabled
+        let mut pose_interval = tokio::time::interval(pose_period.unwrap_or(ROTATION_CHECK_PERIOD));
+        pose_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut clock_interval = tokio::time::interval(VEHICLE_CLOCK_PERIOD);
+        clock_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut last_clock_sync = None;
 
         while {
             let sessions_guard = sessions.read().await;
@@ -326,43 +1530,107 @@ impl RecordingManager {
                 .map(|s| s.session.is_active)
                 .unwrap_or(false)
         } {
-            match receiver.recv().await {
-                Ok(msg) => {
-                    let timestamp = foxglove::schemas::Timestamp::now();
-                    // Handle Ping360
-                    if let Ok(bluerobotics_ping::Messages::Ping360(
-                        bluerobotics_ping::ping360::Messages::AutoDeviceData(answer),
-                    )) = bluerobotics_ping::Messages::try_from(&msg)
-                    {
-                        ping360_channel.log_with_time(&answer, timestamp);
-                    } else if let Ok(bluerobotics_ping::Messages::Ping360(
-                        bluerobotics_ping::ping360::Messages::DeviceData(answer),
-                    )) = bluerobotics_ping::Messages::try_from(&msg)
-                    {
-                        let autotransducer = AutoDeviceDataStruct {
-                            mode: answer.mode,
-                            gain_setting: answer.gain_setting,
-                            angle: answer.angle,
-                            transmit_duration: answer.transmit_duration,
-                            sample_period: answer.sample_period,
-                            transmit_frequency: answer.transmit_frequency,
-                            start_angle: 0,
-                            stop_angle: 399,
-                            num_steps: 1,
-                            delay: 0,
-                            number_of_samples: answer.number_of_samples,
-                            data_length: answer.number_of_samples,
-                            data: answer.data,
-                        };
-                        ping360_channel.log_with_time(&autotransducer, timestamp);
-                    } else if let Ok(bluerobotics_ping::Messages::Ping1D(
-                        bluerobotics_ping::ping1d::Messages::Profile(answer),
-                    )) = bluerobotics_ping::Messages::try_from(&msg)
+            let received = tokio::select! {
+                received = receiver.recv() => received,
+                event = device_events.recv() => {
+                    match event {
+                        Ok(event) if event.device_id == device_id => {
+                            if let Some(session_guard) = sessions.read().await.get(&device_id) {
+                                log_event(&session_guard.events, &event);
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("Recording of {device_id} missed {skipped} device events")
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                sample = mavlink::recv(&mut mavlink_stream) => {
+                    match sample {
+                        Ok(sample) => mavlink_channels.log(&sample),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            debug!("Recording of {device_id} skipped {skipped} MAVLink messages")
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {}
+                    }
+                    continue;
+                }
+                _ = clock_interval.tick() => {
+                    // Only new syncs, a vehicle gone silent leaves no misleading samples
+                    if let Some(clock) = crate::vehicle::clock::current()
+                        .filter(|clock| Some(clock.host_timestamp_ms) != last_clock_sync)
                     {
-                        ping1d_channel.log_with_time(&answer, timestamp);
+                        last_clock_sync = Some(clock.host_timestamp_ms);
+                        channels.log_clock(&clock, foxglove::schemas::Timestamp::now());
+                    }
+                    continue;
+                }
+                _ = pose_interval.tick(), if pose_period.is_some() => {
+                    // Stale poses are left out, they would georeference the sonar data with an old position
+                    let now = chrono::Utc::now();
+                    if let Some(vehicle) = history::pose_at(now.timestamp_millis()) {
+                        let timestamp = timestamp_of(now);
+                        channels.log_vehicle(&vehicle, timestamp);
+                        if let Some(transforms) = &transforms {
+                            transforms.log(Some(&vehicle), timestamp);
+                        }
+                    }
+                    continue;
+                }
+            };
+            match received {
+                Ok(msg) => {
+                    let received = chrono::Utc::now();
+                    let timestamp = timestamp_of(received);
+                    channels.log_message(&msg, timestamp);
+                    let now = received.timestamp_millis();
+                    if let Some(sample) = georeference::georeference(device_id, &msg, now) {
+                        channels.log_georeferenced(&sample, timestamp);
+                    }
+                    if let Some(beam) = stabilized::stabilize_message(device_id, &msg, now) {
+                        channels.log_stabilized(&beam, timestamp);
                     }
-                    if let Some(vehicle) = vehicle_data.read().await.as_ref() {
-                        vehicle_channel.log_with_time(vehicle, timestamp);
+                    // The pose at the sample time, with the same timestamp as the sample
+                    if pose_period.is_none() {
+                        if let Some(vehicle) = history::pose_at(now) {
+                            channels.log_vehicle(&vehicle, timestamp);
+                            if let Some(transforms) = &transforms {
+                                transforms.log(Some(&vehicle), timestamp);
+                            }
+                        }
+                    }
+
+                    // Messages are only logged from this task, switching files here loses none
+                    if rotation.is_enabled()
+                        && last_rotation_check.elapsed() >= ROTATION_CHECK_PERIOD
+                    {
+                        last_rotation_check = Instant::now();
+                        if rotation.is_due(&active_path, file_start) {
+                            part += 1;
+                            match Self::rotate_file(
+                                &sessions,
+                                device_id,
+                                &ctx,
+                                &file_path,
+                                part,
+                                format,
+                                transforms.as_ref(),
+                            )
+                            .await
+                            {
+                                Ok(session) => {
+                                    active_path = session.file_path.clone();
+                                    // The clock mapping is only logged when it changes
+                                    last_clock_sync = None;
+                                    let _ = status_broadcast.send(session);
+                                }
+                                Err(err) => {
+                                    error!("Failed to rotate recording of {device_id}: {err:?}")
+                                }
+                            }
+                            file_start = Instant::now();
+                        }
                     }
                 }
                 Err(e) => {
@@ -375,9 +1643,323 @@ impl RecordingManager {
         sessions.write().await.remove(&device_id);
         Ok(())
     }
+
+    // Start the next numbered file before closing the current one, every part gets the metadata and
+    // the mounting transform logged at the start so it can be played on its own
+    async fn rotate_file(
+        sessions: &RwLock<HashMap<Uuid, SessionGuard>>,
+        device_id: Uuid,
+        ctx: &Arc<Context>,
+        first_path: &Path,
+        part: u32,
+        format: RecordingFormat,
+        transforms: Option<&mounting::TransformChannel>,
+    ) -> Result<RecordingSession, ManagerError> {
+        let path = rotated_path(first_path, part);
+        let writer = McapFileWriter::create(ctx, &path, format.write_options())
+            .map_err(|e| ManagerError::Other(format!("Failed to create MCAP file: {}", e)))?;
+
+        let mut sessions = sessions.write().await;
+        let Some(session_guard) = sessions.get_mut(&device_id) else {
+            let _ = writer.close();
+            return Err(ManagerError::Other(format!(
+                "No recording session for device {}",
+                device_id
+            )));
+        };
+
+        let previous = session_guard.session.file_path.clone();
+        let rotation = BTreeMap::from([
+            ("part".to_string(), part.to_string()),
+            (
+                "previous_file".to_string(),
+                previous
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+        ]);
+        // The vehicle clock of the start would be outdated, every file gets the latest one
+        let vehicle_clock = crate::vehicle::clock::current()
+            .map(|clock| ("vehicle_clock".to_string(), clock.metadata()));
+        for (name, metadata) in session_guard
+            .metadata
+            .iter()
+            .filter(|(name, _)| name != "vehicle_clock")
+            .cloned()
+            .chain(vehicle_clock)
+            .chain([("rotation".to_string(), rotation)])
+        {
+            if let Err(err) = writer.write_metadata(&name, metadata) {
+                warn!("Failed to write {name} metadata for device {device_id}: {err:?}");
+            }
+        }
+
+        let previous_writer = session_guard.writer.lock().unwrap().replace(writer);
+        if let Some(previous_writer) = previous_writer {
+            previous_writer
+                .close()
+                .map_err(|e| ManagerError::Other(format!("Failed to close MCAP writer: {}", e)))?;
+        }
+        session_guard.session.file_path = path;
+        if let Some(transforms) = transforms {
+            let now = chrono::Utc::now();
+            transforms.log(
+                history::pose_at(now.timestamp_millis()).as_ref(),
+                timestamp_of(now),
+            );
+        }
+        info!(
+            "Recording of {device_id} continues in {:?}",
+            session_guard.session.file_path
+        );
+        Ok(session_guard.session.clone())
+    }
+}
+
+// Leaf values of nested answers keep their field name, arrays such as profiles are skipped
+fn flatten_settings(value: &serde_json::Value, settings: &mut BTreeMap<String, String>) {
+    let serde_json::Value::Object(fields) = value else {
+        return;
+    };
+    for (key, value) in fields {
+        match value {
+            serde_json::Value::Object(_) => flatten_settings(value, settings),
+            serde_json::Value::Array(_) | serde_json::Value::Null => {}
+            serde_json::Value::String(text) => {
+                settings.insert(key.clone(), text.clone());
+            }
+            _ => {
+                settings.insert(key.clone(), value.to_string());
+            }
+        }
+    }
+}
+
+// Values people look for first, computed from the raw device fields
+fn derive_settings(settings: &mut BTreeMap<String, String>) {
+    let number = |settings: &BTreeMap<String, String>, key: &str| {
+        settings
+            .get(key)
+            .and_then(|value| value.parse::<f64>().ok())
+    };
+
+    if let (Some(major), Some(minor), Some(patch)) = (
+        number(settings, "firmware_version_major"),
+        number(settings, "firmware_version_minor"),
+        number(settings, "firmware_version_patch"),
+    ) {
+        settings.insert(
+            "firmware_version".to_string(),
+            format!("{major}.{minor}.{patch}"),
+        );
+    }
+
+    // Ping360 angles are in gradians
+    if let (Some(start), Some(stop)) = (
+        number(settings, "start_angle"),
+        number(settings, "stop_angle"),
+    ) {
+        settings.insert(
+            "sector_deg".to_string(),
+            format!("{:.1}-{:.1}", start * 0.9, stop * 0.9),
+        );
+    }
+    if let (Some(sample_period), Some(number_of_samples)) = (
+        number(settings, "sample_period"),
+        number(settings, "number_of_samples"),
+    ) {
+        let range_m = report::ping360_range(sample_period as u16, number_of_samples as usize);
+        settings.insert("range_m".to_string(), format!("{range_m:.2}"));
+    }
+
+    // Ping1D scan window is in millimeters
+    if let (Some(scan_start), Some(scan_length)) = (
+        number(settings, "scan_start"),
+        number(settings, "scan_length"),
+    ) {
+        settings.insert(
+            "range_m".to_string(),
+            format!(
+                "{:.2}-{:.2}",
+                scan_start / 1000.0,
+                (scan_start + scan_length) / 1000.0
+            ),
+        );
+    }
+}
+
+fn log_raw_frame(
+    raw_channel: &foxglove::Channel<RawFrame>,
+    msg: &bluerobotics_ping::message::ProtocolMessage,
+    timestamp: foxglove::schemas::Timestamp,
+) {
+    let frame = RawFrame {
+        message_id: msg.message_id,
+        frame: msg.serialized(),
+    };
+    raw_channel.log_with_time(&frame, timestamp);
+}
+
+// Standard fix for the Foxglove map panel, skipped until the vehicle reports a position
+// Same instant as the pose lookup, so the recorded pose and sample line up exactly
+fn timestamp_of(time: chrono::DateTime<chrono::Utc>) -> foxglove::schemas::Timestamp {
+    foxglove::schemas::Timestamp::new(time.timestamp() as u32, time.timestamp_subsec_nanos())
+}
+
+fn location_fix(
+    vehicle_data: &VehicleData,
+    timestamp: foxglove::schemas::Timestamp,
+) -> Option<foxglove::schemas::LocationFix> {
+    if vehicle_data.lat == 0.0 && vehicle_data.lon == 0.0 {
+        return None;
+    }
+    Some(foxglove::schemas::LocationFix {
+        timestamp: Some(timestamp),
+        frame_id: "vehicle".to_string(),
+        latitude: vehicle_data.lat,
+        longitude: vehicle_data.lon,
+        altitude: vehicle_data.alt,
+        ..Default::default()
+    })
+}
+
+// ROS 2 recordings only have room for text, the event goes there as JSON
+fn log_event(channel: &NoteChannel<events::RecordingEvent>, event: &events::RecordingEvent) {
+    channel.log(
+        event,
+        &serde_json::to_string(event).unwrap_or_default(),
+        foxglove::schemas::Timestamp::now(),
+    );
+}
+
+enum SonarData {
+    Ping1D(ProfileStruct),
+    Ping360(AutoDeviceDataStruct),
+}
+
+fn decode_sonar_message(msg: &bluerobotics_ping::message::ProtocolMessage) -> Option<SonarData> {
+    match bluerobotics_ping::Messages::try_from(msg) {
+        Ok(bluerobotics_ping::Messages::Ping360(
+            bluerobotics_ping::ping360::Messages::AutoDeviceData(answer),
+        )) => Some(SonarData::Ping360(answer)),
+        Ok(bluerobotics_ping::Messages::Ping360(
+            bluerobotics_ping::ping360::Messages::DeviceData(answer),
+        )) => Some(SonarData::Ping360(AutoDeviceDataStruct {
+            mode: answer.mode,
+            gain_setting: answer.gain_setting,
+            angle: answer.angle,
+            transmit_duration: answer.transmit_duration,
+            sample_period: answer.sample_period,
+            transmit_frequency: answer.transmit_frequency,
+            start_angle: 0,
+            stop_angle: 399,
+            num_steps: 1,
+            delay: 0,
+            number_of_samples: answer.number_of_samples,
+            data_length: answer.number_of_samples,
+            data: answer.data,
+        })),
+        Ok(bluerobotics_ping::Messages::Ping1D(bluerobotics_ping::ping1d::Messages::Profile(
+            answer,
+        ))) => Some(SonarData::Ping1D(answer)),
+        _ => None,
+    }
+}
+
+// device_<id>_<time>.mcap continues in device_<id>_<time>_002.mcap and so on
+fn rotated_path(first_path: &Path, part: u32) -> PathBuf {
+    let stem = first_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    first_path.with_file_name(format!("{stem}_{part:03}.mcap"))
+}
+
+/// Every file below the recordings directory, including the date subdirectories
+pub fn recording_files(base_path: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut directories = vec![base_path.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            // Symlinks are not followed, so nothing outside the directory is ever listed
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => directories.push(entry.path()),
+                Ok(file_type) if file_type.is_file() => files.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// Remove the date subdirectories left empty by a removed recording, never the base itself
+pub fn remove_empty_parents(base_path: &Path, file_path: &Path) {
+    let mut directory = file_path.parent();
+    while let Some(current) = directory {
+        if current == base_path || !current.starts_with(base_path) {
+            break;
+        }
+        // Fails on the first directory still holding other recordings
+        if std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        directory = current.parent();
+    }
+}
+
+pub(crate) async fn device_subscriber(
+    devices_manager_handler: &ManagerActorHandler,
+    device_id: Uuid,
+) -> Result<Receiver<bluerobotics_ping::message::ProtocolMessage>, ManagerError> {
+    let handler = match devices_manager_handler
+        .send(crate::device::manager::Request::GetDeviceHandler(
+            crate::device::manager::UuidWrapper { uuid: device_id },
+        ))
+        .await?
+    {
+        crate::device::manager::Answer::InnerDeviceHandler(handler) => handler,
+        _ => return Err(ManagerError::Other("Invalid device handler".to_string())),
+    };
+
+    match handler
+        .send(super::devices::PingRequest::GetSubscriber)
+        .await
+        .map_err(ManagerError::DeviceError)?
+    {
+        super::devices::PingAnswer::Subscriber(subscriber) => Ok(subscriber),
+        answer => Err(ManagerError::Other(format!(
+            "Unexpected answer while subscribing to device {device_id}: {answer:?}"
+        ))),
+    }
+}
+
+// Active recordings keep the device out of idle auto-sleep
+async fn hold_awake(devices_manager_handler: &ManagerActorHandler, device_id: Uuid, hold: bool) {
+    let request =
+        crate::device::manager::Request::KeepAwake(crate::device::manager::idle::KeepAwake {
+            uuid: device_id,
+            hold,
+        });
+    if let Err(err) = devices_manager_handler.send(request).await {
+        warn!("Failed to update idle state of device {device_id}, details: {err:?}");
+    }
 }
 
 impl RecordingsManagerHandler {
+    /// Directory the manager writes recordings to, shared with the file routes
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Requests waiting for the manager, a growing value means it cannot keep up
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
     pub async fn send(&self, request: RecordingManagerCommand) -> Result<Answer, ManagerError> {
         let (result_sender, result_receiver) = oneshot::channel();
 
@@ -385,6 +1967,7 @@ impl RecordingsManagerHandler {
         let device_request = ManagerActorRequest {
             request,
             respond_to: result_sender,
+            span: tracing::Span::current(),
         };
 
         self.sender
@@ -407,3 +1990,244 @@ impl RecordingsManagerHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_name_cannot_escape_directory() {
+        assert_eq!(
+            sanitize_session_name("survey pierA").as_deref(),
+            Some("survey-pierA")
+        );
+        assert_eq!(
+            sanitize_session_name("../../etc/passwd").as_deref(),
+            Some("etc-passwd")
+        );
+        assert_eq!(sanitize_session_name("/.."), None);
+    }
+
+    #[test]
+    fn test_location_fix_waits_for_a_position() {
+        let mut vehicle = VehicleData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+            alt: -12.5,
+            lat: 0.0,
+            lon: 0.0,
+            heading: None,
+            groundspeed: None,
+            depth: None,
+            local_position: None,
+            velocity: None,
+            timestamp_ms: 0,
+        };
+        let timestamp = foxglove::schemas::Timestamp::new(1, 0);
+        assert!(location_fix(&vehicle, timestamp).is_none());
+
+        vehicle.lat = -27.59;
+        vehicle.lon = -48.55;
+        let fix = location_fix(&vehicle, timestamp).unwrap();
+        assert_eq!(fix.latitude, -27.59);
+        assert_eq!(fix.altitude, -12.5);
+    }
+
+    #[test]
+    fn test_group_logs_the_vehicle_once() {
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.mcap", Uuid::new_v4()));
+        let ctx = Context::new();
+        let writer =
+            McapFileWriter::create(&ctx, &path, RecordingFormat::Mcap.write_options()).unwrap();
+        let vehicle = VehicleData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+            alt: 0.0,
+            lat: -27.59,
+            lon: -48.55,
+            heading: None,
+            groundspeed: None,
+            depth: None,
+            local_position: None,
+            velocity: None,
+            timestamp_ms: 0,
+        };
+        let timestamp = foxglove::schemas::Timestamp::new(1, 0);
+        for index in 0..2 {
+            let device_id = Uuid::new_v4();
+            let scope = VehicleScope::of(true, index);
+            let channels =
+                RecordingChannels::new(&ctx, device_id, RecordingFormat::Mcap, false, scope)
+                    .unwrap();
+            let mut mavlink = mavlink::MavlinkChannels::new(
+                &ctx,
+                scope
+                    .map(|scope| scope.prefix(device_id, RecordingFormat::Mcap))
+                    .unwrap_or_default(),
+                scope.map(|_| vec!["*".to_string()]).unwrap_or_default(),
+            );
+            channels.log_vehicle(&vehicle, timestamp);
+            mavlink.log(&crate::vehicle::stream::MavlinkSample {
+                system_id: 1,
+                component_id: 1,
+                name: "ATTITUDE".to_string(),
+                timestamp_ms: 1000,
+                message: serde_json::json!({}),
+            });
+        }
+        writer.close().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut topics: Vec<String> = mcap::MessageStream::new(&data)
+            .unwrap()
+            .map(|message| message.unwrap().channel.topic.clone())
+            .collect();
+        topics.sort();
+        assert_eq!(
+            topics,
+            [
+                "vehicle/Location",
+                "vehicle/MAVLink/ATTITUDE",
+                "vehicle/VehicleData"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotated_file_has_transform_and_metadata() {
+        let directory = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let first_path = directory.join("device_test.mcap");
+        let device_id = Uuid::new_v4();
+        let format = RecordingFormat::Mcap;
+
+        let ctx = Context::new();
+        let writer = McapFileWriter::create(&ctx, &first_path, format.write_options()).unwrap();
+        let metadata = vec![(
+            "device".to_string(),
+            BTreeMap::from([("device_type".to_string(), "Ping360".to_string())]),
+        )];
+        for (name, metadata) in &metadata {
+            writer.write_metadata(name, metadata.clone()).unwrap();
+        }
+        let transforms = mounting::TransformChannel::new(
+            &ctx,
+            device_id,
+            format,
+            mounting::MountingPose::default(),
+        )
+        .unwrap();
+        transforms.log(None, foxglove::schemas::Timestamp::now());
+
+        let session = RecordingSession {
+            device_id,
+            file_path: first_path.clone(),
+            is_active: true,
+            start_time: chrono::Utc::now(),
+            device_type: DeviceSelection::Ping360,
+            name: None,
+            format,
+            group_id: None,
+            bookmarks: Vec::new(),
+        };
+        let sessions = RwLock::new(HashMap::from([(
+            device_id,
+            SessionGuard {
+                session,
+                writer: Arc::new(std::sync::Mutex::new(Some(writer))),
+                metadata,
+                annotations: NoteChannel::new(&ctx, device_id, format, "Annotations").unwrap(),
+                events: NoteChannel::new(&ctx, device_id, format, "Events").unwrap(),
+            },
+        )]));
+
+        let session = RecordingManager::rotate_file(
+            &sessions,
+            device_id,
+            &ctx,
+            &first_path,
+            2,
+            format,
+            Some(&transforms),
+        )
+        .await
+        .unwrap();
+        let writer = sessions.write().await.remove(&device_id).unwrap().writer;
+        let writer = writer.lock().unwrap().take().unwrap();
+        writer.close().unwrap();
+
+        let data = std::fs::read(&session.file_path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let summary = mcap::Summary::read(&data).unwrap().unwrap();
+        let names: Vec<&str> = summary
+            .metadata_indexes
+            .iter()
+            .map(|index| index.name.as_str())
+            .collect();
+        assert!(names.contains(&"device"));
+        assert!(names.contains(&"rotation"));
+        let topics: Vec<String> = mcap::MessageStream::new(&data)
+            .unwrap()
+            .map(|message| message.unwrap().channel.topic.clone())
+            .collect();
+        assert_eq!(topics, [format!("device_{device_id}/FrameTransforms")]);
+    }
+
+    #[tokio::test]
+    async fn test_recording_of_deleted_device_is_finalized() {
+        let directory = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("device_test.mcap");
+        let device_id = Uuid::new_v4();
+        let format = RecordingFormat::Mcap;
+
+        // The device is gone, its manager does not answer anymore
+        let (device_sender, _) = mpsc::channel(1);
+        let (mut manager, _) = RecordingManager::new(
+            1,
+            &directory,
+            ManagerActorHandler {
+                sender: device_sender,
+            },
+        );
+        let ctx = Context::new();
+        let writer = McapFileWriter::create(&ctx, &path, format.write_options()).unwrap();
+        manager.sessions.write().await.insert(
+            device_id,
+            SessionGuard {
+                session: RecordingSession {
+                    device_id,
+                    file_path: path.clone(),
+                    is_active: true,
+                    start_time: chrono::Utc::now(),
+                    device_type: DeviceSelection::Ping360,
+                    name: None,
+                    format,
+                    group_id: None,
+                    bookmarks: Vec::new(),
+                },
+                writer: Arc::new(std::sync::Mutex::new(Some(writer))),
+                metadata: Vec::new(),
+                annotations: NoteChannel::new(&ctx, device_id, format, "Annotations").unwrap(),
+                events: NoteChannel::new(&ctx, device_id, format, "Events").unwrap(),
+            },
+        );
+
+        manager.stop_deleted_device(device_id).await;
+
+        let sessions = manager.sessions.read().await;
+        let guard = sessions.get(&device_id).unwrap();
+        assert!(!guard.session.is_active);
+        assert!(guard.writer.lock().unwrap().is_none());
+        drop(sessions);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(mcap::Summary::read(&data).unwrap().is_some());
+
+        // Deleting it again once stopped is a no-op
+        manager.stop_deleted_device(device_id).await;
+    }
+}
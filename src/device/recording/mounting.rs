@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use foxglove::{
+    schemas::{FrameTransform, FrameTransforms, Quaternion, Timestamp, Vector3},
+    Context, RawChannel,
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::manager::ManagerError;
+use crate::vehicle::VehicleData;
+
+use super::{ros2, RecordingFormat};
+
+pub const WORLD_FRAME: &str = "world";
+pub const VEHICLE_FRAME: &str = "vehicle";
+
+/// Sonar pose in the vehicle frame, x forward, y right and z down as in MAVLink body frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub struct MountingPose {
+    /// Offset from the vehicle origin in meters
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// Rotation from the vehicle frame in degrees
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+impl std::str::FromStr for MountingPose {
+    type Err = String;
+
+    /// Parse `X,Y,Z,ROLL,PITCH,YAW`, e.g. `0.3,0,0.1,0,90,0` for a downward looking sonar
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let values = value
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|err| format!("Invalid mounting pose {value:?}: {err}"))?;
+        let [x, y, z, roll, pitch, yaw] = values[..] else {
+            return Err(format!(
+                "Invalid mounting pose {value:?}: expected X,Y,Z,ROLL,PITCH,YAW"
+            ));
+        };
+        Ok(Self {
+            x,
+            y,
+            z,
+            roll,
+            pitch,
+            yaw,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct MountingRequest {
+    pub device_id: Uuid,
+    /// None goes back to the `--sonar-mount` default
+    pub pose: Option<MountingPose>,
+}
+
+/// `world -> vehicle -> device_<id>` transforms of a recording, for 3D playback of the scans
+pub struct TransformChannel {
+    output: TransformOutput,
+    child_frame: String,
+    mount: MountingPose,
+}
+
+enum TransformOutput {
+    Json(foxglove::Channel<FrameTransforms>),
+    Ros2(Arc<RawChannel>),
+}
+
+impl TransformChannel {
+    pub fn new(
+        ctx: &Arc<Context>,
+        device_id: Uuid,
+        format: RecordingFormat,
+        mount: MountingPose,
+    ) -> Result<Self, ManagerError> {
+        let output = match format {
+            RecordingFormat::Mcap => TransformOutput::Json(
+                ctx.channel_builder(&format!("device_{}/FrameTransforms", device_id))
+                    .build::<FrameTransforms>(),
+            ),
+            RecordingFormat::Ros2 => TransformOutput::Ros2(ros2::tf_channel(ctx)?),
+        };
+        Ok(Self {
+            output,
+            // Same frame as the ROS 2 point clouds, so both formats line up in the 3D panel
+            child_frame: ros2::frame_id(device_id),
+            mount,
+        })
+    }
+
+    /// The mounting transform, and the vehicle attitude once it is known
+    pub fn log(&self, vehicle: Option<&VehicleData>, timestamp: Timestamp) {
+        let transforms = transforms(&self.child_frame, &self.mount, vehicle, timestamp);
+        match &self.output {
+            TransformOutput::Json(channel) => channel.log_with_time(&transforms, timestamp),
+            TransformOutput::Ros2(channel) => ros2::log_transforms(channel, &transforms, timestamp),
+        }
+    }
+}
+
+fn transforms(
+    child_frame: &str,
+    mount: &MountingPose,
+    vehicle: Option<&VehicleData>,
+    timestamp: Timestamp,
+) -> FrameTransforms {
+    let mut transforms = vec![frame_transform(
+        VEHICLE_FRAME,
+        child_frame,
+        Vector3 {
+            x: mount.x,
+            y: mount.y,
+            z: mount.z,
+        },
+        [mount.roll, mount.pitch, mount.yaw].map(|angle| angle.to_radians() as f32),
+        timestamp,
+    )];
+    // Attitude only, the position is on the LocationFix channel
+    if let Some(vehicle) = vehicle {
+        transforms.push(frame_transform(
+            WORLD_FRAME,
+            VEHICLE_FRAME,
+            Vector3::default(),
+            [vehicle.roll, vehicle.pitch, vehicle.yaw],
+            timestamp,
+        ));
+    }
+    FrameTransforms { transforms }
+}
+
+fn frame_transform(
+    parent: &str,
+    child: &str,
+    translation: Vector3,
+    [roll, pitch, yaw]: [f32; 3],
+    timestamp: Timestamp,
+) -> FrameTransform {
+    let [x, y, z, w] = ros2::quaternion(roll, pitch, yaw);
+    FrameTransform {
+        timestamp: Some(timestamp),
+        parent_frame_id: parent.to_string(),
+        child_frame_id: child.to_string(),
+        translation: Some(translation),
+        rotation: Some(Quaternion { x, y, z, w }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mounting_pose() {
+        let pose: MountingPose = "0.3, 0, 0.1, 0, 90, 0".parse().unwrap();
+        assert_eq!(pose.x, 0.3);
+        assert_eq!(pose.pitch, 90.0);
+        assert!("0.3,0,0.1".parse::<MountingPose>().is_err());
+        assert!("a,b,c,d,e,f".parse::<MountingPose>().is_err());
+    }
+
+    #[test]
+    fn test_vehicle_transform_follows_the_mount() {
+        let timestamp = Timestamp::new(1, 0);
+        let mount = MountingPose::default();
+        let only_mount = transforms("device_1", &mount, None, timestamp);
+        assert_eq!(only_mount.transforms.len(), 1);
+        assert_eq!(only_mount.transforms[0].parent_frame_id, VEHICLE_FRAME);
+
+        let vehicle = VehicleData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+            alt: 0.0,
+            lat: 0.0,
+            lon: 0.0,
+            heading: None,
+            groundspeed: None,
+            depth: None,
+            local_position: None,
+            velocity: None,
+            timestamp_ms: 0,
+        };
+        let with_vehicle = transforms("device_1", &mount, Some(&vehicle), timestamp);
+        assert_eq!(with_vehicle.transforms[1].parent_frame_id, WORLD_FRAME);
+        assert_eq!(with_vehicle.transforms[1].rotation.as_ref().unwrap().w, 1.0);
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, time::Duration};
+
+use bluerobotics_ping::{message::ProtocolMessage, ping1d, Messages};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, UdpSocket},
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::device::manager::{ManagerActorHandler, ManagerError};
+
+use super::{device_subscriber, udp::streaming_devices};
+
+// How often the devices to read depths from are refreshed from the DeviceManager
+const NMEA_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+// Below this the Ping1D is guessing, chartplotters handle a missing depth better than a wrong one
+const NMEA_MIN_CONFIDENCE: u16 = 50;
+// Sentences waiting for slow TCP clients, older ones are dropped
+const NMEA_QUEUE_SIZE: usize = 64;
+// Sounder talker id
+const TALKER: &str = "SD";
+
+/// Serves the Ping1D depths as NMEA 0183 DBT and DPT sentences, for chartplotters and hydrographic software
+pub struct NmeaOutput {
+    devices_manager_handler: ManagerActorHandler,
+    /// Meters from the transducer to the waterline (positive) or the keel (negative), sent in DPT
+    transducer_offset: f64,
+    sentences: broadcast::Sender<String>,
+    readers: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl NmeaOutput {
+    pub fn new(devices_manager_handler: ManagerActorHandler, transducer_offset: f64) -> Self {
+        Self {
+            devices_manager_handler,
+            transducer_offset,
+            sentences: broadcast::channel(NMEA_QUEUE_SIZE).0,
+            readers: HashMap::new(),
+        }
+    }
+
+    /// Listen for TCP clients on `tcp` and send every sentence to the `udp` destination, either is optional
+    pub async fn run(
+        mut self,
+        tcp: Option<String>,
+        udp: Option<String>,
+    ) -> Result<(), ManagerError> {
+        if let Some(address) = tcp {
+            let listener = TcpListener::bind(&address).await.map_err(|err| {
+                ManagerError::Other(format!("Failed to listen on NMEA TCP {address}: {err}"))
+            })?;
+            info!("NmeaOutput: Serving depth sentences on TCP {address}");
+            tokio::spawn(serve_tcp(listener, self.sentences.clone()));
+        }
+        if let Some(target) = udp {
+            let local = if target.starts_with('[') {
+                "[::]:0"
+            } else {
+                "0.0.0.0:0"
+            };
+            let socket = UdpSocket::bind(local)
+                .await
+                .map_err(|err| ManagerError::Other(format!("Failed to open UDP socket: {err}")))?;
+            socket.connect(&target).await.map_err(|err| {
+                ManagerError::Other(format!("Invalid NMEA UDP target {target:?}: {err}"))
+            })?;
+            // Broadcast addresses are the usual destination on boat networks
+            socket
+                .set_broadcast(true)
+                .map_err(|err| ManagerError::Other(format!("Failed to enable broadcast: {err}")))?;
+            info!("NmeaOutput: Sending depth sentences to UDP {target}");
+            tokio::spawn(send_udp(socket, self.sentences.subscribe()));
+        }
+
+        let mut refresh_interval = tokio::time::interval(NMEA_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_readers().await;
+        }
+    }
+
+    async fn refresh_readers(&mut self) {
+        let streaming = streaming_devices(&self.devices_manager_handler).await;
+
+        self.readers.retain(|device_id, reader| {
+            let keep = streaming.contains(device_id) && !reader.is_finished();
+            if !keep {
+                reader.abort();
+            }
+            keep
+        });
+
+        for device_id in streaming {
+            if self.readers.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver =
+                match device_subscriber(&self.devices_manager_handler, device_id).await {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        warn!("NmeaOutput: Failed to subscribe to device {device_id}: {err:?}");
+                        continue;
+                    }
+                };
+
+            let sentences = self.sentences.clone();
+            let transducer_offset = self.transducer_offset;
+            let reader = tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            let Some(depth) = Depth::from_message(&msg) else {
+                                continue;
+                            };
+                            // No listener is not an error, sentences are only sent to whoever is connected
+                            let _ = sentences.send(depth.dbt());
+                            let _ = sentences.send(depth.dpt(transducer_offset));
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("NmeaOutput: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            self.readers.insert(device_id, reader);
+        }
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, sentences: broadcast::Sender<String>) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("NmeaOutput: TCP accept failed: {err}");
+                continue;
+            }
+        };
+        debug!("NmeaOutput: TCP client {peer} connected");
+        let mut receiver = sentences.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(sentence) => {
+                        if stream.write_all(sentence.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            debug!("NmeaOutput: TCP client {peer} disconnected");
+        });
+    }
+}
+
+async fn send_udp(socket: UdpSocket, mut sentences: broadcast::Receiver<String>) {
+    loop {
+        match sentences.recv().await {
+            Ok(sentence) => {
+                if let Err(err) = socket.send(sentence.as_bytes()).await {
+                    debug!("NmeaOutput: Failed to send sentence: {err}");
+                }
+            }
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Depth {
+    /// Meters below the transducer
+    meters: f64,
+    /// End of the scanned range in meters, when the message carries it
+    max_range: Option<f64>,
+}
+
+impl Depth {
+    fn from_message(msg: &ProtocolMessage) -> Option<Self> {
+        let (distance, confidence, max_range) = match Messages::try_from(msg).ok()? {
+            Messages::Ping1D(ping1d::Messages::Profile(profile)) => (
+                profile.distance,
+                u16::from(profile.confidence),
+                Some(profile.scan_start + profile.scan_length),
+            ),
+            Messages::Ping1D(ping1d::Messages::Distance(distance)) => (
+                distance.distance,
+                u16::from(distance.confidence),
+                Some(distance.scan_start + distance.scan_length),
+            ),
+            Messages::Ping1D(ping1d::Messages::DistanceSimple(distance)) => {
+                (distance.distance, u16::from(distance.confidence), None)
+            }
+            _ => return None,
+        };
+        if confidence < NMEA_MIN_CONFIDENCE {
+            return None;
+        }
+        Some(Self {
+            meters: distance as f64 / 1000.0,
+            max_range: max_range.map(|range| range as f64 / 1000.0),
+        })
+    }
+
+    /// Depth below transducer in feet, meters and fathoms
+    fn dbt(&self) -> String {
+        sentence(&format!(
+            "{TALKER}DBT,{:.1},f,{:.2},M,{:.1},F",
+            self.meters * 3.28084,
+            self.meters,
+            self.meters * 0.546807
+        ))
+    }
+
+    /// Depth below transducer with the transducer offset and the maximum range
+    fn dpt(&self, transducer_offset: f64) -> String {
+        let max_range = self
+            .max_range
+            .map(|range| format!("{range:.1}"))
+            .unwrap_or_default();
+        sentence(&format!(
+            "{TALKER}DPT,{:.2},{transducer_offset:.2},{max_range}",
+            self.meters
+        ))
+    }
+}
+
+// Framed with the XOR checksum of the characters between $ and *
+fn sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+    format!("${body}*{checksum:02X}\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_sentences() {
+        let depth = Depth {
+            meters: 12.34,
+            max_range: Some(30.0),
+        };
+        assert_eq!(depth.dbt(), "$SDDBT,40.5,f,12.34,M,6.7,F*32\r\n");
+        assert_eq!(depth.dpt(0.5), "$SDDPT,12.34,0.50,30.0*57\r\n");
+
+        let without_range = Depth {
+            meters: 1.0,
+            max_range: None,
+        };
+        assert!(without_range.dpt(0.0).starts_with("$SDDPT,1.00,0.00,*"));
+    }
+
+    #[test]
+    fn test_low_confidence_is_skipped() {
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&ping1d::Messages::DistanceSimple(
+            ping1d::DistanceSimpleStruct {
+                distance: 2500,
+                confidence: 100,
+            },
+        ));
+        assert_eq!(
+            Depth::from_message(&msg),
+            Some(Depth {
+                meters: 2.5,
+                max_range: None
+            })
+        );
+
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&ping1d::Messages::DistanceSimple(
+            ping1d::DistanceSimpleStruct {
+                distance: 2500,
+                confidence: 10,
+            },
+        ));
+        assert_eq!(Depth::from_message(&msg), None);
+    }
+}
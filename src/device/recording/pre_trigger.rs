@@ -0,0 +1,166 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bluerobotics_ping::message::ProtocolMessage;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{debug, trace, warn};
+use uuid::Uuid;
+
+// How often the devices to buffer are refreshed from the DeviceManager
+pub const PRE_TRIGGER_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+
+pub struct BufferedMessage {
+    pub captured: Instant,
+    pub timestamp: foxglove::schemas::Timestamp,
+    pub message: ProtocolMessage,
+}
+
+/// Last messages of a device, shared between the buffering task and the recording task
+#[derive(Clone)]
+pub struct PreTriggerHistory {
+    window: Duration,
+    messages: Arc<Mutex<VecDeque<BufferedMessage>>>,
+}
+
+impl PreTriggerHistory {
+    fn push(&self, message: ProtocolMessage) {
+        let mut messages = self.messages.lock().unwrap();
+        messages.push_back(BufferedMessage {
+            captured: Instant::now(),
+            timestamp: foxglove::schemas::Timestamp::now(),
+            message,
+        });
+        prune(&mut messages, self.window);
+    }
+
+    /// Drain the buffered messages captured before `instant`, later ones reach the live subscriber
+    pub fn take_before(&self, instant: Instant) -> Vec<BufferedMessage> {
+        let mut messages = self.messages.lock().unwrap();
+        prune(&mut messages, self.window);
+        let messages: Vec<BufferedMessage> = messages.drain(..).collect();
+        messages
+            .into_iter()
+            .filter(|buffered| buffered.captured < instant)
+            .collect()
+    }
+}
+
+fn prune(messages: &mut VecDeque<BufferedMessage>, window: Duration) {
+    while messages
+        .front()
+        .is_some_and(|buffered| buffered.captured.elapsed() > window)
+    {
+        messages.pop_front();
+    }
+}
+
+/// Keeps the last `window` of a device stream in memory while it is not recording
+pub struct PreTriggerBuffer {
+    history: PreTriggerHistory,
+    task: JoinHandle<()>,
+}
+
+impl PreTriggerBuffer {
+    pub fn spawn(
+        device_id: Uuid,
+        window: Duration,
+        mut receiver: broadcast::Receiver<ProtocolMessage>,
+    ) -> Self {
+        let history = PreTriggerHistory {
+            window,
+            messages: Arc::new(Mutex::new(VecDeque::new())),
+        };
+
+        let task_history = history.clone();
+        let task = tokio::spawn(async move {
+            debug!("Pre-trigger buffer of {device_id} started");
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => task_history.push(message),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Pre-trigger buffer of {device_id} lagged by {skipped} messages")
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            trace!("Pre-trigger buffer of {device_id} stopped");
+        });
+
+        Self { history, task }
+    }
+
+    pub fn history(&self) -> PreTriggerHistory {
+        self.history.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Drop for PreTriggerBuffer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bluerobotics_ping::{common, Messages};
+
+    use super::*;
+
+    fn history(window: Duration) -> PreTriggerHistory {
+        PreTriggerHistory {
+            window,
+            messages: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    fn message(requested_id: u16) -> ProtocolMessage {
+        let mut message = ProtocolMessage::new();
+        message.set_message(&common::Messages::GeneralRequest(
+            common::GeneralRequestStruct { requested_id },
+        ));
+        message
+    }
+
+    fn requested_ids(messages: &[BufferedMessage]) -> Vec<u16> {
+        messages
+            .iter()
+            .map(|buffered| match Messages::try_from(&buffered.message) {
+                Ok(Messages::Common(common::Messages::GeneralRequest(request))) => {
+                    request.requested_id
+                }
+                _ => panic!("Unexpected message"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_messages_older_than_the_window_are_evicted() {
+        let history = history(Duration::from_millis(50));
+        history.push(message(1));
+        std::thread::sleep(Duration::from_millis(100));
+        history.push(message(2));
+
+        let buffered = history.take_before(Instant::now() + Duration::from_secs(1));
+        assert_eq!(requested_ids(&buffered), [2]);
+        assert!(history.take_before(Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn test_messages_after_the_start_are_left_to_the_subscriber() {
+        let history = history(Duration::from_secs(10));
+        history.push(message(1));
+        std::thread::sleep(Duration::from_millis(1));
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(1));
+        history.push(message(2));
+
+        assert_eq!(requested_ids(&history.take_before(start)), [1]);
+    }
+}
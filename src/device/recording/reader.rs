@@ -0,0 +1,140 @@
+use std::{fs::File, ops::Deref, path::Path};
+
+use memmap2::Mmap;
+
+use crate::device::manager::ManagerError;
+
+use super::recovery;
+
+/// A recording for the MCAP readers, mapped into memory when it is finalized so the pages are read
+/// from disk as the reader reaches them, instead of loading files larger than the companion memory
+pub struct RecordingFile {
+    data: RecordingData,
+}
+
+enum RecordingData {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl RecordingFile {
+    pub fn open(path: &Path) -> Result<Self, ManagerError> {
+        let error = |err: std::io::Error| {
+            ManagerError::Other(format!("Failed to read recording {path:?}: {err}"))
+        };
+
+        // Files of active sessions are patched in place by the writer and crashed ones are replaced by
+        // recovery, a snapshot of them is read instead
+        if recovery::is_unfinished(path) {
+            return Ok(Self {
+                data: RecordingData::Read(std::fs::read(path).map_err(error)?),
+            });
+        }
+
+        let file = File::open(path).map_err(error)?;
+        // Finalized files are never written again, nor truncated: retention, deletion and recovery
+        // only unlink them, which keeps the mapped pages of the open file valid
+        let map = unsafe { Mmap::map(&file) }.map_err(error)?;
+        Ok(Self {
+            data: RecordingData::Mapped(map),
+        })
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.data, RecordingData::Mapped(_))
+    }
+
+    /// Messages listed in the summary, none for files without a footer
+    pub fn message_count(&self) -> Option<u64> {
+        mcap::Summary::read(self)
+            .ok()
+            .flatten()
+            .and_then(|summary| summary.stats)
+            .map(|stats| stats.message_count)
+            .filter(|count| *count > 0)
+    }
+}
+
+impl Deref for RecordingFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.data {
+            RecordingData::Mapped(map) => map,
+            RecordingData::Read(data) => data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::BufWriter};
+
+    use super::*;
+
+    fn write_recording(path: &Path, messages: u32) {
+        let mut writer = mcap::Writer::new(BufWriter::new(File::create(path).unwrap())).unwrap();
+        let channel_id = writer
+            .add_channel(0, "/test", "json", &BTreeMap::new())
+            .unwrap();
+        for sequence in 0..messages {
+            writer
+                .write_to_known_channel(
+                    &mcap::records::MessageHeader {
+                        channel_id,
+                        sequence,
+                        log_time: sequence as u64,
+                        publish_time: sequence as u64,
+                    },
+                    b"{}",
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn temp_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ping-viewer-{}.mcap", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_finalized_recording_is_mapped() {
+        let path = temp_path();
+        write_recording(&path, 3);
+
+        let file = RecordingFile::open(&path).unwrap();
+        assert!(file.is_mapped());
+        assert_eq!(file.message_count(), Some(3));
+        assert_eq!(mcap::MessageStream::new(&file).unwrap().count(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unfinished_recording_is_read() {
+        let path = temp_path();
+        write_recording(&path, 3);
+        let data = std::fs::read(&path).unwrap();
+        // Cut inside the summary, as a session still being written
+        std::fs::write(&path, &data[..data.len() - 40]).unwrap();
+
+        let file = RecordingFile::open(&path).unwrap();
+        assert!(!file.is_mapped());
+        assert_eq!(file.message_count(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_recording_survives_removal() {
+        let path = temp_path();
+        write_recording(&path, 2);
+
+        let file = RecordingFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mcap::MessageStream::new(&file).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_missing_recording_fails() {
+        assert!(RecordingFile::open(&temp_path()).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use mcap::{
+    records::{MessageHeader, Record},
+    sans_io::linear_reader::{LinearReadEvent, LinearReader},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::device::manager::ManagerError;
+
+const LOCK_FILE: &str = ".ping-viewer-next.lock";
+const FOOTER_OPCODE: u8 = 0x02;
+
+#[derive(Debug, Clone)]
+pub struct RecoveredFile {
+    pub path: PathBuf,
+    pub messages: u64,
+}
+
+// Footer record, opcode, length, summary start, summary offset start and summary CRC, then the magic
+const FOOTER_LEN: usize = 1 + 8 + 20;
+
+/// A finalized MCAP ends with its footer and the magic bytes, crashed sessions end anywhere
+pub fn is_unfinished(path: &Path) -> bool {
+    let mut tail = [0u8; FOOTER_LEN + 8];
+    let mut read_tail = || -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::End(-(tail.len() as i64)))?;
+        file.read_exact(&mut tail)
+    };
+    // Too short to even hold the footer is as unfinished as it gets
+    if read_tail().is_err() {
+        return true;
+    }
+    let (footer, magic) = tail.split_at(FOOTER_LEN);
+    magic != mcap::MAGIC || footer[0] != FOOTER_OPCODE || footer[1..9] != 20u64.to_le_bytes()
+}
+
+/// Held by the process that recovers the directory, until it exits. `None` when another `serve` or
+/// `record` already holds it, their files being written look unfinished too
+pub fn lock_directory(base_path: &Path) -> Option<File> {
+    let path = base_path.join(LOCK_FILE);
+    let file = std::fs::create_dir_all(base_path)
+        .and_then(|_| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+        })
+        .inspect_err(|err| warn!("Recovery: Failed to open lock file {path:?}: {err}"))
+        .ok()?;
+    match file.try_lock() {
+        Ok(()) => Some(file),
+        Err(err) => {
+            debug!("Recovery: {path:?} is locked by another process: {err}");
+            None
+        }
+    }
+}
+
+pub fn unfinished_recordings(base_path: &Path) -> Vec<PathBuf> {
+    let unfinished: Vec<PathBuf> = super::recording_files(base_path)
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mcap"))
+        .filter(|path| is_unfinished(path))
+        .collect();
+    debug!(
+        "Recovery: Found {} unfinished recordings in {base_path:?}",
+        unfinished.len()
+    );
+    unfinished
+}
+
+/// Rewrite every readable message and metadata of a crashed recording into a finalized file,
+/// streaming the records so files larger than the memory can be recovered
+pub fn recover(path: &Path, progress: impl FnMut(f64)) -> Result<RecoveredFile, ManagerError> {
+    let file = File::open(path)
+        .map_err(|err| ManagerError::Other(format!("Failed to read recording {path:?}: {err}")))?;
+    // A finalized file is left untouched, rewriting it would only lose its summary and attachments
+    if !is_unfinished(path) {
+        return Err(ManagerError::Other(format!(
+            "Recording {path:?} is already finalized"
+        )));
+    }
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    // Without a valid header there is nothing to recover, leave the file untouched
+    let mut header = [0u8; 8];
+    let mut input = BufReader::new(file);
+    if input.read_exact(&mut header).is_err() || header != mcap::MAGIC {
+        return Err(ManagerError::Other(format!("Invalid MCAP file {path:?}")));
+    }
+    input
+        .rewind()
+        .map_err(|err| ManagerError::Other(format!("Failed to read recording {path:?}: {err}")))?;
+
+    let recovering = path.with_extension("mcap.recovering");
+    let messages = match copy_readable(input, size, &recovering, progress) {
+        Ok(messages) => messages,
+        Err(err) => {
+            let _ = std::fs::remove_file(&recovering);
+            return Err(err);
+        }
+    };
+
+    std::fs::rename(&recovering, path).map_err(|err| {
+        ManagerError::Other(format!(
+            "Failed to replace {path:?} with recovered file: {err}"
+        ))
+    })?;
+    info!("Recovery: Finalized {path:?} with {messages} messages");
+
+    Ok(RecoveredFile {
+        path: path.to_path_buf(),
+        messages,
+    })
+}
+
+fn copy_readable(
+    mut input: impl Read,
+    size: u64,
+    recovering: &Path,
+    mut progress: impl FnMut(f64),
+) -> Result<u64, ManagerError> {
+    let output = File::create(recovering)
+        .map_err(|err| ManagerError::Other(format!("Failed to create {recovering:?}: {err}")))?;
+    let mut writer = mcap::Writer::new(BufWriter::new(output))
+        .map_err(|err| ManagerError::Other(format!("Failed to start {recovering:?}: {err}")))?;
+
+    // Schemas and channels of the crashed file to the ones of the recovered file
+    let mut schemas: HashMap<u16, u16> = HashMap::new();
+    let mut channels: HashMap<u16, u16> = HashMap::new();
+    let mut messages = 0;
+    let mut read_bytes = 0u64;
+
+    let mut reader = LinearReader::new();
+    while let Some(event) = reader.next_event() {
+        let event = match event {
+            Ok(event) => event,
+            // The crash cut the file somewhere, everything before is kept
+            Err(err) => {
+                debug!("Recovery: stopped reading at message {messages}: {err}");
+                break;
+            }
+        };
+        let (opcode, data) = match event {
+            LinearReadEvent::ReadRequest(need) => {
+                let read = input.read(reader.insert(need)).map_err(|err| {
+                    ManagerError::Other(format!("Failed to read recording: {err}"))
+                })?;
+                reader.notify_read(read);
+                read_bytes += read as u64;
+                progress(read_bytes as f64 / size.max(1) as f64);
+                continue;
+            }
+            LinearReadEvent::Record { opcode, data } => (opcode, data),
+        };
+        let record = match mcap::parse_record(opcode, data) {
+            Ok(record) => record,
+            Err(err) => {
+                debug!("Recovery: stopped reading at message {messages}: {err}");
+                break;
+            }
+        };
+        let copied = match record {
+            Record::Schema { header, data } => writer
+                .add_schema(&header.name, &header.encoding, &data)
+                .map(|id| {
+                    schemas.insert(header.id, id);
+                }),
+            Record::Channel(channel) => {
+                let schema_id = schemas.get(&channel.schema_id).copied().unwrap_or(0);
+                writer
+                    .add_channel(
+                        schema_id,
+                        &channel.topic,
+                        &channel.message_encoding,
+                        &channel.metadata,
+                    )
+                    .map(|id| {
+                        channels.insert(channel.id, id);
+                    })
+            }
+            Record::Message { header, data } => {
+                let Some(channel_id) = channels.get(&header.channel_id).copied() else {
+                    warn!(
+                        "Recovery: skipping message of unknown channel {}",
+                        header.channel_id
+                    );
+                    continue;
+                };
+                messages += 1;
+                writer.write_to_known_channel(
+                    &MessageHeader {
+                        channel_id,
+                        ..header
+                    },
+                    &data,
+                )
+            }
+            Record::Metadata(metadata) => writer.write_metadata(&metadata),
+            _ => Ok(()),
+        };
+        copied.map_err(|err| ManagerError::Other(format!("Failed to copy record: {err}")))?;
+    }
+
+    writer
+        .finish()
+        .map_err(|err| ManagerError::Other(format!("Failed to finalize {recovering:?}: {err}")))?;
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    // Each message is its own chunk, so a cut loses only the messages of the chunk it falls in
+    fn write_recording(path: &Path, messages: u32) {
+        let file = BufWriter::new(File::create(path).unwrap());
+        let mut writer = mcap::WriteOptions::new()
+            .chunk_size(Some(1))
+            .create(file)
+            .unwrap();
+        writer
+            .write_metadata(&mcap::records::Metadata {
+                name: "software".to_string(),
+                metadata: [("version".to_string(), "test".to_string())].into(),
+            })
+            .unwrap();
+        let channel_id = writer
+            .add_channel(0, "/test", "json", &Default::default())
+            .unwrap();
+        for sequence in 0..messages {
+            writer
+                .write_to_known_channel(
+                    &MessageHeader {
+                        channel_id,
+                        sequence,
+                        log_time: sequence as u64,
+                        publish_time: sequence as u64,
+                    },
+                    b"{}",
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn chunk_bounds(path: &Path) -> Vec<(u64, u64)> {
+        let data = std::fs::read(path).unwrap();
+        mcap::Summary::read(&data)
+            .unwrap()
+            .unwrap()
+            .chunk_indexes
+            .iter()
+            .map(|index| (index.chunk_start_offset, index.chunk_length))
+            .collect()
+    }
+
+    fn truncate(path: &Path, length: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(length)
+            .unwrap();
+    }
+
+    fn recovered(path: &Path) -> (usize, Vec<String>) {
+        let data = std::fs::read(path).unwrap();
+        let messages = mcap::MessageStream::new(&data).unwrap().count();
+        let metadata = mcap::Summary::read(&data)
+            .unwrap()
+            .unwrap()
+            .metadata_indexes
+            .into_iter()
+            .map(|index| index.name)
+            .collect();
+        (messages, metadata)
+    }
+
+    #[test]
+    fn test_truncated_recording_is_recovered() {
+        let directory = temp_dir();
+        let path = directory.join("truncated.mcap");
+        write_recording(&path, 5);
+        let (start, length) = *chunk_bounds(&path).last().unwrap();
+        // Crashed before writing the summary
+        truncate(&path, start + length);
+        assert!(is_unfinished(&path));
+        assert_eq!(unfinished_recordings(&directory), vec![path.clone()]);
+
+        let result = recover(&path, |_| {}).unwrap();
+        assert_eq!(result.messages, 5);
+        assert!(!is_unfinished(&path));
+        assert_eq!(recovered(&path), (5, vec!["software".to_string()]));
+        assert!(!path.with_extension("mcap.recovering").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_recording_cut_mid_chunk_keeps_complete_chunks() {
+        let directory = temp_dir();
+        let path = directory.join("cut.mcap");
+        write_recording(&path, 5);
+        let (start, length) = *chunk_bounds(&path).last().unwrap();
+        truncate(&path, start + length / 2);
+
+        let result = recover(&path, |_| {}).unwrap();
+        assert_eq!(result.messages, 4);
+        assert_eq!(recovered(&path).0, 4);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_finished_recording_is_not_rewritten() {
+        let directory = temp_dir();
+        let path = directory.join("finished.mcap");
+        write_recording(&path, 3);
+        let data = std::fs::read(&path).unwrap();
+
+        assert!(!is_unfinished(&path));
+        assert!(unfinished_recordings(&directory).is_empty());
+        assert!(recover(&path, |_| {}).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_directory_is_locked_by_one_process() {
+        let directory = temp_dir();
+        let lock = lock_directory(&directory);
+        assert!(lock.is_some());
+        assert!(lock_directory(&directory).is_none());
+
+        drop(lock);
+        assert!(lock_directory(&directory).is_some());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,576 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use bluerobotics_ping::{
+    common::{DeviceInformationStruct, ProtocolVersionStruct},
+    message::{PingMessage, ProtocolMessage},
+    ping1d::{self, ProfileStruct},
+    ping360::{self, AutoDeviceDataStruct, DeviceDataStruct},
+};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, RwLock},
+    time::Instant,
+};
+use tracing::{debug, info, trace, warn};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{
+        DeviceActorHandler, DeviceActorRequest, Ping1DRequest, Ping360Request, PingAnswer,
+        PingCommonRequest, PingRequest, UpgradeResult,
+    },
+    manager::{
+        identity, DeviceSelection, ManagerActorHandler, ManagerError, Request, SourceReplayStruct,
+        SourceSelection, UuidWrapper, VirtualDevice,
+    },
+    recording::{
+        jobs::{JobKind, JOBS},
+        reader::RecordingFile,
+    },
+};
+
+const MAX_SPEED: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ReplayOptions {
+    pub file_name: String,
+    /// Playback speed, 1.0 is real time
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// Start again from the beginning when the recording ends
+    #[serde(default)]
+    pub looped: bool,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub enum ReplayCommand {
+    Play,
+    Pause,
+    /// Position from the start of the recording, in seconds
+    Seek(f64),
+    SetSpeed(f64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ReplayControl {
+    pub uuid: Uuid,
+    pub command: ReplayCommand,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ReplayStatus {
+    pub device_id: Uuid,
+    pub file_name: String,
+    pub device_type: DeviceSelection,
+    pub playing: bool,
+    pub speed: f64,
+    pub looped: bool,
+    pub position_s: f64,
+    pub duration_s: f64,
+    pub messages: usize,
+}
+
+// Commands are acknowledged with the status once applied
+type ControlRequest = (ReplayCommand, oneshot::Sender<ReplayStatus>);
+
+pub struct ReplayHandle {
+    control: mpsc::Sender<ControlRequest>,
+    status: Arc<std::sync::RwLock<ReplayStatus>>,
+}
+
+impl ReplayHandle {
+    pub fn status(&self) -> ReplayStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.control.is_closed()
+    }
+}
+
+pub struct ReplayData {
+    pub device_type: DeviceSelection,
+    /// Messages of the replayed device, with their offset from the first one in nanoseconds
+    pub messages: Vec<(u64, ProtocolMessage)>,
+}
+
+impl ReplayData {
+    fn duration_ns(&self) -> u64 {
+        self.messages.last().map(|(offset, _)| *offset).unwrap_or(0)
+    }
+}
+
+fn protocol_message(message: &impl PingMessage) -> ProtocolMessage {
+    let mut protocol_message = ProtocolMessage::new();
+    protocol_message.set_message(message);
+    protocol_message
+}
+
+/// Read the sonar messages of the first device found in the recording
+pub fn load(path: &Path, mut progress: impl FnMut(f64)) -> Result<ReplayData, ManagerError> {
+    let data = RecordingFile::open(path)?;
+    let stream = mcap::MessageStream::new(&data)
+        .map_err(|err| ManagerError::Other(format!("Invalid MCAP file {path:?}: {err}")))?;
+    let total_messages = data.message_count();
+
+    let mut replayed: Option<(String, DeviceSelection)> = None;
+    let mut messages: Vec<(u64, ProtocolMessage)> = Vec::new();
+
+    for (index, message) in stream.enumerate() {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                // Files still being written have no footer, replay everything read so far
+                debug!("Replay: stopped reading {path:?}: {err}");
+                break;
+            }
+        };
+        if let Some(total) = total_messages {
+            progress((index + 1) as f64 / total as f64);
+        }
+
+        let topic = message.channel.topic.as_str();
+        let device_type = if topic.ends_with("/Ping1D") {
+            DeviceSelection::Ping1D
+        } else if topic.ends_with("/Ping360") {
+            DeviceSelection::Ping360
+        } else {
+            continue;
+        };
+
+        match &replayed {
+            None => replayed = Some((topic.to_string(), device_type.clone())),
+            Some((replayed_topic, _)) if replayed_topic != topic => continue,
+            _ => {}
+        }
+
+        let decoded = match device_type {
+            DeviceSelection::Ping1D => serde_json::from_slice::<ProfileStruct>(&message.data)
+                .map(|profile| protocol_message(&ping1d::Messages::Profile(profile))),
+            _ => serde_json::from_slice::<AutoDeviceDataStruct>(&message.data)
+                .map(|data| protocol_message(&ping360::Messages::AutoDeviceData(data))),
+        };
+        match decoded {
+            Ok(protocol_message) => messages.push((message.log_time, protocol_message)),
+            Err(err) => warn!("Replay: failed to decode message on {topic}: {err}"),
+        }
+    }
+
+    let Some((topic, device_type)) = replayed else {
+        return Err(ManagerError::Other(format!(
+            "No Ping1D or Ping360 messages in {path:?}"
+        )));
+    };
+    info!("Replay: loaded {} messages from {topic}", messages.len());
+
+    messages.sort_by_key(|(log_time, _)| *log_time);
+    let first = messages.first().map(|(log_time, _)| *log_time).unwrap_or(0);
+    for (log_time, _) in messages.iter_mut() {
+        *log_time -= first;
+    }
+
+    Ok(ReplayData {
+        device_type,
+        messages,
+    })
+}
+
+struct Player {
+    data: ReplayData,
+    index: usize,
+    playing: bool,
+    speed: f64,
+    looped: bool,
+    // Wall clock instant matching a position in the recording, in nanoseconds
+    anchor: (Instant, u64),
+    sender: broadcast::Sender<ProtocolMessage>,
+    status: Arc<std::sync::RwLock<ReplayStatus>>,
+}
+
+impl Player {
+    fn position(&self) -> u64 {
+        let (instant, offset) = self.anchor;
+        if !self.playing {
+            return offset;
+        }
+        let elapsed = instant.elapsed().as_nanos() as f64 * self.speed;
+        (offset + elapsed as u64).min(self.data.duration_ns())
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        if !self.playing {
+            return None;
+        }
+        let (offset, _) = self.data.messages.get(self.index)?;
+        let (instant, anchor_offset) = self.anchor;
+        let wait = offset.saturating_sub(anchor_offset) as f64 / self.speed;
+        Some(instant + Duration::from_nanos(wait as u64))
+    }
+
+    fn publish_next(&mut self) {
+        let Some((_, message)) = self.data.messages.get(self.index) else {
+            return;
+        };
+        // Nobody listening is fine, the continuous mode subscribes when enabled
+        let _ = self.sender.send(message.clone());
+        self.index += 1;
+
+        if self.index >= self.data.messages.len() {
+            if self.looped {
+                self.index = 0;
+                self.anchor = (Instant::now(), 0);
+            } else {
+                self.playing = false;
+                self.anchor = (Instant::now(), self.data.duration_ns());
+            }
+        }
+        self.update_status();
+    }
+
+    fn apply(&mut self, command: ReplayCommand) {
+        trace!("Replay: applying {command:?}");
+        match command {
+            ReplayCommand::Play => {
+                let mut position = self.position();
+                if self.index >= self.data.messages.len() {
+                    position = 0;
+                    self.index = 0;
+                }
+                self.anchor = (Instant::now(), position);
+                self.playing = true;
+            }
+            ReplayCommand::Pause => {
+                self.anchor = (Instant::now(), self.position());
+                self.playing = false;
+            }
+            ReplayCommand::Seek(position_s) => {
+                let position = ((position_s.max(0.0) * 1e9) as u64).min(self.data.duration_ns());
+                self.index = self
+                    .data
+                    .messages
+                    .partition_point(|(offset, _)| *offset < position);
+                self.anchor = (Instant::now(), position);
+            }
+            ReplayCommand::SetSpeed(speed) => {
+                self.anchor = (Instant::now(), self.position());
+                self.speed = speed;
+            }
+        }
+        self.update_status();
+    }
+
+    fn update_status(&self) {
+        let mut status = self.status.write().unwrap();
+        status.playing = self.playing;
+        status.speed = self.speed;
+        status.position_s = self.position() as f64 / 1e9;
+    }
+
+    fn current(&self) -> Option<bluerobotics_ping::Messages> {
+        let index = self.index.saturating_sub(1);
+        let (_, message) = self.data.messages.get(index)?;
+        bluerobotics_ping::Messages::try_from(message).ok()
+    }
+
+    fn device_data(&self) -> Option<DeviceDataStruct> {
+        match self.current()? {
+            bluerobotics_ping::Messages::Ping360(
+                bluerobotics_ping::ping360::Messages::AutoDeviceData(data),
+            ) => Some(DeviceDataStruct {
+                mode: data.mode,
+                gain_setting: data.gain_setting,
+                angle: data.angle,
+                transmit_duration: data.transmit_duration,
+                sample_period: data.sample_period,
+                transmit_frequency: data.transmit_frequency,
+                number_of_samples: data.number_of_samples,
+                data_length: data.data_length,
+                data: data.data,
+            }),
+            _ => None,
+        }
+    }
+
+    // Answer like the recorded device would, commands are acknowledged and ignored
+    fn answer(&self, request: PingRequest) -> PingAnswer {
+        let is_ping360 = self.data.device_type == DeviceSelection::Ping360;
+        let message = match &request {
+            PingRequest::GetSubscriber => return PingAnswer::Subscriber(self.sender.subscribe()),
+            PingRequest::Upgrade => {
+                return PingAnswer::UpgradeResult(if is_ping360 {
+                    UpgradeResult::Ping360
+                } else {
+                    UpgradeResult::Ping1D
+                })
+            }
+            PingRequest::Common(PingCommonRequest::DeviceInformation) => {
+                Some(bluerobotics_ping::Messages::Common(
+                    bluerobotics_ping::common::Messages::DeviceInformation(
+                        DeviceInformationStruct {
+                            device_type: if is_ping360 { 2 } else { 1 },
+                            device_revision: 0,
+                            // Recent enough for the Ping360 auto transmit mode
+                            firmware_version_major: 3,
+                            firmware_version_minor: 3,
+                            firmware_version_patch: 0,
+                            reserved: 0,
+                        },
+                    ),
+                ))
+            }
+            PingRequest::Common(PingCommonRequest::ProtocolVersion) => {
+                Some(bluerobotics_ping::Messages::Common(
+                    bluerobotics_ping::common::Messages::ProtocolVersion(ProtocolVersionStruct {
+                        version_major: 1,
+                        version_minor: 0,
+                        version_patch: 0,
+                        reserved: 0,
+                    }),
+                ))
+            }
+            PingRequest::Ping1D(Ping1DRequest::Profile) if !is_ping360 => self.current(),
+            PingRequest::Ping1D(
+                Ping1DRequest::ContinuousStart(_) | Ping1DRequest::ContinuousStop(_),
+            ) if !is_ping360 => return PingAnswer::PingAcknowledge(request),
+            PingRequest::Ping360(
+                Ping360Request::DeviceData
+                | Ping360Request::AutoDeviceData
+                | Ping360Request::Transducer(_),
+            ) if is_ping360 => self.device_data().map(|data| {
+                bluerobotics_ping::Messages::Ping360(
+                    bluerobotics_ping::ping360::Messages::DeviceData(data),
+                )
+            }),
+            PingRequest::Ping360(
+                Ping360Request::AutoTransmit(_)
+                | Ping360Request::MotorOff
+                | Ping360Request::Reset(_),
+            ) if is_ping360 => return PingAnswer::PingAcknowledge(request),
+            _ => None,
+        };
+
+        match message {
+            Some(message) => PingAnswer::PingMessage(message),
+            None => PingAnswer::NotSupported(request),
+        }
+    }
+}
+
+async fn run_player(
+    mut player: Player,
+    mut requests: mpsc::Receiver<DeviceActorRequest>,
+    mut control: mpsc::Receiver<ControlRequest>,
+) {
+    loop {
+        let deadline = player.next_deadline();
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    break;
+                };
+                if matches!(request.request, PingRequest::Stop) {
+                    break;
+                }
+                let answer = player.answer(request.request);
+                let _ = request.respond_to.send(Ok(answer));
+            }
+            command = control.recv() => {
+                let Some((command, applied)) = command else {
+                    break;
+                };
+                player.apply(command);
+                let _ = applied.send(player.status.read().unwrap().clone());
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                player.publish_next();
+            }
+        }
+    }
+    debug!(
+        "Replay of {} finished",
+        player.status.read().unwrap().file_name
+    );
+}
+
+// Virtual device actor for the replay, answering the DeviceManager like a real device
+fn spawn_player(
+    device_id: Uuid,
+    options: &ReplayOptions,
+    data: ReplayData,
+) -> (DeviceActorHandler, ReplayHandle) {
+    let (request_sender, requests) = mpsc::channel(10);
+    let (control_sender, control) = mpsc::channel(10);
+    let (sender, _) = broadcast::channel(100);
+
+    let status = Arc::new(std::sync::RwLock::new(ReplayStatus {
+        device_id,
+        file_name: options.file_name.clone(),
+        device_type: data.device_type.clone(),
+        playing: true,
+        speed: options.speed,
+        looped: options.looped,
+        position_s: 0.0,
+        duration_s: data.duration_ns() as f64 / 1e9,
+        messages: data.messages.len(),
+    }));
+
+    let player = Player {
+        data,
+        index: 0,
+        playing: true,
+        speed: options.speed,
+        looped: options.looped,
+        anchor: (Instant::now(), 0),
+        sender,
+        status: status.clone(),
+    };
+    tokio::spawn(run_player(player, requests, control));
+
+    (
+        DeviceActorHandler {
+            sender: request_sender,
+        },
+        ReplayHandle {
+            control: control_sender,
+            status,
+        },
+    )
+}
+
+fn check_speed(speed: f64) -> Result<(), ManagerError> {
+    if speed > 0.0 && speed <= MAX_SPEED {
+        return Ok(());
+    }
+    Err(ManagerError::Other(format!(
+        "Replay speed must be between 0 and {MAX_SPEED}, got {speed}"
+    )))
+}
+
+fn recording_path(base_path: &Path, file_name: &str) -> Result<PathBuf, ManagerError> {
+    let base = base_path
+        .canonicalize()
+        .map_err(|err| ManagerError::Other(format!("Invalid recordings directory: {err}")))?;
+    let path = base
+        .join(file_name)
+        .canonicalize()
+        .map_err(|_| ManagerError::Other(format!("Recording {file_name:?} not found")))?;
+    if !path.starts_with(&base) || !path.is_file() {
+        return Err(ManagerError::Other(format!(
+            "Recording {file_name:?} not found"
+        )));
+    }
+    Ok(path)
+}
+
+pub async fn start_replay(
+    replays: Arc<RwLock<HashMap<Uuid, ReplayHandle>>>,
+    devices_manager_handler: ManagerActorHandler,
+    base_path: PathBuf,
+    options: ReplayOptions,
+) -> Result<ReplayStatus, ManagerError> {
+    check_speed(options.speed)?;
+    let path = recording_path(&base_path, &options.file_name)?;
+
+    let source = SourceSelection::ReplayStream(SourceReplayStruct {
+        file_name: options.file_name.clone(),
+    });
+    let device_id = identity::device_uuid(&source);
+
+    replays
+        .write()
+        .await
+        .retain(|_, replay| !replay.is_finished());
+    if replays.read().await.contains_key(&device_id) {
+        return Err(ManagerError::Other(format!(
+            "Recording {:?} is already being replayed by device {device_id}",
+            options.file_name
+        )));
+    }
+
+    // Long recordings take a while to index, load them on the job pool
+    let (job_id, result) = JOBS.submit(JobKind::ReplayLoad, &options.file_name, move |progress| {
+        load(&path, |fraction| progress.set(fraction))
+    });
+    let data = result
+        .await
+        .map_err(|err| ManagerError::Other(format!("Replay job {job_id} failed: {err}")))??;
+
+    let device_type = data.device_type.clone();
+    let (handler, replay) = spawn_player(device_id, &options, data);
+
+    devices_manager_handler
+        .send(Request::RegisterVirtualDevice(VirtualDevice {
+            id: device_id,
+            source,
+            device_type,
+            handler,
+        }))
+        .await?;
+
+    let status = replay.status();
+    replays.write().await.insert(device_id, replay);
+    info!("Replaying {:?} as device {device_id}", options.file_name);
+    Ok(status)
+}
+
+pub async fn control_replay(
+    replays: &RwLock<HashMap<Uuid, ReplayHandle>>,
+    control: ReplayControl,
+) -> Result<ReplayStatus, ManagerError> {
+    if let ReplayCommand::SetSpeed(speed) = control.command {
+        check_speed(speed)?;
+    }
+
+    let replays = replays.read().await;
+    let replay = replays
+        .get(&control.uuid)
+        .ok_or(ManagerError::DeviceNotExist(control.uuid))?;
+    let (applied, status) = oneshot::channel();
+    replay
+        .control
+        .send((control.command, applied))
+        .await
+        .map_err(|err| ManagerError::TokioMpsc(err.to_string()))?;
+    status
+        .await
+        .map_err(|err| ManagerError::TokioMpsc(err.to_string()))
+}
+
+pub async fn stop_replay(
+    replays: &RwLock<HashMap<Uuid, ReplayHandle>>,
+    devices_manager_handler: &ManagerActorHandler,
+    device_id: Uuid,
+) -> Result<ReplayStatus, ManagerError> {
+    let replay = replays
+        .write()
+        .await
+        .remove(&device_id)
+        .ok_or(ManagerError::DeviceNotExist(device_id))?;
+    let mut status = replay.status();
+    status.playing = false;
+
+    if let Err(err) = devices_manager_handler
+        .send(Request::Delete(UuidWrapper { uuid: device_id }))
+        .await
+    {
+        warn!("Replay: failed to remove virtual device {device_id}: {err:?}");
+    }
+    info!("Replay of {:?} stopped", status.file_name);
+    Ok(status)
+}
+
+pub async fn list_replays(replays: &RwLock<HashMap<Uuid, ReplayHandle>>) -> Vec<ReplayStatus> {
+    let mut replays = replays.write().await;
+    replays.retain(|_, replay| !replay.is_finished());
+    replays.values().map(ReplayHandle::status).collect()
+}
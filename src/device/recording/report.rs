@@ -0,0 +1,555 @@
+use std::path::Path;
+
+use bluerobotics_ping::{ping1d::ProfileStruct, ping360::AutoDeviceDataStruct};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::device::manager::ManagerError;
+use crate::vehicle::VehicleData;
+
+use super::reader::RecordingFile;
+
+const SPEED_OF_SOUND_M_S: f64 = 1500.0;
+// Ping360 sample period is expressed in 25 ns ticks
+const PING360_SAMPLE_PERIOD_TICK_S: f64 = 25e-9;
+// Skip the transducer ringing close to the head before searching for obstacles
+const PING360_BLANKING_M: f64 = 0.5;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+const MAX_TRACK_POINTS: usize = 2000;
+const MAX_OBSTACLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub enum ReportFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ReportOptions {
+    #[serde(default)]
+    pub format: ReportFormat,
+    /// Minimum echo intensity (0-255) for a Ping360 return to be considered an obstacle
+    #[serde(default = "default_obstacle_intensity")]
+    pub obstacle_intensity: u8,
+    /// Returns closer than this distance in meters are reported as obstacles
+    #[serde(default = "default_obstacle_range")]
+    pub obstacle_range_m: f64,
+}
+
+fn default_obstacle_intensity() -> u8 {
+    200
+}
+
+fn default_obstacle_range() -> f64 {
+    5.0
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self {
+            format: ReportFormat::default(),
+            obstacle_intensity: default_obstacle_intensity(),
+            obstacle_range_m: default_obstacle_range(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurveyReport {
+    pub file_name: String,
+    pub generated_at: String,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub duration_s: f64,
+    pub message_count: u64,
+    /// Set when the file ended unexpectedly, e.g. a recording still in progress
+    pub truncated: bool,
+    pub depth: Option<DepthStatistics>,
+    pub track: Vec<TrackPoint>,
+    pub track_length_m: f64,
+    pub obstacles: Vec<Obstacle>,
+    pub settings: Vec<SettingsUsed>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthStatistics {
+    pub samples: u64,
+    pub low_confidence_samples: u64,
+    pub min_m: f64,
+    pub min_time: String,
+    pub max_m: f64,
+    pub max_time: String,
+    pub mean_m: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackPoint {
+    pub time: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub time: String,
+    pub angle_deg: f64,
+    pub range_m: f64,
+    pub intensity: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsUsed {
+    pub topic: String,
+    pub since: String,
+    pub settings: serde_json::Value,
+}
+
+#[derive(Default)]
+struct DepthAccumulator {
+    samples: u64,
+    low_confidence_samples: u64,
+    sum: f64,
+    min: Option<(f64, u64)>,
+    max: Option<(f64, u64)>,
+}
+
+impl DepthAccumulator {
+    fn push(&mut self, profile: &ProfileStruct, log_time: u64) {
+        let depth = profile.distance as f64 / 1000.0;
+        self.samples += 1;
+        self.sum += depth;
+        // Ping1D reports confidence as a percentage
+        if profile.confidence < 50 {
+            self.low_confidence_samples += 1;
+        }
+        if self.min.is_none_or(|(min, _)| depth < min) {
+            self.min = Some((depth, log_time));
+        }
+        if self.max.is_none_or(|(max, _)| depth > max) {
+            self.max = Some((depth, log_time));
+        }
+    }
+
+    fn finish(self) -> Option<DepthStatistics> {
+        let (min_m, min_time) = self.min?;
+        let (max_m, max_time) = self.max?;
+        Some(DepthStatistics {
+            samples: self.samples,
+            low_confidence_samples: self.low_confidence_samples,
+            min_m,
+            min_time: format_time(min_time),
+            max_m,
+            max_time: format_time(max_time),
+            mean_m: self.sum / self.samples as f64,
+        })
+    }
+}
+
+pub fn generate(path: &Path, options: &ReportOptions) -> Result<SurveyReport, ManagerError> {
+    generate_with_progress(path, options, |_| {})
+}
+
+/// Same as [`generate`], reporting the fraction of messages read when the file has a summary
+pub fn generate_with_progress(
+    path: &Path,
+    options: &ReportOptions,
+    mut progress: impl FnMut(f64),
+) -> Result<SurveyReport, ManagerError> {
+    let data = RecordingFile::open(path)?;
+    let stream = mcap::MessageStream::new(&data)
+        .map_err(|err| ManagerError::Other(format!("Invalid MCAP file {path:?}: {err}")))?;
+    let total_messages = data.message_count();
+
+    let mut message_count = 0;
+    let mut truncated = false;
+    let mut first_time: Option<u64> = None;
+    let mut last_time: Option<u64> = None;
+    let mut depth = DepthAccumulator::default();
+    let mut track: Vec<TrackPoint> = Vec::new();
+    let mut track_length_m = 0.0;
+    let mut last_position: Option<(f64, f64)> = None;
+    let mut obstacles = Vec::new();
+    let mut settings: Vec<SettingsUsed> = Vec::new();
+
+    for message in stream {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                // Files still being written have no footer, keep everything read so far
+                debug!("Survey report: stopped reading {path:?}: {err}");
+                truncated = true;
+                break;
+            }
+        };
+
+        message_count += 1;
+        if let Some(total) = total_messages {
+            progress(message_count as f64 / total as f64);
+        }
+        let log_time = message.log_time;
+        first_time = Some(first_time.map_or(log_time, |time| time.min(log_time)));
+        last_time = Some(last_time.map_or(log_time, |time| time.max(log_time)));
+
+        let topic = message.channel.topic.as_str();
+        if topic.ends_with("/Ping1D") {
+            let Ok(profile) = serde_json::from_slice::<ProfileStruct>(&message.data) else {
+                warn!("Survey report: failed to decode Ping1D message on {topic}");
+                continue;
+            };
+            depth.push(&profile, log_time);
+            track_settings(
+                &mut settings,
+                topic,
+                log_time,
+                serde_json::json!({
+                    "gain_setting": profile.gain_setting,
+                    "scan_start": profile.scan_start,
+                    "scan_length": profile.scan_length,
+                    "transmit_duration": profile.transmit_duration,
+                }),
+            );
+        } else if topic.ends_with("/Ping360") {
+            let Ok(data) = serde_json::from_slice::<AutoDeviceDataStruct>(&message.data) else {
+                warn!("Survey report: failed to decode Ping360 message on {topic}");
+                continue;
+            };
+            if let Some(obstacle) = detect_obstacle(&data, log_time, options) {
+                obstacles.push(obstacle);
+            }
+            track_settings(
+                &mut settings,
+                topic,
+                log_time,
+                serde_json::json!({
+                    "mode": data.mode,
+                    "gain_setting": data.gain_setting,
+                    "transmit_duration": data.transmit_duration,
+                    "sample_period": data.sample_period,
+                    "transmit_frequency": data.transmit_frequency,
+                    "number_of_samples": data.number_of_samples,
+                    "start_angle": data.start_angle,
+                    "stop_angle": data.stop_angle,
+                    "range_m": ping360_range(data.sample_period, data.number_of_samples as usize),
+                }),
+            );
+        } else if topic.ends_with("/VehicleData") {
+            let Ok(vehicle) = serde_json::from_slice::<VehicleData>(&message.data) else {
+                warn!("Survey report: failed to decode vehicle message on {topic}");
+                continue;
+            };
+            // Vehicle data is logged alongside every sonar message, skip repeated fixes
+            if last_position == Some((vehicle.lat, vehicle.lon)) {
+                continue;
+            }
+            if let Some((lat, lon)) = last_position {
+                track_length_m += haversine_m(lat, lon, vehicle.lat, vehicle.lon);
+            }
+            last_position = Some((vehicle.lat, vehicle.lon));
+            track.push(TrackPoint {
+                time: format_time(log_time),
+                lat: vehicle.lat,
+                lon: vehicle.lon,
+            });
+        }
+    }
+
+    // Keep the closest returns, then present them chronologically
+    obstacles.sort_by(|a: &(u64, Obstacle), b| a.1.range_m.total_cmp(&b.1.range_m));
+    obstacles.truncate(MAX_OBSTACLES);
+    obstacles.sort_by_key(|(time, _)| *time);
+
+    let duration_s = match (first_time, last_time) {
+        (Some(first), Some(last)) => (last - first) as f64 / 1e9,
+        _ => 0.0,
+    };
+
+    Ok(SurveyReport {
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        start_time: first_time.map(format_time),
+        end_time: last_time.map(format_time),
+        duration_s,
+        message_count,
+        truncated,
+        depth: depth.finish(),
+        track: decimate(track, MAX_TRACK_POINTS),
+        track_length_m,
+        obstacles: obstacles
+            .into_iter()
+            .map(|(_, obstacle)| obstacle)
+            .collect(),
+        settings,
+    })
+}
+
+fn track_settings(
+    settings: &mut Vec<SettingsUsed>,
+    topic: &str,
+    log_time: u64,
+    current: serde_json::Value,
+) {
+    let unchanged = settings
+        .iter()
+        .rev()
+        .find(|used| used.topic == topic)
+        .is_some_and(|used| used.settings == current);
+    if !unchanged {
+        settings.push(SettingsUsed {
+            topic: topic.to_string(),
+            since: format_time(log_time),
+            settings: current,
+        });
+    }
+}
+
+pub fn ping360_range(sample_period: u16, sample_index: usize) -> f64 {
+    sample_index as f64 * sample_period as f64 * PING360_SAMPLE_PERIOD_TICK_S * SPEED_OF_SOUND_M_S
+        / 2.0
+}
+
+fn detect_obstacle(
+    data: &AutoDeviceDataStruct,
+    log_time: u64,
+    options: &ReportOptions,
+) -> Option<(u64, Obstacle)> {
+    let (index, intensity) = data.data.iter().enumerate().find(|(index, intensity)| {
+        **intensity >= options.obstacle_intensity
+            && ping360_range(data.sample_period, *index) >= PING360_BLANKING_M
+    })?;
+
+    let range_m = ping360_range(data.sample_period, index);
+    if range_m > options.obstacle_range_m {
+        return None;
+    }
+
+    Some((
+        log_time,
+        Obstacle {
+            time: format_time(log_time),
+            // Ping360 angles are in gradians
+            angle_deg: data.angle as f64 * 360.0 / 400.0,
+            range_m,
+            intensity: *intensity,
+        },
+    ))
+}
+
+fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+fn decimate(points: Vec<TrackPoint>, max_points: usize) -> Vec<TrackPoint> {
+    if points.len() <= max_points {
+        return points;
+    }
+    let step = points.len().div_ceil(max_points);
+    let last = points.last().cloned();
+    let mut decimated: Vec<TrackPoint> = points.into_iter().step_by(step).collect();
+    if let Some(last) = last {
+        if decimated.last() != Some(&last) {
+            decimated.push(last);
+        }
+    }
+    decimated
+}
+
+fn format_time(log_time_ns: u64) -> String {
+    chrono::DateTime::from_timestamp_nanos(log_time_ns as i64).to_rfc3339()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn track_svg(track: &[TrackPoint]) -> String {
+    const SIZE: f64 = 400.0;
+    const MARGIN: f64 = 10.0;
+
+    if track.len() < 2 {
+        return "<p>No vehicle position available.</p>".to_string();
+    }
+
+    let (min_lat, max_lat) = track.iter().fold((f64::MAX, f64::MIN), |(min, max), p| {
+        (min.min(p.lat), max.max(p.lat))
+    });
+    let (min_lon, max_lon) = track.iter().fold((f64::MAX, f64::MIN), |(min, max), p| {
+        (min.min(p.lon), max.max(p.lon))
+    });
+    // Equirectangular projection, good enough for survey sized areas
+    let lon_scale = ((min_lat + max_lat) / 2.0).to_radians().cos();
+    let width = ((max_lon - min_lon) * lon_scale).max(f64::EPSILON);
+    let height = (max_lat - min_lat).max(f64::EPSILON);
+    let scale = (SIZE - 2.0 * MARGIN) / width.max(height);
+
+    let points: Vec<(f64, f64)> = track
+        .iter()
+        .map(|p| {
+            (
+                MARGIN + (p.lon - min_lon) * lon_scale * scale,
+                SIZE - MARGIN - (p.lat - min_lat) * scale,
+            )
+        })
+        .collect();
+    let polyline: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{x:.1},{y:.1}"))
+        .collect();
+    let (start_x, start_y) = points[0];
+    let (end_x, end_y) = points[points.len() - 1];
+
+    format!(
+        "<svg width=\"{SIZE}\" height=\"{SIZE}\" viewBox=\"0 0 {SIZE} {SIZE}\">\
+         <rect width=\"100%\" height=\"100%\" fill=\"#f4f6f8\"/>\
+         <polyline fill=\"none\" stroke=\"#1976d2\" stroke-width=\"2\" points=\"{}\"/>\
+         <circle cx=\"{start_x:.1}\" cy=\"{start_y:.1}\" r=\"4\" fill=\"#2e7d32\"/>\
+         <circle cx=\"{end_x:.1}\" cy=\"{end_y:.1}\" r=\"4\" fill=\"#c62828\"/></svg>",
+        polyline.join(" "),
+    )
+}
+
+pub fn render_html(report: &SurveyReport) -> String {
+    let depth = match &report.depth {
+        Some(depth) => format!(
+            "<table>\
+             <tr><th>Samples</th><td>{}</td></tr>\
+             <tr><th>Low confidence samples</th><td>{}</td></tr>\
+             <tr><th>Minimum</th><td>{:.2} m at {}</td></tr>\
+             <tr><th>Maximum</th><td>{:.2} m at {}</td></tr>\
+             <tr><th>Mean</th><td>{:.2} m</td></tr></table>",
+            depth.samples,
+            depth.low_confidence_samples,
+            depth.min_m,
+            depth.min_time,
+            depth.max_m,
+            depth.max_time,
+            depth.mean_m
+        ),
+        None => "<p>No Ping1D data available.</p>".to_string(),
+    };
+
+    let obstacles = if report.obstacles.is_empty() {
+        "<p>No obstacles detected.</p>".to_string()
+    } else {
+        let rows: String = report
+            .obstacles
+            .iter()
+            .map(|obstacle| {
+                format!(
+                    "<tr><td>{}</td><td>{:.1}</td><td>{:.2}</td><td>{}</td></tr>",
+                    obstacle.time, obstacle.angle_deg, obstacle.range_m, obstacle.intensity
+                )
+            })
+            .collect();
+        format!(
+            "<table><tr><th>Time</th><th>Angle (deg)</th><th>Range (m)</th><th>Intensity</th></tr>{rows}</table>"
+        )
+    };
+
+    let settings: String = report
+        .settings
+        .iter()
+        .map(|used| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+                escape_html(&used.topic),
+                used.since,
+                escape_html(&used.settings.to_string())
+            )
+        })
+        .collect();
+
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <title>Survey report - {file_name}</title>\
+         <style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+         th,td{{border:1px solid #ccc;padding:4px 8px;text-align:left}}\
+         @media print{{body{{margin:0}}}}</style></head><body>\
+         <h1>Survey report</h1>\
+         <table>\
+         <tr><th>Recording</th><td>{file_name}</td></tr>\
+         <tr><th>Start</th><td>{start}</td></tr>\
+         <tr><th>End</th><td>{end}</td></tr>\
+         <tr><th>Duration</th><td>{duration:.1} s</td></tr>\
+         <tr><th>Messages</th><td>{messages}{truncated}</td></tr>\
+         <tr><th>Generated</th><td>{generated}</td></tr></table>\
+         <h2>Track</h2>{track}<p>Track length: {track_length:.1} m</p>\
+         <h2>Depth</h2>{depth}\
+         <h2>Obstacles</h2>{obstacles}\
+         <h2>Settings used</h2>\
+         <table><tr><th>Topic</th><th>Since</th><th>Settings</th></tr>{settings}</table>\
+         </body></html>",
+        file_name = escape_html(&report.file_name),
+        start = report.start_time.as_deref().unwrap_or("-"),
+        end = report.end_time.as_deref().unwrap_or("-"),
+        duration = report.duration_s,
+        messages = report.message_count,
+        truncated = if report.truncated {
+            " (recording incomplete)"
+        } else {
+            ""
+        },
+        generated = report.generated_at,
+        track = track_svg(&report.track),
+        track_length = report.track_length_m,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping360_data(data: Vec<u8>) -> AutoDeviceDataStruct {
+        AutoDeviceDataStruct {
+            mode: 1,
+            gain_setting: 0,
+            angle: 100,
+            transmit_duration: 32,
+            sample_period: 80,
+            transmit_frequency: 740,
+            start_angle: 0,
+            stop_angle: 399,
+            num_steps: 1,
+            delay: 0,
+            number_of_samples: data.len() as u16,
+            data_length: data.len() as u16,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_detect_obstacle_within_range() {
+        // 80 ticks of sample period is 1.5 mm per sample
+        let mut samples = vec![0; 1200];
+        samples[10] = 255;
+        samples[1000] = 220;
+        let obstacle = detect_obstacle(&ping360_data(samples), 0, &ReportOptions::default())
+            .unwrap()
+            .1;
+        assert!((obstacle.range_m - 1.5).abs() < 1e-9);
+        assert!((obstacle.angle_deg - 90.0).abs() < 1e-9);
+        assert_eq!(obstacle.intensity, 220);
+    }
+
+    #[test]
+    fn test_detect_obstacle_out_of_range() {
+        let mut samples = vec![0; 1200];
+        samples[1000] = 220;
+        let options = ReportOptions {
+            obstacle_range_m: 1.0,
+            ..Default::default()
+        };
+        assert!(detect_obstacle(&ping360_data(samples), 0, &options).is_none());
+    }
+}
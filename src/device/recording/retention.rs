@@ -0,0 +1,179 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+pub const RETENTION_CHECK_PERIOD: Duration = Duration::from_secs(60);
+
+/// Limits for the recordings directory, the oldest files are removed first
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RetentionPolicy {
+    pub max_total_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_files: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_total_size.is_some() || self.max_age.is_some() || self.max_files.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct DiskUsage {
+    pub recordings: usize,
+    pub total_size: u64,
+    pub oldest: Option<String>,
+    pub newest: Option<String>,
+    pub max_total_size: Option<u64>,
+    pub max_age_s: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordingFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Recordings in the directory and its date subdirectories, oldest first
+pub fn scan(base_path: &Path) -> Vec<RecordingFile> {
+    let mut files: Vec<RecordingFile> = super::recording_files(base_path)
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mcap"))
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(&path).ok()?;
+            Some(RecordingFile {
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                size: metadata.len(),
+                path,
+            })
+        })
+        .collect();
+    files.sort_by_key(|file| file.modified);
+    files
+}
+
+pub fn disk_usage(base_path: &Path, policy: RetentionPolicy) -> DiskUsage {
+    let files = scan(base_path);
+    let modified =
+        |file: &RecordingFile| chrono::DateTime::<chrono::Utc>::from(file.modified).to_rfc3339();
+    DiskUsage {
+        recordings: files.len(),
+        total_size: files.iter().map(|file| file.size).sum(),
+        oldest: files.first().map(modified),
+        newest: files.last().map(modified),
+        max_total_size: policy.max_total_size,
+        max_age_s: policy.max_age.map(|max_age| max_age.as_secs()),
+        max_files: policy.max_files,
+    }
+}
+
+/// Files to remove so the remaining ones respect the policy, never the ones being written
+pub fn select_expired<'a>(
+    files: &'a [RecordingFile],
+    policy: &RetentionPolicy,
+    active: &HashSet<PathBuf>,
+    now: SystemTime,
+) -> Vec<&'a RecordingFile> {
+    let mut remaining_files = files.len();
+    let mut remaining_size: u64 = files.iter().map(|file| file.size).sum();
+    let mut expired = Vec::new();
+
+    for file in files {
+        if active.contains(&file.path) {
+            continue;
+        }
+        let too_old = policy.max_age.is_some_and(|max_age| {
+            now.duration_since(file.modified)
+                .is_ok_and(|age| age > max_age)
+        });
+        let too_many = policy
+            .max_files
+            .is_some_and(|max_files| remaining_files > max_files);
+        let too_big = policy
+            .max_total_size
+            .is_some_and(|max_total_size| remaining_size > max_total_size);
+        if !(too_old || too_many || too_big) {
+            continue;
+        }
+        remaining_files -= 1;
+        remaining_size -= file.size;
+        expired.push(file);
+    }
+
+    expired
+}
+
+pub fn prune(base_path: &Path, policy: &RetentionPolicy, active: &HashSet<PathBuf>) -> usize {
+    let files = scan(base_path);
+    let mut removed = 0;
+    for file in select_expired(&files, policy, active, SystemTime::now()) {
+        match std::fs::remove_file(&file.path) {
+            Ok(()) => {
+                info!(
+                    "Retention: Removed recording {:?} ({} bytes)",
+                    file.path, file.size
+                );
+                super::remove_empty_parents(base_path, &file.path);
+                removed += 1;
+            }
+            Err(err) => warn!("Retention: Failed to remove {:?}: {err}", file.path),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64, age_s: u64, now: SystemTime) -> RecordingFile {
+        RecordingFile {
+            path: PathBuf::from(name),
+            size,
+            modified: now - Duration::from_secs(age_s),
+        }
+    }
+
+    #[test]
+    fn test_oldest_removed_until_policy_holds() {
+        let now = SystemTime::now();
+        let files = vec![
+            file("a.mcap", 100, 300, now),
+            file("b.mcap", 100, 200, now),
+            file("c.mcap", 100, 100, now),
+        ];
+        let policy = RetentionPolicy {
+            max_total_size: Some(150),
+            ..Default::default()
+        };
+        let expired = select_expired(&files, &policy, &HashSet::new(), now);
+        let names: Vec<_> = expired.iter().map(|file| file.path.clone()).collect();
+        assert_eq!(
+            names,
+            vec![PathBuf::from("a.mcap"), PathBuf::from("b.mcap")]
+        );
+    }
+
+    #[test]
+    fn test_active_recording_is_kept() {
+        let now = SystemTime::now();
+        let files = vec![file("a.mcap", 100, 300, now), file("b.mcap", 100, 1, now)];
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            max_files: Some(0),
+            ..Default::default()
+        };
+        let active = HashSet::from([PathBuf::from("a.mcap")]);
+        let expired = select_expired(&files, &policy, &active, now);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].path, PathBuf::from("b.mcap"));
+    }
+}
@@ -0,0 +1,497 @@
+use std::sync::Arc;
+
+use bluerobotics_ping::{ping1d::ProfileStruct, ping360::AutoDeviceDataStruct};
+use foxglove::{schemas::Timestamp, Context, RawChannel};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::device::manager::ManagerError;
+use crate::vehicle::VehicleData;
+
+use super::report;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Apiv2Schema)]
+pub enum RecordingFormat {
+    /// Foxglove channels with JSON schemas of the ping-protocol structures
+    #[default]
+    Mcap,
+    /// `ros2` profile MCAP with CDR encoded sensor_msgs, readable by `ros2 bag`
+    Ros2,
+}
+
+impl RecordingFormat {
+    /// Chunks are buffered before they are written, the file is only appended to so in-progress
+    /// downloads never read a chunk header that is patched later
+    pub fn write_options(&self) -> foxglove::McapWriteOptions {
+        let options = foxglove::McapWriteOptions::new().disable_seeking(true);
+        match self {
+            RecordingFormat::Mcap => options,
+            RecordingFormat::Ros2 => options.profile("ros2"),
+        }
+    }
+}
+
+// Ping1D nominal beam width
+const PING1D_FIELD_OF_VIEW_RAD: f32 = 30.0 * std::f32::consts::PI / 180.0;
+
+const HEADER_DEFINITION: &str = "\
+================================================================================
+MSG: std_msgs/Header
+builtin_interfaces/Time stamp
+string frame_id
+================================================================================
+MSG: builtin_interfaces/Time
+int32 sec
+uint32 nanosec
+";
+
+const RANGE_DEFINITION: &str = "\
+uint8 ULTRASOUND=0
+uint8 INFRARED=1
+std_msgs/Header header
+uint8 radiation_type
+float32 field_of_view
+float32 min_range
+float32 max_range
+float32 range
+";
+
+const POINT_CLOUD_DEFINITION: &str = "\
+std_msgs/Header header
+uint32 height
+uint32 width
+PointField[] fields
+bool is_bigendian
+uint32 point_step
+uint32 row_step
+uint8[] data
+bool is_dense
+================================================================================
+MSG: sensor_msgs/PointField
+uint8 INT8=1
+uint8 UINT8=2
+uint8 INT16=3
+uint8 UINT16=4
+uint8 INT32=5
+uint8 UINT32=6
+uint8 FLOAT32=7
+uint8 FLOAT64=8
+string name
+uint32 offset
+uint8 datatype
+uint32 count
+";
+
+const NAV_SAT_FIX_DEFINITION: &str = "\
+std_msgs/Header header
+NavSatStatus status
+float64 latitude
+float64 longitude
+float64 altitude
+float64[9] position_covariance
+uint8 position_covariance_type
+================================================================================
+MSG: sensor_msgs/NavSatStatus
+int8 STATUS_NO_FIX=-1
+int8 STATUS_FIX=0
+int8 STATUS_SBAS_FIX=1
+int8 STATUS_GBAS_FIX=2
+int8 status
+uint16 SERVICE_GPS=1
+uint16 SERVICE_GLONASS=2
+uint16 SERVICE_COMPASS=4
+uint16 SERVICE_GALILEO=8
+uint16 service
+";
+
+const IMU_DEFINITION: &str = "\
+std_msgs/Header header
+geometry_msgs/Quaternion orientation
+float64[9] orientation_covariance
+geometry_msgs/Vector3 angular_velocity
+float64[9] angular_velocity_covariance
+geometry_msgs/Vector3 linear_acceleration
+float64[9] linear_acceleration_covariance
+================================================================================
+MSG: geometry_msgs/Quaternion
+float64 x
+float64 y
+float64 z
+float64 w
+================================================================================
+MSG: geometry_msgs/Vector3
+float64 x
+float64 y
+float64 z
+";
+
+const STRING_DEFINITION: &str = "string data\n";
+
+const TF_DEFINITION: &str = "\
+geometry_msgs/TransformStamped[] transforms
+================================================================================
+MSG: geometry_msgs/TransformStamped
+std_msgs/Header header
+string child_frame_id
+Transform transform
+================================================================================
+MSG: geometry_msgs/Transform
+Vector3 translation
+Quaternion rotation
+================================================================================
+MSG: geometry_msgs/Vector3
+float64 x
+float64 y
+float64 z
+================================================================================
+MSG: geometry_msgs/Quaternion
+float64 x
+float64 y
+float64 z
+float64 w
+";
+
+/// Little endian CDR as used by rmw, alignment counts from the end of the encapsulation header
+struct CdrWriter {
+    data: Vec<u8>,
+}
+
+impl CdrWriter {
+    fn new() -> Self {
+        Self {
+            data: vec![0x00, 0x01, 0x00, 0x00],
+        }
+    }
+
+    fn align(&mut self, size: usize) {
+        while (self.data.len() - 4) % size != 0 {
+            self.data.push(0);
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn i8(&mut self, value: i8) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.align(2);
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.align(4);
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.align(4);
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.align(8);
+        self.data.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32 + 1);
+        self.data.extend(value.as_bytes());
+        self.data.push(0);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend(value);
+    }
+
+    fn header(&mut self, timestamp: Timestamp, frame_id: &str) {
+        self.i32(timestamp.sec() as i32);
+        self.u32(timestamp.nsec());
+        self.string(frame_id);
+    }
+
+    fn covariance(&mut self, first: f64) {
+        self.f64(first);
+        for _ in 1..9 {
+            self.f64(0.0);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+fn log_time(timestamp: Timestamp) -> u64 {
+    timestamp.sec() as u64 * 1_000_000_000 + timestamp.nsec() as u64
+}
+
+fn build_channel(
+    ctx: &Arc<Context>,
+    topic: &str,
+    schema_name: &str,
+    definition: String,
+) -> Result<Arc<RawChannel>, ManagerError> {
+    ctx.channel_builder(topic)
+        .message_encoding("cdr")
+        .schema(foxglove::Schema::new(
+            schema_name,
+            "ros2msg",
+            definition.into_bytes(),
+        ))
+        .build_raw()
+        .map_err(|err| {
+            ManagerError::Other(format!("Failed to create ROS 2 channel {topic}: {err}"))
+        })
+}
+
+fn log(channel: &RawChannel, writer: CdrWriter, timestamp: Timestamp) {
+    channel.log_with_meta(
+        &writer.finish(),
+        foxglove::PartialMetadata::with_log_time(log_time(timestamp)),
+    );
+}
+
+/// ROS 2 view of a device session, topics live under `/device_<id>` with the simple form of the id
+pub struct Ros2Channels {
+    frame_id: String,
+    range: Arc<RawChannel>,
+    points: Arc<RawChannel>,
+    // NavSatFix and Imu of the vehicle, under `/<vehicle_prefix>`
+    vehicle: Option<(Arc<RawChannel>, Arc<RawChannel>)>,
+}
+
+impl Ros2Channels {
+    /// Without a vehicle prefix the session leaves the vehicle topics to another device of its group
+    pub fn new(
+        ctx: &Arc<Context>,
+        device_id: Uuid,
+        vehicle_prefix: Option<&str>,
+    ) -> Result<Self, ManagerError> {
+        let frame_id = frame_id(device_id);
+        let vehicle = match vehicle_prefix {
+            Some(prefix) => Some((
+                build_channel(
+                    ctx,
+                    &format!("/{prefix}/fix"),
+                    "sensor_msgs/msg/NavSatFix",
+                    format!("{NAV_SAT_FIX_DEFINITION}{HEADER_DEFINITION}"),
+                )?,
+                build_channel(
+                    ctx,
+                    &format!("/{prefix}/imu"),
+                    "sensor_msgs/msg/Imu",
+                    format!("{IMU_DEFINITION}{HEADER_DEFINITION}"),
+                )?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            range: build_channel(
+                ctx,
+                &format!("/{frame_id}/range"),
+                "sensor_msgs/msg/Range",
+                format!("{RANGE_DEFINITION}{HEADER_DEFINITION}"),
+            )?,
+            points: build_channel(
+                ctx,
+                &format!("/{frame_id}/points"),
+                "sensor_msgs/msg/PointCloud2",
+                format!("{POINT_CLOUD_DEFINITION}{HEADER_DEFINITION}"),
+            )?,
+            vehicle,
+            frame_id,
+        })
+    }
+
+    pub fn log_ping1d(&self, profile: &ProfileStruct, timestamp: Timestamp) {
+        let mut writer = CdrWriter::new();
+        writer.header(timestamp, &self.frame_id);
+        writer.u8(0); // ULTRASOUND
+        writer.f32(PING1D_FIELD_OF_VIEW_RAD);
+        writer.f32(profile.scan_start as f32 / 1000.0);
+        writer.f32((profile.scan_start + profile.scan_length) as f32 / 1000.0);
+        writer.f32(profile.distance as f32 / 1000.0);
+        log(&self.range, writer, timestamp);
+    }
+
+    /// One point per sample along the beam, with the echo strength as intensity
+    pub fn log_ping360(&self, data: &AutoDeviceDataStruct, timestamp: Timestamp) {
+        const POINT_STEP: u32 = 16;
+        // Ping360 angles are in gradians
+        let angle = data.angle as f64 * std::f64::consts::PI / 200.0;
+        let mut points = Vec::with_capacity(data.data.len() * POINT_STEP as usize);
+        for (index, intensity) in data.data.iter().enumerate() {
+            let range = report::ping360_range(data.sample_period, index);
+            for value in [
+                (range * angle.cos()) as f32,
+                (range * angle.sin()) as f32,
+                0.0,
+                *intensity as f32,
+            ] {
+                points.extend(value.to_le_bytes());
+            }
+        }
+
+        let mut writer = CdrWriter::new();
+        writer.header(timestamp, &self.frame_id);
+        writer.u32(1);
+        writer.u32(data.data.len() as u32);
+        writer.u32(4);
+        for (offset, name) in ["x", "y", "z", "intensity"].into_iter().enumerate() {
+            writer.string(name);
+            writer.u32(offset as u32 * 4);
+            writer.u8(7); // FLOAT32
+            writer.u32(1);
+        }
+        writer.u8(0);
+        writer.u32(POINT_STEP);
+        writer.u32(POINT_STEP * data.data.len() as u32);
+        writer.bytes(&points);
+        writer.u8(1);
+        log(&self.points, writer, timestamp);
+    }
+
+    /// Position as NavSatFix and attitude as Imu, angles as reported by the autopilot
+    pub fn log_vehicle(&self, vehicle: &VehicleData, timestamp: Timestamp) {
+        let Some((fix, imu)) = &self.vehicle else {
+            return;
+        };
+        let mut writer = CdrWriter::new();
+        writer.header(timestamp, &self.frame_id);
+        writer.i8(0); // STATUS_FIX
+        writer.u16(1); // SERVICE_GPS
+        writer.f64(vehicle.lat);
+        writer.f64(vehicle.lon);
+        writer.f64(vehicle.alt);
+        writer.covariance(0.0);
+        writer.u8(0); // COVARIANCE_TYPE_UNKNOWN
+        log(fix, writer, timestamp);
+
+        let [x, y, z, w] = quaternion(vehicle.roll, vehicle.pitch, vehicle.yaw);
+        let mut writer = CdrWriter::new();
+        writer.header(timestamp, &self.frame_id);
+        for value in [x, y, z, w] {
+            writer.f64(value);
+        }
+        writer.covariance(0.0);
+        // Not measured, marked as such with -1 on the first covariance element
+        for _ in 0..2 {
+            for _ in 0..3 {
+                writer.f64(0.0);
+            }
+            writer.covariance(-1.0);
+        }
+        log(imu, writer, timestamp);
+    }
+}
+
+pub fn frame_id(device_id: Uuid) -> String {
+    format!("device_{}", device_id.simple())
+}
+
+/// `std_msgs/String` topic such as `/device_<id>/annotations`, for notes written alongside the data
+pub fn string_channel(
+    ctx: &Arc<Context>,
+    device_id: Uuid,
+    name: &str,
+) -> Result<Arc<RawChannel>, ManagerError> {
+    build_channel(
+        ctx,
+        &format!("/{}/{name}", frame_id(device_id)),
+        "std_msgs/msg/String",
+        STRING_DEFINITION.to_string(),
+    )
+}
+
+pub fn log_string(channel: &RawChannel, text: &str, timestamp: Timestamp) {
+    let mut writer = CdrWriter::new();
+    writer.string(text);
+    log(channel, writer, timestamp);
+}
+
+/// Shared `/tf` topic, every recorded device adds its own mounting frame
+pub fn tf_channel(ctx: &Arc<Context>) -> Result<Arc<RawChannel>, ManagerError> {
+    build_channel(
+        ctx,
+        "/tf",
+        "tf2_msgs/msg/TFMessage",
+        format!("{TF_DEFINITION}{HEADER_DEFINITION}"),
+    )
+}
+
+pub fn log_transforms(
+    channel: &RawChannel,
+    transforms: &foxglove::schemas::FrameTransforms,
+    timestamp: Timestamp,
+) {
+    let mut writer = CdrWriter::new();
+    writer.u32(transforms.transforms.len() as u32);
+    for transform in &transforms.transforms {
+        writer.header(
+            transform.timestamp.unwrap_or(timestamp),
+            &transform.parent_frame_id,
+        );
+        writer.string(&transform.child_frame_id);
+        let translation = transform.translation.clone().unwrap_or_default();
+        let rotation = transform.rotation.clone().unwrap_or_default();
+        for value in [
+            translation.x,
+            translation.y,
+            translation.z,
+            rotation.x,
+            rotation.y,
+            rotation.z,
+            rotation.w,
+        ] {
+            writer.f64(value);
+        }
+    }
+    log(channel, writer, timestamp);
+}
+
+pub(super) fn quaternion(roll: f32, pitch: f32, yaw: f32) -> [f64; 4] {
+    let (sr, cr) = (roll as f64 / 2.0).sin_cos();
+    let (sp, cp) = (pitch as f64 / 2.0).sin_cos();
+    let (sy, cy) = (yaw as f64 / 2.0).sin_cos();
+    [
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+        cr * cp * cy + sr * sp * sy,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cdr_aligns_after_encapsulation_header() {
+        let mut writer = CdrWriter::new();
+        writer.u8(1);
+        writer.f64(2.0);
+        let data = writer.finish();
+        assert_eq!(&data[..5], &[0, 1, 0, 0, 1]);
+        // 7 bytes of padding so the f64 starts 8 bytes after the header
+        assert_eq!(data.len(), 4 + 8 + 8);
+        assert_eq!(&data[12..], &2.0f64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_cdr_string_is_null_terminated() {
+        let mut writer = CdrWriter::new();
+        writer.string("hi");
+        assert_eq!(writer.finish(), vec![0, 1, 0, 0, 3, 0, 0, 0, b'h', b'i', 0]);
+    }
+}
@@ -0,0 +1,117 @@
+use bluerobotics_ping::message::ProtocolMessage;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::vehicle::{history, VehicleData};
+
+use super::{decode_sonar_message, georeference::body_to_ned, SonarData};
+
+// Ping360 angles are in gradians, a full turn of the head
+const GRADIANS: f64 = 400.0;
+
+/// Ping360 beam direction in the world frame, so displays stay still while the vehicle turns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Apiv2Schema, schemars::JsonSchema)]
+pub struct StabilizedBeam {
+    pub device_id: Uuid,
+    #[schemars(description = "Unix time in milliseconds of the sample")]
+    pub timestamp_ms: i64,
+    #[schemars(
+        description = "Head angle of the sample in gradians, 0 to 399, as sent by the device"
+    )]
+    pub angle: u16,
+    #[schemars(
+        description = "Beam angle from north in gradians, 0 to 400, to draw in place of the head angle"
+    )]
+    pub world_angle: f64,
+    #[schemars(description = "Beam bearing from north in degrees")]
+    pub bearing: f64,
+    #[schemars(
+        description = "Beam angle below the horizon in degrees, from the vehicle roll and pitch"
+    )]
+    pub tilt: f64,
+}
+
+/// World angle of a Ping360 message with the vehicle attitude at the given time, None without a fresh pose
+pub fn stabilize_message(
+    device_id: Uuid,
+    msg: &ProtocolMessage,
+    timestamp_ms: i64,
+) -> Option<StabilizedBeam> {
+    let SonarData::Ping360(data) = decode_sonar_message(msg)? else {
+        return None;
+    };
+    stabilize(device_id, data.angle, timestamp_ms)
+}
+
+/// World angle of a Ping360 head angle in gradians with the vehicle attitude at the given time
+pub fn stabilize(device_id: Uuid, angle: u16, timestamp_ms: i64) -> Option<StabilizedBeam> {
+    let pose = history::pose_at(timestamp_ms)?;
+    Some(beam(device_id, angle, timestamp_ms, &pose))
+}
+
+fn beam(device_id: Uuid, angle: u16, timestamp_ms: i64, pose: &VehicleData) -> StabilizedBeam {
+    // Beam in the vehicle frame, assuming the default mounting with the head angle 0 forward
+    let head = angle as f64 * std::f64::consts::TAU / GRADIANS;
+    let [north, east, down] = body_to_ned([head.cos(), head.sin(), 0.0], pose);
+    let bearing = east.atan2(north).to_degrees().rem_euclid(360.0);
+    StabilizedBeam {
+        device_id,
+        timestamp_ms,
+        angle,
+        world_angle: bearing * GRADIANS / 360.0,
+        bearing,
+        tilt: down.clamp(-1.0, 1.0).asin().to_degrees(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(pitch: f32, yaw: f32) -> VehicleData {
+        VehicleData {
+            roll: 0.0,
+            pitch,
+            yaw,
+            alt: 0.0,
+            lat: 0.0,
+            lon: 0.0,
+            heading: None,
+            groundspeed: None,
+            depth: None,
+            local_position: None,
+            velocity: None,
+            timestamp_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_world_angle_follows_the_yaw() {
+        let device_id = Uuid::new_v4();
+        // Heading east, the forward beam points east and the starboard one south
+        let east = pose(0.0, std::f32::consts::FRAC_PI_2);
+        let forward = beam(device_id, 0, 1, &east);
+        assert!((forward.bearing - 90.0).abs() < 1e-4);
+        assert!((forward.world_angle - 100.0).abs() < 1e-4);
+        assert!(forward.tilt.abs() < 1e-9);
+        let starboard = beam(device_id, 100, 1, &east);
+        assert!((starboard.bearing - 180.0).abs() < 1e-4);
+
+        // Heading north-west the forward beam wraps around instead of going negative
+        let north_west = beam(device_id, 0, 1, &pose(0.0, -std::f32::consts::FRAC_PI_4));
+        assert!((north_west.world_angle - 350.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_pitch_tilts_the_forward_beam() {
+        // Nose down, the forward beam looks below the horizon
+        let sample = beam(
+            Uuid::new_v4(),
+            0,
+            1,
+            &pose(-std::f32::consts::FRAC_PI_6, 0.0),
+        );
+        assert!((sample.tilt - 30.0).abs() < 1e-4, "{}", sample.tilt);
+    }
+}
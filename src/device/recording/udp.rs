@@ -0,0 +1,186 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bluerobotics_ping::message::ProtocolMessage;
+use tokio::{net::UdpSocket, sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::cli::manager::UdpOutputFormat;
+use crate::device::{
+    devices::PingAnswer,
+    manager::{Answer, DeviceAnswer, DeviceStatus, ManagerActorHandler, ManagerError},
+};
+
+use super::device_subscriber;
+
+// How often the devices to forward are refreshed from the DeviceManager
+const UDP_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+
+/// Forwards the messages of every streaming device to one UDP destination, for software that only reads a socket
+pub struct UdpOutput {
+    devices_manager_handler: ManagerActorHandler,
+    format: UdpOutputFormat,
+    senders: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl UdpOutput {
+    pub fn new(devices_manager_handler: ManagerActorHandler, format: UdpOutputFormat) -> Self {
+        Self {
+            devices_manager_handler,
+            format,
+            senders: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self, target: String) -> Result<(), ManagerError> {
+        let local = if target.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local)
+            .await
+            .map_err(|err| ManagerError::Other(format!("Failed to open UDP socket: {err}")))?;
+        // Also resolves host names, once, a moved destination needs a restart
+        socket.connect(&target).await.map_err(|err| {
+            ManagerError::Other(format!("Invalid UDP output target {target:?}: {err}"))
+        })?;
+        info!(
+            "UdpOutput: Sending device messages as {:?} to {target}",
+            self.format
+        );
+        let socket = Arc::new(socket);
+
+        let mut refresh_interval = tokio::time::interval(UDP_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_senders(&socket).await;
+        }
+    }
+
+    async fn refresh_senders(&mut self, socket: &Arc<UdpSocket>) {
+        let streaming = streaming_devices(&self.devices_manager_handler).await;
+
+        self.senders.retain(|device_id, sender| {
+            let keep = streaming.contains(device_id) && !sender.is_finished();
+            if !keep {
+                sender.abort();
+            }
+            keep
+        });
+
+        for device_id in streaming {
+            if self.senders.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver =
+                match device_subscriber(&self.devices_manager_handler, device_id).await {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        warn!("UdpOutput: Failed to subscribe to device {device_id}: {err:?}");
+                        continue;
+                    }
+                };
+
+            let socket = socket.clone();
+            let format = self.format;
+            let sender = tokio::spawn(async move {
+                debug!("UdpOutput: Forwarding device {device_id}");
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            let Some(datagram) = encode(&msg, device_id, format) else {
+                                continue;
+                            };
+                            // Nobody listening is normal for UDP, a refused send is not worth more than a debug line
+                            if let Err(err) = socket.send(&datagram).await {
+                                debug!("UdpOutput: Failed to send message of {device_id}: {err}");
+                            }
+                        }
+                        // Listeners of a live stream prefer fresh data
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("UdpOutput: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+                debug!("UdpOutput: Stopped forwarding device {device_id}");
+            });
+            self.senders.insert(device_id, sender);
+        }
+    }
+}
+
+fn encode(msg: &ProtocolMessage, device_id: Uuid, format: UdpOutputFormat) -> Option<Vec<u8>> {
+    match format {
+        // The Ping protocol frame as received, what Ping-Viewer compatible software parses
+        UdpOutputFormat::Binary => Some(msg.serialized()),
+        UdpOutputFormat::Json => device_message_json(msg, device_id),
+    }
+}
+
+/// Devices currently sending messages, the ones worth forwarding
+pub(crate) async fn streaming_devices(devices_manager_handler: &ManagerActorHandler) -> Vec<Uuid> {
+    match devices_manager_handler
+        .send(crate::device::manager::Request::List)
+        .await
+    {
+        Ok(Answer::DeviceInfo(devices)) => devices
+            .into_iter()
+            .filter(|device| {
+                matches!(
+                    device.status,
+                    DeviceStatus::Running | DeviceStatus::ContinuousMode
+                )
+            })
+            .map(|device| device.id)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Same layout as the device messages of the websocket
+pub(crate) fn device_message_json(msg: &ProtocolMessage, device_id: Uuid) -> Option<Vec<u8>> {
+    let message = bluerobotics_ping::Messages::try_from(msg).ok()?;
+    let answer = Answer::DeviceMessage(DeviceAnswer {
+        answer: PingAnswer::PingMessage(message),
+        device_id,
+    });
+    serde_json::to_vec(&answer).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol_version() -> ProtocolMessage {
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&bluerobotics_ping::common::Messages::ProtocolVersion(
+            bluerobotics_ping::common::ProtocolVersionStruct {
+                version_major: 1,
+                version_minor: 0,
+                version_patch: 0,
+                reserved: 0,
+            },
+        ));
+        msg
+    }
+
+    #[test]
+    fn test_datagram_formats() {
+        let msg = protocol_version();
+        let device_id = Uuid::new_v4();
+
+        let binary = encode(&msg, device_id, UdpOutputFormat::Binary).unwrap();
+        assert_eq!(binary, msg.serialized());
+        assert_eq!(&binary[..2], b"BR");
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&encode(&msg, device_id, UdpOutputFormat::Json).unwrap())
+                .unwrap();
+        assert_eq!(
+            json["DeviceMessage"]["device_id"],
+            serde_json::json!(device_id)
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::device::manager::ManagerError;
+
+use super::jobs::{Job, JobKind, JobProgress, JOBS};
+
+// Long enough for a multi-gigabyte recording over a slow tether
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+// Lifetime of the presigned S3 request, only needs to cover the start of the transfer
+const PRESIGN_DURATION: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    static ref TARGET: RwLock<Option<UploadTarget>> = RwLock::new(None);
+}
+
+/// Remote storage recordings are pushed to, loaded from the `--upload-config` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum UploadTarget {
+    /// Any S3-compatible object storage, e.g. AWS S3 or MinIO
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+        /// Prepended to the recording file name, e.g. `vehicle-1/`
+        #[serde(default)]
+        prefix: String,
+    },
+    WebDav {
+        /// Collection the recordings are written into, e.g. `https://nas.local/remote.php/dav/files/pilot/dives`
+        url: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct UploadRequest {
+    /// Recording file names relative to the recordings directory
+    pub files: Vec<String>,
+}
+
+pub fn load_target(path: &Path) -> Result<UploadTarget, ManagerError> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        ManagerError::Other(format!("Failed to read upload config {path:?}: {err}"))
+    })?;
+    serde_json5::from_str(&content)
+        .map_err(|err| ManagerError::Other(format!("Invalid upload config {path:?}: {err}")))
+}
+
+pub fn set_target(target: UploadTarget) {
+    info!(
+        "Upload: Recordings are pushed to {}",
+        match &target {
+            UploadTarget::S3 {
+                endpoint, bucket, ..
+            } => format!("S3 bucket {bucket} at {endpoint}"),
+            UploadTarget::WebDav { url, .. } => format!("WebDAV {url}"),
+        }
+    );
+    *TARGET.write().unwrap() = Some(target);
+}
+
+pub fn is_configured() -> bool {
+    TARGET.read().unwrap().is_some()
+}
+
+/// Queue the upload of one recording, transfer progress is reported on the job
+pub fn upload(file_path: PathBuf, remote_name: String) -> Result<Job, ManagerError> {
+    let target = TARGET
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| ManagerError::Other("No upload target is configured".to_string()))?;
+
+    let job_name = remote_name.clone();
+    let (id, _) = JOBS.submit(JobKind::Upload, &job_name, move |progress| {
+        upload_file(&target, &file_path, &remote_name, progress)
+    });
+    JOBS.get(id)
+        .ok_or_else(|| ManagerError::Other(format!("Upload job {id} is gone")))
+}
+
+fn upload_file(
+    target: &UploadTarget,
+    file_path: &Path,
+    remote_name: &str,
+    progress: &mut JobProgress,
+) -> Result<(), ManagerError> {
+    let file = std::fs::File::open(file_path)
+        .map_err(|err| ManagerError::Other(format!("Failed to open {file_path:?}: {err}")))?;
+    let size = file
+        .metadata()
+        .map_err(|err| ManagerError::Other(format!("Failed to read {file_path:?}: {err}")))?
+        .len();
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()
+        .map_err(|err| ManagerError::Other(format!("Failed to create HTTP client: {err}")))?;
+
+    let body = reqwest::blocking::Body::sized(
+        ProgressReader {
+            inner: file,
+            sent: 0,
+            size,
+            progress: progress.clone(),
+        },
+        size,
+    );
+
+    let request = match target {
+        UploadTarget::S3 {
+            endpoint,
+            bucket,
+            region,
+            access_key,
+            secret_key,
+            prefix,
+        } => {
+            let endpoint = endpoint
+                .parse()
+                .map_err(|err| ManagerError::Other(format!("Invalid S3 endpoint: {err}")))?;
+            let bucket = rusty_s3::Bucket::new(
+                endpoint,
+                rusty_s3::UrlStyle::Path,
+                bucket.clone(),
+                region.clone(),
+            )
+            .map_err(|err| ManagerError::Other(format!("Invalid S3 bucket: {err}")))?;
+            let credentials = rusty_s3::Credentials::new(access_key.clone(), secret_key.clone());
+            let key = format!("{prefix}{remote_name}");
+            let url = rusty_s3::S3Action::sign(
+                &bucket.put_object(Some(&credentials), &key),
+                PRESIGN_DURATION,
+            );
+            client.put(url)
+        }
+        UploadTarget::WebDav {
+            url,
+            username,
+            password,
+        } => {
+            let base = url.trim_end_matches('/');
+            create_collections(&client, base, remote_name, username, password)?;
+            with_auth(
+                client.put(format!("{base}/{remote_name}")),
+                username,
+                password,
+            )
+        }
+    };
+
+    info!("Upload: Sending {file_path:?} ({size} bytes)");
+    let response = request
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .body(body)
+        .send()
+        .map_err(|err| ManagerError::Other(format!("Upload of {remote_name} failed: {err}")))?;
+    if !response.status().is_success() {
+        return Err(ManagerError::Other(format!(
+            "Upload of {remote_name} was rejected: {}",
+            response.status()
+        )));
+    }
+    info!("Upload: {remote_name} done");
+    Ok(())
+}
+
+// WebDAV does not create missing parents on PUT, e.g. for date directories
+fn create_collections(
+    client: &reqwest::blocking::Client,
+    base: &str,
+    remote_name: &str,
+    username: &Option<String>,
+    password: &Option<String>,
+) -> Result<(), ManagerError> {
+    let mut collection = base.to_string();
+    let Some((directories, _)) = remote_name.rsplit_once('/') else {
+        return Ok(());
+    };
+    for directory in directories.split('/') {
+        collection = format!("{collection}/{directory}");
+        let method = reqwest::Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method");
+        let response = with_auth(client.request(method, &collection), username, password)
+            .send()
+            .map_err(|err| {
+                ManagerError::Other(format!("Failed to create collection {collection}: {err}"))
+            })?;
+        // 405 is the answer for an already existing collection
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            return Err(ManagerError::Other(format!(
+                "Failed to create collection {collection}: {status}"
+            )));
+        }
+        debug!("Upload: Collection {collection} ready");
+    }
+    Ok(())
+}
+
+fn with_auth(
+    request: reqwest::blocking::RequestBuilder,
+    username: &Option<String>,
+    password: &Option<String>,
+) -> reqwest::blocking::RequestBuilder {
+    match username {
+        Some(username) => request.basic_auth(username, password.as_ref()),
+        None => request,
+    }
+}
+
+struct ProgressReader<R> {
+    inner: R,
+    sent: u64,
+    size: u64,
+    progress: JobProgress,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.sent += read as u64;
+        if self.size > 0 {
+            self.progress.set(self.sent as f64 / self.size as f64);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::device::recording::jobs::JobManager;
+
+    struct ReceivedRequest {
+        method: String,
+        path: String,
+        authorization: Option<String>,
+        body: Vec<u8>,
+    }
+
+    /// HTTP server answering `201 Created` to the next `count` requests, which are kept
+    fn serve(count: usize) -> (String, Arc<Mutex<Vec<ReceivedRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server_received = received.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().to_string();
+
+                let mut length = 0;
+                let mut authorization = None;
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "authorization" => authorization = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                server_received.lock().unwrap().push(ReceivedRequest {
+                    method,
+                    path,
+                    authorization,
+                    body,
+                });
+                stream
+                    .write_all(
+                        b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .unwrap();
+            }
+        });
+        (address, received)
+    }
+
+    #[test]
+    fn test_upload_config_is_loaded() {
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.json5", Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{
+                // MinIO on the topside computer
+                kind: "S3",
+                endpoint: "http://192.168.2.1:9000",
+                bucket: "dives",
+                region: "us-east-1",
+                access_key: "pilot",
+                secret_key: "secret",
+            }"#,
+        )
+        .unwrap();
+
+        match load_target(&path).unwrap() {
+            UploadTarget::S3 { bucket, prefix, .. } => {
+                assert_eq!(bucket, "dives");
+                assert_eq!(prefix, "");
+            }
+            target => panic!("Unexpected target {target:?}"),
+        }
+
+        std::fs::write(&path, r#"{ kind: "Ftp" }"#).unwrap();
+        assert!(load_target(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webdav_upload_creates_the_parent_collections() {
+        let (address, received) = serve(2);
+        let target = UploadTarget::WebDav {
+            url: format!("{address}/dav/"),
+            username: Some("pilot".to_string()),
+            password: Some("secret".to_string()),
+        };
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.mcap", Uuid::new_v4()));
+        std::fs::write(&path, b"recording").unwrap();
+
+        let manager = JobManager::new(1);
+        let upload_path = path.clone();
+        let (_, result) = manager.submit(JobKind::Upload, "dive.mcap", move |progress| {
+            upload_file(&target, &upload_path, "2026-10-16/dive.mcap", progress)
+        });
+        result.await.unwrap().unwrap();
+
+        let received = received.lock().unwrap();
+        let requests: Vec<(&str, &str)> = received
+            .iter()
+            .map(|request| (request.method.as_str(), request.path.as_str()))
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                ("MKCOL", "/dav/2026-10-16"),
+                ("PUT", "/dav/2026-10-16/dive.mcap")
+            ]
+        );
+        assert!(received
+            .iter()
+            .all(|request| request.authorization.is_some()));
+        assert_eq!(received[1].body, b"recording");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
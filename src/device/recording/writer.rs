@@ -0,0 +1,194 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+};
+
+use foxglove::{
+    ChannelId, Context, FoxgloveError, McapWriteOptions, Metadata, RawChannel, Sink, SinkId,
+};
+use tracing::warn;
+
+struct WriterState {
+    writer: mcap::Writer<BufWriter<File>>,
+    // Channels of the context to the channel ids of the file
+    channels: HashMap<ChannelId, u16>,
+    sequences: HashMap<u16, u32>,
+}
+
+impl WriterState {
+    fn log(
+        &mut self,
+        channel: &RawChannel,
+        msg: &[u8],
+        metadata: &Metadata,
+    ) -> Result<(), FoxgloveError> {
+        let channel_id = match self.channels.get(&channel.id()) {
+            Some(channel_id) => *channel_id,
+            None => {
+                let schema_id = match channel.schema() {
+                    Some(schema) => {
+                        self.writer
+                            .add_schema(&schema.name, &schema.encoding, &schema.data)?
+                    }
+                    // A channel without a schema
+                    None => 0,
+                };
+                let channel_id = self.writer.add_channel(
+                    schema_id,
+                    channel.topic(),
+                    channel.message_encoding(),
+                    channel.metadata(),
+                )?;
+                self.channels.insert(channel.id(), channel_id);
+                channel_id
+            }
+        };
+        let sequence = self.sequences.entry(channel_id).or_insert(0);
+        *sequence += 1;
+        self.writer.write_to_known_channel(
+            &mcap::records::MessageHeader {
+                channel_id,
+                sequence: *sequence,
+                log_time: metadata.log_time,
+                publish_time: metadata.log_time,
+            },
+            msg,
+        )?;
+        Ok(())
+    }
+}
+
+struct FileSink {
+    id: SinkId,
+    state: Mutex<Option<WriterState>>,
+}
+
+impl Sink for FileSink {
+    fn id(&self) -> SinkId {
+        self.id
+    }
+
+    fn log(
+        &self,
+        channel: &RawChannel,
+        msg: &[u8],
+        metadata: &Metadata,
+    ) -> Result<(), FoxgloveError> {
+        self.state
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or(FoxgloveError::SinkClosed)?
+            .log(channel, msg, metadata)
+    }
+}
+
+/// MCAP file logging the channels of a context, as the foxglove writer does, with metadata records as well
+pub struct McapFileWriter {
+    sink: Arc<FileSink>,
+    context: Weak<Context>,
+}
+
+impl McapFileWriter {
+    /// Fails when the file already exists
+    pub fn create(
+        ctx: &Arc<Context>,
+        path: &Path,
+        options: McapWriteOptions,
+    ) -> Result<Self, FoxgloveError> {
+        let file = BufWriter::new(File::create_new(path)?);
+        let writer = options
+            .library(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ))
+            .create(file)?;
+        let sink = Arc::new(FileSink {
+            id: SinkId::next(),
+            state: Mutex::new(Some(WriterState {
+                writer,
+                channels: HashMap::new(),
+                sequences: HashMap::new(),
+            })),
+        });
+        ctx.add_sink(sink.clone());
+        Ok(Self {
+            sink,
+            context: Arc::downgrade(ctx),
+        })
+    }
+
+    pub fn write_metadata(
+        &self,
+        name: &str,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<(), FoxgloveError> {
+        let mut state = self.sink.state.lock().unwrap();
+        let state = state.as_mut().ok_or(FoxgloveError::SinkClosed)?;
+        state.writer.write_metadata(&mcap::records::Metadata {
+            name: name.to_string(),
+            metadata,
+        })?;
+        Ok(())
+    }
+
+    /// Stops logging, writes the summary and footer, and returns the file
+    pub fn close(self) -> Result<BufWriter<File>, FoxgloveError> {
+        self.finish().map(|file| file.expect("Writer closed twice"))
+    }
+
+    fn finish(&self) -> Result<Option<BufWriter<File>>, FoxgloveError> {
+        if let Some(context) = self.context.upgrade() {
+            context.remove_sink(self.sink.id);
+        }
+        let Some(mut state) = self.sink.state.lock().unwrap().take() else {
+            return Ok(None);
+        };
+        state.writer.finish()?;
+        Ok(Some(state.writer.into_inner()))
+    }
+}
+
+impl Drop for McapFileWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!("Failed to close MCAP file: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_is_written() {
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.mcap", uuid::Uuid::new_v4()));
+        let ctx = Context::new();
+        let writer = McapFileWriter::create(&ctx, &path, McapWriteOptions::default()).unwrap();
+        let channel = ctx
+            .channel_builder("/test")
+            .message_encoding("json")
+            .build_raw()
+            .unwrap();
+        channel.log(b"{}");
+        writer
+            .write_metadata(
+                "bookmark_1",
+                BTreeMap::from([("time".to_string(), "now".to_string())]),
+            )
+            .unwrap();
+        writer.close().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let summary = mcap::Summary::read(&data).unwrap().unwrap();
+        assert_eq!(summary.metadata_indexes.len(), 1);
+        assert_eq!(summary.metadata_indexes[0].name, "bookmark_1");
+        assert_eq!(summary.stats.unwrap().message_count, 1);
+    }
+}
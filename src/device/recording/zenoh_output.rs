@@ -0,0 +1,194 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bluerobotics_ping::message::ProtocolMessage;
+use serde_json::Value;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+use zenoh::bytes::Encoding;
+
+use crate::cli::manager::ZenohPublishEncoding;
+use crate::device::manager::ManagerActorHandler;
+use crate::vehicle;
+
+use super::{device_subscriber, udp::streaming_devices};
+
+// How often the devices to publish are refreshed from the DeviceManager
+const ZENOH_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Mirrors the device messages onto Zenoh, next to the MAVLink topics of the vehicle bridge,
+/// so planners and loggers on the vehicle can use the sonar data without the HTTP API
+pub struct ZenohOutput {
+    devices_manager_handler: ManagerActorHandler,
+    prefix: String,
+    encoding: ZenohPublishEncoding,
+    publishers: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl ZenohOutput {
+    pub fn new(
+        devices_manager_handler: ManagerActorHandler,
+        prefix: String,
+        encoding: ZenohPublishEncoding,
+    ) -> Self {
+        Self {
+            devices_manager_handler,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            encoding,
+            publishers: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        let session = loop {
+            let endpoint = vehicle::bridge_endpoint();
+            let config = vehicle::make_default_config(env!("CARGO_PKG_NAME"), &endpoint);
+            match zenoh::open(config).await {
+                Ok(session) => break Arc::new(session),
+                Err(err) => {
+                    error!(
+                        "ZenohOutput: Session error: {err}, retrying in {}s",
+                        RECONNECT_DELAY.as_secs()
+                    );
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        };
+        info!(
+            "ZenohOutput: Publishing device messages as {:?} on {}/<device>/<message>",
+            self.encoding, self.prefix
+        );
+
+        let mut refresh_interval = tokio::time::interval(ZENOH_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_publishers(&session).await;
+        }
+    }
+
+    async fn refresh_publishers(&mut self, session: &Arc<zenoh::Session>) {
+        let streaming = streaming_devices(&self.devices_manager_handler).await;
+
+        self.publishers.retain(|device_id, publisher| {
+            let keep = streaming.contains(device_id) && !publisher.is_finished();
+            if !keep {
+                publisher.abort();
+            }
+            keep
+        });
+
+        for device_id in streaming {
+            if self.publishers.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver =
+                match device_subscriber(&self.devices_manager_handler, device_id).await {
+                    Ok(receiver) => receiver,
+                    Err(err) => {
+                        warn!("ZenohOutput: Failed to subscribe to device {device_id}: {err:?}");
+                        continue;
+                    }
+                };
+
+            let session = session.clone();
+            let prefix = self.prefix.clone();
+            let encoding = self.encoding;
+            let publisher = tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            let Some((name, payload)) = encode(&msg, encoding) else {
+                                continue;
+                            };
+                            let key = format!("{prefix}/{device_id}/{name}");
+                            if let Err(err) = session
+                                .put(&key, payload)
+                                .encoding(zenoh_encoding(encoding))
+                                .await
+                            {
+                                debug!("ZenohOutput: Failed to publish {key}: {err}");
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("ZenohOutput: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            self.publishers.insert(device_id, publisher);
+        }
+    }
+}
+
+fn zenoh_encoding(encoding: ZenohPublishEncoding) -> Encoding {
+    match encoding {
+        ZenohPublishEncoding::Json => Encoding::APPLICATION_JSON,
+        ZenohPublishEncoding::Cbor => Encoding::APPLICATION_CBOR,
+        ZenohPublishEncoding::Msgpack => Encoding::from("application/msgpack"),
+        ZenohPublishEncoding::Binary => Encoding::APPLICATION_OCTET_STREAM,
+    }
+}
+
+// Key name and payload of a message, e.g. `profile` with the fields of the Ping1D profile
+fn encode(msg: &ProtocolMessage, encoding: ZenohPublishEncoding) -> Option<(String, Vec<u8>)> {
+    let message = bluerobotics_ping::Messages::try_from(msg).ok()?;
+    // `{"Ping1D": {"Profile": {...}}}`, as the v2 envelopes unwrap it
+    let value = serde_json::to_value(&message).ok()?;
+    let (name, fields) = single_entry(&value).and_then(|(_, inner)| single_entry(inner))?;
+    let payload = match encoding {
+        ZenohPublishEncoding::Json => serde_json::to_vec(fields).ok()?,
+        ZenohPublishEncoding::Cbor => {
+            let mut buffer = Vec::new();
+            ciborium::into_writer(fields, &mut buffer).ok()?;
+            buffer
+        }
+        ZenohPublishEncoding::Msgpack => rmp_serde::to_vec_named(fields).ok()?,
+        ZenohPublishEncoding::Binary => msg.serialized(),
+    };
+    Some((key_name(name), payload))
+}
+
+fn single_entry(value: &Value) -> Option<(&String, &Value)> {
+    let object = value.as_object()?;
+    (object.len() == 1).then(|| object.iter().next())?
+}
+
+// Zenoh keys are lower case by convention, `AutoDeviceData` becomes `auto_device_data`
+fn key_name(name: &str) -> String {
+    let mut key = String::with_capacity(name.len() + 4);
+    for (index, character) in name.chars().enumerate() {
+        if character.is_uppercase() && index > 0 {
+            key.push('_');
+        }
+        key.extend(character.to_lowercase());
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_and_payload() {
+        assert_eq!(key_name("Profile"), "profile");
+        assert_eq!(key_name("AutoDeviceData"), "auto_device_data");
+
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&bluerobotics_ping::ping1d::Messages::DistanceSimple(
+            bluerobotics_ping::ping1d::DistanceSimpleStruct {
+                distance: 2500,
+                confidence: 100,
+            },
+        ));
+        let (name, payload) = encode(&msg, ZenohPublishEncoding::Json).unwrap();
+        assert_eq!(name, "distance_simple");
+        let fields: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(fields["distance"], 2500);
+
+        let (_, binary) = encode(&msg, ZenohPublishEncoding::Binary).unwrap();
+        assert_eq!(binary, msg.serialized());
+    }
+}
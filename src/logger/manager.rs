@@ -19,7 +19,8 @@ pub fn init() {
         }
     });
 
-    let console_env_filter = EnvFilter::from_str(&level).expect("logger : Invalid debugging value");
+    let console_env_filter =
+        EnvFilter::from_str(&with_log_filter(&level)).expect("logger : Invalid debugging value");
 
     let console_layer = fmt::Layer::new()
         .with_writer(std::io::stdout)
@@ -34,9 +35,9 @@ pub fn init() {
 
     // Configure the file log
     let file_env_filter = if cli::manager::is_tracing() {
-        EnvFilter::new(LevelFilter::TRACE.to_string())
+        EnvFilter::new(with_log_filter(&LevelFilter::TRACE.to_string()))
     } else {
-        EnvFilter::new(LevelFilter::DEBUG.to_string())
+        EnvFilter::new(with_log_filter(&LevelFilter::DEBUG.to_string()))
     };
 
     let dir = get_app_log_dir();
@@ -70,9 +71,9 @@ pub fn init() {
     ) {
         (true, false) => {
             let lib_name = env!("CARGO_PKG_NAME").replace('-', "_");
-            let subscriber = subscriber.with(EnvFilter::new(format!(
-                "{lib_name}={level},lib{lib_name}={level}"
-            )));
+            let subscriber = subscriber.with(EnvFilter::new(with_log_filter(&format!(
+                "{lib_name}={level},lib{lib_name}={level},access={level}"
+            ))));
             let tracy_layer = tracing_tracy::TracyLayer::default();
             let subscriber = subscriber.with(tracy_layer);
             tracing::subscriber::set_global_default(subscriber)
@@ -80,21 +81,21 @@ pub fn init() {
         }
         (false, false) => {
             let lib_name = env!("CARGO_PKG_NAME").replace('-', "_");
-            let subscriber = subscriber.with(EnvFilter::new(format!(
-                "{lib_name}={level},lib{lib_name}={level}"
-            )));
+            let subscriber = subscriber.with(EnvFilter::new(with_log_filter(&format!(
+                "{lib_name}={level},lib{lib_name}={level},access={level}"
+            ))));
             tracing::subscriber::set_global_default(subscriber)
                 .expect("Unable to set a global subscriber");
         }
         (true, true) => {
-            let subscriber = subscriber.with(EnvFilter::new(&level));
+            let subscriber = subscriber.with(EnvFilter::new(with_log_filter(&level)));
             let tracy_layer = tracing_tracy::TracyLayer::default();
             let subscriber = subscriber.with(tracy_layer);
             tracing::subscriber::set_global_default(subscriber)
                 .expect("Unable to set a global subscriber");
         }
         (false, true) => {
-            let subscriber = subscriber.with(EnvFilter::new(&level));
+            let subscriber = subscriber.with(EnvFilter::new(with_log_filter(&level)));
             tracing::subscriber::set_global_default(subscriber)
                 .expect("Unable to set a global subscriber");
         }
@@ -124,6 +125,14 @@ pub fn init() {
     );
 }
 
+// --log-filter directives come last, so a module can be turned up or down from the level of its crate
+fn with_log_filter(directives: &str) -> String {
+    match cli::manager::log_filter() {
+        Some(log_filter) => format!("{directives},{log_filter}"),
+        None => directives.to_string(),
+    }
+}
+
 // Exclusive to output log-file with 'ping-viewer.2024-09-10-18.log' format.
 fn custom_rolling_appender<P: AsRef<std::path::Path>>(
     dir: P,
@@ -1,8 +1,8 @@
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::info;
+use std::time::Duration;
 
-use ping_viewer_next::{cli, device, logger, server, vehicle::zenoh_client_bridge};
+use tracing::{error, info, warn};
+
+use ping_viewer_next::{cli, device, logger, server, vehicle};
 
 #[tokio::main]
 async fn main() {
@@ -11,12 +11,44 @@ async fn main() {
     // Logger should start before everything else to register any log information
     logger::manager::init();
 
-    let vehicle_data = Arc::new(RwLock::new(None));
+    match cli::manager::command() {
+        cli::manager::Command::Serve => serve().await,
+        cli::manager::Command::Discover { format } => discover(format).await,
+        cli::manager::Command::Convert { paths, output } => convert(&paths, output.as_deref()),
+        cli::manager::Command::Record { duration, name } => {
+            record(Duration::from_secs(duration), name).await
+        }
+    }
+}
+
+async fn serve() {
+    // Removed when the server returns, after a clean shutdown
+    let _pid_file = cli::manager::pid_file().map(|path| {
+        server::daemon::PidFile::create(std::path::Path::new(&path))
+            .unwrap_or_else(|err| panic!("Failed to write pid file {path:?}: {err}"))
+    });
 
-    // Start the Zenoh-client with shared data
-    tokio::spawn(zenoh_client_bridge(vehicle_data.clone()));
+    start_vehicle_bridge();
 
-    let (mut manager, handler) = device::manager::DeviceManager::new(10);
+    let (mut manager, handler) = device_manager();
+
+    // Known hardware first, discovery then skips the ports already in use
+    for device in cli::manager::devices() {
+        match manager
+            .create(
+                device.source.clone(),
+                device.device_selection,
+                device.negotiate_baudrate,
+            )
+            .await
+        {
+            Ok(answer) => info!(
+                "DeviceManager created device {:?}: {answer:?}",
+                device.source
+            ),
+            Err(err) => error!("Failed to create device {:?}: {err:?}", device.source),
+        }
+    }
 
     //Todo: Load previous devices
     if cli::manager::is_enable_auto_create() {
@@ -26,22 +58,395 @@ async fn main() {
         }
     }
 
-    let (recordings_manager, recordings_manager_handler) =
-        device::recording::RecordingManager::new_with_pose(
-            10,
-            "recordings",
+    let (recordings_manager, recordings_manager_handler) = recordings_manager(handler.clone());
+    tokio::spawn(async move { recordings_manager.run().await });
+
+    // Retention limits changed through the settings API apply without a restart
+    let retention_handler = recordings_manager_handler.clone();
+    tokio::spawn(async move {
+        let mut settings = cli::settings::subscribe();
+        let mut retention = retention_policy();
+        while settings.changed().await.is_ok() {
+            let changed = retention_policy();
+            if changed == retention {
+                continue;
+            }
+            retention = changed;
+            let command = device::recording::RecordingManagerCommand::SetRetention(retention);
+            if let Err(err) = retention_handler.send(command).await {
+                error!("Failed to apply the retention settings: {err:?}");
+            }
+        }
+    });
+    // Kept alive until the server stops
+    let _files_watcher =
+        device::recording::files::watch(std::path::Path::new(&cli::manager::recordings_path()));
+
+    #[cfg(feature = "upload")]
+    if let Some(path) = cli::manager::upload_config() {
+        match device::recording::upload::load_target(std::path::Path::new(&path)) {
+            Ok(target) => device::recording::upload::set_target(target),
+            Err(err) => panic!("Invalid upload config: {err:?}"),
+        }
+    }
+
+    // Load the API tokens now, a missing tokens file should stop the start instead of the first request
+    server::middleware::auth::is_enabled();
+    server::middleware::rate_limit::is_enabled();
+
+    tokio::spawn(async move { manager.run().await });
+    tokio::spawn(server::shutdown::listen_signals());
+
+    if let Some(address) = cli::manager::foxglove_server_address() {
+        let address = address
+            .parse()
+            .unwrap_or_else(|err| panic!("Invalid Foxglove server address {address:?}: {err}"));
+        let live_server = device::recording::live::LiveServer::new(handler.clone());
+        tokio::spawn(async move {
+            if let Err(err) = live_server.run(address).await {
+                error!("Foxglove live server stopped: {err:?}");
+            }
+        });
+    }
+
+    if let Some(target) = cli::manager::udp_output() {
+        let udp_output = device::recording::udp::UdpOutput::new(
             handler.clone(),
-            vehicle_data,
+            cli::manager::udp_output_format(),
         );
-    tokio::spawn(async move { recordings_manager.run().await });
+        tokio::spawn(async move {
+            if let Err(err) = udp_output.run(target).await {
+                error!("UDP output stopped: {err:?}");
+            }
+        });
+    }
+
+    if cli::manager::is_zenoh_publish_enabled() {
+        let zenoh_output = device::recording::zenoh_output::ZenohOutput::new(
+            handler.clone(),
+            cli::manager::zenoh_publish_prefix(),
+            cli::manager::zenoh_publish_encoding(),
+        );
+        tokio::spawn(async move { zenoh_output.run().await });
+    }
+
+    let georeferencer = device::recording::georeference::Georeferencer::new(handler.clone());
+    tokio::spawn(async move { georeferencer.run().await });
+
+    let (nmea_tcp, nmea_udp) = (cli::manager::nmea_tcp(), cli::manager::nmea_udp());
+    if nmea_tcp.is_some() || nmea_udp.is_some() {
+        let nmea_output = device::recording::nmea::NmeaOutput::new(
+            handler.clone(),
+            cli::manager::nmea_transducer_offset(),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = nmea_output.run(nmea_tcp, nmea_udp).await {
+                error!("NMEA output stopped: {err:?}");
+            }
+        });
+    }
+
+    if let Some(base_port) = cli::manager::proxy_base_port() {
+        let proxy = server::proxy::DeviceProxy::new(handler.clone(), base_port);
+        tokio::spawn(async move { proxy.run().await });
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(address) = cli::manager::grpc_server_address() {
+        let address = address
+            .parse()
+            .unwrap_or_else(|err| panic!("Invalid gRPC server address {address:?}: {err}"));
+        let devices = handler.clone();
+        let recordings = recordings_manager_handler.clone();
+        tokio::spawn(async move {
+            if let Err(err) = server::protocols::grpc::run(address, devices, recordings).await {
+                error!("gRPC server stopped: {err:?}");
+            }
+        });
+    }
+
+    // Kept alive until the server stops
+    let _mdns = cli::manager::mdns_name()
+        .and_then(|name| server::mdns::advertise(&name, &cli::manager::server_address()));
+
+    let listeners: Vec<server::manager::Listener> = cli::manager::server_addresses()
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .expect("Server addresses are checked while parsing")
+        })
+        .collect();
+    server::manager::run(&listeners, handler, recordings_manager_handler)
+        .await
+        .unwrap();
+}
+
+/// Print the devices answering on the serial ports and the network
+async fn discover(format: cli::manager::DiscoverFormat) {
+    let sources =
+        device::manager::discovery_service::discover_sources(&discovery_filter(), &[]).await;
+    let mut devices = Vec::new();
+    for source in sources {
+        match device::manager::discovery_service::DeviceFactory::create_device(
+            source.clone(),
+            device::manager::DeviceSelection::Auto,
+        )
+        .await
+        {
+            Ok(device) => devices.push(device),
+            Err(err) => warn!("Failed to identify the device on {source:?}: {err:?}"),
+        }
+    }
+    match format {
+        cli::manager::DiscoverFormat::Table if devices.is_empty() => {
+            println!("No devices found")
+        }
+        cli::manager::DiscoverFormat::Table => {
+            print!("{}", device::manager::discovery_service::table(&devices))
+        }
+        cli::manager::DiscoverFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&devices).expect("Failed to serialize devices")
+        ),
+    }
+}
 
+fn convert(paths: &[String], output: Option<&str>) {
+    let mut failed = false;
+    for path in paths {
+        let path = std::path::Path::new(path);
+        let result = if path
+            .extension()
+            .is_some_and(|extension| extension == "mcap")
+        {
+            export_csv(path, output)
+        } else {
+            import_legacy(path)
+        };
+        if let Err(err) = result {
+            error!("Failed to convert {path:?}: {err:?}");
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn export_csv(
+    path: &std::path::Path,
+    output: Option<&str>,
+) -> Result<(), device::manager::ManagerError> {
+    let directory = match output {
+        Some(output) => std::path::PathBuf::from(output),
+        None => path.with_extension(""),
+    };
+    for export in device::recording::export::export_topics_csv(path)? {
+        // Topics come from the file, none may write outside the output directory
+        let relative = std::path::Path::new(&export.file_name);
+        if !relative
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            warn!("Skipping topic file {relative:?} of {path:?}");
+            continue;
+        }
+        let file = directory.join(relative);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| {
+                device::manager::ManagerError::Other(format!(
+                    "Failed to create directory {parent:?}: {err}"
+                ))
+            })?;
+        }
+        std::fs::write(&file, &export.data).map_err(|err| {
+            device::manager::ManagerError::Other(format!("Failed to write {file:?}: {err}"))
+        })?;
+        println!("{}", file.display());
+    }
+    Ok(())
+}
+
+fn import_legacy(path: &std::path::Path) -> Result<(), device::manager::ManagerError> {
+    let directory = std::path::PathBuf::from(cli::manager::recordings_path());
+    std::fs::create_dir_all(&directory).map_err(|err| {
+        device::manager::ManagerError::Other(format!(
+            "Failed to create recordings directory {directory:?}: {err}"
+        ))
+    })?;
+    let report = device::recording::legacy::import(path, &directory, |_| {})?;
+    info!("Imported {path:?}: {report:?}");
+    Ok(())
+}
+
+/// Headless recording of every discovered device, into one file when there are several
+async fn record(duration: Duration, name: Option<String>) {
+    start_vehicle_bridge();
+
+    let (mut manager, handler) = device_manager();
+    // The devices are created once below, not while recording
+    manager.set_background_discovery(None, false);
     tokio::spawn(async move { manager.run().await });
 
-    server::manager::run(
-        &cli::manager::server_address(),
-        handler,
-        recordings_manager_handler,
+    // The --device ones when given, otherwise whatever discovery finds
+    let mut requests = cli::manager::devices();
+    if requests.is_empty() {
+        requests = device::manager::discovery_service::discover_sources(&discovery_filter(), &[])
+            .await
+            .into_iter()
+            .map(|source| device::manager::CreateStruct {
+                source,
+                device_selection: device::manager::DeviceSelection::Auto,
+                negotiate_baudrate: false,
+            })
+            .collect();
+    }
+
+    let mut devices = Vec::new();
+    for request in requests {
+        let source = request.source.clone();
+        match handler
+            .send(device::manager::Request::Create(request))
+            .await
+        {
+            Ok(device::manager::Answer::DeviceInfo(created)) => {
+                devices.extend(created.into_iter().map(|device| device.id))
+            }
+            Ok(answer) => warn!("Unexpected answer creating the device on {source:?}: {answer:?}"),
+            Err(err) => warn!("Failed to create the device on {source:?}: {err:?}"),
+        }
+    }
+    if devices.is_empty() {
+        error!("No devices found to record");
+        std::process::exit(1);
+    }
+
+    let (recordings_manager, recordings_manager_handler) = recordings_manager(handler);
+    tokio::spawn(async move { recordings_manager.run().await });
+
+    let format = device::recording::RecordingFormat::default();
+    let command = match devices.as_slice() {
+        [uuid] => device::recording::RecordingManagerCommand::StartRecording(
+            device::recording::StartRecordingOptions {
+                name,
+                ..(*uuid).into()
+            },
+        ),
+        _ => device::recording::RecordingManagerCommand::StartRecordingGroup(
+            device::recording::GroupRecordingOptions {
+                devices: devices.clone(),
+                raw_frames: false,
+                name,
+                format,
+                mavlink_topics: None,
+            },
+        ),
+    };
+    if let Err(err) = recordings_manager_handler.send(command).await {
+        error!("Failed to start recording: {err:?}");
+        std::process::exit(1);
+    }
+    info!("Recording {} devices for {duration:?}", devices.len());
+
+    tokio::spawn(server::shutdown::listen_signals());
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => {}
+        _ = server::shutdown::requested() => info!("Recording stopped early"),
+    }
+
+    match recordings_manager_handler
+        .send(device::recording::RecordingManagerCommand::StopRecordingAll)
+        .await
+    {
+        Ok(device::recording::Answer::AllRecordingStatus(sessions)) => {
+            for session in sessions {
+                println!("{}", session.file_path.display());
+            }
+        }
+        Ok(answer) => warn!("Unexpected answer stopping the recording: {answer:?}"),
+        Err(err) => {
+            error!("Failed to stop recording: {err:?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// Start the vehicle bridge feeding the pose history, a direct MAVLink link or mavlink2rest replaces the Zenoh-client
+fn start_vehicle_bridge() {
+    #[cfg(feature = "mavlink2rest")]
+    let mavlink2rest = cli::manager::vehicle_mavlink2rest();
+    #[cfg(not(feature = "mavlink2rest"))]
+    let mavlink2rest: Option<String> = None;
+    match (cli::manager::vehicle_mavlink(), mavlink2rest) {
+        (Some(address), _) => {
+            tokio::spawn(vehicle::direct::mavlink_bridge(address));
+        }
+        #[cfg(feature = "mavlink2rest")]
+        (None, Some(url)) => {
+            tokio::spawn(vehicle::mavlink2rest::mavlink2rest_bridge(url));
+        }
+        _ => {
+            tokio::spawn(vehicle::zenoh_client_bridge());
+        }
+    }
+}
+
+fn discovery_filter() -> device::manager::device_discovery::DiscoveryFilter {
+    device::manager::device_discovery::DiscoveryFilter::parse(
+        &cli::manager::discovery_allow(),
+        &cli::manager::discovery_deny(),
     )
-    .await
-    .unwrap();
+    .expect("Discovery rules are checked while parsing")
+}
+
+fn device_manager() -> (
+    device::manager::DeviceManager,
+    device::manager::ManagerActorHandler,
+) {
+    let (mut manager, handler) = device::manager::DeviceManager::new(10);
+    manager.set_idle_policy(device::manager::idle::IdlePolicy::new(
+        cli::manager::idle_timeout(),
+        cli::manager::is_idle_power_down(),
+    ));
+    manager.set_discovery_filter(discovery_filter());
+    manager.set_background_discovery(
+        cli::manager::discovery_interval(),
+        cli::manager::is_auto_create_discovered(),
+    );
+    (manager, handler)
+}
+
+fn recordings_manager(
+    handler: device::manager::ManagerActorHandler,
+) -> (
+    device::recording::RecordingManager,
+    device::recording::RecordingsManagerHandler,
+) {
+    let (mut recordings_manager, recordings_manager_handler) =
+        device::recording::RecordingManager::new(10, cli::manager::recordings_path(), handler);
+    recordings_manager.set_rotation(device::recording::RotationPolicy {
+        max_size: cli::manager::recording_max_size(),
+        max_duration: cli::manager::recording_max_duration(),
+    });
+    recordings_manager.set_pre_trigger(cli::manager::recording_pre_trigger());
+    recordings_manager.set_pose_period(cli::manager::recording_pose_period());
+    recordings_manager.set_mavlink_topics(cli::manager::recording_mavlink());
+    recordings_manager.set_date_directories(cli::manager::is_recordings_by_date());
+    if let Some(mount) = cli::manager::sonar_mount() {
+        match mount.parse() {
+            Ok(mount) => recordings_manager.set_default_mount(Some(mount)),
+            Err(err) => panic!("{err}"),
+        }
+    }
+    recordings_manager.set_retention(retention_policy());
+    (recordings_manager, recordings_manager_handler)
+}
+
+fn retention_policy() -> device::recording::retention::RetentionPolicy {
+    device::recording::retention::RetentionPolicy {
+        max_total_size: cli::manager::recordings_max_size(),
+        max_age: cli::manager::recordings_max_age(),
+        max_files: cli::manager::recordings_max_files(),
+    }
 }
@@ -0,0 +1,141 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tracing::{debug, info, warn};
+
+/// Tell the service manager about the server state, e.g. `READY=1`, only when started by systemd with Type=notify
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    {
+        let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        match send(&socket, state) {
+            Ok(()) => debug!("Daemon: Notified {state:?}"),
+            Err(err) => warn!("Daemon: Failed to notify {state:?} on {socket:?}: {err}"),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        // Abstract namespace socket, the default of systemd
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Abstract sockets are only available on Linux",
+            ))
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), Path::new(socket))?;
+        }
+    }
+    Ok(())
+}
+
+/// Keep the systemd watchdog (WatchdogSec=) fed while the runtime is responsive, returns right away without one
+pub async fn watchdog() {
+    let Some(period) = watchdog_period(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+    ) else {
+        return;
+    };
+    info!(
+        "Daemon: Feeding the systemd watchdog every {:?}",
+        period / 2
+    );
+    let mut interval = tokio::time::interval(period / 2);
+    loop {
+        interval.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+// WATCHDOG_PID names the process expected to send, unset means this one
+fn watchdog_period(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse::<u32>().ok() != Some(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// The process id written for init scripts and monitors, removed again when the server exits cleanly
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+        info!("Daemon: Wrote pid file {path:?}");
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("Daemon: Failed to remove pid file {:?}: {err}", self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_period() {
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_period(Some("30000000"), None),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            watchdog_period(Some("30000000"), Some(&pid)),
+            Some(Duration::from_secs(30))
+        );
+        // Meant for another process of the service
+        assert_eq!(watchdog_period(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_period(Some("0"), None), None);
+        assert_eq!(watchdog_period(None, None), None);
+    }
+
+    #[test]
+    fn test_pid_file_is_removed() {
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.pid", uuid::Uuid::new_v4()));
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
@@ -1,6 +1,19 @@
+use std::{fmt, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::cli;
 use crate::device::{manager::ManagerActorHandler, recording::RecordingsManagerHandler};
 
-use super::protocols;
+use super::{
+    daemon,
+    middleware::{
+        auth::auth,
+        compression::{accept_encoding, skip_incompressible},
+        rate_limit::rate_limit,
+        read_only::read_only,
+        request_id::request_id,
+    },
+    protocols, shutdown,
+};
 use actix_cors::Cors;
 use actix_web::{middleware, web::Data, App, HttpServer};
 use tracing::info;
@@ -10,40 +23,209 @@ use paperclip::actix::{
     OpenApiExt,
 };
 
+// Time left to the open connections once the recordings are closed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the HTTP server accepts connections, several can be served at once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+    Tcp(String),
+    /// Local IPC without a network port, written as `unix:/run/ping-viewer.sock`
+    Unix(PathBuf),
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("unix:") {
+            Some("") => Err(format!("Missing unix socket path in {value:?}")),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None if value.contains(':') => Ok(Self::Tcp(value.to_string())),
+            None => Err(format!(
+                "Invalid server address {value:?}, expected <IP>:<PORT> or unix:<PATH>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "http://{address}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
 fn add_v1_paths(scope: Scope) -> Scope {
     scope.configure(protocols::v1::rest::register_services)
 }
 
+fn add_webrtc_paths(scope: Scope) -> Scope {
+    #[cfg(feature = "webrtc")]
+    let scope = scope.configure(protocols::webrtc::register_services);
+    scope
+}
+
 pub async fn run(
-    server_address: &str,
+    listeners: &[Listener],
     devices_manager_handler: ManagerActorHandler,
     recordings_handler: RecordingsManagerHandler,
 ) -> std::io::Result<()> {
-    let server_address = server_address.to_string();
     info!("ServerManager: Service starting");
+    let origins = cli::manager::cors_allowed_origins();
+    if !origins.is_empty() {
+        info!("ServerManager: Cross-origin requests allowed from {origins:?}");
+    }
 
+    let compression = cli::manager::is_compression_enabled();
+    if compression {
+        info!(
+            "ServerManager: Compressing responses with {:?}",
+            cli::manager::compression_encodings()
+        );
+    }
+
+    protocols::v1::websocket::start_pruning();
+
+    let shutdown_handlers = (devices_manager_handler.clone(), recordings_handler.clone());
     let server = HttpServer::new(move || {
-        let cors = Cors::permissive();
+        let cors = cors();
 
         let v1 = add_v1_paths(web::scope("/v1"));
-        let default = add_v1_paths(web::scope(""));
+        let v2 = web::scope("/v2").configure(protocols::v2::rest::register_services);
+        let default = add_webrtc_paths(add_v1_paths(web::scope("")));
 
         App::new()
             .app_data(Data::new(devices_manager_handler.clone()))
             .app_data(Data::new(recordings_handler.clone()))
+            .wrap(middleware::from_fn(skip_incompressible))
+            .wrap(middleware::from_fn(rate_limit))
+            .wrap(middleware::from_fn(read_only))
+            .wrap(middleware::from_fn(auth))
             .wrap(cors)
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Condition::new(
+                compression,
+                middleware::Compress::default(),
+            ))
+            .wrap(middleware::from_fn(accept_encoding))
+            .wrap(middleware::from_fn(request_id))
             .wrap_api()
             .with_json_spec_at("/api/spec")
             .with_swagger_ui_at("/docs")
             .service(v1)
+            .service(v2)
             .service(protocols::v1::rest::server_metadata)
+            .service(protocols::v1::rest::metrics::metrics_get)
             .service(protocols::v1::websocket::websocket)
             .service(protocols::v1::websocket::recording_websocket)
+            .service(protocols::v1::websocket::jobs_websocket)
+            .service(protocols::v1::websocket::files_websocket)
+            .service(protocols::v1::websocket::georeferenced_websocket)
             .service(default)
             .build()
     });
 
-    info!("ServerManager: HTTP server running at http://{server_address}");
-    server.bind(server_address)?.run().await
+    // Signals are handled by the shutdown module, recordings must be closed before the workers go away
+    let mut server = server
+        .disable_signals()
+        .shutdown_timeout(SHUTDOWN_TIMEOUT.as_secs());
+    for listener in listeners {
+        server = match listener {
+            Listener::Tcp(address) => server.bind(address)?,
+            #[cfg(unix)]
+            Listener::Unix(path) => {
+                remove_stale_socket(path)?;
+                server.bind_uds(path)?
+            }
+            #[cfg(not(unix))]
+            Listener::Unix(path) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("Unix sockets are not supported on this platform: {path:?}"),
+                ))
+            }
+        };
+        info!("ServerManager: HTTP server running at {listener}");
+    }
+    let server = server.run();
+    let server_handle = server.handle();
+    // Every listener is bound, clients can connect from now on
+    daemon::notify("READY=1");
+    let watchdog = tokio::spawn(daemon::watchdog());
+    tokio::spawn(async move {
+        shutdown::requested().await;
+        info!("ServerManager: Shutting down");
+        daemon::notify("STOPPING=1");
+        let (devices, recordings) = shutdown_handlers;
+        shutdown::stop(&devices, &recordings).await;
+        server_handle.stop(true).await;
+    });
+    server.await?;
+    watchdog.abort();
+    for listener in listeners {
+        if let Listener::Unix(path) = listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    info!("ServerManager: Service stopped");
+    Ok(())
+}
+
+// A socket left by a previous run would fail the bind
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+// Permissive unless origins or methods are restricted through the command line
+fn cors() -> Cors {
+    let origins = cli::manager::cors_allowed_origins();
+    let methods = cli::manager::cors_allowed_methods();
+
+    let mut cors = Cors::default()
+        .allow_any_header()
+        .expose_any_header()
+        .max_age(3600);
+    cors = if origins.is_empty() {
+        cors.allow_any_origin()
+    } else {
+        origins
+            .iter()
+            .fold(cors, |cors, origin| cors.allowed_origin(origin))
+    };
+    cors = if methods.is_empty() {
+        cors.allow_any_method()
+    } else {
+        cors.allowed_methods(methods.iter().map(String::as_str))
+    };
+    if cli::manager::is_cors_allow_credentials() {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_parsing() {
+        assert_eq!(
+            "0.0.0.0:8080".parse(),
+            Ok(Listener::Tcp("0.0.0.0:8080".to_string()))
+        );
+        assert_eq!(
+            "unix:/run/ping-viewer.sock".parse(),
+            Ok(Listener::Unix(PathBuf::from("/run/ping-viewer.sock")))
+        );
+        assert!("unix:".parse::<Listener>().is_err());
+        assert!("8080".parse::<Listener>().is_err());
+    }
 }
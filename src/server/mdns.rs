@@ -0,0 +1,79 @@
+use std::net::{IpAddr, SocketAddr};
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tracing::{info, warn};
+
+const SERVICE_TYPE: &str = "_http._tcp.local.";
+
+/// Advertise the HTTP server as `<name>.local`, the daemon stops advertising when dropped
+pub fn advertise(name: &str, server_address: &str) -> Option<ServiceDaemon> {
+    let address: SocketAddr = match server_address.parse() {
+        Ok(address) => address,
+        Err(err) => {
+            warn!("mDNS: Not advertising, invalid server address {server_address:?}: {err}");
+            return None;
+        }
+    };
+
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(err) => {
+            warn!("mDNS: Failed to start the responder: {err}");
+            return None;
+        }
+    };
+
+    let version = env!("CARGO_PKG_VERSION");
+    let properties = [
+        ("version", version),
+        ("api", "/v1"),
+        ("api_v2", "/v2"),
+        ("docs", "/docs"),
+    ];
+    let host_name = format!("{name}.local.");
+    let service = match ServiceInfo::new(
+        SERVICE_TYPE,
+        name,
+        &host_name,
+        advertised_ip(address.ip()),
+        address.port(),
+        &properties[..],
+    ) {
+        Ok(service) if address.ip().is_unspecified() => service.enable_addr_auto(),
+        Ok(service) => service,
+        Err(err) => {
+            warn!("mDNS: Invalid service description: {err}");
+            return None;
+        }
+    };
+
+    if let Err(err) = daemon.register(service) {
+        warn!("mDNS: Failed to register the service: {err}");
+        return None;
+    }
+    info!(
+        "mDNS: Advertising {SERVICE_TYPE} as {host_name} on port {}",
+        address.port()
+    );
+    Some(daemon)
+}
+
+// A server bound to every interface is advertised on the addresses of all of them
+fn advertised_ip(ip: IpAddr) -> String {
+    if ip.is_unspecified() {
+        String::new()
+    } else {
+        ip.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unspecified_address_uses_every_interface() {
+        assert_eq!(advertised_ip("0.0.0.0".parse().unwrap()), "");
+        assert_eq!(advertised_ip("192.168.2.2".parse().unwrap()), "192.168.2.2");
+    }
+}
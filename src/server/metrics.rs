@@ -0,0 +1,191 @@
+use lazy_static::lazy_static;
+use prometheus::{Encoder, GaugeVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use tracing::warn;
+
+use crate::device::{
+    manager::{self, metrics::ThroughputMetrics, ManagerActorHandler},
+    recording::{self, RecordingSession, RecordingsManagerHandler},
+};
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new_custom(Some("ping_viewer".to_string()), None)
+        .expect("Metrics prefix is valid");
+    static ref DEVICE_MESSAGE_RATE: GaugeVec = register(GaugeVec::new(
+        Opts::new(
+            "device_messages_per_second",
+            "Messages received from the device over the last second"
+        ),
+        &["device"]
+    ));
+    static ref DEVICE_BYTE_RATE: GaugeVec = register(GaugeVec::new(
+        Opts::new(
+            "device_bytes_per_second",
+            "Bytes received from the device over the last second"
+        ),
+        &["device"]
+    ));
+    static ref DEVICE_MESSAGES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "device_messages_total",
+            "Messages received from the device since it was created"
+        ),
+        &["device"]
+    ));
+    static ref DEVICE_UNKNOWN_MESSAGES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "device_unknown_messages_total",
+            "Messages from the device that could not be decoded, e.g. unknown ids"
+        ),
+        &["device"]
+    ));
+    static ref WEBSOCKET_CLIENTS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("websocket_clients", "Connected websocket clients"),
+        &["route"]
+    ));
+    static ref ACTOR_QUEUE_DEPTH: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("actor_queue_depth", "Requests waiting in the actor mailbox"),
+        &["actor"]
+    ));
+    static ref RECORDING_BYTES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "recording_bytes_written",
+            "Size of the file the active recording is writing"
+        ),
+        &["device"]
+    ));
+    static ref SCRAPE_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "scrape_errors_total",
+            "Managers that did not answer while collecting the metrics"
+        ),
+        &["actor"]
+    ));
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    collector: prometheus::Result<T>,
+) -> T {
+    let collector = collector.expect("Metric definition is valid");
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Metric is registered once");
+    collector
+}
+
+/// Websocket routes count their own clients, the device route is counted from its manager
+pub fn websocket_connected(route: &str) {
+    WEBSOCKET_CLIENTS.with_label_values(&[route]).inc();
+}
+
+pub fn websocket_disconnected(route: &str) {
+    WEBSOCKET_CLIENTS.with_label_values(&[route]).dec();
+}
+
+/// Refresh the values owned by the managers and encode everything in the Prometheus text format
+pub async fn gather(
+    devices: &ManagerActorHandler,
+    recordings: &RecordingsManagerHandler,
+) -> Result<String, String> {
+    ACTOR_QUEUE_DEPTH
+        .with_label_values(&["device_manager"])
+        .set(devices.queue_depth() as i64);
+    ACTOR_QUEUE_DEPTH
+        .with_label_values(&["recordings_manager"])
+        .set(recordings.queue_depth() as i64);
+    WEBSOCKET_CLIENTS
+        .with_label_values(&["ws"])
+        .set(super::protocols::v1::websocket::client_count() as i64);
+
+    match devices.send(manager::Request::GetMetrics).await {
+        Ok(manager::Answer::DeviceMetrics(metrics)) => set_device_metrics(&metrics),
+        answer => {
+            warn!("Metrics: Failed to collect device metrics: {answer:?}");
+            SCRAPE_ERRORS.with_label_values(&["device_manager"]).inc();
+        }
+    }
+
+    match recordings
+        .send(recording::RecordingManagerCommand::GetAllRecordingStatus)
+        .await
+    {
+        Ok(recording::Answer::AllRecordingStatus(sessions)) => set_recording_metrics(&sessions),
+        answer => {
+            warn!("Metrics: Failed to collect recording status: {answer:?}");
+            SCRAPE_ERRORS
+                .with_label_values(&["recordings_manager"])
+                .inc();
+        }
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .map_err(|err| format!("Failed to encode metrics: {err}"))?;
+    String::from_utf8(buffer).map_err(|err| format!("Failed to encode metrics: {err}"))
+}
+
+// Devices come and go, stale labels are dropped on every refresh
+fn set_device_metrics(metrics: &[ThroughputMetrics]) {
+    DEVICE_MESSAGE_RATE.reset();
+    DEVICE_BYTE_RATE.reset();
+    DEVICE_MESSAGES.reset();
+    DEVICE_UNKNOWN_MESSAGES.reset();
+    for metrics in metrics {
+        let device = metrics.device_id.to_string();
+        let labels = [device.as_str()];
+        DEVICE_MESSAGE_RATE
+            .with_label_values(&labels)
+            .set(metrics.messages_per_second);
+        DEVICE_BYTE_RATE
+            .with_label_values(&labels)
+            .set(metrics.bytes_per_second);
+        DEVICE_MESSAGES
+            .with_label_values(&labels)
+            .set(metrics.total_messages as i64);
+        DEVICE_UNKNOWN_MESSAGES
+            .with_label_values(&labels)
+            .set(metrics.unknown_messages as i64);
+    }
+}
+
+fn set_recording_metrics(sessions: &[RecordingSession]) {
+    RECORDING_BYTES.reset();
+    for session in sessions.iter().filter(|session| session.is_active) {
+        // Buffered data is not counted until the writer flushes it
+        let size = std::fs::metadata(&session.file_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        RECORDING_BYTES
+            .with_label_values(&[&session.device_id.to_string()])
+            .set(size as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_labels_are_replaced() {
+        let first = ThroughputMetrics {
+            device_id: uuid::Uuid::new_v4(),
+            messages_per_second: 10.0,
+            ..Default::default()
+        };
+        let second = ThroughputMetrics {
+            device_id: uuid::Uuid::new_v4(),
+            ..Default::default()
+        };
+        set_device_metrics(&[first.clone()]);
+        set_device_metrics(&[second.clone()]);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&REGISTRY.gather(), &mut buffer)
+            .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.contains("ping_viewer_device_messages_per_second"));
+        assert!(text.contains(&second.device_id.to_string()));
+        assert!(!text.contains(&first.device_id.to_string()));
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, Method},
+    middleware::Next,
+    web,
+};
+use lazy_static::lazy_static;
+use tracing::{debug, info, warn};
+
+use crate::{cli, server::protocols::v1::errors::Error};
+
+use super::read_only::{is_mutating, is_webrtc_signaling};
+
+lazy_static! {
+    static ref TOKENS: Vec<String> = load_tokens();
+}
+
+fn load_tokens() -> Vec<String> {
+    let mut tokens: Vec<String> = cli::manager::api_token().into_iter().collect();
+    if let Some(path) = cli::manager::api_tokens_file() {
+        match std::fs::read_to_string(&path) {
+            Ok(content) => tokens.extend(parse_tokens(&content)),
+            // Starting without the file would leave the server open, refuse instead
+            Err(err) => panic!("Failed to read API tokens file {path:?}: {err}"),
+        }
+    }
+    if !tokens.is_empty() {
+        info!(
+            "ServerManager: API token authentication enabled with {} tokens",
+            tokens.len()
+        );
+    }
+    tokens
+}
+
+// One token per line, blank lines and `#` comments are skipped
+fn parse_tokens(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+// Tokens set through the settings API, read on every request so changes apply without a restart
+fn settings_tokens() -> Vec<String> {
+    cli::settings::current().api_tokens.unwrap_or_default()
+}
+
+pub fn is_enabled() -> bool {
+    !TOKENS.is_empty() || !settings_tokens().is_empty()
+}
+
+pub fn is_valid_token(token: &str) -> bool {
+    TOKENS
+        .iter()
+        .chain(settings_tokens().iter())
+        .any(|known| constant_time_eq(known, token))
+}
+
+pub async fn auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if is_enabled() && is_protected(&req) {
+        let token = request_token(&req);
+        if !token.as_deref().is_some_and(is_valid_token) {
+            debug!(
+                "ServerManager: Rejected unauthenticated {} {}",
+                req.method(),
+                req.path()
+            );
+            if token.is_some() {
+                warn!("ServerManager: Invalid API token for {}", req.path());
+            }
+            return Err(Error::Unauthorized(format!(
+                "A valid API token is required for {} {}",
+                req.method(),
+                req.path()
+            ))
+            .into());
+        }
+    }
+
+    next.call(req).await
+}
+
+fn is_protected(req: &ServiceRequest) -> bool {
+    is_mutating(req.method(), req.path(), req.query_string())
+        || is_admin(req.path())
+        || is_websocket_upgrade(req.method(), req.headers())
+        || is_webrtc_signaling(req.path())
+}
+
+// Every admin route, the log downloads included, and the settings expose client addresses and configuration,
+// they need a token even when only reading
+fn is_admin(path: &str) -> bool {
+    let path = path
+        .strip_prefix("/v1")
+        .unwrap_or(path)
+        .trim_end_matches('/');
+    path == "/admin" || path.starts_with("/admin/") || path == "/settings"
+}
+
+fn is_websocket_upgrade(method: &Method, headers: &header::HeaderMap) -> bool {
+    *method == Method::GET
+        && headers
+            .get(header::UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+// Browsers cannot set headers on websockets, those pass the token as `?token=`
+fn request_token(req: &ServiceRequest) -> Option<String> {
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(token.trim().to_string());
+    }
+    query_token(req.query_string())
+}
+
+fn query_token(query: &str) -> Option<String> {
+    web::Query::<HashMap<String, String>>::from_query(query)
+        .ok()?
+        .into_inner()
+        .remove("token")
+}
+
+fn constant_time_eq(known: &str, candidate: &str) -> bool {
+    known.len() == candidate.len()
+        && known
+            .bytes()
+            .zip(candidate.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_file_skips_comments() {
+        let tokens = parse_tokens("# surface laptop\nabc123\n\n  def456  \n");
+        assert_eq!(tokens, vec!["abc123", "def456"]);
+    }
+
+    #[test]
+    fn test_token_from_query() {
+        assert_eq!(
+            query_token("device-number=1&token=abc%2B1").as_deref(),
+            Some("abc+1")
+        );
+        assert_eq!(query_token("filter=x"), None);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+
+    #[test]
+    fn test_admin_routes_are_protected() {
+        assert!(is_admin("/admin/ws_clients"));
+        assert!(is_admin("/v1/admin/ws_clients"));
+        assert!(is_admin("/admin/logs"));
+        assert!(is_admin("/v1/admin/logs/ping-viewer-next.log"));
+        assert!(is_admin("/admin/"));
+        assert!(is_admin("/settings"));
+        assert!(!is_admin("/administration"));
+        assert!(!is_admin("/recordings/list"));
+    }
+
+    #[test]
+    fn test_websocket_upgrade_is_detected() {
+        let mut headers = header::HeaderMap::new();
+        assert!(!is_websocket_upgrade(&Method::GET, &headers));
+        headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+        assert!(is_websocket_upgrade(&Method::GET, &headers));
+    }
+}
@@ -0,0 +1,145 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    middleware::Next,
+};
+
+use crate::cli::{self, manager::CompressionEncoding};
+
+/// Keep only the encodings enabled from the command line, runs before `Compress` negotiates
+pub async fn accept_encoding(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let allowed = cli::manager::compression_encodings();
+    if let Some(accepted) = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    {
+        match filter_accept_encoding(accepted, &allowed).and_then(|value| value.parse().ok()) {
+            Some(value) => {
+                req.headers_mut().insert(header::ACCEPT_ENCODING, value);
+            }
+            None => {
+                req.headers_mut().remove(header::ACCEPT_ENCODING);
+            }
+        }
+    }
+
+    next.call(req).await
+}
+
+/// Mark the responses `Compress` must leave alone, runs after the handler
+pub async fn skip_incompressible(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // Nothing to leave alone when no response of this request gets compressed
+    let compressing = negotiates_compression(
+        req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok()),
+        &cli::manager::compression_encodings(),
+    );
+    let mut response = next.call(req).await?;
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let status = response.status();
+    // Ranges must stay byte exact for resumed downloads, and recordings or archives are already compressed
+    if compressing
+        && status != StatusCode::SWITCHING_PROTOCOLS
+        && (status == StatusCode::PARTIAL_CONTENT || !is_compressible(content_type))
+    {
+        response.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
+    }
+    Ok(response)
+}
+
+fn filter_accept_encoding(accepted: &str, allowed: &[CompressionEncoding]) -> Option<String> {
+    let kept: Vec<&str> = accepted
+        .split(',')
+        .map(str::trim)
+        .filter(|coding| {
+            let name = coding.split(';').next().unwrap_or_default().trim();
+            name.eq_ignore_ascii_case("identity")
+                || allowed
+                    .iter()
+                    .any(|encoding| name.eq_ignore_ascii_case(encoding.name()))
+        })
+        .collect();
+    (!kept.is_empty()).then(|| kept.join(", "))
+}
+
+fn negotiates_compression(accepted: Option<&str>, allowed: &[CompressionEncoding]) -> bool {
+    accepted
+        .and_then(|accepted| filter_accept_encoding(accepted, allowed))
+        .is_some_and(|kept| {
+            kept.split(',').any(|coding| {
+                let name = coding.split(';').next().unwrap_or_default().trim();
+                !name.eq_ignore_ascii_case("identity")
+            })
+        })
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    content_type.starts_with("text/")
+        || ["json", "javascript", "xml", "yaml"]
+            .iter()
+            .any(|kind| content_type.contains(kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_allowed_encodings_are_kept() {
+        let allowed = [CompressionEncoding::Gzip];
+        assert_eq!(
+            filter_accept_encoding("gzip, deflate, br;q=0.9, zstd", &allowed).as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(filter_accept_encoding("br, zstd", &allowed), None);
+        assert_eq!(
+            filter_accept_encoding("identity, ZSTD", &[CompressionEncoding::Zstd]).as_deref(),
+            Some("identity, ZSTD")
+        );
+    }
+
+    #[test]
+    fn test_disabled_compression_is_never_negotiated() {
+        assert!(!negotiates_compression(Some("gzip, zstd"), &[]));
+        assert!(!negotiates_compression(Some("identity"), &[]));
+        assert!(!negotiates_compression(None, &[CompressionEncoding::Gzip]));
+        assert!(!negotiates_compression(
+            Some("br"),
+            &[CompressionEncoding::Gzip]
+        ));
+        assert!(negotiates_compression(
+            Some("br, gzip;q=0.5"),
+            &[CompressionEncoding::Gzip]
+        ));
+    }
+
+    #[test]
+    fn test_compressible_content_types() {
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("text/csv; charset=utf-8"));
+        assert!(is_compressible("application/x-ndjson"));
+        assert!(!is_compressible("application/octet-stream"));
+        assert!(!is_compressible("application/zip"));
+        assert!(!is_compressible(""));
+    }
+}
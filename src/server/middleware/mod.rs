@@ -0,0 +1,10 @@
+/// Requires an API token on mutating requests and websocket upgrades when tokens are configured
+pub mod auth;
+/// Restricts response compression to the enabled encodings and to compressible content
+pub mod compression;
+/// Limits the rate of mutating requests of each client when configured
+pub mod rate_limit;
+/// Rejects mutating requests when the server runs in read-only viewing mode
+pub mod read_only;
+/// Tags every request with an id for tracing, error responses and access logs
+pub mod request_id;
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+};
+use lazy_static::lazy_static;
+use tracing::{debug, info};
+
+use crate::{cli, server::protocols::v1::errors::Error};
+
+use super::read_only::is_mutating;
+
+// Full buckets are forgotten past this many clients, they carry no state worth keeping
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+lazy_static! {
+    static ref LIMITER: Option<Mutex<RateLimiter>> = cli::manager::rate_limit().map(|limit| {
+        let burst = cli::manager::rate_limit_burst().unwrap_or(limit);
+        info!(
+            "ServerManager: Rate limiting mutating requests to {limit} per minute per client, burst of {burst}"
+        );
+        Mutex::new(RateLimiter::new(limit, burst))
+    });
+}
+
+/// Token bucket of one client, refilled continuously up to the burst size
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_minute as f64 / 60.0,
+            burst: burst.max(1) as f64,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take one token, the error is the time until the next one is available
+    fn acquire(&mut self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.buckets.len() >= MAX_TRACKED_CLIENTS && !self.buckets.contains_key(&client) {
+            self.prune(now);
+        }
+
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.per_second,
+        ))
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let (per_second, burst) = (self.per_second, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * per_second < burst
+        });
+    }
+}
+
+pub fn is_enabled() -> bool {
+    LIMITER.is_some()
+}
+
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(limiter) = LIMITER.as_ref() {
+        // The socket peer, forwarded headers are set by the client and cannot be trusted here
+        if let Some(client) = req.peer_addr().map(|address| address.ip()) {
+            if is_mutating(req.method(), req.path(), req.query_string()) {
+                if let Err(retry) = limiter.lock().unwrap().acquire(client, Instant::now()) {
+                    debug!(
+                        "ServerManager: Rate limit rejected {} {} from {client}",
+                        req.method(),
+                        req.path()
+                    );
+                    return Err(Error::TooManyRequests(format!(
+                        "Rate limit exceeded for {} {}, retry in {:.1} seconds",
+                        req.method(),
+                        req.path(),
+                        retry.as_secs_f64()
+                    ))
+                    .into());
+                }
+            }
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let client: IpAddr = "192.168.2.10".parse().unwrap();
+        let other: IpAddr = "192.168.2.11".parse().unwrap();
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(60, 2);
+
+        assert!(limiter.acquire(client, start).is_ok());
+        assert!(limiter.acquire(client, start).is_ok());
+        let retry = limiter.acquire(client, start).unwrap_err();
+        assert_eq!(retry.as_secs(), 1);
+        assert!(limiter.acquire(other, start).is_ok());
+
+        assert!(limiter
+            .acquire(client, start + Duration::from_secs(1))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_idle_clients_are_pruned() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(60, 1);
+        limiter.acquire("10.0.0.1".parse().unwrap(), start).unwrap();
+        limiter.acquire("10.0.0.2".parse().unwrap(), start).unwrap();
+
+        limiter.prune(start + Duration::from_secs(2));
+        assert!(limiter.buckets.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+};
+use tracing::debug;
+
+use crate::{cli, server::protocols::v1::errors::Error};
+
+// Device manager queries that only read state, every other device manager route reaches devices
+const READ_ONLY_DEVICE_MANAGER_ROUTES: [&str; 7] = [
+    "List",
+    "Metrics",
+    "PollSchedulerStats",
+    "Presets",
+    "serial_ports",
+    "settings/export",
+    "state/export",
+];
+
+pub async fn read_only(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if cli::manager::is_read_only() && is_mutating(req.method(), req.path(), req.query_string()) {
+        debug!(
+            "ServerManager: Read-only mode rejected {} {}",
+            req.method(),
+            req.path()
+        );
+        return Err(Error::Forbidden(format!(
+            "Server is in read-only mode, {} {} is not allowed",
+            req.method(),
+            req.path()
+        ))
+        .into());
+    }
+
+    next.call(req).await
+}
+
+pub fn is_mutating(method: &Method, path: &str, query: &str) -> bool {
+    // Signaling only opens a viewing stream, like a websocket upgrade
+    if *method == Method::POST && is_webrtc_signaling(path) {
+        return false;
+    }
+    if !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    // A background report is queued as a job and its output kept on disk
+    if is_background_job(query) {
+        return true;
+    }
+
+    // The v2 message routes ask the device, like the v1 device routes, only the listings read the manager state
+    if let Some(route) = path.strip_prefix("/v2/device/") {
+        return route.trim_end_matches('/').contains('/');
+    }
+
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    let Some(route) = path.strip_prefix("/device_manager/") else {
+        return false;
+    };
+
+    let route = route.trim_end_matches('/');
+    // The latest sample is served from the cache, the device is not queried
+    if let Some((device, "latest")) = route.split_once('/') {
+        if device.parse::<uuid::Uuid>().is_ok() {
+            return false;
+        }
+    }
+
+    !READ_ONLY_DEVICE_MANAGER_ROUTES.contains(&route)
+}
+
+fn is_background_job(query: &str) -> bool {
+    query
+        .split('&')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .any(|(key, value)| key == "background" && value != "false")
+}
+
+pub fn is_webrtc_signaling(path: &str) -> bool {
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    path.trim_end_matches('/') == "/webrtc/offer"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewing_routes_are_allowed() {
+        assert!(!is_mutating(&Method::GET, "/", ""));
+        assert!(!is_mutating(&Method::GET, "/ws", ""));
+        assert!(!is_mutating(&Method::GET, "/recordings/list", ""));
+        assert!(!is_mutating(
+            &Method::GET,
+            "/recordings/report/survey.mcap",
+            "background=false"
+        ));
+        assert!(!is_mutating(&Method::GET, "/device_manager/List", ""));
+        assert!(!is_mutating(
+            &Method::GET,
+            "/v1/device_manager/serial_ports",
+            ""
+        ));
+        assert!(!is_mutating(
+            &Method::GET,
+            "/device_manager/00000000-0000-0000-b9c0-f5752d453eb3/latest",
+            ""
+        ));
+        assert!(!is_mutating(&Method::POST, "/webrtc/offer", ""));
+        assert!(!is_mutating(&Method::GET, "/v2/devices", ""));
+        assert!(!is_mutating(
+            &Method::GET,
+            "/v2/device/00000000-0000-0000-b9c0-f5752d453eb3",
+            ""
+        ));
+    }
+
+    #[test]
+    fn test_mutating_routes_are_rejected() {
+        assert!(is_mutating(&Method::POST, "/device_manager/request", ""));
+        assert!(is_mutating(
+            &Method::GET,
+            "/recordings/report/survey.mcap",
+            "format=html&background=true"
+        ));
+        assert!(is_mutating(
+            &Method::DELETE,
+            "/recordings/delete/file.mcap",
+            ""
+        ));
+        assert!(is_mutating(&Method::GET, "/device_manager/AutoCreate", ""));
+        assert!(is_mutating(
+            &Method::GET,
+            "/v1/device_manager/00000000-0000-0000-b9c0-f5752d453eb3/ping1d/Profile",
+            ""
+        ));
+        assert!(is_mutating(
+            &Method::GET,
+            "/v2/device/00000000-0000-0000-b9c0-f5752d453eb3/ping1d/Profile",
+            ""
+        ));
+    }
+}
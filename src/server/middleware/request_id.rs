@@ -0,0 +1,118 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+};
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+// Longer ids from clients are replaced, they end up in every log line of the request
+const MAX_LENGTH: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled by the current task, used to tag error responses
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+pub fn generate() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// Reuse the id of a client or proxy, so its logs can be matched with ours
+fn incoming_id(headers: &HeaderMap) -> Option<String> {
+    let id = headers.get(HEADER)?.to_str().ok()?.trim();
+    let valid = !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    valid.then(|| id.to_string())
+}
+
+/// Tags the request with an id, runs it inside a tracing span and writes one access log line
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let id = incoming_id(req.headers()).unwrap_or_else(generate);
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    let peer = req
+        .peer_addr()
+        .map(|address| address.ip().to_string())
+        .unwrap_or_default();
+    let span = info_span!("request", request_id = %id, %method, %path);
+    let http_request = req.request().clone();
+    let start = Instant::now();
+
+    let mut response = REQUEST_ID
+        .scope(
+            id.clone(),
+            async move {
+                // Errors become responses here, so they are logged and tagged like any other answer
+                match next.call(req).await {
+                    Ok(response) => response.map_into_boxed_body(),
+                    Err(err) => ServiceResponse::from_err(err, http_request),
+                }
+            }
+            .instrument(span.clone()),
+        )
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+
+    let status = response.status().as_u16();
+    let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+    span.in_scope(|| {
+        info!(
+            target: "access",
+            %peer,
+            status,
+            elapsed_ms,
+            "{method} {path} {status} in {elapsed_ms:.1} ms"
+        )
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incoming_id_is_validated() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(incoming_id(&headers), None);
+
+        headers.insert(HEADER, HeaderValue::from_static("surface-42.a_b"));
+        assert_eq!(incoming_id(&headers).as_deref(), Some("surface-42.a_b"));
+
+        headers.insert(HEADER, HeaderValue::from_static("bad id\""));
+        assert_eq!(incoming_id(&headers), None);
+
+        let long = "a".repeat(MAX_LENGTH + 1);
+        headers.insert(HEADER, HeaderValue::from_str(&long).unwrap());
+        assert_eq!(incoming_id(&headers), None);
+    }
+
+    #[test]
+    fn test_current_follows_the_scope() {
+        assert_eq!(current(), None);
+        let id = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(REQUEST_ID.scope("abc".to_string(), async { current() }));
+        assert_eq!(id.as_deref(), Some("abc"));
+    }
+}
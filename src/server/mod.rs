@@ -1,5 +1,11 @@
+pub mod daemon;
 pub mod manager;
+pub mod mdns;
+pub mod metrics;
+pub mod middleware;
 pub mod protocols;
+pub mod proxy;
+pub mod shutdown;
 
 // The Server module consists of a manager and all available layers that provide access to internal services.
 //
@@ -7,19 +13,190 @@ pub mod protocols;
 // The Manager module requires a DeviceManagerHandler, which will be used to forward all incoming requests.
 // This allows the Manager to receive and process requests from RestAPI and WebSocket methods.
 // The requests are forwarded to the DeviceManager using the server's AppData, which holds a clone of the DeviceManager's Handler and will provide the responses.
+// The server listens on every --rest-server given, network addresses and unix:<PATH> sockets, e.g.
+//     --rest-server 0.0.0.0:8080 --rest-server unix:/run/ping-viewer.sock
+// so local tools can reach it without a network port, e.g. curl --unix-socket /run/ping-viewer.sock http://localhost/v1/.
+// Known hardware is created at startup with repeated --device specifications (PING_VIEWER_DEVICE, comma separated),
+//     --device serial:/dev/ttyUSB0:115200:ping1d --device udp:192.168.2.2:12345:ping360
+// serial:<path>[:<baudrate>][:<type>] or udp:<ip>:<port>[:<type>], a missing baudrate is detected and a missing type
+// probed. Discovery then leaves those ports alone, and the record command uses them instead of discovering.
 //
 // Front-end:
+// With the embed-frontend feature (default) the built frontend is part of the binary, served offline from {address}/
+// with ETags, long lived caching of the hashed assets and index.html for the client side routes
+// of browser navigations (Accept: text/html), other unknown paths answer 404.
 // The frontend provides access to REST API documentation through {address}/docs with a Swagger interface and the API specifications.
 //
 // RestAPI:
 // The REST API will have a default route and versioned routes.
 // To keep the application stable through updates, users can use {address}/v{x}/route.
+// GET /device_manager/{uuid}/latest returns the most recent Profile or DeviceData of a device with its age, for
+// scripts and low-rate consumers polling over HTTP instead of keeping a websocket open.
 //
 // WebSocket:
 // WebSocket is provided via the {address}/ws route.
 // Users can use the following queries:
 //     ?filter="some_desired_string_to_use_regex"
 //     ?device-number="00000000-0000-0000-b9c0-f5752d453eb3" // The UUID provided by the source of the device created
+//     ?encoding=cbor // Binary CBOR or MessagePack (msgpack) frames instead of JSON text, also negotiable with the
+//                    // ping-viewer.cbor or ping-viewer.msgpack subprotocols. Requests are still sent as JSON text.
+//     ?message_types=Ping1D.Profile,DeviceInfo // Structured filters, checked before the message is serialized: message
+//     ?device_types=Ping360                    // types as in v2 envelopes (or only their name), device types of device
+//     ?min_interval_ms=200                     // messages, and the minimum time between messages of one type and device
 // Otherwise, if they are not defined, the WebSocket channel will receive all available messages.
+// Connected clients can change them without reconnecting:
+//     {"control": "subscribe", "filter": "...", "device_number": "..."} // Missing fields receive everything, the
+//                                                                       // structured filters are accepted as well
+//     {"control": "unsubscribe"} // Stop receiving broadcasts
+// The server pings clients every --ws-heartbeat-interval seconds and closes the ones silent for --ws-client-timeout.
+// Each client has a queue of --ws-queue-size messages, a slow client loses the oldest (or newest, --ws-drop-policy)
+// messages and receives {"dropped": {"messages": n, "total": n, "policy": "oldest"}} every few seconds while it happens.
+// New clients first receive the last --ws-replay messages of each device that match their filters, so they can render
+// data immediately instead of waiting for the next ping cycle.
 // All operations made through REST API and WebSocket routes will be broadcast to all clients subscribed to device-number=null (default),
 // except for errors, which are forwarded directly to the requester.
+// The {address}/ws/files route sends a FileChange for every recording created, finished, renamed or deleted in the
+// recordings directory, including changes made outside the server, so file browsers do not need to poll.
+// The {address}/ws/georeferenced route (?device_number=... for one device) sends the latitude, longitude and depth of
+// each Ping1D return and of the strongest echo of each Ping360 beam, located with the vehicle pose interpolated at the
+// sample time. Recordings carry the same records on the device_<id>/Georeferenced channel.
+// While the vehicle pose is fresh, every Ping360 message sent on {address}/ws is followed by a
+// {"StabilizedBeam": {"device_id", "angle", "world_angle", "bearing", "tilt", ...}} message with the beam angle from
+// north in the gradians of the head angle, so displays can draw the scan still while the vehicle turns. Recordings
+// carry it on the device_<id>/Ping360Stabilized channel.
+//
+// Read-only mode:
+// When started with --read-only, every mutating route, device command and websocket request is rejected,
+// while the frontend, listings, downloads and websocket streams remain available to spectators.
+//
+// Protocol v2:
+// {address}/v2 has typed routes, e.g. GET /v2/device/{uuid}/ping1d/profile, and a websocket at /v2/ws.
+// Answers and websocket messages are envelopes {"request_id", "device_id", "message_type", "payload"},
+// where message_type names the payload layout, e.g. "Ping1D.Profile" or "DeviceInfo".
+// Websocket requests are {"request_id": "...", "request": <device manager request>}, the answer repeats the request_id.
+// v1 stays unchanged.
+//
+// gRPC:
+// With the grpc feature and --grpc-server, the DeviceManager and Recordings services of proto/ping_viewer.proto
+// mirror the device, streaming and recording APIs. Tokens are sent as "authorization: Bearer <token>" metadata.
+//
+// WebRTC:
+// With the webrtc feature, POST /webrtc/offer {"sdp": "...", "device_number": null} answers a browser SDP offer, with
+// every ICE candidate, and sends the device messages as JSON on the data channels it opens. Created with
+// {ordered: false, maxRetransmits: 0}, late or lost messages are skipped instead of holding back the newer ones as
+// a websocket does over lossy long-range links. --webrtc-ice-server adds STUN/TURN servers. Like websockets, it needs
+// a token once tokens are configured and stays available in read-only mode.
+//
+// Device proxy:
+// With --proxy-base-port, every running device is also served as a raw Ping protocol device on its own TCP and UDP
+// port, from that port up, so classic Ping-Viewer or other tools can use a sonar opened by this server at the same
+// time. Every client receives every message of the device, and their requests go through the DeviceManager, so
+// read-only mode applies to them. UDP clients are served while they sent something in the last minute.
+// GET /proxy lists the port of each device.
+//
+// mDNS:
+// The HTTP server is advertised as _http._tcp on <--mdns-name>.local (ping-viewer-next.local by default),
+// with TXT records for the version and the API base paths. --disable-mdns turns it off.
+//
+// Metrics:
+// {address}/metrics exports device message rates, websocket clients, actor queue depths and recording sizes
+// in the Prometheus text format, refreshed from the managers on every scrape.
+//
+// Authentication:
+// When started with --api-token or --api-tokens-file, mutating requests and websocket upgrades need a valid token,
+// sent as "Authorization: Bearer <token>" or, for browser websockets, as ?token=<token>.
+// Read-only requests such as listings, downloads and the frontend stay open.
+//
+// Admin:
+// GET /admin/ws_clients lists the connected device websocket clients with their address, subscription, connection
+// time and sent, dropped and queued messages. DELETE /admin/ws_clients/{id} closes one of them.
+// GET /admin/logs lists the server log files and GET /admin/logs/{file_name}?tail=N downloads one, so diagnostics can
+// be retrieved from a vehicle without shell access. With API tokens configured all of them need a token.
+//
+// Compression:
+// JSON and text responses are compressed with gzip or zstd when the client accepts it, e.g. /recordings/list, the
+// schemas and large device answers. --compression selects the offered encodings and --disable-compression turns it
+// off. Recordings, archives and range requests are always sent as they are.
+//
+// Schemas:
+// GET /schemas returns a JSON Schema (draft 4) catalog of the device manager and recording requests and answers,
+// and of every websocket message, built from the types behind the OpenAPI spec. GET /schemas/{name} returns one
+// of them as a standalone schema, for client code generators and validators.
+//
+// Request ids:
+// Every HTTP request gets an id, taken from its X-Request-Id header when valid, returned in the X-Request-Id response
+// header and appended to error messages. Logs of the request, including the device and recording managers, carry
+// it in a "request" span, and each request is written to the "access" log target. Websocket commands get their own
+// id in a "websocket" span, protocol v2 answers carry it as request_id.
+//
+// Rate limiting:
+// When started with --rate-limit, each client IP can send that many mutating requests per minute, with bursts up to
+// --rate-limit-burst. Requests over the limit get 429 Too Many Requests, viewing routes are never limited.
+//
+// Shutdown:
+// SIGINT, SIGTERM or closing the desktop window stop the continuous modes and close the active recordings, so every
+// MCAP file gets its summary and footer, before the server drains its connections and exits.
+// As a system service on vehicle computers, the server notifies systemd (Type=notify) once every listener is bound
+// and again when it starts stopping, feeds the watchdog when WatchdogSec= is set, and writes its process id to
+// --pid-file, removed on exit, e.g.
+//     [Service]
+//     Type=notify
+//     ExecStart=/usr/bin/ping-viewer-next --pid-file /run/ping-viewer-next.pid
+//     WatchdogSec=30
+//     KillSignal=SIGTERM
+//     TimeoutStopSec=20
+//
+// Vehicle:
+// The bridges keep the last few seconds of poses, recordings, georeferencing and the live server use the pose
+// interpolated at the sonar sample time rather than the last one received.
+// GET /vehicle returns the latest pose from the autopilot with its age. Poses older than --vehicle-stale-after are
+// reported as stale and not written to recordings, so sonar data is never georeferenced with an old position.
+// GET /vehicle/systems lists the MAVLink systems heard from.
+// Recordings map the vehicle clocks to the host clock for aligning them with dataflash logs: the vehicle_clock metadata
+// record and the device_<id>/VehicleClock channel, written every second, give the host time of the vehicle boot
+// (boot_offset_ms, from the ATTITUDE and SYSTEM_TIME boot times) and, once the vehicle has a GPS time, the difference
+// of the vehicle and host unix times (unix_offset_ms).
+// Vehicles navigating by DVL, e.g. with the Water Linked extension, get their position from VISION_POSITION_ESTIMATE
+// and VISION_SPEED_ESTIMATE (from --dvl-system-id and --dvl-component-id, any sender by default): the pose carries it as
+// local_position and velocity, and without a GPS fix the latitude and longitude are placed from GPS_GLOBAL_ORIGIN, so
+// recordings and georeferencing work as with a GPS.
+// Recordings also keep the complete vehicle telemetry with --recording-mavlink ATTITUDE,GPS_RAW_INT,RC_CHANNELS,...
+// (or * for every message) or the mavlink query parameter of StartRecording: each MAVLink message of the vehicle is
+// logged as it arrived on a schemaless JSON device_<id>/MAVLink/<MESSAGE> channel. Only the Zenoh and --vehicle-mavlink
+// bridges provide the stream, and ROS 2 recordings leave it out.
+// Group recordings share one vehicle: its VehicleData, Location, VehicleClock and MAVLink channels are written once
+// per file under vehicle/ instead of device_<id>/.
+// The pose comes from the Zenoh bridge by default, from a MAVLink connection with --vehicle-mavlink, or, with the
+// mavlink2rest feature (part of blueos-extension), polled from a mavlink2rest instance with --vehicle-mavlink2rest.
+// GET /vehicle/bridge shows the bridge connection state, its last error and retry delay, which starts at one second
+// and doubles up to 30 seconds while connecting fails. POST /vehicle/bridge/endpoint {"endpoint": "tcp/..."} moves the
+// Zenoh bridge to another router (saved like PATCH /settings), POST /vehicle/bridge/reconnect connects again right
+// away, and POST /vehicle/bridge/pause and /vehicle/bridge/resume disconnect it for a while.
+// With --zenoh-publish, the device messages are published on the vehicle Zenoh bridge as <prefix>/<device id>/<message>,
+// e.g. pingviewer/<uuid>/profile, for planners and loggers already on that middleware. --zenoh-publish-prefix changes
+// the prefix and --zenoh-publish-encoding the payload: the message fields as json (default), cbor or msgpack, or the
+// raw Ping protocol frames (binary).
+//
+// Settings:
+// Every command line option can also be set with a PING_VIEWER_ environment variable of its name, e.g.
+// PING_VIEWER_REST_SERVER=0.0.0.0:6060 or PING_VIEWER_ENABLE_AUTO_CREATE=true, for containers and BlueOS extensions.
+// The precedence is command line, environment, settings file, then the defaults.
+// Recordings are written to and served from --recordings-path (PING_VIEWER_RECORDINGS_PATH), `recordings` by default.
+// A relative path is resolved against the working directory at startup, the recording manager, the REST file routes,
+// the commands and the desktop app all use the same absolute directory.
+// GET /settings and PATCH /settings (a JSON merge patch) manage the recordings path, retention limits, device
+// auto-creation, vehicle bridge endpoint and extra API tokens. They are kept in --settings-file, below the options set
+// on the command line or in the environment: a field of an option given there is saved but reported as overridden by
+// PATCH /settings. Retention, tokens and the bridge endpoint apply immediately, the recordings path and auto-creation on
+// the next start. Both routes need a token once tokens are configured, and tokens are never sent back.
+// The bridge endpoint is also set with --vehicle-bridge-endpoint, the extra API tokens only in the settings file: they
+// are accepted next to --api-token and --api-tokens-file, which never override them.
+//
+// Commands:
+// The server is the default command, `ping-viewer-next serve`. The same binary also works as a toolbox, every option
+// still applies and can follow the command: `discover` probes the serial ports and the network and prints the devices
+// found as a table, sources written as --device takes them, or with --format json as the REST API lists them, `convert <PATH>...` turns MCAP recordings into one CSV file per topic (the columns of the REST CSV export) in
+// a directory named after each recording or --output <DIR>, e.g. device_<id>/Ping1D.csv, and classic Ping-Viewer .bin
+// logs into MCAP recordings in the recordings directory (as --import-legacy did), and
+// `record --duration <SECONDS> [--name <NAME>]` records the devices found, together in one file when there are
+// several, then prints the file paths. Ctrl-C ends a recording early and still closes the file.
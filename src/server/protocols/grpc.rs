@@ -0,0 +1,522 @@
+use std::{net::SocketAddr, pin::Pin, str::FromStr};
+
+use futures::Stream;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{PingAnswer, PingRequest},
+    manager::{
+        self, Answer, CreateStruct, DeviceInfo, DeviceSelection, ManagerActorHandler, ManagerError,
+        SourceSelection, SourceSerialStruct, SourceUdpStruct, UuidWrapper,
+    },
+    recording::{
+        self, RecordingFormat, RecordingManagerCommand, RecordingsManagerHandler,
+        StartRecordingOptions,
+    },
+};
+use crate::server::{middleware::auth, protocols::v2::envelope::Envelope};
+
+pub mod proto {
+    tonic::include_proto!("ping_viewer");
+}
+
+use proto::{
+    device_manager_server::{DeviceManager, DeviceManagerServer},
+    recordings_server::{Recordings, RecordingsServer},
+};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+pub async fn run(
+    address: SocketAddr,
+    devices_manager_handler: ManagerActorHandler,
+    recordings_handler: RecordingsManagerHandler,
+) -> Result<(), tonic::transport::Error> {
+    info!("gRPC: Server running at {address}");
+    tonic::transport::Server::builder()
+        .add_service(DeviceManagerServer::new(DeviceManagerService {
+            handler: devices_manager_handler,
+        }))
+        .add_service(RecordingsServer::new(RecordingsService {
+            handler: recordings_handler,
+        }))
+        .serve(address)
+        .await
+}
+
+// Same rules as the HTTP server: tokens for every mutating call and stream, nothing in read-only mode
+fn authorize<T>(request: &Request<T>, mutating: bool) -> Result<(), Status> {
+    if mutating && crate::cli::manager::is_read_only() {
+        return Err(Status::permission_denied(
+            "Server is in read-only mode, requests are not allowed",
+        ));
+    }
+    if !auth::is_enabled() {
+        return Ok(());
+    }
+    let token = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if auth::is_valid_token(token.trim()) => Ok(()),
+        _ => Err(Status::unauthenticated("A valid API token is required")),
+    }
+}
+
+fn parse_uuid(id: &str) -> Result<Uuid, Status> {
+    Uuid::from_str(id).map_err(|err| Status::invalid_argument(format!("Invalid id {id:?}: {err}")))
+}
+
+// Parse a REST enum name, e.g. `Ping360`, through its serde representation
+fn parse_name<T: serde::de::DeserializeOwned>(field: &str, value: &str) -> Result<T, Status> {
+    serde_json::from_value(json!(value))
+        .map_err(|err| Status::invalid_argument(format!("Invalid {field} {value:?}: {err}")))
+}
+
+// Unit variants become their name, anything else its JSON
+fn name<T: Serialize>(value: &T) -> String {
+    match json!(value) {
+        serde_json::Value::String(name) => name,
+        value => value.to_string(),
+    }
+}
+
+fn status(error: ManagerError) -> Status {
+    let message = serde_json::to_string(&error).unwrap_or_default();
+    match error {
+        ManagerError::DeviceNotExist(_) => Status::not_found(message),
+        ManagerError::DeviceAlreadyExist(_) | ManagerError::SourceInUse(..) => {
+            Status::already_exists(message)
+        }
+        ManagerError::NotImplemented(_) => Status::unimplemented(message),
+        _ => Status::internal(message),
+    }
+}
+
+fn unexpected<T: std::fmt::Debug>(answer: T) -> Status {
+    Status::internal(format!("Unexpected answer: {answer:?}"))
+}
+
+impl From<DeviceInfo> for proto::Device {
+    fn from(info: DeviceInfo) -> Self {
+        let source = match info.source {
+            SourceSelection::UdpStream(udp) => proto::device::Source::Udp(proto::UdpSource {
+                ip: udp.ip.to_string(),
+                port: udp.port as u32,
+            }),
+            SourceSelection::SerialStream(serial) => {
+                proto::device::Source::Serial(proto::SerialSource {
+                    path: serial.path,
+                    baudrate: serial.baudrate,
+                })
+            }
+            SourceSelection::ReplayStream(replay) => {
+                proto::device::Source::ReplayFile(replay.file_name)
+            }
+        };
+        Self {
+            id: info.id.to_string(),
+            device_type: name(&info.device_type),
+            status: name(&info.status),
+            source: Some(source),
+            properties_json: info
+                .properties
+                .map(|properties| json!(properties).to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<recording::RecordingSession> for proto::RecordingSession {
+    fn from(session: recording::RecordingSession) -> Self {
+        Self {
+            device_id: session.device_id.to_string(),
+            file_path: session.file_path.to_string_lossy().to_string(),
+            is_active: session.is_active,
+            start_time: session.start_time.to_rfc3339(),
+            device_type: name(&session.device_type),
+            name: session.name.unwrap_or_default(),
+        }
+    }
+}
+
+pub struct DeviceManagerService {
+    handler: ManagerActorHandler,
+}
+
+impl DeviceManagerService {
+    async fn send(&self, request: manager::Request) -> Result<Answer, Status> {
+        self.handler.send(request).await.map_err(status)
+    }
+
+    async fn device(&self, uuid: Uuid) -> Result<proto::Device, Status> {
+        match self
+            .send(manager::Request::Info(UuidWrapper { uuid }))
+            .await?
+        {
+            Answer::DeviceInfo(devices) => devices
+                .into_iter()
+                .next()
+                .map(proto::Device::from)
+                .ok_or_else(|| Status::not_found(format!("Device {uuid} does not exist"))),
+            answer => Err(unexpected(answer)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl DeviceManager for DeviceManagerService {
+    type StreamMessagesStream = ResponseStream<proto::DeviceMessage>;
+
+    async fn list_devices(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::DeviceList>, Status> {
+        authorize(&request, false)?;
+        match self.send(manager::Request::List).await? {
+            Answer::DeviceInfo(devices) => Ok(Response::new(proto::DeviceList {
+                devices: devices.into_iter().map(proto::Device::from).collect(),
+            })),
+            answer => Err(unexpected(answer)),
+        }
+    }
+
+    async fn get_device(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<proto::Device>, Status> {
+        authorize(&request, false)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        self.device(uuid).await.map(Response::new)
+    }
+
+    async fn create_device(
+        &self,
+        request: Request<proto::CreateDeviceRequest>,
+    ) -> Result<Response<proto::Device>, Status> {
+        authorize(&request, true)?;
+        let request = request.into_inner();
+        let source = match request.source {
+            Some(proto::create_device_request::Source::Udp(udp)) => {
+                SourceSelection::UdpStream(SourceUdpStruct {
+                    ip: udp.ip.parse().map_err(|err| {
+                        Status::invalid_argument(format!("Invalid ip {:?}: {err}", udp.ip))
+                    })?,
+                    port: u16::try_from(udp.port)
+                        .map_err(|_| Status::invalid_argument("Invalid port"))?,
+                })
+            }
+            Some(proto::create_device_request::Source::Serial(serial)) => {
+                SourceSelection::SerialStream(SourceSerialStruct {
+                    path: serial.path,
+                    baudrate: serial.baudrate,
+                })
+            }
+            None => return Err(Status::invalid_argument("A source is required")),
+        };
+        let device_selection: DeviceSelection = if request.device_type.is_empty() {
+            DeviceSelection::Auto
+        } else {
+            parse_name("device_type", &request.device_type)?
+        };
+
+        match self
+            .send(manager::Request::Create(CreateStruct {
+                source,
+                device_selection,
+                negotiate_baudrate: request.negotiate_baudrate,
+            }))
+            .await?
+        {
+            Answer::DeviceInfo(devices) => devices
+                .into_iter()
+                .next()
+                .map(|device| Response::new(proto::Device::from(device)))
+                .ok_or_else(|| Status::internal("No device was created")),
+            answer => Err(unexpected(answer)),
+        }
+    }
+
+    async fn delete_device(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, true)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        self.send(manager::Request::Delete(UuidWrapper { uuid }))
+            .await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn enable_continuous_mode(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, true)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        self.send(manager::Request::EnableContinuousMode(UuidWrapper { uuid }))
+            .await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn disable_continuous_mode(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, true)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        self.send(manager::Request::DisableContinuousMode(UuidWrapper {
+            uuid,
+        }))
+        .await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn request(
+        &self,
+        request: Request<proto::JsonRequest>,
+    ) -> Result<Response<proto::JsonAnswer>, Status> {
+        authorize(&request, true)?;
+        let request: manager::Request = serde_json::from_str(&request.get_ref().json)
+            .map_err(|err| Status::invalid_argument(format!("Invalid request: {err}")))?;
+        let device_id = request.device_id();
+        let answer = json!(self.send(request).await?);
+        // Same as the REST route, other clients see the answer on the websockets
+        crate::server::protocols::v1::websocket::send_to_websockets(answer.clone(), device_id);
+        Ok(Response::new(proto::JsonAnswer {
+            json: answer.to_string(),
+        }))
+    }
+
+    async fn stream_messages(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<Self::StreamMessagesStream>, Status> {
+        authorize(&request, true)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        let handler = match self
+            .send(manager::Request::GetDeviceHandler(UuidWrapper { uuid }))
+            .await?
+        {
+            Answer::InnerDeviceHandler(handler) => handler,
+            answer => return Err(unexpected(answer)),
+        };
+        let receiver = match handler.send(PingRequest::GetSubscriber).await {
+            Ok(PingAnswer::Subscriber(receiver)) => receiver,
+            answer => return Err(unexpected(answer)),
+        };
+
+        let stream = futures::stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        let decoded = bluerobotics_ping::Messages::try_from(&message)
+                            .map(|decoded| json!({ "PingMessage": decoded }))
+                            .unwrap_or_default();
+                        let envelope =
+                            Envelope::from_answer(&json!({ "DeviceMessage": decoded }), Some(uuid));
+                        let timestamp_ns =
+                            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+                        let message = proto::DeviceMessage {
+                            device_id: uuid.to_string(),
+                            message_type: envelope.message_type,
+                            payload_json: envelope.payload.to_string(),
+                            frame: message.serialized(),
+                            timestamp_ns,
+                        };
+                        return Some((Ok(message), receiver));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("gRPC: Message stream of {uuid} skipped {skipped} messages");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+pub struct RecordingsService {
+    handler: RecordingsManagerHandler,
+}
+
+impl RecordingsService {
+    async fn send(&self, request: RecordingManagerCommand) -> Result<recording::Answer, Status> {
+        self.handler.send(request).await.map_err(status)
+    }
+}
+
+#[tonic::async_trait]
+impl Recordings for RecordingsService {
+    type StreamRecordingStatusStream = ResponseStream<proto::RecordingSession>;
+
+    async fn start_recording(
+        &self,
+        request: Request<proto::StartRecordingRequest>,
+    ) -> Result<Response<proto::RecordingSession>, Status> {
+        authorize(&request, true)?;
+        let request = request.into_inner();
+        let format: RecordingFormat = if request.format.is_empty() {
+            RecordingFormat::default()
+        } else {
+            parse_name("format", &request.format)?
+        };
+        let options = StartRecordingOptions {
+            uuid: parse_uuid(&request.device_id)?,
+            raw_frames: request.raw_frames,
+            name: (!request.name.is_empty()).then_some(request.name),
+            format,
+            mavlink_topics: None,
+        };
+        match self
+            .send(RecordingManagerCommand::StartRecording(options))
+            .await?
+        {
+            recording::Answer::RecordingSession(session) => Ok(Response::new(session.into())),
+            answer => Err(unexpected(answer)),
+        }
+    }
+
+    async fn stop_recording(
+        &self,
+        request: Request<proto::DeviceId>,
+    ) -> Result<Response<proto::RecordingSession>, Status> {
+        authorize(&request, true)?;
+        let uuid = parse_uuid(&request.get_ref().id)?;
+        match self
+            .send(RecordingManagerCommand::StopRecording(UuidWrapper { uuid }))
+            .await?
+        {
+            recording::Answer::RecordingSession(session) => Ok(Response::new(session.into())),
+            answer => Err(unexpected(answer)),
+        }
+    }
+
+    async fn list_recording_status(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::RecordingSessionList>, Status> {
+        authorize(&request, false)?;
+        match self
+            .send(RecordingManagerCommand::GetAllRecordingStatus)
+            .await?
+        {
+            recording::Answer::AllRecordingStatus(sessions) => {
+                Ok(Response::new(proto::RecordingSessionList {
+                    sessions: sessions.into_iter().map(Into::into).collect(),
+                }))
+            }
+            answer => Err(unexpected(answer)),
+        }
+    }
+
+    async fn stream_recording_status(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::StreamRecordingStatusStream>, Status> {
+        authorize(&request, true)?;
+        let receiver = match self.send(RecordingManagerCommand::GetSubscriber).await? {
+            recording::Answer::RecordingManager(receiver) => receiver,
+            answer => return Err(unexpected(answer)),
+        };
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(session) => {
+                        return Some((Ok(proto::RecordingSession::from(session)), receiver))
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tonic::Code;
+
+    use super::*;
+    use crate::device::manager::DeviceStatus;
+
+    #[test]
+    fn test_enum_names_round_trip() {
+        assert_eq!(name(&DeviceSelection::Ping360), "Ping360");
+        assert!(matches!(
+            parse_name::<DeviceSelection>("device_type", "Ping360"),
+            Ok(DeviceSelection::Ping360)
+        ));
+        assert_eq!(
+            parse_name::<DeviceSelection>("device_type", "Ping2")
+                .unwrap_err()
+                .code(),
+            Code::InvalidArgument
+        );
+        // Variants with data are sent as their JSON
+        assert_eq!(
+            name(&DeviceStatus::Error {
+                reason: "timeout".to_string()
+            }),
+            r#"{"Error":{"reason":"timeout"}}"#
+        );
+    }
+
+    #[test]
+    fn test_manager_errors_map_to_status_codes() {
+        let id = Uuid::new_v4();
+        assert_eq!(
+            status(ManagerError::DeviceNotExist(id)).code(),
+            Code::NotFound
+        );
+        assert_eq!(
+            status(ManagerError::DeviceAlreadyExist(id)).code(),
+            Code::AlreadyExists
+        );
+        assert_eq!(
+            status(ManagerError::Other("failed".to_string())).code(),
+            Code::Internal
+        );
+        assert_eq!(
+            parse_uuid("device").unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn test_device_info_is_converted() {
+        let id = Uuid::new_v4();
+        let device = proto::Device::from(DeviceInfo {
+            id,
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::new(192, 168, 2, 2),
+                port: 12345,
+            }),
+            status: DeviceStatus::ContinuousMode,
+            device_type: DeviceSelection::Ping360,
+            properties: None,
+        });
+        assert_eq!(device.id, id.to_string());
+        assert_eq!(device.device_type, "Ping360");
+        assert_eq!(device.status, "ContinuousMode");
+        assert_eq!(device.properties_json, "");
+        assert_eq!(
+            device.source,
+            Some(proto::device::Source::Udp(proto::UdpSource {
+                ip: "192.168.2.2".to_string(),
+                port: 12345,
+            }))
+        );
+    }
+}
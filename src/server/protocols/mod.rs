@@ -1 +1,6 @@
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod v1;
+pub mod v2;
+#[cfg(feature = "webrtc")]
+pub mod webrtc;
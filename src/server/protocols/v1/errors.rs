@@ -1,4 +1,7 @@
-use actix_web::{http::StatusCode, ResponseError};
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    HttpResponse, ResponseError,
+};
 
 use paperclip::actix::api_v2_errors;
 use validator::ValidationErrors;
@@ -7,6 +10,12 @@ use validator::ValidationErrors;
 #[api_v2_errors(
     code = 400,
     description = "Bad Request: The client's request contains invalid or malformed data.",
+    code = 401,
+    description = "Unauthorized: A valid API token is required for this request.",
+    code = 403,
+    description = "Forbidden: The server does not allow this request.",
+    code = 429,
+    description = "Too Many Requests: The client exceeded the rate limit for this request.",
     code = 500,
     description = "Internal Server Error: An unexpected server error has occurred."
 )]
@@ -14,6 +23,12 @@ use validator::ValidationErrors;
 pub enum Error {
     #[error("Bad Request: {0}")]
     BadRequest(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Too Many Requests: {0}")]
+    TooManyRequests(String),
     #[error("Internal Server Error: {0}")]
     Internal(String),
 }
@@ -22,9 +37,23 @@ impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    // The request id lets a client report which call failed, matching the server logs
+    fn error_response(&self) -> HttpResponse {
+        let body = match crate::server::middleware::request_id::current() {
+            Some(id) => format!("{self} (request id {id})"),
+            None => self.to_string(),
+        };
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(body)
+    }
 }
 
 impl From<ValidationErrors> for Error {
@@ -0,0 +1,166 @@
+use crate::logger;
+use crate::server::protocols::v1::{
+    errors::Error,
+    websocket::{self, WebsocketClientInfo},
+};
+use chrono::{DateTime, Utc};
+use paperclip::actix::{
+    api_v2_operation, delete, get,
+    web::{self, HttpResponse, Json},
+    Apiv2Schema,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Written by the rolling file appender, e.g. ping-viewer.2024-09-10-18.log
+const LOG_PREFIX: &str = "ping-viewer";
+const LOG_SUFFIX: &str = ".log";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: String,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct LogQuery {
+    /// Only the last lines of the file
+    pub tail: Option<usize>,
+}
+
+/// Connected device websocket clients with their subscription and traffic
+#[api_v2_operation(tags("Admin"))]
+#[get("/admin/ws_clients")]
+async fn list_ws_clients() -> Result<Json<Vec<WebsocketClientInfo>>, Error> {
+    Ok(Json(websocket::clients()))
+}
+
+/// Close the connection of one websocket client, it may reconnect
+#[api_v2_operation(tags("Admin"))]
+#[delete("/admin/ws_clients/{id}")]
+async fn disconnect_ws_client(id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+    if !websocket::disconnect(id) {
+        return Err(Error::BadRequest(format!("No websocket client {id}")));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Server log files, newest first
+#[api_v2_operation(tags("Admin"))]
+#[get("/admin/logs")]
+async fn list_logs() -> Result<Json<Vec<LogFile>>, Error> {
+    let dir = logger::manager::get_app_log_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        // Nothing was logged yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Json(Vec::new())),
+        Err(err) => {
+            return Err(Error::Internal(format!(
+                "Failed to read log directory {dir:?}: {err}"
+            )))
+        }
+    };
+
+    let mut files: Vec<LogFile> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let metadata = entry
+                .metadata()
+                .ok()
+                .filter(|metadata| metadata.is_file())?;
+            is_log_file(&name).then(|| LogFile {
+                name,
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .map(|mtime| DateTime::<Utc>::from(mtime).to_rfc3339())
+                    .unwrap_or_else(|_| "unknown".to_string()),
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| b.modified.cmp(&a.modified).then(b.name.cmp(&a.name)));
+    Ok(Json(files))
+}
+
+/// Download one server log file, `tail` keeps only its last lines
+#[api_v2_operation(tags("Admin"))]
+#[get("/admin/logs/{file_name}")]
+async fn download_log(
+    file_name: web::Path<String>,
+    query: web::Query<LogQuery>,
+) -> Result<HttpResponse, Error> {
+    let file_name = file_name.into_inner();
+    // Only names from the listing, nothing outside the log directory
+    if !is_log_file(&file_name) {
+        return Err(Error::BadRequest(format!("Unknown log file {file_name:?}")));
+    }
+    let path = logger::manager::get_app_log_dir().join(&file_name);
+    let content = match tokio::fs::read(&path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::BadRequest(format!("Unknown log file {file_name:?}")))
+        }
+        Err(err) => return Err(Error::Internal(format!("Failed to read {path:?}: {err}"))),
+    };
+    let content = match query.tail {
+        Some(lines) => tail(&content, lines).to_vec(),
+        None => content,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{file_name}\""),
+        ))
+        .append_header(("Cache-Control", "no-cache"))
+        .body(content))
+}
+
+fn is_log_file(name: &str) -> bool {
+    name.starts_with(LOG_PREFIX)
+        && name.ends_with(LOG_SUFFIX)
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+fn tail(content: &[u8], lines: usize) -> &[u8] {
+    if lines == 0 {
+        return &[];
+    }
+    // A trailing newline ends the last line, it does not start a new one
+    let body = content.strip_suffix(b"\n").unwrap_or(content);
+    let start = body
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(lines - 1)
+        .map_or(0, |(index, _)| index + 1);
+    &content[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_log_files_are_served() {
+        assert!(is_log_file("ping-viewer.2024-09-10-18.log"));
+        assert!(!is_log_file("../ping-viewer.log"));
+        assert!(!is_log_file("ping-viewer/../../etc/passwd.log"));
+        assert!(!is_log_file("recording.mcap"));
+    }
+
+    #[test]
+    fn test_tail_keeps_the_last_lines() {
+        let content = b"one\ntwo\nthree\n";
+        assert_eq!(tail(content, 2), b"two\nthree\n");
+        assert_eq!(tail(content, 5), content);
+        assert_eq!(tail(content, 0), b"");
+        assert_eq!(tail(b"one\ntwo", 1), b"two");
+    }
+}
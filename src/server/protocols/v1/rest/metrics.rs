@@ -0,0 +1,21 @@
+use crate::device::{manager::ManagerActorHandler, recording::RecordingsManagerHandler};
+use crate::server::{metrics, protocols::v1::errors::Error};
+use paperclip::actix::{
+    api_v2_operation, get,
+    web::{self, HttpResponse},
+};
+
+/// Prometheus text exposition of the device, websocket, actor and recording metrics
+#[api_v2_operation(tags("Metrics"))]
+#[get("/metrics")]
+async fn metrics_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+    recordings_handler: web::Data<RecordingsManagerHandler>,
+) -> Result<HttpResponse, Error> {
+    let body = metrics::gather(&manager_handler, &recordings_handler)
+        .await
+        .map_err(Error::Internal)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
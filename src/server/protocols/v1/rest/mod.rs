@@ -1,4 +1,7 @@
-use crate::device::manager::{ManagerActorHandler, Request, UuidWrapper};
+use crate::device::manager::{
+    settings_sync::{ManagerState, SettingsSnapshot},
+    ManagerActorHandler, Request, UuidWrapper,
+};
 use crate::server::protocols::v1::errors::Error;
 use actix_web::Responder;
 use mime_guess::from_path;
@@ -11,7 +14,13 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
 
+pub mod admin;
+pub mod metrics;
+pub mod proxy;
 pub mod recording;
+pub mod schemas;
+pub mod settings;
+pub mod vehicle;
 
 #[cfg(not(feature = "embed-frontend"))]
 #[derive(rust_embed::RustEmbed)]
@@ -23,32 +32,75 @@ struct Asset;
 #[folder = "ping-viewer-next-frontend/dist"]
 struct Asset;
 
-fn handle_embedded_file(path: &str) -> HttpResponse {
-    match Asset::get(path) {
-        Some(content) => HttpResponse::Ok()
-            .content_type(from_path(path).first_or_octet_stream().as_ref())
-            .body(content.data.into_owned()),
-        None => HttpResponse::NotFound().body("404 Not Found"),
+fn handle_embedded_file(req: &actix_web::HttpRequest, path: &str) -> HttpResponse {
+    let Some(content) = Asset::get(path) else {
+        // Client side routes of the Vue router when a browser navigates to them, files and unknown
+        // API routes keep their 404
+        if !path.contains('.') && accepts_html(req) {
+            return handle_embedded_file(req, "index.html");
+        }
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+
+    let etag = format!(
+        "\"{}\"",
+        content
+            .metadata
+            .sha256_hash()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    );
+    // Vite puts a content hash in every asset name, only the entry points change in place
+    let cache_control = if path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    let is_cached = req
+        .headers()
+        .get(actix_web::http::header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if is_cached {
+        return HttpResponse::NotModified()
+            .insert_header((actix_web::http::header::ETAG, etag))
+            .insert_header((actix_web::http::header::CACHE_CONTROL, cache_control))
+            .finish();
     }
+
+    HttpResponse::Ok()
+        .content_type(from_path(path).first_or_octet_stream().as_ref())
+        .insert_header((actix_web::http::header::ETAG, etag))
+        .insert_header((actix_web::http::header::CACHE_CONTROL, cache_control))
+        .body(content.data.into_owned())
+}
+
+fn accepts_html(req: &actix_web::HttpRequest) -> bool {
+    req.headers()
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"))
 }
 
 #[api_v2_operation(skip)]
 #[get("/")]
-async fn index() -> impl Responder {
-    handle_embedded_file("index.html")
+async fn index(req: actix_web::HttpRequest) -> impl Responder {
+    handle_embedded_file(&req, "index.html")
 }
 
 #[api_v2_operation(skip)]
 #[get("/{file_path:.*}")]
-async fn index_files(file_path: web::Path<String>) -> impl Responder {
-    handle_embedded_file(&file_path)
+async fn index_files(req: actix_web::HttpRequest, file_path: web::Path<String>) -> impl Responder {
+    handle_embedded_file(&req, &file_path)
 }
 
 #[api_v2_operation(skip)]
 #[get("/addons/{tail:.*}")]
-async fn addons_handler() -> impl Responder {
+async fn addons_handler(req: actix_web::HttpRequest) -> impl Responder {
     // Vue router handle /addons routes
-    handle_embedded_file("index.html")
+    handle_embedded_file(&req, "index.html")
 }
 
 /// The "register_service" route is used by BlueOS extensions manager
@@ -62,22 +114,66 @@ async fn server_metadata() -> Result<Json<ServerMetadata>, Error> {
 pub fn register_services(cfg: &mut web::ServiceConfig) {
     cfg.service(index)
         .service(post_request)
+        .service(device_manager_settings_export)
+        .service(device_manager_settings_diff)
+        .service(device_manager_settings_apply)
+        .service(device_manager_state_export)
+        .service(device_manager_state_import)
+        .service(device_manager_serial_ports)
         .service(device_manager_get)
         .service(device_manager_post)
         .service(recording::recording_manager_get)
+        .service(recording::recording_manager_post_all)
+        .service(recording::recording_manager_post_group)
+        .service(recording::recording_manager_mounting)
+        .service(recording::recording_manager_annotate)
         .service(recording::recording_manager_post)
         .service(recording::recordings_manager_post_request)
         .service(post_create)
+        .service(device_manager_device_latest)
         .service(device_manager_device_get)
         .service(device_manager_device_ping1d_get)
         .service(device_manager_device_ping360_get)
         .service(device_manager_device_common_get)
+        .service(vehicle::vehicle_get)
+        .service(vehicle::vehicle_systems)
+        .service(vehicle::vehicle_bridge_get)
+        .service(vehicle::vehicle_bridge_endpoint)
+        .service(vehicle::vehicle_bridge_reconnect)
+        .service(vehicle::vehicle_bridge_pause)
+        .service(vehicle::vehicle_bridge_resume)
+        .service(proxy::proxy_ports)
+        .service(settings::settings_get)
+        .service(settings::settings_patch)
+        .service(schemas::schemas_get)
+        .service(schemas::schema_get)
+        .service(admin::list_ws_clients)
+        .service(admin::disconnect_ws_client)
+        .service(admin::list_logs)
+        .service(admin::download_log)
         .service(addons_handler)
+        .service(service_icon_file)
         .service(cockpit_extras)
         .service(recording::list_mcap_recordings)
         .service(recording::download_mcap_file)
+        .service(recording::download_archive)
         .service(recording::delete_mcap_file)
-        .service(index_files);
+        .service(recording::recording_info)
+        .service(recording::survey_report)
+        .service(recording::export_recording)
+        .service(recording::import_legacy_log)
+        .service(recording::list_jobs)
+        .service(recording::get_job)
+        .service(recording::get_job_result)
+        .service(recording::recordings_disk_usage)
+        .service(recording::list_replays)
+        .service(recording::control_replay)
+        .service(recording::start_replay)
+        .service(recording::stop_replay);
+    #[cfg(feature = "upload")]
+    cfg.service(recording::upload_recordings);
+    // Frontend catch-all, keep it after every API route
+    cfg.service(index_files);
 }
 
 async fn send_request_and_broadcast(
@@ -91,6 +187,10 @@ async fn send_request_and_broadcast(
         Request::Info(uuid_wrapper) => Some(uuid_wrapper.uuid),
         Request::EnableContinuousMode(uuid_wrapper) => Some(uuid_wrapper.uuid),
         Request::DisableContinuousMode(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::GetDeviceMetrics(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::GetPing360Diagnostics(uuid_wrapper) => Some(uuid_wrapper.uuid),
+        Request::SetPollWeight(poll_weight) => Some(poll_weight.uuid),
+        Request::ApplyPreset(apply_preset) => Some(apply_preset.uuid),
         _ => None,
     };
 
@@ -110,11 +210,71 @@ async fn post_request(
     send_request_and_broadcast(&manager_handler, request).await
 }
 
+#[api_v2_operation(tags("Device Manager : Settings"))]
+#[get("device_manager/settings/export")]
+async fn device_manager_settings_export(
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let answer = manager_handler.send(Request::ExportSettings).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Device Manager : Settings"))]
+#[post("device_manager/settings/diff")]
+async fn device_manager_settings_diff(
+    manager_handler: web::Data<ManagerActorHandler>,
+    json: web::Json<SettingsSnapshot>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let answer = manager_handler
+        .send(Request::DiffSettings(json.into_inner()))
+        .await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Device Manager : Settings"))]
+#[post("device_manager/settings/apply")]
+async fn device_manager_settings_apply(
+    manager_handler: web::Data<ManagerActorHandler>,
+    json: web::Json<SettingsSnapshot>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    send_request_and_broadcast(&manager_handler, Request::ApplySettings(json.into_inner())).await
+}
+
+#[api_v2_operation(tags("Device Manager : Settings"))]
+#[get("device_manager/state/export")]
+async fn device_manager_state_export(
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let answer = manager_handler.send(Request::ExportState).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Device Manager : Settings"))]
+#[post("device_manager/state/import")]
+async fn device_manager_state_import(
+    manager_handler: web::Data<ManagerActorHandler>,
+    json: web::Json<ManagerState>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    send_request_and_broadcast(&manager_handler, Request::ImportState(json.into_inner())).await
+}
+
+#[api_v2_operation(tags("Device Manager"))]
+#[get("device_manager/serial_ports")]
+async fn device_manager_serial_ports(
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let answer = manager_handler.send(Request::ListSerialPorts).await?;
+    Ok(Json(answer))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
 pub enum DeviceManagerGetOptionsV1 {
     AutoCreate,
     List,
     Search,
+    Metrics,
+    PollSchedulerStats,
+    Presets,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
@@ -123,6 +283,8 @@ pub enum DeviceManagerPostOptionsV1 {
     Info,
     EnableContinuousMode,
     DisableContinuousMode,
+    Metrics,
+    Ping360Diagnostics,
 }
 
 #[api_v2_operation(tags("Device Manager"))]
@@ -135,6 +297,11 @@ async fn device_manager_get(
         DeviceManagerGetOptionsV1::AutoCreate => crate::device::manager::Request::AutoCreate,
         DeviceManagerGetOptionsV1::List => crate::device::manager::Request::List,
         DeviceManagerGetOptionsV1::Search => crate::device::manager::Request::Search,
+        DeviceManagerGetOptionsV1::Metrics => crate::device::manager::Request::GetMetrics,
+        DeviceManagerGetOptionsV1::PollSchedulerStats => {
+            crate::device::manager::Request::GetPollSchedulerStats
+        }
+        DeviceManagerGetOptionsV1::Presets => crate::device::manager::Request::ListPresets,
     };
 
     send_request_and_broadcast(&manager_handler, request).await
@@ -176,11 +343,31 @@ async fn device_manager_post(
         DeviceManagerPostOptionsV1::DisableContinuousMode => {
             crate::device::manager::Request::DisableContinuousMode(UuidWrapper { uuid })
         }
+        DeviceManagerPostOptionsV1::Metrics => {
+            crate::device::manager::Request::GetDeviceMetrics(UuidWrapper { uuid })
+        }
+        DeviceManagerPostOptionsV1::Ping360Diagnostics => {
+            crate::device::manager::Request::GetPing360Diagnostics(UuidWrapper { uuid })
+        }
     };
 
     send_request_and_broadcast(&manager_handler, request).await
 }
 
+/// Most recent Profile or DeviceData of the device, for scripts polling instead of using the websocket
+#[api_v2_operation(tags("Device Manager : Device"))]
+#[get("device_manager/{device}/latest")]
+async fn device_manager_device_latest(
+    manager_handler: web::Data<ManagerActorHandler>,
+    device: web::Path<Uuid>,
+) -> Result<Json<crate::device::manager::Answer>, Error> {
+    let request = crate::device::manager::Request::GetLatestSample(UuidWrapper {
+        uuid: device.into_inner(),
+    });
+    // Not broadcast, pollers would flood the websocket clients with copies of the stream
+    Ok(Json(manager_handler.send(request).await?))
+}
+
 #[api_v2_operation(tags("Device Manager : Device"))]
 #[get("device_manager/{device}/{request}")]
 async fn device_manager_device_get(
@@ -273,6 +460,8 @@ pub struct ServerMetadata {
     pub new_page: bool,
     pub webpage: &'static str,
     pub api: &'static str,
+    /// The frontend and API use absolute paths, BlueOS has to open it on its own port
+    pub works_in_relative_paths: bool,
     pub extras: Extras,
 }
 
@@ -302,12 +491,13 @@ impl Default for ServerMetadata {
         Self {
             name: "Ping Viewer Next",
             description: "A ping protocol extension for expose devices to web.",
-            icon: "mdi-compass-outline",
+            icon: service_icon(),
             company: "BlueRobotics",
-            version: "0.0.0",
+            version: env!("CARGO_PKG_VERSION"),
             new_page: true,
-            webpage: "https://github.com/RaulTrombin/navigator-assistant",
+            webpage: env!("CARGO_PKG_REPOSITORY"),
             api: "/docs",
+            works_in_relative_paths: false,
             extras: Extras {
                 cockpit: "/cockpit_extras.json",
             },
@@ -315,6 +505,24 @@ impl Default for ServerMetadata {
     }
 }
 
+const ICON_FILE: &str = "favicon.ico";
+
+// The embedded frontend icon when there is one, BlueOS shows paths as images and `mdi-` names as icons
+fn service_icon() -> &'static str {
+    if Asset::get(ICON_FILE).is_some() {
+        "/icon"
+    } else {
+        "mdi-compass-outline"
+    }
+}
+
+/// Icon shown by BlueOS in the extensions list and sidebar
+#[api_v2_operation(skip)]
+#[get("/icon")]
+async fn service_icon_file(req: actix_web::HttpRequest) -> impl Responder {
+    handle_embedded_file(&req, ICON_FILE)
+}
+
 #[api_v2_operation]
 #[get("/cockpit_extras.json")]
 async fn cockpit_extras(
@@ -361,3 +569,32 @@ async fn cockpit_extras(
         widgets,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header::ACCEPT, test::TestRequest};
+
+    #[test]
+    fn test_only_browser_navigation_falls_back_to_index() {
+        let navigation = TestRequest::default()
+            .insert_header((ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8"))
+            .to_http_request();
+        assert_eq!(
+            handle_embedded_file(&navigation, "recordings").status(),
+            actix_web::http::StatusCode::OK
+        );
+
+        let api = TestRequest::default()
+            .insert_header((ACCEPT, "application/json"))
+            .to_http_request();
+        assert_eq!(
+            handle_embedded_file(&api, "device_manager/Unknown").status(),
+            actix_web::http::StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            handle_embedded_file(&TestRequest::default().to_http_request(), "v1/unknown").status(),
+            actix_web::http::StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,10 @@
+use crate::server::protocols::v1::errors::Error;
+use crate::server::proxy::{self, ProxyPort};
+use paperclip::actix::{api_v2_operation, get, web::Json};
+
+/// Ports serving the devices as raw Ping protocol servers, empty unless --proxy-base-port is set
+#[api_v2_operation(tags("Device Proxy"))]
+#[get("/proxy")]
+async fn proxy_ports() -> Result<Json<Vec<ProxyPort>>, Error> {
+    Ok(Json(proxy::ports()))
+}
@@ -1,7 +1,21 @@
 use crate::device::manager::UuidWrapper;
-use crate::device::recording::{RecordingManagerCommand, RecordingsManagerHandler};
+use crate::device::recording::{
+    archive,
+    export::{self, ExportOptions},
+    info,
+    jobs::{Job, JobArtifact, JobKind, JOBS},
+    legacy,
+    mounting::{MountingPose, MountingRequest},
+    replay::{ReplayControl, ReplayOptions},
+    report::{self, ReportFormat, ReportOptions},
+    AnnotationRequest, GroupRecordingOptions, RecordingFormat, RecordingManagerCommand,
+    RecordingsManagerHandler, StartRecordingOptions,
+};
 use crate::server::protocols::v1::errors::Error;
-use actix_web::Responder;
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    Responder,
+};
 use chrono::{DateTime, Utc};
 use mime_guess::from_path;
 use paperclip::actix::{
@@ -11,12 +25,18 @@ use paperclip::actix::{
 };
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path};
+use tokio::io::AsyncReadExt;
 use tracing::debug;
 use uuid::Uuid;
 
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+// How often an in-progress recording is checked for newly flushed data
+const DOWNLOAD_POLL_PERIOD: std::time::Duration = std::time::Duration::from_millis(500);
+
 #[derive(Debug, Serialize, Deserialize, Apiv2Schema)]
 pub struct McapFileInfo {
+    /// Path relative to the recordings directory, nested when recordings are organized by date
     pub file_name: String,
     pub file_size: u64,
     pub modified: String,
@@ -27,12 +47,77 @@ pub enum RecordingsManagerPostOptionsV1 {
     StartRecording,
     StopRecording,
     GetRecordingStatus,
+    Bookmark,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub enum RecordingsManagerAllOptionsV1 {
+    StartRecording,
+    StopRecording,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct RecordingQuery {
+    /// Also record the undecoded protocol frames, only used by StartRecording
+    pub raw_frames: Option<bool>,
+    /// Session label used as the file name prefix, only used by StartRecording
+    pub name: Option<String>,
+    /// Output format of the session, only used by StartRecording
+    pub format: Option<RecordingFormat>,
+    /// Comma separated MAVLink messages to record, e.g. `ATTITUDE,BATTERY_STATUS`, only used by StartRecording
+    pub mavlink: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct AnnotationText {
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct BackgroundQuery {
+    /// Answer right away with the queued job, the output is fetched from `/recordings/jobs/{id}/result`
+    pub background: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct ReplayQuery {
+    /// Playback speed, 1.0 is real time
+    pub speed: Option<f64>,
+    /// Start again from the beginning when the recording ends
+    pub looped: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum ListSort {
+    Name,
+    Size,
+    Mtime,
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct ListQuery {
+    /// Page number starting at 1, only used with `limit`
+    pub page: Option<usize>,
+    /// Files per page, the total is in the `X-Total-Count` header
+    pub limit: Option<usize>,
+    pub sort: Option<ListSort>,
+    /// Largest, newest or last name first
+    pub descending: Option<bool>,
+    /// Only files whose path contains this text
+    pub name: Option<String>,
+    /// Only recordings of this device, matched by file name or the recording metadata
+    pub device: Option<Uuid>,
 }
 
 #[api_v2_operation(tags("Recordings Server"))]
 #[get("/recordings/list")]
-async fn list_mcap_recordings(req: web::HttpRequest) -> Result<Json<Vec<McapFileInfo>>, Error> {
-    let recordings_dir = Path::new("recordings");
+async fn list_mcap_recordings(
+    req: web::HttpRequest,
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
+    let recordings_dir = recording_tx.base_path();
     debug!("Listing MCAP files in directory: {:?}", recordings_dir);
 
     let show_detailed_listing = req
@@ -42,70 +127,94 @@ async fn list_mcap_recordings(req: web::HttpRequest) -> Result<Json<Vec<McapFile
         .map(|v| v == "?1")
         .unwrap_or(false);
 
-    let mut files = Vec::new();
-
     if !recordings_dir.exists() {
         debug!("Creating recordings directory: {:?}", recordings_dir);
         if let Err(e) = fs::create_dir_all(recordings_dir) {
             debug!("Failed to create recordings directory: {:?}", e);
-            return Ok(Json(files));
+            return list_response(Vec::new(), 0);
         }
     }
 
-    match fs::read_dir(recordings_dir) {
-        Ok(entries) => {
-            for entry in entries {
-                match entry {
-                    Ok(entry) => {
-                        let path = entry.path();
-                        debug!("Found entry: {:?}", path);
-
-                        // Filter for .mcap files or show all files if detailed listing is requested
-                        let is_mcap = path.extension().map_or(false, |ext| ext == "mcap");
-                        let should_include = is_mcap || (show_detailed_listing && path.is_file());
-
-                        if should_include {
-                            match entry.metadata() {
-                                Ok(metadata) => {
-                                    let modified = metadata
-                                        .modified()
-                                        .ok()
-                                        .and_then(|mtime| {
-                                            DateTime::<Utc>::from(mtime).to_rfc3339().into()
-                                        })
-                                        .unwrap_or_else(|| "unknown".to_string());
-
-                                    debug!("Adding file: {:?}", path.file_name());
-                                    files.push(McapFileInfo {
-                                        file_name: path
-                                            .file_name()
-                                            .unwrap()
-                                            .to_string_lossy()
-                                            .to_string(),
-                                        file_size: metadata.len(),
-                                        modified,
-                                    });
-                                }
-                                Err(e) => debug!("Failed to get metadata for {:?}: {:?}", path, e),
-                            }
-                        }
-                    }
-                    Err(e) => debug!("Failed to read entry: {:?}", e),
-                }
-            }
+    let (files, total) = list_recordings(recordings_dir, &query, show_detailed_listing);
+    list_response(files, total)
+}
+
+// The requested page of the matching files, with the count of all of them
+fn list_recordings(
+    recordings_dir: &Path,
+    query: &ListQuery,
+    show_detailed_listing: bool,
+) -> (Vec<McapFileInfo>, usize) {
+    let mut files = Vec::new();
+
+    // Recordings organized by date live in nested directories, listed by their relative path
+    for path in crate::device::recording::recording_files(recordings_dir) {
+        debug!("Found entry: {:?}", path);
+
+        // Filter for .mcap files or show all files if detailed listing is requested
+        let is_mcap = path.extension().map_or(false, |ext| ext == "mcap");
+        if !(is_mcap || show_detailed_listing) {
+            continue;
         }
-        Err(e) => {
-            debug!("Failed to read recordings directory: {:?}", e);
-            // Try to create the directory if it doesn't exist
-            if recordings_dir.parent().is_some() {
-                let _ = fs::create_dir_all(recordings_dir);
+
+        let Some(file_name) = relative_file_name(recordings_dir, &path) else {
+            continue;
+        };
+        if query
+            .name
+            .as_ref()
+            .is_some_and(|name| !file_name.to_lowercase().contains(&name.to_lowercase()))
+        {
+            continue;
+        }
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                debug!("Failed to get metadata for {:?}: {:?}", path, e);
+                continue;
+            }
+        };
+        if let Some(device) = query.device {
+            // Named and group sessions do not carry the id in their file name
+            if !file_name.contains(&device.to_string()) {
+                let devices = match metadata.modified() {
+                    Ok(modified) => info::cached_device_ids(&path, modified),
+                    Err(_) => info::device_ids(&path),
+                };
+                if !devices.contains(&device) {
+                    continue;
+                }
             }
         }
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| DateTime::<Utc>::from(mtime).to_rfc3339().into())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        debug!("Adding file: {:?}", file_name);
+        files.push(McapFileInfo {
+            file_name,
+            file_size: metadata.len(),
+            modified,
+        });
     }
 
     // Sort files by modification time (newest first) when detailed listing is enabled
-    if show_detailed_listing {
-        files.sort_by(|a, b| b.modified.cmp(&a.modified));
+    let sort = query
+        .sort
+        .or(show_detailed_listing.then_some(ListSort::Mtime));
+    let descending = query.descending.unwrap_or(query.sort.is_none());
+    match sort {
+        Some(ListSort::Name) => files.sort_by(|a, b| a.file_name.cmp(&b.file_name)),
+        Some(ListSort::Size) => files.sort_by_key(|file| file.file_size),
+        Some(ListSort::Mtime) => files.sort_by(|a, b| a.modified.cmp(&b.modified)),
+        None => {}
+    }
+    if sort.is_some() && descending {
+        files.reverse();
     }
 
     debug!(
@@ -113,7 +222,43 @@ async fn list_mcap_recordings(req: web::HttpRequest) -> Result<Json<Vec<McapFile
         files.len(),
         !show_detailed_listing
     );
-    Ok(Json(files))
+    let total = files.len();
+    if let Some(limit) = query.limit {
+        let page = query.page.unwrap_or(1).max(1);
+        files = files
+            .into_iter()
+            .skip((page - 1).saturating_mul(limit))
+            .take(limit)
+            .collect();
+    }
+    (files, total)
+}
+
+fn list_response(files: Vec<McapFileInfo>, total: usize) -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header(("X-Total-Count", total.to_string()))
+        .json(files)
+}
+
+// Forward slash separated path below the recordings directory, as used by the file routes
+fn relative_file_name(base: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+    let segments: Option<Vec<&str>> = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => segment.to_str(),
+            _ => None,
+        })
+        .collect();
+    Some(segments?.join("/"))
+}
+
+// Only plain segments are accepted, absolute paths and `..` never reach the filesystem
+fn is_plain_relative(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && Path::new(file_name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
 }
 
 // Helper function to securely resolve a file path
@@ -121,6 +266,9 @@ fn secure_file_path(
     base: &Path,
     file_name: &str,
 ) -> Result<std::path::PathBuf, actix_web::HttpResponse> {
+    if !is_plain_relative(file_name) {
+        return Err(actix_web::HttpResponse::Forbidden().body("Access denied"));
+    }
     let file_path = base.join(file_name);
     let canonical_base = base.canonicalize().map_err(|_| {
         actix_web::HttpResponse::InternalServerError().body("Invalid recordings directory")
@@ -135,68 +283,225 @@ fn secure_file_path(
 }
 
 #[api_v2_operation(tags("Recordings Server"))]
-#[get("/recordings/download/{file_name}")]
+#[get("/recordings/download/{file_name:.*}")]
 async fn download_mcap_file(
+    recording_tx: web::Data<RecordingsManagerHandler>,
     file_name: web::Path<String>,
     req: web::HttpRequest,
     query: web::Query<std::collections::HashMap<String, String>>,
-) -> impl Responder {
-    let recordings_dir = Path::new("recordings");
+) -> Result<HttpResponse, Error> {
+    let recordings_dir = recording_tx.base_path();
     let canonical_file = match secure_file_path(recordings_dir, &*file_name) {
         Ok(path) => path,
-        Err(resp) => return resp,
+        Err(resp) => return Ok(resp),
     };
 
-    if canonical_file.exists() && canonical_file.is_file() {
-        match fs::read(&canonical_file) {
-            Ok(data) => {
-                let mime = from_path(&canonical_file).first_or_octet_stream();
-                let content_type = mime.as_ref();
-
-                let is_inline = req
-                    .headers()
-                    .get("prefer-inline")
-                    .and_then(|h| h.to_str().ok())
-                    .map(|v| v == "true")
-                    .unwrap_or(false)
-                    || query.get("inline").is_some();
-
-                let disposition = if is_inline {
-                    format!("inline; filename=\"{}\"", file_name)
-                } else {
-                    format!("attachment; filename=\"{}\"", file_name)
-                };
+    if !(canonical_file.exists() && canonical_file.is_file()) {
+        debug!("File not found or not a regular file: {:?}", canonical_file);
+        return Ok(HttpResponse::NotFound().body("File not found"));
+    }
+
+    let mime = from_path(&canonical_file).first_or_octet_stream();
+    let content_type = mime.as_ref();
+
+    let is_inline = req
+        .headers()
+        .get("prefer-inline")
+        .and_then(|h| h.to_str().ok())
+        .map(|v| v == "true")
+        .unwrap_or(false)
+        || query.get("inline").is_some();
+
+    let download_name = canonical_file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Recordings still being written are streamed with chunked encoding until the session ends
+    if is_recording(&recording_tx, &canonical_file).await {
+        let disposition = if is_inline {
+            format!("inline; filename=\"{}\"", download_name)
+        } else {
+            format!("attachment; filename=\"{}\"", download_name)
+        };
+        let mut response = HttpResponse::Ok();
+        response
+            .content_type(content_type)
+            .append_header(("Content-Disposition", disposition))
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"));
+
+        debug!("Streaming in-progress recording: {:?}", canonical_file);
+        return Ok(match tokio::fs::File::open(&canonical_file).await {
+            Ok(file) => {
+                let recording_tx = recording_tx.get_ref().clone();
+                let path = canonical_file.clone();
+                response.streaming(follow_recording(file, move || {
+                    let recording_tx = recording_tx.clone();
+                    let path = path.clone();
+                    async move { is_recording(&recording_tx, &path).await }
+                }))
+            }
+            Err(e) => {
+                debug!("Failed to open file {:?}: {:?}", canonical_file, e);
+                HttpResponse::InternalServerError().body("Failed to read file")
+            }
+        });
+    }
 
+    // Finished files are streamed from disk, with Range and If-Range so downloads can resume
+    Ok(
+        match actix_files::NamedFile::open_async(&canonical_file).await {
+            Ok(file) => {
                 debug!(
-                    "Serving file: {:?} (size: {} bytes, type: {})",
-                    canonical_file,
-                    data.len(),
-                    content_type
+                    "Serving file: {:?} (type: {})",
+                    canonical_file, content_type
                 );
-
-                HttpResponse::Ok()
-                    .content_type(content_type)
-                    .append_header(("Content-Disposition", disposition))
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .body(data)
+                file.set_content_type(mime.clone())
+                    .set_content_disposition(ContentDisposition {
+                        disposition: if is_inline {
+                            DispositionType::Inline
+                        } else {
+                            DispositionType::Attachment
+                        },
+                        parameters: vec![DispositionParam::Filename(download_name)],
+                    })
+                    .into_response(&req)
             }
             Err(e) => {
                 debug!("Failed to read file {:?}: {:?}", canonical_file, e);
                 HttpResponse::InternalServerError().body("Failed to read file")
             }
+        },
+    )
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct ArchiveRequest {
+    /// Recording file names relative to the recordings directory
+    pub files: Vec<String>,
+    /// Name of the downloaded zip, without extension
+    pub name: Option<String>,
+}
+
+#[api_v2_operation(tags("Recordings Server"))]
+#[post("/recordings/download_archive")]
+async fn download_archive(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    json: web::Json<ArchiveRequest>,
+) -> Result<HttpResponse, Error> {
+    let recordings_dir = recording_tx.base_path();
+    let request = json.into_inner();
+    if request.files.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("No files requested"));
+    }
+
+    let mut entries: Vec<archive::ArchiveEntry> = Vec::new();
+    for file_name in request.files {
+        let canonical_file = match secure_file_path(recordings_dir, &file_name) {
+            Ok(path) => path,
+            Err(resp) => return Ok(resp),
+        };
+        if entries.iter().any(|entry| entry.path == canonical_file) {
+            continue;
         }
-    } else {
-        debug!("File not found or not a regular file: {:?}", canonical_file);
-        HttpResponse::NotFound().body("File not found")
+        // Sizes are fixed in the zip, files still growing cannot be part of it
+        if is_recording(&recording_tx, &canonical_file).await {
+            return Ok(
+                HttpResponse::Conflict().body(format!("{file_name} is still being recorded"))
+            );
+        }
+        let metadata = match fs::metadata(&canonical_file) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Ok(HttpResponse::NotFound().body(format!("{file_name} not found"))),
+        };
+        entries.push(archive::ArchiveEntry {
+            name: file_name.trim_start_matches('/').to_string(),
+            path: canonical_file,
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| Utc::now()),
+        });
     }
+
+    let name = request
+        .name
+        .as_deref()
+        .and_then(crate::device::recording::sanitize_session_name)
+        .unwrap_or_else(|| format!("recordings_{}", Utc::now().format("%Y%m%d_%H%M%S")));
+    debug!("Streaming archive {name}.zip of {} files", entries.len());
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{name}.zip\""),
+        ))
+        .streaming(archive::stream(entries)))
+}
+
+async fn is_recording(recording_tx: &RecordingsManagerHandler, canonical_file: &Path) -> bool {
+    match recording_tx
+        .send(RecordingManagerCommand::GetAllRecordingStatus)
+        .await
+    {
+        Ok(crate::device::recording::Answer::AllRecordingStatus(sessions)) => {
+            sessions.iter().any(|session| {
+                session.is_active
+                    && session
+                        .file_path
+                        .canonicalize()
+                        .is_ok_and(|path| path == canonical_file)
+            })
+        }
+        _ => false,
+    }
+}
+
+// Yield what was flushed so far, then poll for more until the session stops writing the file
+fn follow_recording<F, R>(
+    file: tokio::fs::File,
+    is_recording: F,
+) -> impl futures::Stream<Item = Result<web::Bytes, std::io::Error>>
+where
+    F: Fn() -> R,
+    R: std::future::Future<Output = bool>,
+{
+    futures::stream::unfold(
+        (file, false, is_recording),
+        |(mut file, mut finished, is_recording)| async move {
+            let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+            loop {
+                match file.read(&mut buffer).await {
+                    Ok(0) if finished => return None,
+                    Ok(0) => {
+                        tokio::time::sleep(DOWNLOAD_POLL_PERIOD).await;
+                        // Read once more for the footer written when the session closed the file
+                        finished = !is_recording().await;
+                    }
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        return Some((
+                            Ok(web::Bytes::from(buffer)),
+                            (file, finished, is_recording),
+                        ));
+                    }
+                    Err(err) => return Some((Err(err), (file, true, is_recording))),
+                }
+            }
+        },
+    )
 }
 
 #[api_v2_operation(tags("Recordings Server"))]
-#[delete("/recordings/delete/{file_name}")]
-async fn delete_mcap_file(file_name: web::Path<String>) -> impl Responder {
-    let recordings_dir = Path::new("recordings");
+#[delete("/recordings/delete/{file_name:.*}")]
+async fn delete_mcap_file(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+) -> impl Responder {
+    let recordings_dir = recording_tx.base_path();
     let canonical_file = match secure_file_path(recordings_dir, &*file_name) {
         Ok(path) => path,
         Err(resp) => return resp,
@@ -206,6 +511,12 @@ async fn delete_mcap_file(file_name: web::Path<String>) -> impl Responder {
         match fs::remove_file(&canonical_file) {
             Ok(_) => {
                 debug!("Deleted file: {:?}", canonical_file);
+                if let Ok(canonical_base) = recordings_dir.canonicalize() {
+                    crate::device::recording::remove_empty_parents(
+                        &canonical_base,
+                        &canonical_file,
+                    );
+                }
                 HttpResponse::Ok().body("File deleted")
             }
             Err(e) => {
@@ -219,6 +530,354 @@ async fn delete_mcap_file(file_name: web::Path<String>) -> impl Responder {
     }
 }
 
+#[api_v2_operation(tags("Recordings Server"))]
+#[get("/recordings/info/{file_name:.*}")]
+async fn recording_info(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let recordings_dir = recording_tx.base_path();
+    let canonical_file = match secure_file_path(recordings_dir, &file_name) {
+        Ok(path) => path,
+        Err(resp) => return Ok(resp),
+    };
+
+    let result = tokio::task::spawn_blocking(move || info::read_info(&canonical_file)).await;
+    Ok(match result {
+        Ok(Ok(info)) => HttpResponse::Ok().json(info),
+        Ok(Err(err)) => {
+            debug!("Failed to read info of {file_name}: {err:?}");
+            HttpResponse::BadRequest().body(format!("Failed to read recording: {err:?}"))
+        }
+        Err(err) => {
+            debug!("Info task failed for {file_name}: {err:?}");
+            HttpResponse::InternalServerError().body("Failed to read recording")
+        }
+    })
+}
+
+#[api_v2_operation(tags("Recordings Server"))]
+#[get("/recordings/report/{file_name:.*}")]
+async fn survey_report(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+    options: web::Query<ReportOptions>,
+    background: web::Query<BackgroundQuery>,
+) -> Result<HttpResponse, Error> {
+    let recordings_dir = recording_tx.base_path();
+    let canonical_file = match secure_file_path(recordings_dir, &file_name) {
+        Ok(path) => path,
+        Err(resp) => return Ok(resp),
+    };
+    let options = options.into_inner();
+    let format = options.format;
+
+    if background.background.unwrap_or(false) {
+        let stem = file_stem(&canonical_file);
+        let job = JOBS.submit_detached(JobKind::SurveyReport, &file_name, move |progress| {
+            let report = report::generate_with_progress(&canonical_file, &options, |fraction| {
+                progress.set(fraction)
+            })?;
+            Ok(match format {
+                ReportFormat::Html => JobArtifact {
+                    file_name: format!("{stem}_report.html"),
+                    content_type: "text/html; charset=utf-8".to_string(),
+                    data: report::render_html(&report).into_bytes(),
+                },
+                ReportFormat::Json => JobArtifact {
+                    file_name: format!("{stem}_report.json"),
+                    content_type: "application/json".to_string(),
+                    data: serde_json::to_vec(&report).map_err(|err| {
+                        crate::device::manager::ManagerError::Other(format!(
+                            "Failed to serialize report: {err}"
+                        ))
+                    })?,
+                },
+            })
+        });
+        return Ok(accepted_job(job));
+    }
+
+    // Reports scan the whole recording, run them on the job pool so progress shows up on ws/jobs
+    let (job_id, result) = JOBS.submit(JobKind::SurveyReport, &file_name, move |progress| {
+        report::generate_with_progress(&canonical_file, &options, |fraction| progress.set(fraction))
+    });
+    let report = match result.await {
+        Ok(Ok(report)) => report,
+        Ok(Err(err)) => {
+            debug!("Failed to generate report for {file_name}: {err:?}");
+            return Ok(
+                HttpResponse::BadRequest().body(format!("Failed to generate report: {err:?}"))
+            );
+        }
+        Err(err) => {
+            debug!("Report job {job_id} failed for {file_name}: {err:?}");
+            return Ok(HttpResponse::InternalServerError().body("Failed to generate report"));
+        }
+    };
+
+    let mut response = HttpResponse::Ok();
+    response.append_header(("X-Job-Id", job_id.to_string()));
+    Ok(match format {
+        ReportFormat::Html => response
+            .content_type("text/html; charset=utf-8")
+            .body(report::render_html(&report)),
+        ReportFormat::Json => response.json(report),
+    })
+}
+
+#[api_v2_operation(tags("Recordings Server"))]
+#[post("/recordings/export/{file_name:.*}")]
+async fn export_recording(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+    options: web::Query<ExportOptions>,
+    background: web::Query<BackgroundQuery>,
+) -> Result<HttpResponse, Error> {
+    let recordings_dir = recording_tx.base_path();
+    let canonical_file = match secure_file_path(recordings_dir, &file_name) {
+        Ok(path) => path,
+        Err(resp) => return Ok(resp),
+    };
+    let options = options.into_inner();
+    let format = options.format;
+
+    if background.background.unwrap_or(false) {
+        let job = JOBS.submit_detached(JobKind::Export, &file_name, move |progress| {
+            let export = export::export_with_progress(&canonical_file, &options, |fraction| {
+                progress.set(fraction)
+            })?;
+            Ok(JobArtifact {
+                file_name: export.file_name,
+                content_type: format.content_type().to_string(),
+                data: export.data,
+            })
+        });
+        return Ok(accepted_job(job));
+    }
+
+    let (job_id, result) = JOBS.submit(JobKind::Export, &file_name, move |progress| {
+        export::export_with_progress(&canonical_file, &options, |fraction| progress.set(fraction))
+    });
+    let export = match result.await {
+        Ok(Ok(export)) => export,
+        Ok(Err(err)) => {
+            debug!("Failed to export {file_name}: {err:?}");
+            return Ok(
+                HttpResponse::BadRequest().body(format!("Failed to export recording: {err:?}"))
+            );
+        }
+        Err(err) => {
+            debug!("Export job {job_id} failed for {file_name}: {err:?}");
+            return Ok(HttpResponse::InternalServerError().body("Failed to export recording"));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .append_header(("X-Job-Id", job_id.to_string()))
+        .append_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", export.file_name),
+        ))
+        .body(export.data))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn accepted_job(job: Job) -> HttpResponse {
+    HttpResponse::Accepted()
+        .append_header(("X-Job-Id", job.id.to_string()))
+        .json(job)
+}
+
+#[api_v2_operation(tags("Recordings Server"))]
+#[post("/recordings/import/{file_name:.*}")]
+async fn import_legacy_log(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+) -> impl Responder {
+    let recordings_dir = recording_tx.base_path();
+    let canonical_file = match secure_file_path(recordings_dir, &file_name) {
+        Ok(path) => path,
+        Err(resp) => return resp,
+    };
+    let Some(directory) = canonical_file.parent().map(Path::to_path_buf) else {
+        return HttpResponse::BadRequest().body("Invalid file name");
+    };
+
+    // Written next to the log, listed with the other recordings once done
+    let job = JOBS.submit_detached(JobKind::Import, &file_name, move |progress| {
+        let report = legacy::import(&canonical_file, &directory, |fraction| {
+            progress.set(fraction)
+        })?;
+        Ok(JobArtifact {
+            file_name: format!("{}.json", file_stem(Path::new(&report.file_name))),
+            content_type: "application/json".to_string(),
+            data: serde_json::to_vec(&report).unwrap_or_default(),
+        })
+    });
+    accepted_job(job)
+}
+
+#[api_v2_operation(tags("Recordings Jobs"))]
+#[get("/recordings/jobs")]
+async fn list_jobs() -> Result<Json<Vec<Job>>, Error> {
+    Ok(Json(JOBS.list()))
+}
+
+#[api_v2_operation(tags("Recordings Jobs"))]
+#[get("/recordings/jobs/{id}")]
+async fn get_job(id: web::Path<Uuid>) -> impl Responder {
+    match JOBS.get(id.into_inner()) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => HttpResponse::NotFound().body("Job not found"),
+    }
+}
+
+#[api_v2_operation(tags("Recordings Jobs"))]
+#[get("/recordings/jobs/{id}/result")]
+async fn get_job_result(id: web::Path<Uuid>, req: web::HttpRequest) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+    let Some(job) = JOBS.get(id) else {
+        return Ok(HttpResponse::NotFound().body("Job not found"));
+    };
+    if !job.status.is_finished() {
+        return Ok(HttpResponse::Conflict().json(job));
+    }
+    // Failed jobs and the ones started by a waiting request have no stored output
+    let Some(artifact) = JOBS.artifact(id) else {
+        return Ok(HttpResponse::NotFound().json(job));
+    };
+    Ok(
+        match actix_files::NamedFile::open_async(&artifact.path).await {
+            Ok(file) => file
+                .set_content_type(
+                    artifact
+                        .content_type
+                        .parse()
+                        .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM),
+                )
+                .set_content_disposition(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(artifact.file_name)],
+                })
+                .into_response(&req),
+            Err(err) => {
+                debug!("Failed to read job output {:?}: {err:?}", artifact.path);
+                HttpResponse::InternalServerError().body("Failed to read job output")
+            }
+        },
+    )
+}
+
+#[cfg(feature = "upload")]
+#[api_v2_operation(tags("Recordings Server"))]
+#[post("/recordings/upload")]
+async fn upload_recordings(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    json: web::Json<crate::device::recording::upload::UploadRequest>,
+) -> impl Responder {
+    use crate::device::recording::upload;
+
+    if !upload::is_configured() {
+        return HttpResponse::ServiceUnavailable().body("No upload target is configured");
+    }
+
+    let recordings_dir = recording_tx.base_path();
+    let mut files = Vec::new();
+    for file_name in &json.files {
+        let canonical_file = match secure_file_path(recordings_dir, file_name) {
+            Ok(path) => path,
+            Err(resp) => return resp,
+        };
+        // The footer is missing until the session stops, the remote copy would be unreadable
+        if is_recording(&recording_tx, &canonical_file).await {
+            return HttpResponse::Conflict().body(format!("{file_name} is still being recorded"));
+        }
+        files.push((canonical_file, file_name.clone()));
+    }
+
+    let mut jobs = Vec::new();
+    for (canonical_file, file_name) in files {
+        match upload::upload(canonical_file, file_name) {
+            Ok(job) => jobs.push(job),
+            Err(err) => {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Failed to queue upload: {err:?}"))
+            }
+        }
+    }
+    HttpResponse::Accepted().json(jobs)
+}
+
+#[api_v2_operation(tags("Recordings Replay"))]
+#[post("/recordings/replay/{file_name:.*}")]
+async fn start_replay(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    file_name: web::Path<String>,
+    query: web::Query<ReplayQuery>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let query = query.into_inner();
+    let request = RecordingManagerCommand::StartReplay(ReplayOptions {
+        file_name: file_name.into_inner(),
+        speed: query.speed.unwrap_or(1.0),
+        looped: query.looped.unwrap_or(false),
+    });
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Replay"))]
+#[get("/recordings/replay/list")]
+async fn list_replays(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let answer = recording_tx
+        .send(RecordingManagerCommand::ListReplays)
+        .await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Replay"))]
+#[post("/recordings/replay/control")]
+async fn control_replay(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    json: web::Json<ReplayControl>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = RecordingManagerCommand::ControlReplay(json.into_inner());
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Replay"))]
+#[delete("/recordings/replay/{device}")]
+async fn stop_replay(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    device: web::Path<Uuid>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = RecordingManagerCommand::StopReplay(UuidWrapper {
+        uuid: device.into_inner(),
+    });
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Server"))]
+#[get("/recordings/usage")]
+async fn recordings_disk_usage(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let answer = recording_tx
+        .send(RecordingManagerCommand::GetDiskUsage)
+        .await?;
+    Ok(Json(answer))
+}
+
 #[api_v2_operation(tags("Recordings Manager"))]
 #[get("recordings_manager/list")]
 async fn recording_manager_get(
@@ -229,11 +888,67 @@ async fn recording_manager_get(
     Ok(Json(answer))
 }
 
+#[api_v2_operation(tags("Recordings Manager"))]
+#[post("recordings_manager/all/{selection}")]
+async fn recording_manager_post_all(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    selection: web::Path<RecordingsManagerAllOptionsV1>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = match selection.into_inner() {
+        RecordingsManagerAllOptionsV1::StartRecording => RecordingManagerCommand::StartRecordingAll,
+        RecordingsManagerAllOptionsV1::StopRecording => RecordingManagerCommand::StopRecordingAll,
+    };
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Manager"))]
+#[post("recordings_manager/group")]
+async fn recording_manager_post_group(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    json: web::Json<GroupRecordingOptions>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = RecordingManagerCommand::StartRecordingGroup(json.into_inner());
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Manager : Device"))]
+#[post("recordings_manager/{device}/mounting")]
+async fn recording_manager_mounting(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    device: web::Path<Uuid>,
+    json: web::Json<Option<MountingPose>>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = RecordingManagerCommand::SetMounting(MountingRequest {
+        device_id: device.into_inner(),
+        pose: json.into_inner(),
+    });
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
+#[api_v2_operation(tags("Recordings Manager : Device"))]
+#[post("recordings_manager/{device}/annotate")]
+async fn recording_manager_annotate(
+    recording_tx: web::Data<RecordingsManagerHandler>,
+    device: web::Path<Uuid>,
+    json: web::Json<AnnotationText>,
+) -> Result<Json<crate::device::recording::Answer>, Error> {
+    let request = RecordingManagerCommand::Annotate(AnnotationRequest {
+        device_id: device.into_inner(),
+        text: json.into_inner().text,
+    });
+    let answer = recording_tx.send(request).await?;
+    Ok(Json(answer))
+}
+
 #[api_v2_operation(tags("Recordings Manager : Device"))]
 #[post("recordings_manager/{device}/{selection}")]
 async fn recording_manager_post(
     recording_tx: web::Data<RecordingsManagerHandler>,
     info: web::Path<(Uuid, RecordingsManagerPostOptionsV1)>,
+    query: web::Query<RecordingQuery>,
 ) -> Result<Json<crate::device::recording::Answer>, Error> {
     let info = info.into_inner();
     let uuid = info.0;
@@ -241,7 +956,20 @@ async fn recording_manager_post(
 
     let request: RecordingManagerCommand = match request {
         RecordingsManagerPostOptionsV1::StartRecording => {
-            RecordingManagerCommand::StartRecording(UuidWrapper { uuid })
+            RecordingManagerCommand::StartRecording(StartRecordingOptions {
+                uuid,
+                raw_frames: query.raw_frames.unwrap_or(false),
+                name: query.name.clone(),
+                format: query.format.unwrap_or_default(),
+                mavlink_topics: query.mavlink.as_deref().map(|topics| {
+                    topics
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|topic| !topic.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }),
+            })
         }
         RecordingsManagerPostOptionsV1::StopRecording => {
             RecordingManagerCommand::StopRecording(UuidWrapper { uuid })
@@ -249,6 +977,9 @@ async fn recording_manager_post(
         RecordingsManagerPostOptionsV1::GetRecordingStatus => {
             RecordingManagerCommand::GetRecordingStatus(UuidWrapper { uuid })
         }
+        RecordingsManagerPostOptionsV1::Bookmark => {
+            RecordingManagerCommand::Bookmark(UuidWrapper { uuid })
+        }
     };
 
     let answer = recording_tx.send(request).await?;
@@ -265,3 +996,195 @@ async fn recordings_manager_post_request(
     let answer = manager_handler.send(request).await?;
     Ok(Json(answer))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_file_names_stay_inside_recordings() {
+        assert!(is_plain_relative("device_1.mcap"));
+        assert!(is_plain_relative("2025/01/31/device_1.mcap"));
+        assert!(!is_plain_relative("../secret.mcap"));
+        assert!(!is_plain_relative("2025/../../secret.mcap"));
+        assert!(!is_plain_relative("/etc/passwd"));
+        assert!(!is_plain_relative(""));
+    }
+
+    #[test]
+    fn test_relative_file_name_uses_forward_slashes() {
+        let base = Path::new("recordings");
+        assert_eq!(
+            relative_file_name(base, &base.join("2025").join("01").join("a.mcap")).as_deref(),
+            Some("2025/01/a.mcap")
+        );
+        assert_eq!(relative_file_name(base, Path::new("other/a.mcap")), None);
+    }
+
+    fn write_recording(path: &Path, device: Option<Uuid>) {
+        let mut writer =
+            mcap::Writer::new(std::io::BufWriter::new(fs::File::create(path).unwrap())).unwrap();
+        if let Some(device) = device {
+            writer
+                .write_metadata(&mcap::records::Metadata {
+                    name: format!("device_{device}"),
+                    metadata: std::collections::BTreeMap::from([(
+                        "id".to_string(),
+                        device.to_string(),
+                    )]),
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_followed_recording_is_read_back() {
+        use crate::device::recording::{writer::McapFileWriter, RecordingFormat};
+        use futures::StreamExt;
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let path = std::env::temp_dir().join(format!("ping-viewer-{}.mcap", Uuid::new_v4()));
+        let ctx = foxglove::Context::new();
+        let writer =
+            McapFileWriter::create(&ctx, &path, RecordingFormat::Mcap.write_options()).unwrap();
+        let channel = ctx
+            .channel_builder("/test")
+            .message_encoding("json")
+            .build_raw()
+            .unwrap();
+        // Random payloads, enough to leave part of an open chunk on disk if the writer seeks
+        let log = |count: usize| {
+            for _ in 0..count {
+                channel.log(
+                    format!(r#"{{"a":"{}","b":"{}"}}"#, Uuid::new_v4(), Uuid::new_v4()).as_bytes(),
+                );
+            }
+        };
+        log(2000);
+
+        let recording = Arc::new(AtomicBool::new(true));
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let active = recording.clone();
+        let stream = follow_recording(file, move || {
+            let active = active.clone();
+            async move { active.load(Ordering::SeqCst) }
+        });
+        let download = tokio::spawn(stream.collect::<Vec<_>>());
+
+        tokio::time::sleep(DOWNLOAD_POLL_PERIOD / 2).await;
+        log(2000);
+        tokio::time::sleep(DOWNLOAD_POLL_PERIOD * 2).await;
+        assert!(!download.is_finished());
+
+        // The summary and footer are written as the session stops
+        writer.close().unwrap();
+        recording.store(false, Ordering::SeqCst);
+        let chunks = tokio::time::timeout(DOWNLOAD_POLL_PERIOD * 4, download)
+            .await
+            .unwrap()
+            .unwrap();
+        let data: Vec<u8> = chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap().to_vec())
+            .collect();
+        assert_eq!(data, fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        let messages = mcap::MessageStream::new(&data)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(messages.len(), 4000);
+    }
+
+    #[test]
+    fn test_device_filter_is_paginated_with_total_count() {
+        let directory = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let device = Uuid::new_v4();
+        write_recording(&directory.join(format!("device_{device}_1.mcap")), None);
+        write_recording(&directory.join("survey-a.mcap"), Some(device));
+        write_recording(&directory.join("group_2.mcap"), Some(device));
+        write_recording(&directory.join("survey-b.mcap"), Some(Uuid::new_v4()));
+        write_recording(&directory.join("survey-c.mcap"), None);
+
+        let query = |page| ListQuery {
+            page: Some(page),
+            limit: Some(2),
+            sort: Some(ListSort::Name),
+            descending: Some(false),
+            name: None,
+            device: Some(device),
+        };
+        let (first, total) = list_recordings(&directory, &query(1), false);
+        assert_eq!(total, 3);
+        let names: Vec<&str> = first.iter().map(|file| file.file_name.as_str()).collect();
+        assert_eq!(
+            names,
+            [format!("device_{device}_1.mcap").as_str(), "group_2.mcap"]
+        );
+
+        // Served from the cache the second time
+        let (second, total) = list_recordings(&directory, &query(2), false);
+        assert_eq!(total, 3);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].file_name, "survey-a.mcap");
+
+        let response = list_response(second, total);
+        assert_eq!(response.headers().get("X-Total-Count").unwrap(), "3");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_finished_recording_download_resumes_from_range() {
+        use actix_web::{http::StatusCode, test};
+
+        let base_path = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        fs::create_dir_all(&base_path).unwrap();
+        let content: Vec<u8> = (0..100).collect();
+        fs::write(base_path.join("dive.mcap"), &content).unwrap();
+
+        let (device_sender, _device_receiver) = tokio::sync::mpsc::channel(1);
+        let (recordings_manager, recordings) = crate::device::recording::RecordingManager::new(
+            1,
+            &base_path,
+            crate::device::manager::ManagerActorHandler {
+                sender: device_sender,
+            },
+        );
+        tokio::spawn(recordings_manager.run());
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(recordings))
+                .service(download_mcap_file),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/recordings/download/dive.mcap")
+            .insert_header(("Range", "bytes=10-19"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get("Content-Range").unwrap(),
+            "bytes 10-19/100"
+        );
+        assert_eq!(test::read_body(response).await, content[10..20]);
+
+        // A file changed since the first part restarts from the beginning
+        let request = test::TestRequest::get()
+            .uri("/recordings/download/dive.mcap")
+            .insert_header(("Range", "bytes=10-19"))
+            .insert_header(("If-Range", "\"stale\""))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, content);
+
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+
+use crate::device::{manager, recording};
+use crate::server::protocols::{v1::errors::Error, v1::websocket, v2::envelope::RequestEnvelope};
+use paperclip::{
+    actix::{
+        api_v2_operation, get,
+        web::{self, HttpResponse},
+    },
+    v2::schema::Apiv2Schema,
+};
+use serde_json::{json, Map, Value};
+
+const DRAFT: &str = "http://json-schema.org/draft-04/schema#";
+const DEFINITIONS: &str = "#/definitions/";
+
+/// JSON Schema of the messages exchanged with the server, built from the same types as the OpenAPI spec
+#[derive(Default)]
+struct SchemaCatalog {
+    definitions: Map<String, Value>,
+    /// Catalog name and what it is used for
+    messages: BTreeMap<String, &'static str>,
+}
+
+impl SchemaCatalog {
+    fn add<T: Apiv2Schema>(&mut self, name: &str, usage: &'static str) {
+        let schema = serde_json::to_value(T::raw_schema()).unwrap_or_default();
+        self.add_value(name, usage, schema);
+    }
+
+    fn add_value(&mut self, name: &str, usage: &'static str, mut schema: Value) {
+        self.hoist_definitions(&mut schema);
+        strip_schema_fields(&mut schema);
+        self.definitions.insert(name.to_string(), schema);
+        self.messages.insert(name.to_string(), usage);
+    }
+
+    // Referenced types are inlined next to their `$ref`, move them into the shared definitions
+    fn hoist_definitions(&mut self, schema: &mut Value) {
+        match schema {
+            Value::Object(fields) => {
+                let reference = fields
+                    .get("$ref")
+                    .and_then(Value::as_str)
+                    .and_then(|reference| reference.strip_prefix(DEFINITIONS))
+                    .map(str::to_string);
+                if let Some(name) = reference.filter(|_| fields.len() > 1) {
+                    let mut definition = Value::Object(std::mem::take(fields));
+                    fields.insert("$ref".to_string(), json!(format!("{DEFINITIONS}{name}")));
+                    if let Some(definition_fields) = definition.as_object_mut() {
+                        definition_fields.remove("$ref");
+                    }
+                    if !self.definitions.contains_key(&name) {
+                        self.hoist_definitions(&mut definition);
+                        strip_schema_fields(&mut definition);
+                        self.definitions.insert(name, definition);
+                    }
+                    return;
+                }
+                for value in fields.values_mut() {
+                    self.hoist_definitions(value);
+                }
+            }
+            Value::Array(values) => values
+                .iter_mut()
+                .for_each(|value| self.hoist_definitions(value)),
+            _ => {}
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let messages: Map<String, Value> = self
+            .messages
+            .iter()
+            .map(|(name, usage)| {
+                (
+                    name.clone(),
+                    json!({ "$ref": format!("{DEFINITIONS}{name}"), "description": usage }),
+                )
+            })
+            .collect();
+        json!({
+            "$schema": DRAFT,
+            "messages": messages,
+            "definitions": self.definitions,
+        })
+    }
+
+    /// Standalone schema of one message, the definitions are kept so references resolve
+    fn message(&self, name: &str) -> Option<Value> {
+        self.messages.contains_key(name).then(|| {
+            json!({
+                "$schema": DRAFT,
+                "$ref": format!("{DEFINITIONS}{name}"),
+                "definitions": self.definitions,
+            })
+        })
+    }
+}
+
+// Paperclip keeps the Rust type name on its schemas, it is not a JSON Schema keyword
+fn strip_schema_fields(schema: &mut Value) {
+    if let Some(fields) = schema.as_object_mut() {
+        fields.remove("name");
+    }
+}
+
+fn catalog() -> SchemaCatalog {
+    let mut catalog = SchemaCatalog::default();
+    catalog.add::<manager::Request>(
+        "Request",
+        "Device manager request, body of POST /device_manager/request and ws messages",
+    );
+    catalog.add::<manager::Answer>(
+        "Answer",
+        "Device manager answer, also broadcast to ws clients",
+    );
+    catalog.add::<recording::RecordingManagerCommand>(
+        "RecordingManagerCommand",
+        "Recording manager request, body of POST /recordings_manager/request",
+    );
+    catalog.add::<recording::Answer>("RecordingAnswer", "Recording manager answer");
+    catalog.add::<recording::RecordingSession>(
+        "RecordingSession",
+        "Recording status, sent on ws/recording",
+    );
+    catalog.add::<recording::jobs::Job>("Job", "Background job progress, sent on ws/jobs");
+    catalog.add::<recording::files::FileChange>(
+        "FileChange",
+        "Change in the recordings directory, sent on ws/files",
+    );
+    catalog.add::<websocket::WebsocketControl>(
+        "WebsocketControl",
+        "Subscription change sent by a ws client",
+    );
+    catalog.add::<websocket::WebsocketSubscription>(
+        "WebsocketSubscription",
+        "Reply to a ws control message, wrapped as {\"subscription\": ...}",
+    );
+    catalog.add::<websocket::WebsocketDropped>(
+        "WebsocketDropped",
+        "Messages dropped for a slow ws client, wrapped as {\"dropped\": ...}",
+    );
+    catalog.add::<websocket::WebsocketError>("WebsocketError", "Rejected ws request");
+    catalog.add::<RequestEnvelope>("RequestEnvelope", "Request sent on v2/ws");
+    // The payload is any JSON, its layout is given by `message_type`
+    catalog.add_value(
+        "Envelope",
+        "Every message sent on v2/ws and by the v2 REST routes",
+        json!({
+            "type": "object",
+            "required": ["message_type", "payload"],
+            "properties": {
+                "request_id": { "type": "string" },
+                "device_id": { "type": "string", "format": "uuid" },
+                "message_type": { "type": "string" },
+                "payload": {},
+            },
+        }),
+    );
+    catalog
+}
+
+/// JSON Schema of every request, answer and websocket message, for generating client validators and types
+#[api_v2_operation(tags("Schemas"))]
+#[get("/schemas")]
+async fn schemas_get() -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(catalog().to_json()))
+}
+
+/// Standalone JSON Schema of one message of the catalog, e.g. `Request`
+#[api_v2_operation(tags("Schemas"))]
+#[get("/schemas/{name}")]
+async fn schema_get(name: web::Path<String>) -> Result<HttpResponse, Error> {
+    let name = name.into_inner();
+    catalog()
+        .message(&name)
+        .map(|schema| HttpResponse::Ok().json(schema))
+        .ok_or_else(|| Error::BadRequest(format!("Unknown schema {name:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references_are_hoisted() {
+        let mut catalog = SchemaCatalog::default();
+        catalog.add_value(
+            "Outer",
+            "test",
+            json!({
+                "properties": {
+                    "inner": {
+                        "$ref": "#/definitions/Inner",
+                        "name": "Inner",
+                        "type": "object",
+                        "properties": { "value": { "type": "integer" } },
+                    },
+                },
+            }),
+        );
+
+        let schema = catalog.to_json();
+        assert_eq!(
+            schema["definitions"]["Outer"]["properties"]["inner"],
+            json!({ "$ref": "#/definitions/Inner" })
+        );
+        assert_eq!(schema["definitions"]["Inner"]["type"], "object");
+        assert!(schema["definitions"]["Inner"].get("name").is_none());
+        assert!(catalog.message("Outer").is_some());
+        assert!(catalog.message("Inner").is_none());
+    }
+
+    #[test]
+    fn test_catalog_lists_the_api_messages() {
+        let catalog = catalog();
+        for name in ["Request", "Answer", "RecordingManagerCommand", "Envelope"] {
+            assert!(catalog.messages.contains_key(name), "{name} is missing");
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::cli::settings::{self, ServerSettings, SettingsError, SettingsUpdate};
+use crate::server::protocols::v1::errors::Error;
+use paperclip::actix::{
+    api_v2_operation, get, patch,
+    web::{self, Json},
+};
+use serde_json::Value;
+
+/// Settings changed through the API, unset fields use the command line value. API tokens are redacted
+#[api_v2_operation(tags("Settings"))]
+#[get("/settings")]
+async fn settings_get() -> Result<Json<ServerSettings>, Error> {
+    Ok(Json(settings::current().redacted()))
+}
+
+/// Change settings with a JSON merge patch, null returns a field to its command line value
+#[api_v2_operation(tags("Settings"))]
+#[patch("/settings")]
+async fn settings_patch(patch: web::Json<Value>) -> Result<Json<SettingsUpdate>, Error> {
+    settings::update(&patch).map(Json).map_err(|err| match err {
+        SettingsError::Invalid(reason) => Error::BadRequest(reason),
+        SettingsError::Persist(reason) => Error::Internal(reason),
+    })
+}
@@ -0,0 +1,98 @@
+use crate::cli::settings::SettingsError;
+use crate::server::protocols::v1::errors::Error;
+use crate::vehicle::{
+    self,
+    control::{self, BridgeStatus},
+    systems::MavlinkSystem,
+    VehicleData,
+};
+use paperclip::actix::{
+    api_v2_operation, get, post,
+    web::{self, Json},
+    Apiv2Schema,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Apiv2Schema)]
+pub struct VehicleState {
+    /// Latest pose from the autopilot, None until both attitude and position arrived
+    pub vehicle: Option<VehicleData>,
+    pub updated: Option<String>,
+    /// Milliseconds since the last update, large values mean the MAVLink link is gone
+    pub age_ms: Option<i64>,
+    /// Older than --vehicle-stale-after, such poses are not written to recordings
+    pub stale: bool,
+}
+
+#[api_v2_operation(tags("Vehicle"))]
+#[get("/vehicle")]
+async fn vehicle_get() -> Result<Json<VehicleState>, Error> {
+    let vehicle = vehicle::history::latest();
+    let updated = vehicle
+        .as_ref()
+        .and_then(|vehicle| chrono::DateTime::from_timestamp_millis(vehicle.timestamp_ms));
+    Ok(Json(VehicleState {
+        stale: vehicle.as_ref().is_none_or(VehicleData::is_stale),
+        age_ms: vehicle
+            .as_ref()
+            .map(|vehicle| vehicle.age().num_milliseconds()),
+        updated: updated.map(|updated| updated.to_rfc3339()),
+        vehicle,
+    }))
+}
+
+/// MAVLink systems and components heard from, to choose --vehicle-system-id and --vehicle-component-id
+#[api_v2_operation(tags("Vehicle"))]
+#[get("/vehicle/systems")]
+async fn vehicle_systems() -> Result<Json<Vec<MavlinkSystem>>, Error> {
+    Ok(Json(vehicle::systems::list()))
+}
+
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct BridgeEndpoint {
+    /// Zenoh endpoint, e.g. tcp/192.168.2.2:7447, null for the default one
+    pub endpoint: Option<String>,
+}
+
+/// Connection state of the vehicle bridge, with the last error and the retry delay
+#[api_v2_operation(tags("Vehicle"))]
+#[get("/vehicle/bridge")]
+async fn vehicle_bridge_get() -> Result<Json<BridgeStatus>, Error> {
+    Ok(Json(control::status()))
+}
+
+/// Connect the Zenoh bridge to another endpoint, saved as the vehicle_bridge_endpoint setting
+#[api_v2_operation(tags("Vehicle"))]
+#[post("/vehicle/bridge/endpoint")]
+async fn vehicle_bridge_endpoint(
+    json: web::Json<BridgeEndpoint>,
+) -> Result<Json<BridgeStatus>, Error> {
+    control::set_endpoint(json.into_inner().endpoint).map_err(|err| match err {
+        SettingsError::Invalid(reason) => Error::BadRequest(reason),
+        SettingsError::Persist(reason) => Error::Internal(reason),
+    })?;
+    Ok(Json(control::status()))
+}
+
+/// Drop the bridge connection and connect again without waiting for the retry delay
+#[api_v2_operation(tags("Vehicle"))]
+#[post("/vehicle/bridge/reconnect")]
+async fn vehicle_bridge_reconnect() -> Result<Json<BridgeStatus>, Error> {
+    control::reconnect();
+    Ok(Json(control::status()))
+}
+
+/// Disconnect the bridge until resumed, recordings get no vehicle data meanwhile
+#[api_v2_operation(tags("Vehicle"))]
+#[post("/vehicle/bridge/pause")]
+async fn vehicle_bridge_pause() -> Result<Json<BridgeStatus>, Error> {
+    control::set_paused(true);
+    Ok(Json(control::status()))
+}
+
+#[api_v2_operation(tags("Vehicle"))]
+#[post("/vehicle/bridge/resume")]
+async fn vehicle_bridge_resume() -> Result<Json<BridgeStatus>, Error> {
+    control::set_paused(false);
+    Ok(Json(control::status()))
+}
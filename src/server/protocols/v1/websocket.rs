@@ -1,6 +1,6 @@
 use actix::{
-    dev::ContextFutureSpawner, fut, Actor, ActorFutureExt, Addr, AsyncContext, Handler, Message,
-    StreamHandler, WrapFuture,
+    dev::ContextFutureSpawner, fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext,
+    Handler, Message, StreamHandler, WrapFuture,
 };
 use actix_web::HttpRequest;
 use actix_web_actors::ws;
@@ -13,15 +13,57 @@ use paperclip::actix::{
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 use tokio::sync::broadcast;
-use tracing::info;
+use tracing::{info, info_span, warn, Instrument};
 use uuid::Uuid;
 
+use crate::cli::manager::DropPolicy;
 use crate::device::{
     manager::{ManagerActorHandler, Request},
-    recording::{RecordingManagerCommand, RecordingsManagerHandler},
+    recording::{
+        files::{self, FileChange},
+        georeference::{self, GeoreferencedSample},
+        jobs::{Job, JOBS},
+        RecordingManagerCommand, RecordingsManagerHandler,
+    },
 };
+use crate::server::metrics;
+use crate::server::middleware::request_id;
+use crate::server::protocols::v2::envelope::{self, Envelope, RequestEnvelope};
+
+const DROP_REPORT_PERIOD: Duration = Duration::from_secs(5);
+const PRUNE_PERIOD: Duration = Duration::from_secs(30);
+// Default regex filter, matches without serializing the message
+const MATCH_ALL: &str = ".*";
+
+/// Activity of a websocket client, refreshed by every frame it sends
+pub trait Heartbeat: Actor<Context = ws::WebsocketContext<Self>> {
+    fn last_seen(&mut self) -> &mut Instant;
+}
+
+// Server pings keep idle connections alive, clients gone without a Close frame stop answering and are closed
+fn start_heartbeat<A: Heartbeat>(ctx: &mut ws::WebsocketContext<A>) {
+    let Some(interval) = crate::cli::manager::ws_heartbeat_interval() else {
+        return;
+    };
+    let timeout = crate::cli::manager::ws_client_timeout();
+    ctx.run_interval(interval, move |actor, ctx| {
+        if actor.last_seen().elapsed() > timeout {
+            info!("ServerManager: Websocket client silent for more than {timeout:?}, closing it");
+            ctx.stop();
+            return;
+        }
+        ctx.ping(b"");
+    });
+}
 
 pub struct StringMessage(String);
 
@@ -29,73 +71,706 @@ impl Message for StringMessage {
     type Result = ();
 }
 
-#[derive(Serialize, Debug)]
+/// Wakes the actor up to send what is waiting in its queue
+pub struct FlushQueue;
+
+impl Message for FlushQueue {
+    type Result = ();
+}
+
+/// Closes the connection from the server side, e.g. from `DELETE /admin/ws_clients/{id}`
+pub struct Disconnect;
+
+impl Message for Disconnect {
+    type Result = ();
+}
+
+#[derive(Debug, Clone)]
+pub enum WebsocketFrame {
+    Text(String),
+    Binary(actix_web::web::Bytes),
+}
+
+impl WebsocketFrame {
+    fn encode(value: &Value, encoding: WebsocketEncoding) -> Result<Self, String> {
+        match encoding {
+            WebsocketEncoding::Json => serde_json::to_string(value)
+                .map(Self::Text)
+                .map_err(|err| err.to_string()),
+            encoding => encoding
+                .encode(value)
+                .map(|bytes| Self::Binary(bytes.into())),
+        }
+    }
+}
+
+/// Message layout of a connection, v2 clients receive typed envelopes instead of the free-form answers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum WebsocketProtocol {
+    #[default]
+    V1,
+    V2,
+}
+
+impl WebsocketProtocol {
+    fn frame(
+        &self,
+        value: &Value,
+        device_number: Option<Uuid>,
+        encoding: WebsocketEncoding,
+    ) -> Result<WebsocketFrame, String> {
+        match self {
+            Self::V1 => WebsocketFrame::encode(value, encoding),
+            Self::V2 => WebsocketFrame::encode(
+                &json!(Envelope::from_answer(value, device_number)),
+                encoding,
+            ),
+        }
+    }
+}
+
+/// Bounded outgoing queue of a device websocket client, a slow client loses messages instead of growing the mailbox
+#[derive(Debug)]
+pub struct ClientQueue {
+    frames: Mutex<VecDeque<WebsocketFrame>>,
+    capacity: usize,
+    policy: DropPolicy,
+    dropped: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl ClientQueue {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            frames: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// True when the queue was empty, the actor has to be woken up
+    fn push(&self, frame: WebsocketFrame) -> bool {
+        let mut frames = self.frames.lock().unwrap();
+        let was_empty = frames.is_empty();
+        if frames.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.policy {
+                DropPolicy::Oldest => {
+                    frames.pop_front();
+                }
+                DropPolicy::Newest => return false,
+            }
+        }
+        frames.push_back(frame);
+        was_empty
+    }
+
+    fn drain(&self) -> VecDeque<WebsocketFrame> {
+        let frames = std::mem::take(&mut *self.frames.lock().unwrap());
+        self.sent.fetch_add(frames.len() as u64, Ordering::Relaxed);
+        frames
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.frames.lock().unwrap().len()
+    }
+}
+
+#[derive(Serialize, Debug, Apiv2Schema)]
+pub struct WebsocketDropped {
+    /// Messages dropped since the previous report
+    pub messages: u64,
+    pub total: u64,
+    pub policy: DropPolicy,
+}
+
+/// Frame encoding of the `ws` route, binary encodings carry the same structure as the JSON messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum WebsocketEncoding {
+    #[default]
+    Json,
+    Cbor,
+    Msgpack,
+}
+
+impl WebsocketEncoding {
+    const SUBPROTOCOLS: [(&'static str, Self); 3] = [
+        ("ping-viewer.json", Self::Json),
+        ("ping-viewer.cbor", Self::Cbor),
+        ("ping-viewer.msgpack", Self::Msgpack),
+    ];
+
+    fn subprotocol(&self) -> &'static str {
+        Self::SUBPROTOCOLS
+            .iter()
+            .find(|(_, encoding)| encoding == self)
+            .map(|(name, _)| *name)
+            .unwrap_or("ping-viewer.json")
+    }
+
+    // Same choice as the handshake, the first protocol offered by the client that is supported
+    fn from_subprotocols(header: &str) -> Option<Self> {
+        header.split(',').map(str::trim).find_map(|offered| {
+            Self::SUBPROTOCOLS
+                .iter()
+                .find(|(name, _)| *name == offered)
+                .map(|(_, encoding)| *encoding)
+        })
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(|err| err.to_string()),
+            Self::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer).map_err(|err| err.to_string())?;
+                Ok(buffer)
+            }
+            Self::Msgpack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Structured selection of the broadcasts a client receives, checked before the message is serialized
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Apiv2Schema)]
+pub struct MessageFilter {
+    /// Message types as in v2 envelopes, e.g. `Ping360.AutoDeviceData`, or only their name, e.g. `Profile`
+    #[serde(default)]
+    pub message_types: Vec<String>,
+    /// `Ping1D`, `Ping360` or `Common`, only applies to device messages
+    #[serde(default)]
+    pub device_types: Vec<String>,
+    /// Minimum time between two messages of the same type from the same device
+    #[serde(default)]
+    pub min_interval_ms: Option<u64>,
+}
+
+impl MessageFilter {
+    fn from_query(query: &WebsocketQuery) -> Self {
+        let list = |value: &Option<String>| -> Vec<String> {
+            value
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Self {
+            message_types: list(&query.message_types),
+            device_types: list(&query.device_types),
+            min_interval_ms: query.min_interval_ms,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.message_types.is_empty()
+            && self.device_types.is_empty()
+            && self.min_interval().is_none()
+    }
+
+    fn min_interval(&self) -> Option<Duration> {
+        self.min_interval_ms
+            .filter(|interval| *interval > 0)
+            .map(Duration::from_millis)
+    }
+
+    fn accepts(&self, message_type: &str) -> bool {
+        let (device_type, name) = match message_type.split_once('.') {
+            Some((device_type, name)) => (Some(device_type), name),
+            None => (None, message_type),
+        };
+        let type_matches = self.message_types.is_empty()
+            || self
+                .message_types
+                .iter()
+                .any(|selected| selected == message_type || selected == name);
+        let device_matches = match device_type {
+            Some(device_type) if !self.device_types.is_empty() => self
+                .device_types
+                .iter()
+                .any(|selected| selected.eq_ignore_ascii_case(device_type)),
+            _ => true,
+        };
+        type_matches && device_matches
+    }
+}
+
+/// Subscription changes sent by a connected client, e.g. `{"control": "subscribe", "device_number": "<uuid>"}`
+#[derive(Deserialize, Debug, Clone, Apiv2Schema)]
+#[serde(tag = "control", rename_all = "lowercase")]
+pub enum WebsocketControl {
+    /// Replace the filter and device of the connection, missing fields receive everything
+    Subscribe {
+        filter: Option<String>,
+        device_number: Option<Uuid>,
+        #[serde(default)]
+        message_types: Vec<String>,
+        #[serde(default)]
+        device_types: Vec<String>,
+        min_interval_ms: Option<u64>,
+    },
+    /// Stop receiving broadcasts, requests are still answered
+    Unsubscribe,
+}
+
+#[derive(Serialize, Debug, Apiv2Schema)]
+pub struct WebsocketSubscription {
+    pub subscribed: bool,
+    pub filter: String,
+    pub device_number: Option<Uuid>,
+    pub message_filter: MessageFilter,
+}
+
+#[derive(Serialize, Debug, Apiv2Schema)]
 pub struct WebsocketError {
     pub error: String,
 }
 
 #[derive(Debug)]
 pub struct WebsocketActorContent {
+    pub id: Uuid,
+    pub peer: Option<String>,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
     pub actor: Addr<WebsocketActor>,
     pub re: Option<Regex>,
     pub device_number: Option<Uuid>,
+    pub encoding: WebsocketEncoding,
+    pub protocol: WebsocketProtocol,
+    pub queue: Arc<ClientQueue>,
+    pub message_filter: MessageFilter,
+    /// Last broadcast of each device and message type, for `min_interval_ms`
+    last_sent: HashMap<(Option<Uuid>, String), Instant>,
+}
+
+impl WebsocketActorContent {
+    fn enqueue(&self, frame: WebsocketFrame) {
+        if self.queue.push(frame) {
+            self.actor.do_send(FlushQueue);
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct WebsocketManager {
     pub clients: Vec<WebsocketActorContent>,
+    /// Messages kept per device for clients connecting later, 0 disables the replay
+    replay_size: usize,
+    recent: HashMap<Option<Uuid>, VecDeque<Value>>,
 }
 
 impl WebsocketManager {
-    pub fn send(&self, value: &serde_json::Value, name: &str, device_number: Option<Uuid>) {
-        if self.clients.is_empty() {
+    pub fn with_replay(replay_size: usize) -> Self {
+        Self {
+            replay_size,
+            ..Default::default()
+        }
+    }
+
+    /// Forget clients whose actor already stopped, returns how many were removed
+    pub fn prune(&mut self) -> usize {
+        let before = self.clients.len();
+        self.clients.retain(|client| client.actor.connected());
+        before - self.clients.len()
+    }
+
+    pub fn send(&mut self, value: &serde_json::Value, device_number: Option<Uuid>) {
+        if self.replay_size > 0 {
+            let recent = self.recent.entry(device_number).or_default();
+            if recent.len() == self.replay_size {
+                recent.pop_front();
+            }
+            recent.push_back(value.clone());
+        }
+
+        let mut broadcast = Broadcast::new(value, device_number);
+        for client in &mut self.clients {
+            broadcast.deliver(client);
+        }
+    }
+
+    /// Send the recent messages matching the client filters, so it has data before the next broadcast
+    pub fn replay(&mut self, id: Uuid) {
+        let Some(client) = self.clients.iter_mut().find(|client| client.id == id) else {
             return;
+        };
+        for (device_number, recent) in &self.recent {
+            for value in recent {
+                Broadcast::new(value, *device_number).deliver(client);
+            }
         }
+    }
 
-        let string = serde_json::to_string(value).unwrap();
-        for client in &self.clients {
-            // check client list was subscribed or subscribed to all
-            if client.device_number.is_none() || client.device_number == device_number {
-                let is_match = client.re.as_ref().is_some_and(|regx| regx.is_match(name));
-                if is_match {
-                    client.actor.do_send(StringMessage(string.clone()));
-                }
+    #[cfg(test)]
+    fn recent(&self, device_number: Option<Uuid>) -> Vec<Value> {
+        self.recent
+            .get(&device_number)
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+// One message being sent, the message type, the text for regex filters and the frames are only built when a client
+// needs them
+struct Broadcast<'a> {
+    value: &'a Value,
+    device_number: Option<Uuid>,
+    message_type: Option<String>,
+    text: Option<String>,
+    frames: Vec<((WebsocketProtocol, WebsocketEncoding), WebsocketFrame)>,
+    now: Instant,
+}
+
+impl<'a> Broadcast<'a> {
+    fn new(value: &'a Value, device_number: Option<Uuid>) -> Self {
+        Self {
+            value,
+            device_number,
+            message_type: None,
+            text: None,
+            frames: Vec::new(),
+            now: Instant::now(),
+        }
+    }
+
+    fn message_type(&mut self) -> &str {
+        self.message_type
+            .get_or_insert_with(|| envelope::message_type(self.value))
+    }
+
+    fn deliver(&mut self, client: &mut WebsocketActorContent) {
+        // check client list was subscribed or subscribed to all
+        if client.device_number.is_some() && client.device_number != self.device_number {
+            return;
+        }
+        let Some(re) = client.re.as_ref() else {
+            return;
+        };
+        if !client.message_filter.is_empty() && !client.message_filter.accepts(self.message_type())
+        {
+            return;
+        }
+        if re.as_str() != MATCH_ALL {
+            let text = self.text.get_or_insert_with(|| self.value.to_string());
+            if !re.is_match(text) {
+                return;
             }
         }
+        if let Some(interval) = client.message_filter.min_interval() {
+            let key = (self.device_number, self.message_type().to_string());
+            if client
+                .last_sent
+                .get(&key)
+                .is_some_and(|sent| self.now.duration_since(*sent) < interval)
+            {
+                return;
+            }
+            client.last_sent.insert(key, self.now);
+        }
+
+        let key = (client.protocol, client.encoding);
+        let frame = match self.frames.iter().find(|(frame_key, _)| *frame_key == key) {
+            Some((_, frame)) => frame.clone(),
+            None => match client
+                .protocol
+                .frame(self.value, self.device_number, client.encoding)
+            {
+                Ok(frame) => {
+                    self.frames.push((key, frame.clone()));
+                    frame
+                }
+                Err(err) => {
+                    warn!(
+                        "ServerManager: Failed to encode websocket message as {:?}: {err}",
+                        client.encoding
+                    );
+                    return;
+                }
+            },
+        };
+        client.enqueue(frame);
+    }
+}
+
+/// Connected device websocket client, as listed by `GET /admin/ws_clients`
+#[derive(Debug, Clone, Serialize, Apiv2Schema)]
+pub struct WebsocketClientInfo {
+    pub id: Uuid,
+    pub peer: Option<String>,
+    pub protocol: WebsocketProtocol,
+    pub encoding: WebsocketEncoding,
+    /// Regex filter, None once the client unsubscribed
+    pub filter: Option<String>,
+    pub device_number: Option<Uuid>,
+    pub message_filter: MessageFilter,
+    pub connected_at: String,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    /// Messages waiting to be written to the socket
+    pub messages_queued: usize,
+}
+
+impl From<&WebsocketActorContent> for WebsocketClientInfo {
+    fn from(client: &WebsocketActorContent) -> Self {
+        Self {
+            id: client.id,
+            peer: client.peer.clone(),
+            protocol: client.protocol,
+            encoding: client.encoding,
+            filter: client.re.as_ref().map(|re| re.as_str().to_string()),
+            device_number: client.device_number,
+            message_filter: client.message_filter.clone(),
+            connected_at: client.connected_at.to_rfc3339(),
+            messages_sent: client.queue.sent(),
+            messages_dropped: client.queue.dropped(),
+            messages_queued: client.queue.queued(),
+        }
     }
 }
 
 lazy_static! {
-    pub static ref MANAGER: Arc<Mutex<WebsocketManager>> =
-        Arc::new(Mutex::new(WebsocketManager::default()));
+    pub static ref MANAGER: Arc<Mutex<WebsocketManager>> = Arc::new(Mutex::new(
+        WebsocketManager::with_replay(crate::cli::manager::ws_replay_size())
+    ));
 }
 
-pub fn send_to_websockets(message: Value, device: Option<Uuid>) {
+// Number of websocket clients receiving messages from the device, including clients subscribed to all devices
+pub fn subscribers(device: Uuid) -> usize {
     MANAGER
         .lock()
         .unwrap()
-        .send(&message, &message.to_string(), device);
+        .clients
+        .iter()
+        .filter(|client| client.device_number.is_none() || client.device_number == Some(device))
+        .count()
+}
+
+/// Periodically drop the clients that vanished without the stream finishing
+pub fn start_pruning() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(PRUNE_PERIOD);
+        loop {
+            interval.tick().await;
+            let pruned = MANAGER.lock().unwrap().prune();
+            if pruned > 0 {
+                info!("ServerManager: Removed {pruned} dead websocket clients");
+            }
+        }
+    });
+}
+
+pub fn client_count() -> usize {
+    MANAGER.lock().unwrap().clients.len()
+}
+
+pub fn clients() -> Vec<WebsocketClientInfo> {
+    MANAGER
+        .lock()
+        .unwrap()
+        .clients
+        .iter()
+        .map(WebsocketClientInfo::from)
+        .collect()
+}
+
+/// Ask a client to close, false when no client has that id
+pub fn disconnect(id: Uuid) -> bool {
+    let manager = MANAGER.lock().unwrap();
+    let Some(client) = manager.clients.iter().find(|client| client.id == id) else {
+        return false;
+    };
+    info!("ServerManager: Disconnecting websocket client {id}");
+    client.actor.do_send(Disconnect);
+    true
+}
+
+pub fn send_to_websockets(message: Value, device: Option<Uuid>) {
+    MANAGER.lock().unwrap().send(&message, device);
 }
 
 pub struct WebsocketActor {
     server: Arc<Mutex<WebsocketManager>>,
+    id: Uuid,
+    peer: Option<String>,
     pub filter: String,
     pub device_number: Option<Uuid>,
+    pub encoding: WebsocketEncoding,
+    pub protocol: WebsocketProtocol,
+    pub message_filter: MessageFilter,
     pub manager_handler: web::Data<ManagerActorHandler>,
+    queue: Arc<ClientQueue>,
+    reported_drops: u64,
+    last_seen: Instant,
 }
 
 impl WebsocketActor {
     pub fn new(
         message_filter: String,
         device_number: Option<Uuid>,
+        encoding: WebsocketEncoding,
         manager_handler: web::Data<ManagerActorHandler>,
     ) -> Self {
         Self {
             server: MANAGER.clone(),
+            id: Uuid::new_v4(),
+            peer: None,
             filter: message_filter,
             device_number,
+            encoding,
+            protocol: WebsocketProtocol::V1,
+            message_filter: MessageFilter::default(),
             manager_handler,
+            queue: Arc::new(ClientQueue::new(
+                crate::cli::manager::ws_queue_size(),
+                crate::cli::manager::ws_drop_policy(),
+            )),
+            reported_drops: 0,
+            last_seen: Instant::now(),
+        }
+    }
+
+    pub fn with_protocol(mut self, protocol: WebsocketProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_message_filter(mut self, message_filter: MessageFilter) -> Self {
+        self.message_filter = message_filter;
+        self
+    }
+
+    pub fn with_peer(mut self, peer: Option<String>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    fn send_envelope(&self, envelope: &Envelope, ctx: &mut <Self as Actor>::Context) {
+        match WebsocketFrame::encode(&json!(envelope), self.encoding) {
+            Ok(WebsocketFrame::Text(text)) => ctx.text(text),
+            Ok(WebsocketFrame::Binary(bytes)) => ctx.binary(bytes),
+            Err(err) => warn!("ServerManager: Failed to encode websocket answer: {err}"),
+        }
+    }
+
+    // Answers go back to the requester with its request id, and to every client as a broadcast like in v1
+    fn handle_v2_request(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+        let request: RequestEnvelope = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => {
+                let error = Envelope {
+                    request_id: None,
+                    device_id: None,
+                    message_type: "Error".to_string(),
+                    payload: json!(err.to_string()),
+                };
+                self.send_envelope(&error, ctx);
+                return;
+            }
+        };
+
+        let manager_handler = self.manager_handler.clone();
+        let device_id = request.request.device_id();
+        // Answers always carry an id, generated when the client did not send one
+        let request_id = request.request_id.unwrap_or_else(request_id::generate);
+        let span = info_span!("websocket", request_id = %request_id);
+        async move { manager_handler.send(request.request).await }
+            .instrument(span)
+            .into_actor(self)
+            .then(move |res, actor, ctx| {
+                let envelope = match res {
+                    Ok(answer) => {
+                        let answer = json!(answer);
+                        send_to_websockets(answer.clone(), device_id.or(actor.device_number));
+                        Envelope::from_answer(&answer, device_id)
+                    }
+                    Err(err) => Envelope {
+                        request_id: None,
+                        device_id,
+                        message_type: "Error".to_string(),
+                        payload: json!(err),
+                    },
+                };
+                actor.send_envelope(&envelope.with_request_id(Some(request_id)), ctx);
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+}
+
+impl WebsocketActor {
+    fn update_subscription(
+        &mut self,
+        control: WebsocketControl,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let (re, subscribed) = match control {
+            WebsocketControl::Subscribe {
+                filter,
+                device_number,
+                message_types,
+                device_types,
+                min_interval_ms,
+            } => {
+                let filter = filter.unwrap_or_else(|| MATCH_ALL.to_owned());
+                let re = match Regex::new(&filter) {
+                    Ok(re) => re,
+                    Err(err) => {
+                        let error = WebsocketError {
+                            error: format!("Invalid filter {filter:?}: {err}"),
+                        };
+                        ctx.text(serde_json::to_string_pretty(&error).unwrap());
+                        return;
+                    }
+                };
+                self.filter = filter;
+                self.device_number = device_number;
+                self.message_filter = MessageFilter {
+                    message_types,
+                    device_types,
+                    min_interval_ms,
+                };
+                (Some(re), true)
+            }
+            WebsocketControl::Unsubscribe => (None, false),
+        };
+
+        if let Some(client) = self
+            .server
+            .lock()
+            .unwrap()
+            .clients
+            .iter_mut()
+            .find(|client| client.actor == ctx.address())
+        {
+            client.re = re;
+            client.device_number = self.device_number;
+            client.message_filter = self.message_filter.clone();
+            client.last_sent.clear();
         }
+        info!(
+            "ServerManager: Websocket subscription changed, subscribed: {subscribed}, filter: {:?}, device: {:?}",
+            self.filter, self.device_number
+        );
+
+        let subscription = WebsocketSubscription {
+            subscribed,
+            filter: self.filter.clone(),
+            device_number: self.device_number,
+            message_filter: self.message_filter.clone(),
+        };
+        ctx.text(serde_json::to_string(&json!({ "subscription": subscription })).unwrap());
     }
 }
 
@@ -107,22 +782,87 @@ impl Handler<StringMessage> for WebsocketActor {
     }
 }
 
+impl Handler<Disconnect> for WebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, _message: Disconnect, context: &mut Self::Context) {
+        context.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("Disconnected by the server administrator".to_string()),
+        }));
+        context.stop();
+    }
+}
+
+impl Handler<FlushQueue> for WebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, _message: FlushQueue, context: &mut Self::Context) {
+        for frame in self.queue.drain() {
+            match frame {
+                WebsocketFrame::Text(text) => context.text(text),
+                WebsocketFrame::Binary(bytes) => context.binary(bytes),
+            }
+        }
+    }
+}
+
 impl Actor for WebsocketActor {
     type Context = ws::WebsocketContext<Self>;
-}
 
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
     fn started(&mut self, ctx: &mut Self::Context) {
-        info!("ServerManager: Starting websocket client, add itself in manager.");
+        start_heartbeat(ctx);
+    }
+
+    // Also reached when the heartbeat closes the client, the stream may never finish then
+    fn stopped(&mut self, ctx: &mut Self::Context) {
         self.server
             .lock()
             .unwrap()
             .clients
-            .push(WebsocketActorContent {
-                actor: ctx.address(),
-                re: Regex::new(&self.filter).ok(),
-                device_number: (self.device_number),
-            });
+            .retain(|x| x.actor != ctx.address());
+    }
+}
+
+impl Heartbeat for WebsocketActor {
+    fn last_seen(&mut self) -> &mut Instant {
+        &mut self.last_seen
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("ServerManager: Starting websocket client, add itself in manager.");
+        let mut server = self.server.lock().unwrap();
+        server.clients.push(WebsocketActorContent {
+            id: self.id,
+            peer: self.peer.clone(),
+            connected_at: chrono::Utc::now(),
+            actor: ctx.address(),
+            re: Regex::new(&self.filter).ok(),
+            device_number: (self.device_number),
+            encoding: self.encoding,
+            protocol: self.protocol,
+            queue: self.queue.clone(),
+            message_filter: self.message_filter.clone(),
+            last_sent: HashMap::new(),
+        });
+        server.replay(self.id);
+        drop(server);
+
+        ctx.run_interval(DROP_REPORT_PERIOD, |actor, ctx| {
+            let total = actor.queue.dropped();
+            if total == actor.reported_drops {
+                return;
+            }
+            let dropped = WebsocketDropped {
+                messages: total - actor.reported_drops,
+                total,
+                policy: actor.queue.policy,
+            };
+            actor.reported_drops = total;
+            ctx.text(serde_json::to_string(&json!({ "dropped": dropped })).unwrap());
+        });
     }
 
     fn finished(&mut self, ctx: &mut Self::Context) {
@@ -135,9 +875,31 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
     }
 
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_seen = Instant::now();
+        }
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
+                // Subscription changes only affect this connection, also allowed in read-only mode
+                if let Ok(control) = serde_json::from_str::<WebsocketControl>(&text) {
+                    self.update_subscription(control, ctx);
+                    return;
+                }
+
+                if crate::cli::manager::is_read_only() {
+                    let error = WebsocketError {
+                        error: "Server is in read-only mode, requests are not allowed".to_string(),
+                    };
+                    ctx.text(serde_json::to_string_pretty(&error).unwrap());
+                    return;
+                }
+
+                if self.protocol == WebsocketProtocol::V2 {
+                    self.handle_v2_request(&text, ctx);
+                    return;
+                }
+
                 let manager_requests: Vec<crate::ModuleType> = match serde_json::from_str(&text) {
                     Ok(requests) => requests,
                     Err(err) => match serde_json::from_str(&text) {
@@ -169,8 +931,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
                                 _ => None,
                             };
 
-                            let future =
-                                async move { manager_handler.send(request).await }.into_actor(self);
+                            let span =
+                                info_span!("websocket", request_id = %request_id::generate());
+                            let future = async move { manager_handler.send(request).await }
+                                .instrument(span)
+                                .into_actor(self);
 
                             future
                                 .then(move |res, actor, ctx| {
@@ -209,14 +974,33 @@ pub async fn websocket(
     query: web::Query<WebsocketQuery>,
     stream: web::Payload,
     manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<HttpResponse, actix_web::Error> {
+    start_websocket(req, query, stream, manager_handler, WebsocketProtocol::V1).await
+}
+
+/// Device websocket of both protocol versions, they only differ in the message layout
+pub async fn start_websocket(
+    req: HttpRequest,
+    query: web::Query<WebsocketQuery>,
+    stream: web::Payload,
+    manager_handler: web::Data<ManagerActorHandler>,
+    protocol: WebsocketProtocol,
 ) -> Result<HttpResponse, actix_web::Error> {
     let query_inner = query.into_inner();
 
+    let message_filter = MessageFilter::from_query(&query_inner);
     let filter = match query_inner.filter {
         Some(filter) => filter,
-        _ => ".*".to_owned(),
+        _ => MATCH_ALL.to_owned(),
     };
     let device_number = query_inner.device_number;
+    // The query wins over the subprotocol, for clients that cannot set one
+    let subprotocol = req
+        .headers()
+        .get(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(WebsocketEncoding::from_subprotocols);
+    let encoding = query_inner.encoding.or(subprotocol).unwrap_or_default();
 
     if let Some(device_number) = device_number {
         let request = crate::device::manager::Request::Info(crate::device::manager::UuidWrapper {
@@ -234,29 +1018,55 @@ pub async fn websocket(
         }
     }
 
-    ws::start(
-        WebsocketActor::new(filter, device_number, manager_handler.clone()),
-        &req,
-        stream,
-    )
+    let actor = WebsocketActor::new(filter, device_number, encoding, manager_handler.clone())
+        .with_protocol(protocol)
+        .with_message_filter(message_filter)
+        .with_peer(req.peer_addr().map(|address| address.to_string()));
+    match subprotocol {
+        // Echo the negotiated subprotocol, browsers drop the connection otherwise
+        Some(subprotocol) => ws::WsResponseBuilder::new(actor, &req, stream)
+            .protocols(&[subprotocol.subprotocol()])
+            .start(),
+        None => ws::start(actor, &req, stream),
+    }
 }
 
 pub struct RecordingStatusActor {
     recording_subscriber: broadcast::Receiver<crate::device::recording::RecordingSession>,
+    recorder_handler: web::Data<RecordingsManagerHandler>,
+    last_seen: Instant,
 }
 
 impl RecordingStatusActor {
     pub fn new(
         recording_subscriber: broadcast::Receiver<crate::device::recording::RecordingSession>,
+        recorder_handler: web::Data<RecordingsManagerHandler>,
     ) -> Self {
         Self {
             recording_subscriber,
+            recorder_handler,
+            last_seen: Instant::now(),
         }
     }
 }
 
 impl Actor for RecordingStatusActor {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        metrics::websocket_connected("ws/recording");
+        start_heartbeat(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        metrics::websocket_disconnected("ws/recording");
+    }
+}
+
+impl Heartbeat for RecordingStatusActor {
+    fn last_seen(&mut self) -> &mut Instant {
+        &mut self.last_seen
+    }
 }
 
 impl Handler<StringMessage> for RecordingStatusActor {
@@ -282,8 +1092,53 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for RecordingStatusAc
     }
 
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_seen = Instant::now();
+        }
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            // Annotations typed while watching the stream
+            Ok(ws::Message::Text(text)) => {
+                if crate::cli::manager::is_read_only() {
+                    let error = WebsocketError {
+                        error: "Server is in read-only mode, requests are not allowed".to_string(),
+                    };
+                    ctx.text(serde_json::to_string_pretty(&error).unwrap());
+                    return;
+                }
+
+                // Only annotations, recordings are started and stopped through the REST API
+                let request = match serde_json::from_str(&text) {
+                    Ok(request @ RecordingManagerCommand::Annotate(_)) => request,
+                    Ok(_) => {
+                        let error = WebsocketError {
+                            error: "Only Annotate requests are accepted".to_string(),
+                        };
+                        ctx.text(serde_json::to_string_pretty(&error).unwrap());
+                        return;
+                    }
+                    Err(err) => {
+                        ctx.text(format!("Error: {}", err));
+                        return;
+                    }
+                };
+
+                let recorder_handler = self.recorder_handler.clone();
+                async move { recorder_handler.send(request).await }
+                    .into_actor(self)
+                    .then(|res, _actor, ctx| {
+                        match res {
+                            // Subscribers can't travel over the websocket
+                            Ok(answer) => match serde_json::to_string(&answer) {
+                                Ok(answer) => ctx.text(answer),
+                                Err(err) => ctx.text(format!("Error: {}", err)),
+                            },
+                            Err(err) => ctx.text(serde_json::to_string_pretty(&err).unwrap()),
+                        }
+                        fut::ready(())
+                    })
+                    .wait(ctx);
+            }
             Ok(ws::Message::Close(msg)) => ctx.close(msg),
             _ => (),
         }
@@ -310,7 +1165,297 @@ pub async fn recording_websocket(
     };
     let subscriber = recording_manager;
 
-    ws::start(RecordingStatusActor::new(subscriber), &req, stream)
+    ws::start(
+        RecordingStatusActor::new(subscriber, recorder_handler),
+        &req,
+        stream,
+    )
+}
+
+pub struct JobsActor {
+    jobs_subscriber: broadcast::Receiver<Job>,
+    last_seen: Instant,
+}
+
+impl JobsActor {
+    pub fn new(jobs_subscriber: broadcast::Receiver<Job>) -> Self {
+        Self {
+            jobs_subscriber,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+impl Actor for JobsActor {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        metrics::websocket_connected("ws/jobs");
+        start_heartbeat(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        metrics::websocket_disconnected("ws/jobs");
+    }
+}
+
+impl Heartbeat for JobsActor {
+    fn last_seen(&mut self) -> &mut Instant {
+        &mut self.last_seen
+    }
+}
+
+impl Handler<StringMessage> for JobsActor {
+    type Result = ();
+
+    fn handle(&mut self, message: StringMessage, ctx: &mut Self::Context) {
+        ctx.text(message.0);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for JobsActor {
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("JobsActor: Starting websocket client");
+
+        // Late subscribers first receive the jobs already known
+        for job in JOBS.list() {
+            ctx.text(serde_json::to_string(&job).unwrap());
+        }
+
+        let addr = ctx.address();
+        let mut subscriber = self.jobs_subscriber.resubscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match subscriber.recv().await {
+                    Ok(job) => {
+                        if !addr.connected() {
+                            break;
+                        }
+                        addr.do_send(StringMessage(serde_json::to_string(&job).unwrap()));
+                    }
+                    // Progress updates are superseded by the next ones, skipping is fine
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_seen = Instant::now();
+        }
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(msg)) => ctx.close(msg),
+            _ => (),
+        }
+    }
+}
+
+#[api_v2_operation(skip)]
+#[get("ws/jobs")]
+pub async fn jobs_websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::start(JobsActor::new(JOBS.subscribe()), &req, stream)
+}
+
+pub struct FilesActor {
+    files_subscriber: broadcast::Receiver<FileChange>,
+    last_seen: Instant,
+}
+
+impl FilesActor {
+    pub fn new(files_subscriber: broadcast::Receiver<FileChange>) -> Self {
+        Self {
+            files_subscriber,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+impl Actor for FilesActor {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        metrics::websocket_connected("ws/files");
+        start_heartbeat(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        metrics::websocket_disconnected("ws/files");
+    }
+}
+
+impl Heartbeat for FilesActor {
+    fn last_seen(&mut self) -> &mut Instant {
+        &mut self.last_seen
+    }
+}
+
+impl Handler<StringMessage> for FilesActor {
+    type Result = ();
+
+    fn handle(&mut self, message: StringMessage, ctx: &mut Self::Context) {
+        ctx.text(message.0);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for FilesActor {
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("FilesActor: Starting websocket client");
+
+        let addr = ctx.address();
+        let mut subscriber = self.files_subscriber.resubscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match subscriber.recv().await {
+                    Ok(change) => {
+                        if !addr.connected() {
+                            break;
+                        }
+                        addr.do_send(StringMessage(serde_json::to_string(&change).unwrap()));
+                    }
+                    // Clients that missed changes have to list the directory again
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("FilesActor: Client missed {skipped} file changes");
+                        addr.do_send(StringMessage(json!({ "lagged": skipped }).to_string()));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_seen = Instant::now();
+        }
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(msg)) => ctx.close(msg),
+            _ => (),
+        }
+    }
+}
+
+#[api_v2_operation(skip)]
+#[get("ws/files")]
+pub async fn files_websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::start(FilesActor::new(files::subscribe()), &req, stream)
+}
+
+pub struct GeoreferencedActor {
+    samples_subscriber: broadcast::Receiver<GeoreferencedSample>,
+    device_number: Option<Uuid>,
+    last_seen: Instant,
+}
+
+impl GeoreferencedActor {
+    pub fn new(
+        samples_subscriber: broadcast::Receiver<GeoreferencedSample>,
+        device_number: Option<Uuid>,
+    ) -> Self {
+        Self {
+            samples_subscriber,
+            device_number,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+impl Actor for GeoreferencedActor {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        metrics::websocket_connected("ws/georeferenced");
+        start_heartbeat(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        metrics::websocket_disconnected("ws/georeferenced");
+    }
+}
+
+impl Heartbeat for GeoreferencedActor {
+    fn last_seen(&mut self) -> &mut Instant {
+        &mut self.last_seen
+    }
+}
+
+impl Handler<StringMessage> for GeoreferencedActor {
+    type Result = ();
+
+    fn handle(&mut self, message: StringMessage, ctx: &mut Self::Context) {
+        ctx.text(message.0);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GeoreferencedActor {
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("GeoreferencedActor: Starting websocket client");
+
+        let addr = ctx.address();
+        let mut subscriber = self.samples_subscriber.resubscribe();
+        let device_number = self.device_number;
+
+        tokio::spawn(async move {
+            loop {
+                match subscriber.recv().await {
+                    Ok(sample) => {
+                        if !addr.connected() {
+                            break;
+                        }
+                        if device_number.is_some_and(|device_id| device_id != sample.device_id) {
+                            continue;
+                        }
+                        addr.do_send(StringMessage(serde_json::to_string(&sample).unwrap()));
+                    }
+                    // Samples are a live stream, the next ones replace the missed ones
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_seen = Instant::now();
+        }
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(msg)) => ctx.close(msg),
+            _ => (),
+        }
+    }
+}
+
+#[derive(Deserialize, Apiv2Schema, Clone)]
+pub struct GeoreferencedQuery {
+    /// Only the samples of this device, all devices otherwise
+    device_number: Option<Uuid>,
+}
+
+#[api_v2_operation(skip)]
+#[get("ws/georeferenced")]
+pub async fn georeferenced_websocket(
+    req: HttpRequest,
+    query: web::Query<GeoreferencedQuery>,
+    stream: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::start(
+        GeoreferencedActor::new(georeference::subscribe(), query.device_number),
+        &req,
+        stream,
+    )
 }
 
 #[derive(Deserialize, Apiv2Schema, Clone)]
@@ -318,4 +1463,139 @@ pub struct WebsocketQuery {
     /// Regex filter to select the desired incoming messages
     filter: Option<String>,
     device_number: Option<Uuid>,
+    /// Frame encoding, `json` (default), `cbor` or `msgpack`
+    encoding: Option<WebsocketEncoding>,
+    /// Comma separated message types, e.g. `Ping1D.Profile,DeviceInfo`
+    message_types: Option<String>,
+    /// Comma separated device types of the device messages, e.g. `Ping360`
+    device_types: Option<String>,
+    /// Minimum milliseconds between two messages of the same type from the same device
+    min_interval_ms: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_messages() {
+        let control: WebsocketControl = serde_json::from_str(
+            r#"{"control": "subscribe", "device_number": "00000000-0000-0000-b9c0-f5752d453eb3"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            control,
+            WebsocketControl::Subscribe {
+                filter: None,
+                device_number: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"control": "unsubscribe"}"#).unwrap(),
+            WebsocketControl::Unsubscribe
+        ));
+        // Device manager requests are not mistaken for control messages
+        assert!(
+            serde_json::from_str::<WebsocketControl>(r#"{"module": "DeviceManager"}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_message_filter() {
+        let filter = MessageFilter {
+            message_types: vec!["Profile".to_string(), "DeviceInfo".to_string()],
+            device_types: vec!["ping1d".to_string()],
+            min_interval_ms: None,
+        };
+        assert!(filter.accepts("Ping1D.Profile"));
+        assert!(filter.accepts("DeviceInfo"));
+        assert!(!filter.accepts("Ping360.Profile"));
+        assert!(!filter.accepts("Ping1D.Distance"));
+        assert!(MessageFilter::default().accepts("Ping360.AutoDeviceData"));
+
+        let query: WebsocketQuery = serde_json::from_value(json!({
+            "message_types": "Ping360.AutoDeviceData, DeviceInfo",
+            "min_interval_ms": 200,
+        }))
+        .unwrap();
+        let filter = MessageFilter::from_query(&query);
+        assert_eq!(
+            filter.message_types,
+            vec!["Ping360.AutoDeviceData", "DeviceInfo"]
+        );
+        assert_eq!(filter.min_interval(), Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_recent_messages_are_kept_per_device() {
+        let mut manager = WebsocketManager::with_replay(2);
+        let device = Some(Uuid::new_v4());
+        for index in 0..3 {
+            manager.send(&json!({ "index": index }), device);
+        }
+        manager.send(&json!({ "index": 9 }), None);
+        assert_eq!(
+            manager.recent(device),
+            vec![json!({ "index": 1 }), json!({ "index": 2 })]
+        );
+        assert_eq!(manager.recent(None), vec![json!({ "index": 9 })]);
+
+        let mut disabled = WebsocketManager::default();
+        disabled.send(&json!({ "index": 0 }), device);
+        assert!(disabled.recent(device).is_empty());
+    }
+
+    #[test]
+    fn test_full_queue_applies_the_drop_policy() {
+        let text = |value: &str| WebsocketFrame::Text(value.to_string());
+        let queued = |queue: &ClientQueue| -> Vec<String> {
+            queue
+                .drain()
+                .into_iter()
+                .filter_map(|frame| match frame {
+                    WebsocketFrame::Text(text) => Some(text),
+                    WebsocketFrame::Binary(_) => None,
+                })
+                .collect()
+        };
+
+        let queue = ClientQueue::new(2, DropPolicy::Oldest);
+        assert!(queue.push(text("a")));
+        assert!(!queue.push(text("b")));
+        assert!(!queue.push(text("c")));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queued(&queue), vec!["b", "c"]);
+        // Empty again, the next message wakes the actor up
+        assert!(queue.push(text("d")));
+
+        let queue = ClientQueue::new(2, DropPolicy::Newest);
+        for value in ["a", "b", "c"] {
+            queue.push(text(value));
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queued(&queue), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_first_supported_subprotocol_is_chosen() {
+        assert_eq!(
+            WebsocketEncoding::from_subprotocols("chat, ping-viewer.msgpack, ping-viewer.cbor"),
+            Some(WebsocketEncoding::Msgpack)
+        );
+        assert_eq!(WebsocketEncoding::from_subprotocols("chat"), None);
+    }
+
+    #[test]
+    fn test_binary_encodings_round_trip() {
+        let value = json!({"profile": [0, 12, 255], "angle": 200});
+        let cbor = WebsocketEncoding::Cbor.encode(&value).unwrap();
+        let decoded: Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(decoded, value);
+
+        let msgpack = WebsocketEncoding::Msgpack.encode(&value).unwrap();
+        let decoded: Value = rmp_serde::from_slice(&msgpack).unwrap();
+        assert_eq!(decoded, value);
+        assert!(msgpack.len() < WebsocketEncoding::Json.encode(&value).unwrap().len());
+    }
 }
@@ -0,0 +1,123 @@
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Every v2 message, the payload layout is fixed by `message_type`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Copied from the request this message answers, None for broadcasts
+    pub request_id: Option<String>,
+    pub device_id: Option<Uuid>,
+    /// `Ping1D.Profile`, `Ping360.DeviceData`, `DeviceInfo`, `DeviceStatusChange`, ...
+    pub message_type: String,
+    pub payload: Value,
+}
+
+/// Websocket request, the answer carries the same `request_id`
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct RequestEnvelope {
+    pub request_id: Option<String>,
+    pub request: crate::device::manager::Request,
+}
+
+impl Envelope {
+    /// Unwrap the externally tagged v1 `Answer` JSON into a typed envelope
+    pub fn from_answer(answer: &Value, device_id: Option<Uuid>) -> Self {
+        let (message_type, answer_device_id, payload) = unwrap_answer(answer);
+        Self::new(
+            answer_device_id.or(device_id),
+            message_type,
+            payload.clone(),
+        )
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    fn new(device_id: Option<Uuid>, message_type: String, payload: Value) -> Self {
+        Self {
+            request_id: None,
+            device_id,
+            message_type,
+            payload,
+        }
+    }
+}
+
+/// Type of a v1 `Answer` as named in envelopes, e.g. `Ping1D.Profile` or `DeviceInfo`
+pub fn message_type(answer: &Value) -> String {
+    unwrap_answer(answer).0
+}
+
+// Message type, device and payload of an answer, the payload is borrowed from it
+fn unwrap_answer(answer: &Value) -> (String, Option<Uuid>, &Value) {
+    let Some((kind, inner)) = single_entry(answer) else {
+        return ("Message".to_string(), None, answer);
+    };
+    if kind != "DeviceMessage" {
+        return (kind.to_string(), None, inner);
+    }
+
+    let device_id = inner
+        .get("device_id")
+        .and_then(|id| serde_json::from_value(id.clone()).ok());
+    let Some((answer_kind, answer)) = inner
+        .as_object()
+        .and_then(|fields| fields.iter().find(|(key, _)| key.as_str() != "device_id"))
+    else {
+        return (kind.to_string(), device_id, inner);
+    };
+    if answer_kind != "PingMessage" {
+        return (answer_kind.clone(), device_id, answer);
+    }
+
+    // `{"Ping1D": {"Profile": {...}}}` becomes `Ping1D.Profile` with the struct as payload
+    match single_entry(answer).and_then(|(device_type, message)| {
+        single_entry(message).map(|(name, payload)| (device_type, name, payload))
+    }) {
+        Some((device_type, name, payload)) => (format!("{device_type}.{name}"), device_id, payload),
+        None => (answer_kind.clone(), device_id, answer),
+    }
+}
+
+fn single_entry(value: &Value) -> Option<(&String, &Value)> {
+    let object = value.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+    object.iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_ping_message_is_unwrapped() {
+        let device_id = Uuid::new_v4();
+        let answer = json!({
+            "DeviceMessage": {
+                "PingMessage": {"Ping1D": {"Profile": {"distance": 1200, "profile_data": [1, 2]}}},
+                "device_id": device_id,
+            }
+        });
+        let envelope = Envelope::from_answer(&answer, None);
+        assert_eq!(envelope.device_id, Some(device_id));
+        assert_eq!(envelope.message_type, "Ping1D.Profile");
+        assert_eq!(envelope.payload["distance"], 1200);
+        assert_eq!(message_type(&answer), "Ping1D.Profile");
+    }
+
+    #[test]
+    fn test_manager_answers_keep_their_kind() {
+        let answer = json!({"DeviceInfo": [{"id": "x"}]});
+        let envelope = Envelope::from_answer(&answer, None).with_request_id(Some("1".into()));
+        assert_eq!(envelope.message_type, "DeviceInfo");
+        assert_eq!(envelope.request_id.as_deref(), Some("1"));
+        assert!(envelope.payload.is_array());
+    }
+}
@@ -0,0 +1,4 @@
+/// Envelope shared by the typed REST answers and the websocket messages
+pub mod envelope;
+pub mod rest;
+pub mod websocket;
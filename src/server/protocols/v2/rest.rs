@@ -0,0 +1,177 @@
+use crate::device::{
+    devices::{Ping1DRequest, Ping360Request, PingCommonRequest, PingRequest},
+    manager::{Answer, DeviceRequestStruct, ManagerActorHandler, Request, UuidWrapper},
+};
+use crate::server::protocols::v1::errors::Error;
+use paperclip::actix::{
+    api_v2_operation, get,
+    web::{self, HttpResponse},
+    Apiv2Schema,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{envelope::Envelope, websocket};
+
+/// Ping1D messages readable through `GET /v2/device/{uuid}/ping1d/{message}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ping1DMessage {
+    Profile,
+    Distance,
+    DistanceSimple,
+    GeneralInfo,
+    DeviceId,
+    FirmwareVersion,
+    ModeAuto,
+    SpeedOfSound,
+    Voltage5,
+    Range,
+    TransmitDuration,
+    PingInterval,
+    ProcessorTemperature,
+    PcbTemperature,
+    GainSetting,
+    PingEnable,
+}
+
+impl From<Ping1DMessage> for Ping1DRequest {
+    fn from(message: Ping1DMessage) -> Self {
+        match message {
+            Ping1DMessage::Profile => Ping1DRequest::Profile,
+            Ping1DMessage::Distance => Ping1DRequest::Distance,
+            Ping1DMessage::DistanceSimple => Ping1DRequest::DistanceSimple,
+            Ping1DMessage::GeneralInfo => Ping1DRequest::GeneralInfo,
+            Ping1DMessage::DeviceId => Ping1DRequest::DeviceId,
+            Ping1DMessage::FirmwareVersion => Ping1DRequest::FirmwareVersion,
+            Ping1DMessage::ModeAuto => Ping1DRequest::ModeAuto,
+            Ping1DMessage::SpeedOfSound => Ping1DRequest::SpeedOfSound,
+            Ping1DMessage::Voltage5 => Ping1DRequest::Voltage5,
+            Ping1DMessage::Range => Ping1DRequest::Range,
+            Ping1DMessage::TransmitDuration => Ping1DRequest::TransmitDuration,
+            Ping1DMessage::PingInterval => Ping1DRequest::PingInterval,
+            Ping1DMessage::ProcessorTemperature => Ping1DRequest::ProcessorTemperature,
+            Ping1DMessage::PcbTemperature => Ping1DRequest::PcbTemperature,
+            Ping1DMessage::GainSetting => Ping1DRequest::GainSetting,
+            Ping1DMessage::PingEnable => Ping1DRequest::PingEnable,
+        }
+    }
+}
+
+/// Ping360 messages readable through `GET /v2/device/{uuid}/ping360/{message}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ping360Message {
+    DeviceData,
+}
+
+impl From<Ping360Message> for Ping360Request {
+    fn from(message: Ping360Message) -> Self {
+        match message {
+            Ping360Message::DeviceData => Ping360Request::DeviceData,
+        }
+    }
+}
+
+/// Messages of every device, through `GET /v2/device/{uuid}/common/{message}`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Apiv2Schema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommonMessage {
+    DeviceInformation,
+    ProtocolVersion,
+}
+
+impl From<CommonMessage> for PingCommonRequest {
+    fn from(message: CommonMessage) -> Self {
+        match message {
+            CommonMessage::DeviceInformation => PingCommonRequest::DeviceInformation,
+            CommonMessage::ProtocolVersion => PingCommonRequest::ProtocolVersion,
+        }
+    }
+}
+
+async fn device_message(
+    manager_handler: &ManagerActorHandler,
+    uuid: Uuid,
+    device_request: PingRequest,
+) -> Result<HttpResponse, Error> {
+    let answer = manager_handler
+        .send(Request::Ping(DeviceRequestStruct {
+            uuid,
+            device_request,
+        }))
+        .await?;
+    Ok(HttpResponse::Ok().json(Envelope::from_answer(&json!(answer), Some(uuid))))
+}
+
+#[api_v2_operation(tags("Device : v2"))]
+#[get("devices")]
+async fn devices_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<HttpResponse, Error> {
+    match manager_handler.send(Request::List).await? {
+        Answer::DeviceInfo(devices) => Ok(HttpResponse::Ok().json(devices)),
+        answer => Err(Error::Internal(format!("Unexpected answer: {answer:?}"))),
+    }
+}
+
+#[api_v2_operation(tags("Device : v2"))]
+#[get("device/{device}")]
+async fn device_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+    device: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let uuid = device.into_inner();
+    match manager_handler
+        .send(Request::Info(UuidWrapper { uuid }))
+        .await?
+    {
+        Answer::DeviceInfo(devices) => devices
+            .into_iter()
+            .next()
+            .map(|device| HttpResponse::Ok().json(device))
+            .ok_or_else(|| Error::BadRequest(format!("Device {uuid} does not exist"))),
+        answer => Err(Error::Internal(format!("Unexpected answer: {answer:?}"))),
+    }
+}
+
+/// Latest message of the given type, as a typed envelope
+#[api_v2_operation(tags("Device : v2"))]
+#[get("device/{device}/ping1d/{message}")]
+async fn device_ping1d_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+    info: web::Path<(Uuid, Ping1DMessage)>,
+) -> Result<HttpResponse, Error> {
+    let (uuid, message) = info.into_inner();
+    device_message(&manager_handler, uuid, PingRequest::Ping1D(message.into())).await
+}
+
+#[api_v2_operation(tags("Device : v2"))]
+#[get("device/{device}/ping360/{message}")]
+async fn device_ping360_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+    info: web::Path<(Uuid, Ping360Message)>,
+) -> Result<HttpResponse, Error> {
+    let (uuid, message) = info.into_inner();
+    device_message(&manager_handler, uuid, PingRequest::Ping360(message.into())).await
+}
+
+#[api_v2_operation(tags("Device : v2"))]
+#[get("device/{device}/common/{message}")]
+async fn device_common_get(
+    manager_handler: web::Data<ManagerActorHandler>,
+    info: web::Path<(Uuid, CommonMessage)>,
+) -> Result<HttpResponse, Error> {
+    let (uuid, message) = info.into_inner();
+    device_message(&manager_handler, uuid, PingRequest::Common(message.into())).await
+}
+
+pub fn register_services(cfg: &mut web::ServiceConfig) {
+    cfg.service(devices_get)
+        .service(device_get)
+        .service(device_ping1d_get)
+        .service(device_ping360_get)
+        .service(device_common_get)
+        .service(websocket::websocket);
+}
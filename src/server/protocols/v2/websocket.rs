@@ -0,0 +1,20 @@
+use actix_web::HttpRequest;
+use paperclip::actix::{
+    api_v2_operation, get,
+    web::{self, HttpResponse},
+};
+
+use crate::device::manager::ManagerActorHandler;
+use crate::server::protocols::v1::websocket::{start_websocket, WebsocketProtocol, WebsocketQuery};
+
+/// Same queries and encodings as the v1 `ws` route, every message is an `Envelope`
+#[api_v2_operation(skip)]
+#[get("ws")]
+pub async fn websocket(
+    req: HttpRequest,
+    query: web::Query<WebsocketQuery>,
+    stream: web::Payload,
+    manager_handler: web::Data<ManagerActorHandler>,
+) -> Result<HttpResponse, actix_web::Error> {
+    start_websocket(req, query, stream, manager_handler, WebsocketProtocol::V2).await
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use actix_web::web::Bytes;
+use paperclip::actix::{
+    api_v2_operation, post,
+    web::{self, Json},
+    Apiv2Schema,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use webrtc::{
+    api::APIBuilder,
+    data_channel::{data_channel_state::RTCDataChannelState, RTCDataChannel},
+    ice_transport::ice_server::RTCIceServer,
+    peer_connection::{
+        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+    },
+};
+
+use crate::cli;
+use crate::device::{
+    manager::ManagerActorHandler,
+    recording::{
+        device_subscriber,
+        udp::{device_message_json, streaming_devices},
+    },
+};
+use crate::server::protocols::v1::errors::Error;
+
+// How often the devices sent to a peer are refreshed, also how long a closed channel keeps its task
+const WEBRTC_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+
+/// SDP offer of a browser, which creates the data channel itself, e.g. with
+/// `createDataChannel("devices", { ordered: false, maxRetransmits: 0 })` for unreliable and unordered delivery
+#[derive(Debug, Deserialize, Apiv2Schema)]
+pub struct WebrtcOffer {
+    pub sdp: String,
+    /// Only send the messages of this device, all streaming devices otherwise
+    pub device_number: Option<Uuid>,
+}
+
+/// SDP answer with every ICE candidate, signaling is a single request
+#[derive(Debug, Serialize, Apiv2Schema)]
+pub struct WebrtcAnswer {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub sdp: String,
+}
+
+pub fn register_services(cfg: &mut web::ServiceConfig) {
+    cfg.service(webrtc_offer);
+}
+
+/// Open a WebRTC peer connection, device messages are sent on the data channels of the offer as JSON
+#[api_v2_operation(tags("WebRTC"))]
+#[post("/webrtc/offer")]
+async fn webrtc_offer(
+    manager_handler: web::Data<ManagerActorHandler>,
+    offer: web::Json<WebrtcOffer>,
+) -> Result<Json<WebrtcAnswer>, Error> {
+    let offer = offer.into_inner();
+    let peer = new_peer_connection()
+        .await
+        .map_err(|err| Error::Internal(format!("Failed to create WebRTC peer: {err}")))?;
+
+    let handler = manager_handler.get_ref().clone();
+    let device_number = offer.device_number;
+    peer.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+        let handler = handler.clone();
+        Box::pin(async move {
+            info!(
+                "WebRTC: New data channel {:?}, ordered: {}, max retransmits: {:?}",
+                channel.label(),
+                channel.ordered(),
+                channel.max_retransmits()
+            );
+            let forwarded = channel.clone();
+            channel.on_open(Box::new(move || {
+                tokio::spawn(forward(forwarded, handler, device_number));
+                Box::pin(async {})
+            }));
+        })
+    }));
+
+    // Holding the peer in its own callback would keep it alive forever
+    let weak_peer: Weak<RTCPeerConnection> = Arc::downgrade(&peer);
+    peer.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+        let weak_peer = weak_peer.clone();
+        Box::pin(async move {
+            debug!("WebRTC: Peer connection {state}");
+            if state == RTCPeerConnectionState::Failed {
+                if let Some(peer) = weak_peer.upgrade() {
+                    if let Err(err) = peer.close().await {
+                        warn!("WebRTC: Failed to close peer connection: {err}");
+                    }
+                }
+            }
+        })
+    }));
+
+    let answer = negotiate(&peer, offer.sdp).await.map_err(|err| {
+        // A half negotiated peer would never be released otherwise
+        let peer = peer.clone();
+        tokio::spawn(async move { peer.close().await });
+        Error::BadRequest(format!("WebRTC negotiation failed: {err}"))
+    })?;
+
+    Ok(Json(WebrtcAnswer {
+        kind: "answer".to_string(),
+        sdp: answer,
+    }))
+}
+
+async fn new_peer_connection() -> Result<Arc<RTCPeerConnection>, webrtc::Error> {
+    let ice_servers = cli::manager::webrtc_ice_servers();
+    let configuration = RTCConfiguration {
+        // Peers on the same network connect with host candidates only
+        ice_servers: if ice_servers.is_empty() {
+            Vec::new()
+        } else {
+            vec![RTCIceServer {
+                urls: ice_servers,
+                ..Default::default()
+            }]
+        },
+        ..Default::default()
+    };
+    // Data channels only, no media engine or interceptors needed
+    let api = APIBuilder::new().build();
+    Ok(Arc::new(api.new_peer_connection(configuration).await?))
+}
+
+async fn negotiate(peer: &RTCPeerConnection, offer: String) -> Result<String, webrtc::Error> {
+    peer.set_remote_description(RTCSessionDescription::offer(offer)?)
+        .await?;
+    let answer = peer.create_answer(None).await?;
+    let mut gathered = peer.gathering_complete_promise().await;
+    peer.set_local_description(answer).await?;
+    // No trickle ICE, the answer carries every candidate
+    let _ = gathered.recv().await;
+    peer.local_description()
+        .await
+        .map(|description| description.sdp)
+        .ok_or(webrtc::Error::ErrNoRemoteDescription)
+}
+
+async fn forward(
+    channel: Arc<RTCDataChannel>,
+    devices_manager_handler: ManagerActorHandler,
+    device_number: Option<Uuid>,
+) {
+    let mut senders: HashMap<Uuid, JoinHandle<()>> = HashMap::new();
+    let mut refresh_interval = tokio::time::interval(WEBRTC_REFRESH_PERIOD);
+    loop {
+        refresh_interval.tick().await;
+        if channel.ready_state() != RTCDataChannelState::Open {
+            break;
+        }
+
+        let devices = match device_number {
+            Some(device_id) => vec![device_id],
+            None => streaming_devices(&devices_manager_handler).await,
+        };
+        senders.retain(|device_id, sender| {
+            let keep = devices.contains(device_id) && !sender.is_finished();
+            if !keep {
+                sender.abort();
+            }
+            keep
+        });
+
+        for device_id in devices {
+            if senders.contains_key(&device_id) {
+                continue;
+            }
+            let mut receiver = match device_subscriber(&devices_manager_handler, device_id).await {
+                Ok(receiver) => receiver,
+                Err(err) => {
+                    warn!("WebRTC: Failed to subscribe to device {device_id}: {err:?}");
+                    continue;
+                }
+            };
+
+            let channel = channel.clone();
+            let sender = tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(msg) => {
+                            let Some(data) = device_message_json(&msg, device_id) else {
+                                continue;
+                            };
+                            if let Err(err) = channel.send(&Bytes::from(data)).await {
+                                debug!("WebRTC: Stopped sending device {device_id}: {err}");
+                                break;
+                            }
+                        }
+                        // Skipping is what an unreliable channel is for, fresh data comes first
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("WebRTC: Device {device_id} lagged by {skipped} messages")
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            senders.insert(device_id, sender);
+        }
+    }
+
+    for sender in senders.into_values() {
+        sender.abort();
+    }
+    info!("WebRTC: Data channel {:?} closed", channel.label());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn peer() -> RTCPeerConnection {
+        APIBuilder::new()
+            .build()
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_answer_carries_every_candidate() {
+        let browser = peer().await;
+        browser.create_data_channel("devices", None).await.unwrap();
+        let offer = browser.create_offer(None).await.unwrap();
+        let mut gathered = browser.gathering_complete_promise().await;
+        browser.set_local_description(offer).await.unwrap();
+        let _ = gathered.recv().await;
+        let offer = browser.local_description().await.unwrap().sdp;
+
+        let server = peer().await;
+        let answer = negotiate(&server, offer).await.unwrap();
+        assert!(answer.contains("a=end-of-candidates"));
+        browser
+            .set_remote_description(RTCSessionDescription::answer(answer).unwrap())
+            .await
+            .unwrap();
+
+        browser.close().await.unwrap();
+        server.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_offer_is_rejected() {
+        let server = peer().await;
+        assert!(negotiate(&server, "v=0".to_string()).await.is_err());
+        server.close().await.unwrap();
+    }
+}
@@ -0,0 +1,560 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use bluerobotics_ping::{
+    decoder::{Decoder, DecoderResult},
+    message::ProtocolMessage,
+};
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::device::{
+    devices::{Ping1DRequest, Ping360Request, PingCommonRequest, PingRequest},
+    manager::{
+        Answer, DeviceRequestStruct, DeviceStatus, ManagerActorHandler, ManagerError, Request,
+    },
+    recording::device_subscriber,
+};
+use crate::{cli, server::middleware::auth};
+
+// How often the proxied devices are refreshed from the DeviceManager
+const PROXY_REFRESH_PERIOD: Duration = Duration::from_secs(5);
+// UDP has no connection, a peer silent for this long stops receiving messages
+const UDP_PEER_TIMEOUT: Duration = Duration::from_secs(60);
+// Pause after a failed UDP receive, a persistent error must not spin the task
+const UDP_RETRY_PERIOD: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref PORTS: RwLock<HashMap<Uuid, u16>> = RwLock::new(HashMap::new());
+}
+
+/// Port serving one device, over both TCP and UDP
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct ProxyPort {
+    pub device_id: Uuid,
+    pub port: u16,
+}
+
+/// Ports currently serving devices, ordered by port
+pub fn ports() -> Vec<ProxyPort> {
+    let mut ports: Vec<ProxyPort> = PORTS
+        .read()
+        .map(|ports| {
+            ports
+                .iter()
+                .map(|(device_id, port)| ProxyPort {
+                    device_id: *device_id,
+                    port: *port,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    ports.sort_by_key(|proxy| proxy.port);
+    ports
+}
+
+/// Re-exposes every managed device as a raw Ping protocol server, so classic Ping-Viewer and other tools
+/// can use a sonar already opened by this server. Every client receives every message of the device,
+/// and requests go through the DeviceManager like the ones of the API.
+pub struct DeviceProxy {
+    devices_manager_handler: ManagerActorHandler,
+    base_port: u16,
+    servers: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl DeviceProxy {
+    pub fn new(devices_manager_handler: ManagerActorHandler, base_port: u16) -> Self {
+        Self {
+            devices_manager_handler,
+            base_port,
+            servers: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        info!("DeviceProxy: Serving devices from port {}", self.base_port);
+        let mut refresh_interval = tokio::time::interval(PROXY_REFRESH_PERIOD);
+        loop {
+            refresh_interval.tick().await;
+            self.refresh_servers().await;
+        }
+    }
+
+    async fn refresh_servers(&mut self) {
+        let devices: Vec<Uuid> = match self.devices_manager_handler.send(Request::List).await {
+            Ok(Answer::DeviceInfo(devices)) => devices
+                .into_iter()
+                .filter(|device| {
+                    matches!(
+                        device.status,
+                        DeviceStatus::Running | DeviceStatus::ContinuousMode
+                    )
+                })
+                .map(|device| device.id)
+                .collect(),
+            _ => return,
+        };
+
+        self.servers.retain(|device_id, server| {
+            let keep = devices.contains(device_id) && !server.is_finished();
+            if !keep {
+                server.abort();
+                release_port(device_id);
+            }
+            keep
+        });
+
+        for device_id in devices {
+            if self.servers.contains_key(&device_id) {
+                continue;
+            }
+            let Some((port, tcp, udp)) = self.bind_port(device_id).await else {
+                continue;
+            };
+            let handler = self.devices_manager_handler.clone();
+            let server = tokio::spawn(async move {
+                if let Err(err) = serve_device(handler, device_id, tcp, udp).await {
+                    warn!(
+                        "DeviceProxy: Stopped serving device {device_id} on port {port}: {err:?}"
+                    );
+                }
+            });
+            self.servers.insert(device_id, server);
+        }
+    }
+
+    // The lowest free port, a device keeps it while it is available. Ports taken by other processes are
+    // skipped, retrying them would leave the device unserved forever
+    async fn bind_port(&self, device_id: Uuid) -> Option<(u16, TcpListener, UdpSocket)> {
+        let mut first = self.base_port;
+        loop {
+            let port = PORTS.read().ok().and_then(|ports| {
+                (first..=u16::MAX).find(|port| !ports.values().any(|used| used == port))
+            });
+            let Some(port) = port else {
+                warn!("DeviceProxy: No port left for device {device_id}");
+                return None;
+            };
+            match bind(port).await {
+                Ok((tcp, udp)) => {
+                    PORTS.write().ok()?.insert(device_id, port);
+                    return Some((port, tcp, udp));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                    debug!("DeviceProxy: Port {port} is used by another process");
+                    first = port.checked_add(1)?;
+                }
+                Err(err) => {
+                    warn!("DeviceProxy: Failed to listen on port {port} for device {device_id}: {err}");
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+// Raw Ping clients can not present an API token, keep the devices off the network when one is required
+async fn bind(port: u16) -> std::io::Result<(TcpListener, UdpSocket)> {
+    let address = if auth::is_enabled() {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    } else {
+        SocketAddr::from(([0, 0, 0, 0], port))
+    };
+    Ok((
+        TcpListener::bind(address).await?,
+        UdpSocket::bind(address).await?,
+    ))
+}
+
+fn release_port(device_id: &Uuid) {
+    if let Ok(mut ports) = PORTS.write() {
+        ports.remove(device_id);
+    }
+}
+
+async fn serve_device(
+    handler: ManagerActorHandler,
+    device_id: Uuid,
+    tcp: TcpListener,
+    udp: UdpSocket,
+) -> Result<(), ManagerError> {
+    if let Ok(address) = tcp.local_addr() {
+        info!("DeviceProxy: Device {device_id} available on TCP and UDP {address}");
+    }
+
+    let udp = tokio::spawn(serve_udp(handler.clone(), device_id, Arc::new(udp)));
+    let result = loop {
+        match tcp.accept().await {
+            Ok((stream, peer)) => {
+                debug!("DeviceProxy: TCP client {peer} connected to device {device_id}");
+                tokio::spawn(serve_tcp_client(handler.clone(), device_id, stream, peer));
+            }
+            Err(err) => break Err(ManagerError::Other(format!("TCP accept failed: {err}"))),
+        }
+    };
+    udp.abort();
+    result
+}
+
+async fn serve_tcp_client(
+    handler: ManagerActorHandler,
+    device_id: Uuid,
+    stream: TcpStream,
+    peer: SocketAddr,
+) {
+    let mut receiver = match device_subscriber(&handler, device_id).await {
+        Ok(receiver) => receiver,
+        Err(err) => {
+            warn!("DeviceProxy: Failed to subscribe to device {device_id}: {err:?}");
+            return;
+        }
+    };
+    let (mut reader, mut writer) = stream.into_split();
+
+    let writing = async {
+        loop {
+            match receiver.recv().await {
+                Ok(msg) => {
+                    if writer.write_all(&msg.serialized()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("DeviceProxy: TCP client {peer} lagged by {skipped} messages")
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    let reading = async {
+        let mut decoder = Decoder::new();
+        let mut buffer = [0u8; 1024];
+        loop {
+            let read = match reader.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            for byte in &buffer[..read] {
+                if let DecoderResult::Success(msg) = decoder.parse_byte(*byte) {
+                    forward_request(&handler, device_id, &msg).await;
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = writing => {}
+        _ = reading => {}
+    }
+    debug!("DeviceProxy: TCP client {peer} disconnected from device {device_id}");
+}
+
+async fn serve_udp(handler: ManagerActorHandler, device_id: Uuid, socket: Arc<UdpSocket>) {
+    let peers: Arc<Mutex<HashMap<SocketAddr, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    let mut receiver = match device_subscriber(&handler, device_id).await {
+        Ok(receiver) => receiver,
+        Err(err) => {
+            warn!("DeviceProxy: Failed to subscribe to device {device_id}: {err:?}");
+            return;
+        }
+    };
+    let sender = {
+        let socket = socket.clone();
+        let peers = peers.clone();
+        tokio::spawn(async move {
+            loop {
+                let msg = match receiver.recv().await {
+                    Ok(msg) => msg,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let targets: Vec<SocketAddr> = match peers.lock() {
+                    Ok(mut peers) => {
+                        peers.retain(|_, last_seen| last_seen.elapsed() < UDP_PEER_TIMEOUT);
+                        peers.keys().copied().collect()
+                    }
+                    Err(_) => break,
+                };
+                let datagram = msg.serialized();
+                for target in targets {
+                    if let Err(err) = socket.send_to(&datagram, target).await {
+                        debug!("DeviceProxy: Failed to send to UDP client {target}: {err}");
+                    }
+                }
+            }
+        })
+    };
+
+    let mut decoder = Decoder::new();
+    let mut buffer = [0u8; 2048];
+    loop {
+        let (read, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(err) => {
+                debug!("DeviceProxy: UDP receive failed for device {device_id}: {err}");
+                if sender.is_finished() {
+                    break;
+                }
+                tokio::time::sleep(UDP_RETRY_PERIOD).await;
+                continue;
+            }
+        };
+        // Any datagram registers the client, like a device answering the address it was polled from
+        if let Ok(mut peers) = peers.lock() {
+            if peers.insert(peer, Instant::now()).is_none() {
+                debug!("DeviceProxy: UDP client {peer} connected to device {device_id}");
+            }
+        }
+        for byte in &buffer[..read] {
+            if let DecoderResult::Success(msg) = decoder.parse_byte(*byte) {
+                forward_request(&handler, device_id, &msg).await;
+            }
+        }
+        if sender.is_finished() {
+            break;
+        }
+    }
+}
+
+async fn forward_request(handler: &ManagerActorHandler, device_id: Uuid, msg: &ProtocolMessage) {
+    let Some(request) = to_request(msg) else {
+        debug!(
+            "DeviceProxy: Ignoring unsupported message {} for device {device_id}",
+            msg.message_id
+        );
+        return;
+    };
+    if cli::manager::is_read_only() && !is_query(msg) {
+        debug!("DeviceProxy: Read-only mode, ignoring {request:?} for device {device_id}");
+        return;
+    }
+
+    // Answers reach the clients through the device stream, like the device would send them
+    let request = Request::Ping(DeviceRequestStruct {
+        uuid: device_id,
+        device_request: request,
+    });
+    if let Err(err) = handler.send(request).await {
+        debug!("DeviceProxy: Request to device {device_id} failed: {err:?}");
+    }
+}
+
+// Only requests asking for a message, anything else configures or drives the device
+fn is_query(msg: &ProtocolMessage) -> bool {
+    use bluerobotics_ping::{common, message::MessageInfo};
+
+    msg.message_id == common::GeneralRequestStruct::id()
+}
+
+// Requests Ping-Viewer sends, as the typed requests of the DeviceManager
+fn to_request(msg: &ProtocolMessage) -> Option<PingRequest> {
+    use bluerobotics_ping::{common, ping1d, ping360, Messages};
+
+    let request = match Messages::try_from(msg).ok()? {
+        Messages::Common(common::Messages::GeneralRequest(request)) => {
+            return general_request(request.requested_id)
+        }
+        Messages::Common(common::Messages::SetDeviceId(request)) => {
+            PingRequest::Common(PingCommonRequest::SetDeviceId(request))
+        }
+        Messages::Ping1D(message) => PingRequest::Ping1D(match message {
+            ping1d::Messages::SetDeviceId(request) => Ping1DRequest::SetDeviceId(request),
+            ping1d::Messages::SetModeAuto(request) => Ping1DRequest::SetModeAuto(request),
+            ping1d::Messages::SetPingInterval(request) => Ping1DRequest::SetPingInterval(request),
+            ping1d::Messages::SetPingEnable(request) => Ping1DRequest::SetPingEnable(request),
+            ping1d::Messages::SetSpeedOfSound(request) => Ping1DRequest::SetSpeedOfSound(request),
+            ping1d::Messages::SetRange(request) => Ping1DRequest::SetRange(request),
+            ping1d::Messages::SetGainSetting(request) => Ping1DRequest::SetGainSetting(request),
+            ping1d::Messages::ContinuousStart(request) => Ping1DRequest::ContinuousStart(request),
+            ping1d::Messages::ContinuousStop(request) => Ping1DRequest::ContinuousStop(request),
+            _ => return None,
+        }),
+        Messages::Ping360(message) => PingRequest::Ping360(match message {
+            ping360::Messages::SetDeviceId(request) => Ping360Request::SetDeviceId(request),
+            ping360::Messages::Transducer(request) => Ping360Request::Transducer(request),
+            ping360::Messages::Reset(request) => Ping360Request::Reset(request),
+            ping360::Messages::AutoTransmit(request) => Ping360Request::AutoTransmit(request),
+            ping360::Messages::MotorOff(_) => Ping360Request::MotorOff,
+            _ => return None,
+        }),
+        _ => return None,
+    };
+    Some(request)
+}
+
+fn general_request(requested_id: u16) -> Option<PingRequest> {
+    use bluerobotics_ping::{common, message::MessageInfo, ping1d, ping360};
+
+    let requests = [
+        (
+            common::DeviceInformationStruct::id(),
+            PingRequest::Common(PingCommonRequest::DeviceInformation),
+        ),
+        (
+            common::ProtocolVersionStruct::id(),
+            PingRequest::Common(PingCommonRequest::ProtocolVersion),
+        ),
+        (
+            ping1d::FirmwareVersionStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::FirmwareVersion),
+        ),
+        (
+            ping1d::DeviceIdStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::DeviceId),
+        ),
+        (
+            ping1d::Voltage5Struct::id(),
+            PingRequest::Ping1D(Ping1DRequest::Voltage5),
+        ),
+        (
+            ping1d::SpeedOfSoundStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::SpeedOfSound),
+        ),
+        (
+            ping1d::RangeStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::Range),
+        ),
+        (
+            ping1d::ModeAutoStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::ModeAuto),
+        ),
+        (
+            ping1d::PingIntervalStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::PingInterval),
+        ),
+        (
+            ping1d::GainSettingStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::GainSetting),
+        ),
+        (
+            ping1d::TransmitDurationStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::TransmitDuration),
+        ),
+        (
+            ping1d::GeneralInfoStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::GeneralInfo),
+        ),
+        (
+            ping1d::DistanceSimpleStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::DistanceSimple),
+        ),
+        (
+            ping1d::DistanceStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::Distance),
+        ),
+        (
+            ping1d::ProcessorTemperatureStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::ProcessorTemperature),
+        ),
+        (
+            ping1d::PcbTemperatureStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::PcbTemperature),
+        ),
+        (
+            ping1d::PingEnableStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::PingEnable),
+        ),
+        (
+            ping1d::ProfileStruct::id(),
+            PingRequest::Ping1D(Ping1DRequest::Profile),
+        ),
+        (
+            ping360::DeviceDataStruct::id(),
+            PingRequest::Ping360(Ping360Request::DeviceData),
+        ),
+    ];
+    requests
+        .into_iter()
+        .find(|(id, _)| *id == requested_id)
+        .map(|(_, request)| request)
+}
+
+#[cfg(test)]
+mod tests {
+    use bluerobotics_ping::{common, message::MessageInfo, ping1d, ping360};
+
+    use super::*;
+
+    fn general_request(requested_id: u16) -> ProtocolMessage {
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&common::Messages::GeneralRequest(
+            common::GeneralRequestStruct { requested_id },
+        ));
+        msg
+    }
+
+    #[test]
+    fn test_client_messages_become_device_requests() {
+        let msg = general_request(ping1d::ProfileStruct::id());
+        assert!(matches!(
+            to_request(&msg),
+            Some(PingRequest::Ping1D(Ping1DRequest::Profile))
+        ));
+
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&ping1d::Messages::SetPingEnable(
+            ping1d::SetPingEnableStruct { ping_enabled: 1 },
+        ));
+        let request = to_request(&msg).unwrap();
+        assert!(request.is_setting());
+
+        // Answers of a device are not requests
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&common::Messages::ProtocolVersion(
+            common::ProtocolVersionStruct {
+                version_major: 1,
+                version_minor: 0,
+                version_patch: 0,
+                reserved: 0,
+            },
+        ));
+        assert!(to_request(&msg).is_none());
+    }
+
+    #[test]
+    fn test_only_general_requests_are_queries() {
+        assert!(is_query(&general_request(ping1d::ProfileStruct::id())));
+
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&ping1d::Messages::ContinuousStart(
+            ping1d::ContinuousStartStruct { id: 1300 },
+        ));
+        assert!(!is_query(&msg));
+
+        let mut msg = ProtocolMessage::new();
+        msg.set_message(&ping360::Messages::MotorOff(ping360::MotorOffStruct {}));
+        assert!(!is_query(&msg));
+    }
+
+    #[tokio::test]
+    async fn test_port_used_by_another_process_is_skipped() {
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let base_port = taken.local_addr().unwrap().port();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let proxy = DeviceProxy::new(ManagerActorHandler { sender }, base_port);
+        let device_id = Uuid::new_v4();
+
+        let (port, tcp, _udp) = proxy.bind_port(device_id).await.unwrap();
+        assert!(port > base_port);
+        assert_eq!(tcp.local_addr().unwrap().port(), port);
+        assert!(ports()
+            .iter()
+            .any(|proxy| proxy.device_id == device_id && proxy.port == port));
+        release_port(&device_id);
+    }
+}
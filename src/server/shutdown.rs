@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::device::{
+    manager::{Answer, DeviceStatus, ManagerActorHandler, Request, UuidWrapper},
+    recording::{RecordingManagerCommand, RecordingsManagerHandler},
+};
+
+// Each step is bounded, a stuck device must not keep the process alive
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Ask the application to stop cleanly, e.g. when the desktop window is closed
+pub fn request() {
+    SHUTDOWN.send_replace(true);
+}
+
+/// Completes once a shutdown was requested
+pub async fn requested() {
+    let mut receiver = SHUTDOWN.subscribe();
+    let _ = receiver.wait_for(|requested| *requested).await;
+}
+
+/// Request a shutdown on SIGINT or SIGTERM
+pub async fn listen_signals() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(terminate) => terminate,
+                Err(err) => {
+                    warn!("Shutdown: Failed to listen for SIGTERM: {err}");
+                    let _ = tokio::signal::ctrl_c().await;
+                    info!("Shutdown: SIGINT received");
+                    request();
+                    return;
+                }
+            };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("Shutdown: SIGINT received"),
+            _ = terminate.recv() => info!("Shutdown: SIGTERM received"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Shutdown: Ctrl-C received");
+    }
+    request();
+}
+
+/// Stop the devices streaming and finalize the recordings, before the server goes away
+pub async fn stop(devices: &ManagerActorHandler, recordings: &RecordingsManagerHandler) {
+    info!("Shutdown: Stopping continuous modes");
+    match tokio::time::timeout(STEP_TIMEOUT, devices.send(Request::List)).await {
+        Ok(Ok(Answer::DeviceInfo(devices_info))) => {
+            for device in devices_info
+                .iter()
+                .filter(|device| device.status == DeviceStatus::ContinuousMode)
+            {
+                let request = Request::DisableContinuousMode(UuidWrapper { uuid: device.id });
+                match tokio::time::timeout(STEP_TIMEOUT, devices.send(request)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => warn!(
+                        "Shutdown: Failed to stop continuous mode of {}: {err:?}",
+                        device.id
+                    ),
+                    Err(_) => warn!(
+                        "Shutdown: Device {} did not stop its continuous mode in time",
+                        device.id
+                    ),
+                }
+            }
+        }
+        Ok(Ok(answer)) => warn!("Shutdown: Unexpected answer to the device list: {answer:?}"),
+        Ok(Err(err)) => warn!("Shutdown: Failed to list devices: {err:?}"),
+        Err(_) => warn!("Shutdown: Device manager did not answer in time"),
+    }
+
+    // Writers are closed by the manager, so the MCAP summary and footer are written
+    info!("Shutdown: Closing active recordings");
+    match tokio::time::timeout(
+        STEP_TIMEOUT,
+        recordings.send(RecordingManagerCommand::StopRecordingAll),
+    )
+    .await
+    {
+        Ok(Ok(_)) => info!("Shutdown: Recordings closed"),
+        Ok(Err(err)) => warn!("Shutdown: Failed to close recordings: {err:?}"),
+        Err(_) => warn!("Shutdown: Recording manager did not answer in time"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+    };
+
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::device::{
+        manager::{
+            DeviceInfo, DeviceSelection, ManagerActorRequest, SourceSelection, SourceUdpStruct,
+        },
+        recording::RecordingManager,
+    };
+
+    fn device(status: DeviceStatus) -> DeviceInfo {
+        DeviceInfo {
+            id: Uuid::new_v4(),
+            source: SourceSelection::UdpStream(SourceUdpStruct {
+                ip: Ipv4Addr::LOCALHOST,
+                port: 12345,
+            }),
+            status,
+            device_type: DeviceSelection::Ping360,
+            properties: None,
+        }
+    }
+
+    /// Device manager listing `devices`, recording the ones whose continuous mode is disabled
+    fn device_manager(devices: Vec<DeviceInfo>) -> (ManagerActorHandler, Arc<Mutex<Vec<Uuid>>>) {
+        let (sender, mut receiver) = mpsc::channel::<ManagerActorRequest>(10);
+        let disabled = Arc::new(Mutex::new(Vec::new()));
+        let task_disabled = disabled.clone();
+        tokio::spawn(async move {
+            while let Some(request) = receiver.recv().await {
+                let answer = match request.request {
+                    Request::List => Answer::DeviceInfo(devices.clone()),
+                    Request::DisableContinuousMode(UuidWrapper { uuid }) => {
+                        task_disabled.lock().unwrap().push(uuid);
+                        Answer::DeviceInfo(vec![])
+                    }
+                    _ => Answer::DeviceInfo(vec![]),
+                };
+                let _ = request.respond_to.send(Ok(answer));
+            }
+        });
+        (ManagerActorHandler { sender }, disabled)
+    }
+
+    #[tokio::test]
+    async fn test_requested_completes_once_requested() {
+        let waiting = tokio::spawn(requested());
+        request();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        // Later waiters see the request as well
+        tokio::time::timeout(Duration::from_secs(1), requested())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_disables_continuous_modes_and_closes_recordings() {
+        let streaming = device(DeviceStatus::ContinuousMode);
+        let (devices, disabled) =
+            device_manager(vec![device(DeviceStatus::Running), streaming.clone()]);
+        let base_path = std::env::temp_dir().join(format!("ping-viewer-{}", Uuid::new_v4()));
+        let (recordings_manager, recordings) =
+            RecordingManager::new(10, &base_path, devices.clone());
+        tokio::spawn(recordings_manager.run());
+
+        tokio::time::timeout(STEP_TIMEOUT, stop(&devices, &recordings))
+            .await
+            .unwrap();
+
+        assert_eq!(*disabled.lock().unwrap(), vec![streaming.id]);
+        let _ = std::fs::remove_dir_all(&base_path);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use mavlink::ardupilotmega::SYSTEM_TIME_DATA;
+use serde::{Deserialize, Serialize};
+
+// Reboots and clock steps move the offset further than the link latency does
+const RESYNC_THRESHOLD_MS: i64 = 1000;
+
+lazy_static! {
+    static ref CLOCK: std::sync::RwLock<Option<VehicleClock>> = std::sync::RwLock::new(None);
+}
+
+/// Mapping of the autopilot clocks to the host wall clock, to align recordings with dataflash logs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct VehicleClock {
+    #[schemars(
+        description = "Host unix time in milliseconds of the vehicle boot, host time = time_boot_ms + boot_offset_ms"
+    )]
+    pub boot_offset_ms: i64,
+    #[schemars(description = "Vehicle time since boot in milliseconds at the last sync")]
+    pub time_boot_ms: u32,
+    #[schemars(description = "Host unix time in milliseconds at the last sync")]
+    pub host_timestamp_ms: i64,
+    #[schemars(
+        description = "Vehicle unix time minus host unix time in milliseconds, from SYSTEM_TIME once the vehicle has a GPS time"
+    )]
+    pub unix_offset_ms: Option<i64>,
+}
+
+impl VehicleClock {
+    /// Written as the `vehicle_clock` metadata record of recordings
+    pub fn metadata(&self) -> BTreeMap<String, String> {
+        let mut metadata = BTreeMap::from([
+            (
+                "boot_offset_ms".to_string(),
+                self.boot_offset_ms.to_string(),
+            ),
+            ("time_boot_ms".to_string(), self.time_boot_ms.to_string()),
+            (
+                "host_timestamp_ms".to_string(),
+                self.host_timestamp_ms.to_string(),
+            ),
+        ]);
+        if let Some(boot_time) = chrono::DateTime::from_timestamp_millis(self.boot_offset_ms) {
+            metadata.insert("vehicle_boot_time".to_string(), boot_time.to_rfc3339());
+        }
+        if let Some(unix_offset_ms) = self.unix_offset_ms {
+            metadata.insert("unix_offset_ms".to_string(), unix_offset_ms.to_string());
+        }
+        metadata
+    }
+}
+
+/// Latest mapping, None until the vehicle sent a timestamped message
+pub fn current() -> Option<VehicleClock> {
+    *CLOCK.read().unwrap()
+}
+
+/// A message stamped with the vehicle boot time just arrived
+pub(super) fn observe_boot(time_boot_ms: u32) {
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut clock = CLOCK.write().unwrap();
+    *clock = Some(synced(*clock, time_boot_ms, now));
+}
+
+/// SYSTEM_TIME carries both the boot time and, with a GPS fix, the vehicle unix time
+pub(super) fn observe_system_time(system_time: &SYSTEM_TIME_DATA) {
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut clock = CLOCK.write().unwrap();
+    let mut synced = synced(*clock, system_time.time_boot_ms, now);
+    // Zero until the autopilot knows the time
+    if system_time.time_unix_usec > 0 {
+        synced.unix_offset_ms = Some((system_time.time_unix_usec / 1000) as i64 - now);
+    }
+    *clock = Some(synced);
+}
+
+// Messages only arrive late, the smallest offset seen is the closest to the real one
+fn synced(
+    previous: Option<VehicleClock>,
+    time_boot_ms: u32,
+    host_timestamp_ms: i64,
+) -> VehicleClock {
+    let offset = host_timestamp_ms - time_boot_ms as i64;
+    let (boot_offset_ms, unix_offset_ms) = match previous {
+        Some(previous) if (offset - previous.boot_offset_ms).abs() < RESYNC_THRESHOLD_MS => {
+            (previous.boot_offset_ms.min(offset), previous.unix_offset_ms)
+        }
+        _ => (offset, None),
+    };
+    VehicleClock {
+        boot_offset_ms,
+        time_boot_ms,
+        host_timestamp_ms,
+        unix_offset_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_keeps_the_lowest_latency() {
+        let clock = synced(None, 1_000, 10_050);
+        assert_eq!(clock.boot_offset_ms, 9_050);
+
+        // Arrived faster, the offset gets closer to the real one
+        let clock = synced(Some(clock), 2_000, 11_020);
+        assert_eq!(clock.boot_offset_ms, 9_020);
+        // Arrived slower, kept
+        let clock = synced(Some(clock), 3_000, 12_200);
+        assert_eq!(clock.boot_offset_ms, 9_020);
+        assert_eq!(clock.time_boot_ms, 3_000);
+
+        // The vehicle rebooted
+        let clock = synced(Some(clock), 500, 20_000);
+        assert_eq!(clock.boot_offset_ms, 19_500);
+    }
+}
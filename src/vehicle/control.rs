@@ -0,0 +1,203 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::Serialize;
+use serde_json::json;
+use tokio::{
+    sync::{watch, Notify},
+    time::{sleep, Duration},
+};
+use tracing::{error, info};
+
+use crate::cli::{
+    manager,
+    settings::{self, SettingsError},
+};
+
+// First retry after a failure, doubled on each failure in a row
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref STATUS: std::sync::RwLock<BridgeStatus> = std::sync::RwLock::new(BridgeStatus {
+        kind: BridgeKind::Zenoh,
+        endpoint: None,
+        state: BridgeState::Connecting,
+        since: chrono::Utc::now().to_rfc3339(),
+        last_error: None,
+        failures: 0,
+        retry_delay_ms: None,
+    });
+    static ref PAUSED: watch::Sender<bool> = watch::channel(false).0;
+    static ref RECONNECT: Notify = Notify::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeKind {
+    /// MAVLink messages from the Zenoh router, the default
+    Zenoh,
+    /// MAVLink connection of --vehicle-mavlink
+    Mavlink,
+    /// HTTP polling of --vehicle-mavlink2rest
+    Mavlink2rest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Apiv2Schema)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeState {
+    Connecting,
+    Connected,
+    /// Waiting retry_delay_ms after a failure
+    Retrying,
+    Paused,
+}
+
+/// Answer of the `/vehicle/bridge` routes
+#[derive(Debug, Clone, Serialize, Apiv2Schema)]
+pub struct BridgeStatus {
+    pub kind: BridgeKind,
+    /// Zenoh endpoint or MAVLink address in use
+    pub endpoint: Option<String>,
+    pub state: BridgeState,
+    /// When the bridge entered its current state
+    pub since: String,
+    pub last_error: Option<String>,
+    /// Failed connections in a row
+    pub failures: u32,
+    /// Delay before the next attempt while retrying
+    pub retry_delay_ms: Option<u64>,
+}
+
+pub fn status() -> BridgeStatus {
+    STATUS.read().unwrap().clone()
+}
+
+/// Drop the connection and open a new one, skipping the retry delay
+pub fn reconnect() {
+    info!("Vehicle bridge: Reconnect requested");
+    RECONNECT.notify_waiters();
+}
+
+/// Disconnect until resumed, the pose goes stale in the meantime
+pub fn set_paused(paused: bool) {
+    info!(
+        "Vehicle bridge: {}",
+        if paused { "Paused" } else { "Resumed" }
+    );
+    PAUSED.send_replace(paused);
+}
+
+/// Point the Zenoh bridge at another endpoint, None returns to the default one.
+/// Kept in the settings as `vehicle_bridge_endpoint`, the bridge reconnects on the change.
+pub fn set_endpoint(endpoint: Option<String>) -> Result<(), SettingsError> {
+    let option = match status().kind {
+        BridgeKind::Zenoh => None,
+        BridgeKind::Mavlink => Some("--vehicle-mavlink"),
+        BridgeKind::Mavlink2rest => Some("--vehicle-mavlink2rest"),
+    };
+    if let Some(option) = option {
+        return Err(SettingsError::Invalid(format!(
+            "The vehicle data comes from {option}, its address is set on the command line"
+        )));
+    }
+    if let Some(option) = manager::overriding_option("vehicle_bridge_endpoint") {
+        return Err(SettingsError::Invalid(format!(
+            "The vehicle bridge endpoint is set by {option}"
+        )));
+    }
+    settings::update(&json!({ "vehicle_bridge_endpoint": endpoint })).map(|_| ())
+}
+
+fn next_retry_delay(delay: Duration) -> Duration {
+    (delay * 2).min(MAX_RETRY_DELAY)
+}
+
+fn set_state(state: BridgeState, update: impl FnOnce(&mut BridgeStatus)) {
+    let mut status = STATUS.write().unwrap();
+    if status.state != state {
+        status.state = state;
+        status.since = chrono::Utc::now().to_rfc3339();
+    }
+    update(&mut status);
+}
+
+/// Connection loop side of the control API, one per running bridge
+pub(super) struct Bridge {
+    retry_delay: Duration,
+    paused: watch::Receiver<bool>,
+}
+
+impl Bridge {
+    pub(super) fn new(kind: BridgeKind) -> Self {
+        STATUS.write().unwrap().kind = kind;
+        Self {
+            retry_delay: MIN_RETRY_DELAY,
+            paused: PAUSED.subscribe(),
+        }
+    }
+
+    /// Returns right away unless the bridge is paused
+    pub(super) async fn wait_resumed(&mut self) {
+        if *self.paused.borrow_and_update() {
+            set_state(BridgeState::Paused, |status| status.retry_delay_ms = None);
+            // The sender lives in a static, it is never dropped
+            let _ = self.paused.wait_for(|paused| !paused).await;
+        }
+    }
+
+    pub(super) fn connecting(&self, endpoint: &str) {
+        set_state(BridgeState::Connecting, |status| {
+            status.endpoint = Some(endpoint.to_string());
+            status.retry_delay_ms = None;
+        });
+    }
+
+    pub(super) fn connected(&mut self) {
+        self.retry_delay = MIN_RETRY_DELAY;
+        set_state(BridgeState::Connected, |status| status.failures = 0);
+    }
+
+    /// Record the failure and wait before the next attempt, a reconnect or pause request cuts the wait short
+    pub(super) async fn retry(&mut self, err: String) {
+        let delay = self.retry_delay;
+        error!(
+            "Vehicle bridge: {err}, retrying in {:.0}s",
+            delay.as_secs_f64()
+        );
+        set_state(BridgeState::Retrying, |status| {
+            status.last_error = Some(err);
+            status.failures += 1;
+            status.retry_delay_ms = Some(delay.as_millis() as u64);
+        });
+        self.retry_delay = next_retry_delay(delay);
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = self.interrupted() => {}
+        }
+    }
+
+    /// Completes when the connection has to be dropped, on a reconnect or pause request
+    pub(super) async fn interrupted(&mut self) {
+        tokio::select! {
+            _ = RECONNECT.notified() => {}
+            _ = self.paused.wait_for(|paused| *paused) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        let mut delay = MIN_RETRY_DELAY;
+        let delays: Vec<_> = (0..7)
+            .map(|_| {
+                delay = next_retry_delay(delay);
+                delay.as_secs()
+            })
+            .collect();
+        assert_eq!(delays, vec![2, 4, 8, 16, 30, 30, 30]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use mavlink::{
+    ardupilotmega::{MavAutopilot, MavMessage, MavModeFlag, MavState, MavType, HEARTBEAT_DATA},
+    AsyncMavConnection, MavHeader, Message,
+};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, info};
+
+use super::{
+    clock,
+    control::{Bridge, BridgeKind},
+    dvl::DvlFilter,
+    stream, systems, PoseSources,
+};
+
+// Links like udpout only receive once the other side knows about us
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+
+type Connection = Arc<Box<dyn AsyncMavConnection<MavMessage> + Sync + Send>>;
+
+/// Same VehicleData as the Zenoh bridge, read from a MAVLink connection string of the mavlink crate,
+/// e.g. `udpin:0.0.0.0:14550`, `udpout:192.168.2.1:14550`, `tcpout:127.0.0.1:5760` or `serial:/dev/ttyACM0:115200`
+pub async fn mavlink_bridge(address: String) {
+    let mut bridge = Bridge::new(BridgeKind::Mavlink);
+    loop {
+        bridge.wait_resumed().await;
+        bridge.connecting(&address);
+        let connection: Connection = match mavlink::connect_async::<MavMessage>(&address).await {
+            Ok(connection) => Arc::new(connection),
+            Err(err) => {
+                bridge
+                    .retry(format!("MAVLink connection to {address} failed: {err}"))
+                    .await;
+                continue;
+            }
+        };
+        info!("Reading the vehicle data from MAVLink {address}");
+        bridge.connected();
+
+        let heartbeat = tokio::spawn(send_heartbeats(connection.clone()));
+        let lost = tokio::select! {
+            _ = receive(&connection) => true,
+            _ = bridge.interrupted() => false,
+        };
+        heartbeat.abort();
+
+        if lost {
+            bridge
+                .retry(format!("MAVLink connection to {address} lost"))
+                .await;
+        }
+    }
+}
+
+async fn receive(connection: &Connection) {
+    let mut sources = PoseSources::default();
+    let filter = systems::VehicleFilter::from_cli();
+    let dvl_filter = DvlFilter::from_cli();
+
+    loop {
+        let (header, message) = match connection.recv().await {
+            Ok(received) => received,
+            // Corrupted or unknown messages are common on serial links, only I/O errors end the connection
+            Err(mavlink::error::MessageReadError::Io(err)) => {
+                error!("MAVLink read error: {err}");
+                return;
+            }
+            Err(err) => {
+                debug!("Skipping MAVLink message: {err}");
+                continue;
+            }
+        };
+        if stream::has_subscribers() && filter.matches(header.system_id, header.component_id) {
+            match serde_json::to_value(&message) {
+                Ok(value) => stream::publish(
+                    header.system_id,
+                    header.component_id,
+                    message.message_name(),
+                    value,
+                ),
+                Err(err) => debug!("Skipping MAVLink message for the stream: {err}"),
+            }
+        }
+        if let MavMessage::HEARTBEAT(_) = message {
+            systems::seen(header.system_id, header.component_id);
+            continue;
+        }
+        // The DVL odometry comes from the DVL driver ids, not the autopilot ones
+        if dvl_filter.matches(header.system_id, header.component_id) {
+            match message {
+                MavMessage::VISION_POSITION_ESTIMATE(position) => {
+                    sources.vision_position = Some(position);
+                    sources.publish();
+                    continue;
+                }
+                MavMessage::VISION_SPEED_ESTIMATE(speed) => {
+                    sources.vision_speed = Some(speed);
+                    sources.publish();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if !filter.matches(header.system_id, header.component_id) {
+            continue;
+        }
+        if let MavMessage::SYSTEM_TIME(system_time) = message {
+            clock::observe_system_time(&system_time);
+            continue;
+        }
+        match message {
+            MavMessage::ATTITUDE(attitude) => sources.set_attitude(attitude),
+            MavMessage::GLOBAL_POSITION_INT(position) => sources.position = Some(position),
+            MavMessage::VFR_HUD(vfr_hud) => sources.vfr_hud = Some(vfr_hud),
+            MavMessage::SCALED_PRESSURE2(pressure) => sources.pressure = Some(pressure),
+            MavMessage::GPS_GLOBAL_ORIGIN(origin) => sources.origin = Some(origin),
+            _ => continue,
+        }
+        sources.publish();
+    }
+}
+
+async fn send_heartbeats(connection: Connection) {
+    let header = MavHeader::default();
+    let heartbeat = MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+        custom_mode: 0,
+        mavtype: MavType::MAV_TYPE_ONBOARD_CONTROLLER,
+        autopilot: MavAutopilot::MAV_AUTOPILOT_INVALID,
+        base_mode: MavModeFlag::empty(),
+        system_status: MavState::MAV_STATE_ACTIVE,
+        mavlink_version: 3,
+    });
+    loop {
+        if let Err(err) = connection.send(&header, &heartbeat).await {
+            debug!("Failed to send MAVLink heartbeat: {err}");
+        }
+        sleep(HEARTBEAT_PERIOD).await;
+    }
+}
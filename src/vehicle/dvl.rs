@@ -0,0 +1,93 @@
+use mavlink::ardupilotmega::GPS_GLOBAL_ORIGIN_DATA;
+
+// Mean earth radius of WGS84, enough for the distances a DVL travels from its origin
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Who sends the DVL odometry, VISION_POSITION_ESTIMATE and VISION_SPEED_ESTIMATE.
+/// DVL drivers such as the Water Linked extension send them from their own ids, not the autopilot ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DvlFilter {
+    /// Any system when unset
+    pub system_id: Option<u8>,
+    /// Any component when unset
+    pub component_id: Option<u8>,
+}
+
+impl DvlFilter {
+    pub fn from_cli() -> Self {
+        Self {
+            system_id: crate::cli::manager::dvl_system_id(),
+            component_id: crate::cli::manager::dvl_component_id(),
+        }
+    }
+
+    pub fn matches(&self, system_id: u8, component_id: u8) -> bool {
+        self.system_id.is_none_or(|id| id == system_id)
+            && self.component_id.is_none_or(|id| id == component_id)
+    }
+
+    /// Zenoh key of every VISION_* message of the DVL
+    pub fn key_expr(&self) -> String {
+        let id = |id: Option<u8>| id.map_or_else(|| "*".to_string(), |id| id.to_string());
+        format!(
+            "mavlink/{}/{}/VISION_$*",
+            id(self.system_id),
+            id(self.component_id)
+        )
+    }
+}
+
+/// Latitude, longitude and altitude of a position in meters north, east and down of the navigation origin
+pub fn global_position(
+    origin: &GPS_GLOBAL_ORIGIN_DATA,
+    [north, east, down]: [f32; 3],
+) -> (f64, f64, f64) {
+    let origin_lat = origin.latitude as f64 / 1e7;
+    let origin_lon = origin.longitude as f64 / 1e7;
+    let lat = origin_lat + (north as f64 / EARTH_RADIUS_M).to_degrees();
+    let lon =
+        origin_lon + (east as f64 / (EARTH_RADIUS_M * origin_lat.to_radians().cos())).to_degrees();
+    (lat, lon, origin.altitude as f64 / 1000.0 - down as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_global_position_from_origin() {
+        let origin = GPS_GLOBAL_ORIGIN_DATA {
+            latitude: -275_000_000,
+            longitude: -485_000_000,
+            altitude: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            global_position(&origin, [0.0, 0.0, 0.0]),
+            (-27.5, -48.5, 0.0)
+        );
+
+        // A degree of latitude is about 111 km
+        let (lat, lon, alt) = global_position(&origin, [1111.95, 0.0, 10.0]);
+        assert!((lat - -27.49).abs() < 1e-5, "{lat}");
+        assert_eq!(lon, -48.5);
+        assert_eq!(alt, -10.0);
+    }
+
+    #[test]
+    fn test_filter() {
+        let any = DvlFilter {
+            system_id: None,
+            component_id: None,
+        };
+        assert!(any.matches(255, 0));
+        assert_eq!(any.key_expr(), "mavlink/*/*/VISION_$*");
+
+        let dvl = DvlFilter {
+            system_id: Some(1),
+            component_id: Some(197),
+        };
+        assert!(!dvl.matches(1, 1));
+        assert_eq!(dvl.key_expr(), "mavlink/1/197/VISION_$*");
+    }
+}
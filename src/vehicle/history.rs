@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use lazy_static::lazy_static;
+
+use super::VehicleData;
+
+// Poses kept, a few seconds with every attitude, position, VFR_HUD and pressure update pushing one
+const HISTORY_SIZE: usize = 256;
+
+lazy_static! {
+    static ref HISTORY: std::sync::RwLock<VecDeque<VehicleData>> =
+        std::sync::RwLock::new(VecDeque::with_capacity(HISTORY_SIZE));
+}
+
+pub(super) fn push(pose: VehicleData) {
+    if let Ok(mut history) = HISTORY.write() {
+        push_into(&mut history, pose);
+    }
+}
+
+fn push_into(history: &mut VecDeque<VehicleData>, pose: VehicleData) {
+    if history.len() == HISTORY_SIZE {
+        history.pop_front();
+    }
+    history.push_back(pose);
+}
+
+/// Newest pose received, stale or not
+pub fn latest() -> Option<VehicleData> {
+    HISTORY.read().ok()?.back().cloned()
+}
+
+/// Pose at a unix time in milliseconds, interpolated between the updates around it.
+/// Past the last update the last pose is returned, unless it is stale.
+pub fn pose_at(timestamp_ms: i64) -> Option<VehicleData> {
+    let history = HISTORY.read().ok()?;
+    let pose = interpolate(&history, timestamp_ms)?;
+    (!pose.is_stale()).then_some(pose)
+}
+
+fn interpolate(history: &VecDeque<VehicleData>, timestamp_ms: i64) -> Option<VehicleData> {
+    let newest = history.back()?;
+    if timestamp_ms >= newest.timestamp_ms {
+        return Some(newest.clone());
+    }
+    // Poses are pushed in time order
+    let after = history.partition_point(|pose| pose.timestamp_ms <= timestamp_ms);
+    let (before, after) = (history.get(after.checked_sub(1)?)?, &history[after]);
+
+    let span = (after.timestamp_ms - before.timestamp_ms) as f64;
+    let t = if span > 0.0 {
+        (timestamp_ms - before.timestamp_ms) as f64 / span
+    } else {
+        0.0
+    };
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+    let lerp_angle = |a: f32, b: f32| {
+        // The shortest way around, a yaw going from 359° to 1° does not turn back, and stays in [-π, π)
+        let delta = (b - a + PI).rem_euclid(2.0 * PI) - PI;
+        (a + delta * t as f32 + PI).rem_euclid(2.0 * PI) - PI
+    };
+    Some(VehicleData {
+        roll: lerp_angle(before.roll, after.roll),
+        pitch: lerp_angle(before.pitch, after.pitch),
+        yaw: lerp_angle(before.yaw, after.yaw),
+        alt: lerp(before.alt, after.alt),
+        lat: lerp(before.lat, after.lat),
+        lon: lerp(before.lon, after.lon),
+        heading: after.heading,
+        groundspeed: after.groundspeed,
+        depth: match (before.depth, after.depth) {
+            (Some(a), Some(b)) => Some(lerp(a, b)),
+            (_, depth) => depth,
+        },
+        local_position: match (before.local_position, after.local_position) {
+            (Some(a), Some(b)) => {
+                Some([0, 1, 2].map(|axis| lerp(a[axis] as f64, b[axis] as f64) as f32))
+            }
+            (_, local_position) => local_position,
+        },
+        velocity: after.velocity,
+        timestamp_ms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(timestamp_ms: i64, yaw: f32, lat: f64) -> VehicleData {
+        VehicleData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw,
+            alt: 0.0,
+            lat,
+            lon: 0.0,
+            heading: None,
+            groundspeed: None,
+            depth: None,
+            local_position: None,
+            velocity: None,
+            timestamp_ms,
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut history = VecDeque::new();
+        assert!(interpolate(&history, 1000).is_none());
+
+        push_into(&mut history, pose(1000, 3.0, 10.0));
+        push_into(&mut history, pose(2000, -3.0, 20.0));
+
+        let middle = interpolate(&history, 1500).unwrap();
+        assert!((middle.lat - 15.0).abs() < 1e-9);
+        // Across ±π the yaw keeps turning the same way
+        assert!(middle.yaw.abs() > 3.0, "{}", middle.yaw);
+        assert_eq!(middle.timestamp_ms, 1500);
+
+        assert_eq!(interpolate(&history, 3000).unwrap().lat, 20.0);
+        assert!(interpolate(&history, 500).is_none());
+    }
+
+    #[test]
+    fn test_interpolated_yaw_wraps_across_the_seam() {
+        let mut history = VecDeque::new();
+        push_into(&mut history, pose(1000, 3.0, 0.0));
+        push_into(&mut history, pose(2000, -3.0, 0.0));
+
+        // Past +π the yaw continues from -π
+        let yaw = interpolate(&history, 1750).unwrap().yaw;
+        assert!((-PI..PI).contains(&yaw), "{yaw}");
+        assert!(
+            (yaw - (3.0 + 0.75 * (2.0 * PI - 6.0) - 2.0 * PI)).abs() < 1e-4,
+            "{yaw}"
+        );
+        let yaw = interpolate(&history, 1250).unwrap().yaw;
+        assert!(
+            (yaw - (3.0 + 0.25 * (2.0 * PI - 6.0))).abs() < 1e-4,
+            "{yaw}"
+        );
+    }
+}
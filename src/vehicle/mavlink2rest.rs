@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use mavlink::ardupilotmega::{
+    ATTITUDE_DATA, GLOBAL_POSITION_INT_DATA, GPS_GLOBAL_ORIGIN_DATA, SCALED_PRESSURE2_DATA,
+    SYSTEM_TIME_DATA, VFR_HUD_DATA, VISION_POSITION_ESTIMATE_DATA, VISION_SPEED_ESTIMATE_DATA,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::time::{Duration, MissedTickBehavior};
+use tracing::info;
+
+use super::{
+    clock,
+    control::{Bridge, BridgeKind},
+    dvl::DvlFilter,
+    systems::VehicleFilter,
+    PoseSources,
+};
+
+// The autopilot streams the attitude at about 10 Hz
+const POLL_PERIOD: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+// mavlink2rest needs a system id in the path, ArduPilot vehicles use 1 by default
+const DEFAULT_SYSTEM_ID: u8 = 1;
+
+#[derive(Deserialize)]
+struct Reply<T> {
+    message: T,
+    #[serde(default)]
+    status: Status,
+}
+
+#[derive(Default, Deserialize)]
+struct Status {
+    #[serde(default)]
+    time: Time,
+}
+
+#[derive(Default, Deserialize)]
+struct Time {
+    #[serde(default)]
+    last_update: String,
+}
+
+/// Same VehicleData as the Zenoh bridge, polled from a mavlink2rest instance for setups without a Zenoh router,
+/// e.g. `http://blueos.local/mavlink2rest` or `http://127.0.0.1:6040`
+pub async fn mavlink2rest_bridge(url: String) {
+    let base = url.trim_end_matches('/').to_string();
+    let filter = VehicleFilter::from_cli();
+    let messages = format!(
+        "{base}/v1/mavlink/vehicles/{}/components/{}/messages",
+        filter.system_id.unwrap_or(DEFAULT_SYSTEM_ID),
+        filter.component_id
+    );
+    // mavlink2rest files messages under their sender, the DVL is only polled once both its ids are known
+    let dvl_filter = DvlFilter::from_cli();
+    let dvl_messages =
+        dvl_filter
+            .system_id
+            .zip(dvl_filter.component_id)
+            .map(|(system_id, component_id)| {
+                format!("{base}/v1/mavlink/vehicles/{system_id}/components/{component_id}/messages")
+            });
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to build the mavlink2rest HTTP client");
+    let mut bridge = Bridge::new(BridgeKind::Mavlink2rest);
+
+    loop {
+        bridge.wait_resumed().await;
+        bridge.connecting(&base);
+
+        let mut poller = Poller {
+            client: &client,
+            messages: &messages,
+            dvl_messages: dvl_messages.as_deref(),
+            last_updates: HashMap::new(),
+        };
+        let mut sources = PoseSources::default();
+        let mut connected = false;
+        let mut interval = tokio::time::interval(POLL_PERIOD);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let failure = loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = bridge.interrupted() => break None,
+            }
+            match poller.poll(&mut sources).await {
+                Ok(updated) => {
+                    if !connected {
+                        info!("Reading the vehicle data from mavlink2rest {base}");
+                        bridge.connected();
+                        connected = true;
+                    }
+                    if updated {
+                        sources.publish();
+                    }
+                }
+                Err(err) => break Some(err),
+            }
+        };
+        if let Some(failure) = failure {
+            bridge.retry(failure).await;
+        }
+    }
+}
+
+struct Poller<'a> {
+    client: &'a reqwest::Client,
+    messages: &'a str,
+    dvl_messages: Option<&'a str>,
+    // mavlink2rest keeps serving the last message after the vehicle is gone, only new ones update the pose
+    last_updates: HashMap<String, String>,
+}
+
+impl Poller<'_> {
+    /// True when any of the messages changed since the last poll
+    async fn poll(&mut self, sources: &mut PoseSources) -> Result<bool, String> {
+        let mut updated = false;
+        if let Some(attitude) = self.fetch::<ATTITUDE_DATA>("ATTITUDE").await? {
+            sources.set_attitude(attitude);
+            updated = true;
+        }
+        if let Some(position) = self
+            .fetch::<GLOBAL_POSITION_INT_DATA>("GLOBAL_POSITION_INT")
+            .await?
+        {
+            sources.position = Some(position);
+            updated = true;
+        }
+        if let Some(vfr_hud) = self.fetch::<VFR_HUD_DATA>("VFR_HUD").await? {
+            sources.vfr_hud = Some(vfr_hud);
+            updated = true;
+        }
+        if let Some(pressure) = self
+            .fetch::<SCALED_PRESSURE2_DATA>("SCALED_PRESSURE2")
+            .await?
+        {
+            sources.pressure = Some(pressure);
+            updated = true;
+        }
+        if let Some(origin) = self
+            .fetch::<GPS_GLOBAL_ORIGIN_DATA>("GPS_GLOBAL_ORIGIN")
+            .await?
+        {
+            sources.origin = Some(origin);
+            updated = true;
+        }
+        if let Some(dvl_messages) = self.dvl_messages {
+            if let Some(position) = self
+                .fetch_at::<VISION_POSITION_ESTIMATE_DATA>(dvl_messages, "VISION_POSITION_ESTIMATE")
+                .await?
+            {
+                sources.vision_position = Some(position);
+                updated = true;
+            }
+            if let Some(speed) = self
+                .fetch_at::<VISION_SPEED_ESTIMATE_DATA>(dvl_messages, "VISION_SPEED_ESTIMATE")
+                .await?
+            {
+                sources.vision_speed = Some(speed);
+                updated = true;
+            }
+        }
+        if let Some(system_time) = self.fetch::<SYSTEM_TIME_DATA>("SYSTEM_TIME").await? {
+            clock::observe_system_time(&system_time);
+        }
+        Ok(updated)
+    }
+
+    async fn fetch<T: DeserializeOwned>(&mut self, name: &str) -> Result<Option<T>, String> {
+        self.fetch_at(self.messages, name).await
+    }
+
+    /// None while the message was never received or did not change
+    async fn fetch_at<T: DeserializeOwned>(
+        &mut self,
+        messages: &str,
+        name: &str,
+    ) -> Result<Option<T>, String> {
+        let url = format!("{messages}/{name}");
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|err| format!("mavlink2rest request {url} failed: {err}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let reply: Reply<T> = response
+            .error_for_status()
+            .map_err(|err| format!("mavlink2rest request {url} failed: {err}"))?
+            .json()
+            .await
+            .map_err(|err| format!("Invalid mavlink2rest {name} message: {err}"))?;
+
+        let last_update = reply.status.time.last_update;
+        if !last_update.is_empty() && self.last_updates.get(&url) == Some(&last_update) {
+            return Ok(None);
+        }
+        self.last_updates.insert(url, last_update);
+        Ok(Some(reply.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_parsing() {
+        let reply: Reply<ATTITUDE_DATA> = serde_json::from_str(
+            r#"{
+                "message": {
+                    "type": "ATTITUDE", "time_boot_ms": 1000, "roll": 0.1, "pitch": -0.2, "yaw": 1.5,
+                    "rollspeed": 0.0, "pitchspeed": 0.0, "yawspeed": 0.0
+                },
+                "status": {"time": {"first_update": "2025-01-01T00:00:00Z", "last_update": "2025-01-01T00:00:01Z",
+                    "counter": 10, "frequency": 10.0}}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(reply.message.yaw, 1.5);
+        assert_eq!(reply.status.time.last_update, "2025-01-01T00:00:01Z");
+    }
+}
@@ -1,14 +1,36 @@
-use std::sync::Arc;
+/// Specially for aligning recordings with the autopilot logs, mapping the vehicle clocks to the host clock
+pub mod clock;
+/// Specially for inspecting and steering the bridge connection at runtime
+pub mod control;
+/// Specially for installations without a Zenoh router, reading the vehicle MAVLink stream directly
+pub mod direct;
+/// Specially for vehicles navigating by DVL, placing them from the odometry and the navigation origin
+pub mod dvl;
+/// Specially for looking up the pose at the time of a sonar sample
+pub mod history;
+/// Specially for vehicles reachable only through mavlink2rest, polling it over HTTP
+#[cfg(feature = "mavlink2rest")]
+pub mod mavlink2rest;
+/// Specially for recording the raw vehicle telemetry next to the sonar data
+pub mod stream;
+/// Specially for vehicles with non-default MAVLink ids, selecting and listing the systems seen
+pub mod systems;
 
 use mavlink::ardupilotmega::ATTITUDE_DATA;
 use mavlink::ardupilotmega::GLOBAL_POSITION_INT_DATA;
+use mavlink::ardupilotmega::GPS_GLOBAL_ORIGIN_DATA;
+use mavlink::ardupilotmega::SCALED_PRESSURE2_DATA;
+use mavlink::ardupilotmega::SYSTEM_TIME_DATA;
+use mavlink::ardupilotmega::VFR_HUD_DATA;
+use mavlink::ardupilotmega::VISION_POSITION_ESTIMATE_DATA;
+use mavlink::ardupilotmega::VISION_SPEED_ESTIMATE_DATA;
 
+use paperclip::actix::Apiv2Schema;
 use serde::Deserialize;
 use serde::Serialize;
-use tokio::sync::RwLock;
-use tracing::{error, info};
+use tracing::info;
 
-#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema, Apiv2Schema)]
 pub struct VehicleData {
     #[schemars(description = "Roll angle in radians")]
     pub roll: f32,
@@ -22,6 +44,126 @@ pub struct VehicleData {
     pub lat: f64,
     #[schemars(description = "Longitude in decimal degrees")]
     pub lon: f64,
+    #[schemars(description = "Heading in degrees from north, from VFR_HUD")]
+    pub heading: Option<f32>,
+    #[schemars(description = "Speed over ground in meters per second, from VFR_HUD")]
+    pub groundspeed: Option<f32>,
+    #[schemars(
+        description = "Depth below the surface in meters, from the SCALED_PRESSURE2 water pressure sensor"
+    )]
+    pub depth: Option<f64>,
+    #[schemars(
+        description = "Meters north, east and down of the navigation origin, from the DVL VISION_POSITION_ESTIMATE"
+    )]
+    #[serde(default)]
+    pub local_position: Option<[f32; 3]>,
+    #[schemars(
+        description = "Velocity north, east and down in meters per second, from the DVL VISION_SPEED_ESTIMATE"
+    )]
+    #[serde(default)]
+    pub velocity: Option<[f32; 3]>,
+    #[schemars(
+        description = "Unix time in milliseconds of the MAVLink update this pose comes from"
+    )]
+    #[serde(default)]
+    pub timestamp_ms: i64,
+}
+
+impl VehicleData {
+    /// Time since the pose was updated
+    pub fn age(&self) -> chrono::Duration {
+        chrono::Utc::now()
+            - chrono::DateTime::from_timestamp_millis(self.timestamp_ms).unwrap_or_default()
+    }
+
+    /// Older than --vehicle-stale-after, the link to the autopilot is probably gone
+    pub fn is_stale(&self) -> bool {
+        self.age().to_std().unwrap_or_default() > crate::cli::manager::vehicle_stale_after()
+    }
+}
+
+// Sea level, the pressure the sensor reads at the surface
+const SURFACE_PRESSURE_HPA: f32 = 1013.25;
+const GRAVITY: f64 = 9.80665;
+
+/// Latest MAVLink messages a pose is built from, shared by the Zenoh and MAVLink sources
+#[derive(Debug, Default)]
+struct PoseSources {
+    attitude: Option<ATTITUDE_DATA>,
+    position: Option<GLOBAL_POSITION_INT_DATA>,
+    vfr_hud: Option<VFR_HUD_DATA>,
+    pressure: Option<SCALED_PRESSURE2_DATA>,
+    origin: Option<GPS_GLOBAL_ORIGIN_DATA>,
+    vision_position: Option<VISION_POSITION_ESTIMATE_DATA>,
+    vision_speed: Option<VISION_SPEED_ESTIMATE_DATA>,
+}
+
+impl PoseSources {
+    /// Available once the attitude and a GPS or DVL position arrived, the other fields are optional
+    fn pose(&self, timestamp_ms: i64) -> Option<VehicleData> {
+        let attitude = self.attitude.as_ref()?;
+        let local_position = self
+            .vision_position
+            .as_ref()
+            .map(|position| [position.x, position.y, position.z]);
+        let fix = self
+            .position
+            .as_ref()
+            .filter(|position| position.lat != 0 || position.lon != 0);
+        // A GPS fix first, without one the DVL position placed from the navigation origin.
+        // Zero latitude and longitude mean no fix, see location_fix.
+        let (lat, lon, alt) = match (fix, self.origin.as_ref(), local_position) {
+            (Some(fix), _, _) => (
+                fix.lat as f64 / 1e7,
+                fix.lon as f64 / 1e7,
+                fix.alt as f64 / 1000.0,
+            ),
+            (None, Some(origin), Some(local_position)) => {
+                dvl::global_position(origin, local_position)
+            }
+            (None, _, _) => match (&self.position, local_position) {
+                (Some(position), _) => (0.0, 0.0, position.alt as f64 / 1000.0),
+                (None, Some(_)) => (0.0, 0.0, 0.0),
+                (None, None) => return None,
+            },
+        };
+        Some(VehicleData {
+            roll: attitude.roll,
+            pitch: attitude.pitch,
+            yaw: attitude.yaw,
+            alt,
+            lat,
+            lon,
+            heading: self.vfr_hud.as_ref().map(|vfr_hud| vfr_hud.heading as f32),
+            groundspeed: self.vfr_hud.as_ref().map(|vfr_hud| vfr_hud.groundspeed),
+            depth: self.pressure.as_ref().map(|pressure| {
+                pressure_depth(pressure.press_abs, crate::cli::manager::water_density())
+            }),
+            local_position,
+            velocity: self
+                .vision_speed
+                .as_ref()
+                .map(|speed| [speed.x, speed.y, speed.z]),
+            timestamp_ms,
+        })
+    }
+
+    /// The attitude is the most frequent message stamped with the vehicle boot time, it keeps the clock in sync
+    fn set_attitude(&mut self, attitude: ATTITUDE_DATA) {
+        clock::observe_boot(attitude.time_boot_ms);
+        self.attitude = Some(attitude);
+    }
+
+    fn publish(&self) {
+        if let Some(pose) = self.pose(chrono::Utc::now().timestamp_millis()) {
+            history::push(pose);
+        }
+    }
+}
+
+// Hydrostatic depth of an absolute pressure in hPa, with the water density in kg/m³
+fn pressure_depth(press_abs: f32, water_density: f64) -> f64 {
+    ((press_abs - SURFACE_PRESSURE_HPA) as f64 * 100.0 / (water_density * GRAVITY)).max(0.0)
 }
 
 #[derive(Deserialize)]
@@ -29,7 +171,14 @@ struct Envelope<T> {
     message: T,
 }
 
-fn make_default_config(node_name: &str) -> zenoh::Config {
+// Used unless --vehicle-bridge-endpoint or the settings point the bridge somewhere else
+const DEFAULT_ENDPOINT: &str = "tcp/127.0.0.1:7447";
+
+pub(crate) fn bridge_endpoint() -> String {
+    crate::cli::manager::vehicle_bridge_endpoint().unwrap_or_else(|| DEFAULT_ENDPOINT.to_string())
+}
+
+pub(crate) fn make_default_config(node_name: &str, endpoint: &str) -> zenoh::Config {
     let mut config = zenoh::Config::default();
 
     // Set client mode (common to both)
@@ -43,66 +192,264 @@ fn make_default_config(node_name: &str) -> zenoh::Config {
         .insert_json5("adminspace/enabled", r#"true"#)
         .expect("Failed to insert adminspace/enabled");
     config
-        .insert_json5("connect/endpoints", r#"["tcp/127.0.0.1:7447"]"#)
+        .insert_json5(
+            "connect/endpoints",
+            &serde_json::json!([endpoint]).to_string(),
+        )
         .expect("Failed to insert endpoints");
-    info!("Generated zenoh config with default settings");
+    info!("Generated zenoh config for {endpoint}");
     config
 }
 
-pub async fn zenoh_client_bridge(latest_pose: Arc<RwLock<Option<VehicleData>>>) {
-    use tokio::time::{sleep, Duration};
+pub async fn zenoh_client_bridge() {
     let node_name = env!("CARGO_PKG_NAME");
 
-    let reconnect_delay_secs = 5;
-    let reconnect_delay = Duration::from_secs(reconnect_delay_secs);
+    let mut settings = crate::cli::settings::subscribe();
+    let filter = systems::VehicleFilter::from_cli();
+    let dvl_filter = dvl::DvlFilter::from_cli();
+    let mut bridge = control::Bridge::new(control::BridgeKind::Zenoh);
 
     loop {
-        let config = make_default_config(node_name);
-
-        sleep(reconnect_delay).await;
+        bridge.wait_resumed().await;
+        let endpoint = bridge_endpoint();
+        let config = make_default_config(node_name, &endpoint);
+        bridge.connecting(&endpoint);
 
         let session = match zenoh::open(config).await {
             Ok(s) => s,
             Err(e) => {
-                error!("Zenoh session error: {e}, retrying in {reconnect_delay_secs}s");
+                bridge.retry(format!("Zenoh session error: {e}")).await;
+                continue;
+            }
+        };
+        let attitude_key = filter.key_expr("ATTITUDE");
+        let attitude_sub = match session.declare_subscriber(&attitude_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for ATTITUDE: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        let position_key = filter.key_expr("GLOBAL_POSITION_INT");
+        let position_sub = match session.declare_subscriber(&position_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!(
+                        "Zenoh subscribe error for GLOBAL_POSITION_INT: {e}"
+                    ))
+                    .await;
                 continue;
             }
         };
-        let attitude_sub = match session.declare_subscriber("mavlink/**/1/ATTITUDE").await {
+        let vfr_hud_key = filter.key_expr("VFR_HUD");
+        let vfr_hud_sub = match session.declare_subscriber(&vfr_hud_key).await {
             Ok(s) => s,
             Err(e) => {
-                error!(
-                    "Zenoh subscribe error for ATTITUDE: {e}, retrying in {reconnect_delay_secs}s"
-                );
+                bridge
+                    .retry(format!("Zenoh subscribe error for VFR_HUD: {e}"))
+                    .await;
                 continue;
             }
         };
-        let position_sub = match session
-            .declare_subscriber("mavlink/**/1/GLOBAL_POSITION_INT")
-            .await
-        {
+        let pressure_key = filter.key_expr("SCALED_PRESSURE2");
+        let pressure_sub = match session.declare_subscriber(&pressure_key).await {
             Ok(s) => s,
             Err(e) => {
-                error!("Zenoh subscribe error for GLOBAL_POSITION_INT: {e}, retrying in {reconnect_delay_secs}s");
+                bridge
+                    .retry(format!("Zenoh subscribe error for SCALED_PRESSURE2: {e}"))
+                    .await;
                 continue;
             }
         };
-        info!("Subscribed to mavlink/**/1/ATTITUDE and mavlink/**/1/GLOBAL_POSITION_INT");
+        let system_time_key = filter.key_expr("SYSTEM_TIME");
+        let system_time_sub = match session.declare_subscriber(&system_time_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for SYSTEM_TIME: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        let origin_key = filter.key_expr("GPS_GLOBAL_ORIGIN");
+        let origin_sub = match session.declare_subscriber(&origin_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for GPS_GLOBAL_ORIGIN: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        let dvl_key = dvl_filter.key_expr();
+        let dvl_sub = match session.declare_subscriber(&dvl_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for {dvl_key}: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        // Every system, whatever the filter, so GET /vehicle/systems can show the ids to use
+        let heartbeat_sub = match session.declare_subscriber("mavlink/*/*/HEARTBEAT").await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for HEARTBEAT: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        // Every message of the vehicle, only parsed while a recording keeps MAVLink topics
+        let stream_key = filter.key_expr("*");
+        let stream_sub = match session.declare_subscriber(&stream_key).await {
+            Ok(s) => s,
+            Err(e) => {
+                bridge
+                    .retry(format!("Zenoh subscribe error for {stream_key}: {e}"))
+                    .await;
+                continue;
+            }
+        };
+        info!("Subscribed to {attitude_key}, {position_key}, {vfr_hud_key}, {pressure_key}, {system_time_key}, {origin_key} and {dvl_key}");
+
+        bridge.connected();
 
-        let mut latest_attitude: Option<ATTITUDE_DATA> = None;
-        let mut latest_position: Option<GLOBAL_POSITION_INT_DATA> = None;
+        let mut sources = PoseSources::default();
+        let mut failure = None;
 
         loop {
             tokio::select! {
+                _ = bridge.interrupted() => break,
+                Ok(()) = settings.changed() => {
+                    if bridge_endpoint() != endpoint {
+                        info!("Vehicle bridge endpoint changed, reconnecting");
+                        break;
+                    }
+                    continue;
+                }
+                res = heartbeat_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if let Some((system_id, component_id)) = systems::parse_key(sample.key_expr().as_str()) {
+                                systems::seen(system_id, component_id);
+                            }
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh HEARTBEAT recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = stream_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if stream::has_subscribers() {
+                                if let Some((system_id, component_id, name, message)) = stream::from_zenoh(sample.key_expr().as_str(), &sample.payload().to_bytes()) {
+                                    stream::publish(system_id, component_id, &name, message);
+                                }
+                            }
+                            // The pose has its own subscribers
+                            continue;
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh MAVLink stream recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = origin_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if let Ok(env) = serde_json5::from_slice::<Envelope<GPS_GLOBAL_ORIGIN_DATA>>(&sample.payload().to_bytes()) {
+                                sources.origin = Some(env.message);
+                            }
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh GPS_GLOBAL_ORIGIN recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = dvl_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            let payload = sample.payload().to_bytes();
+                            match sample.key_expr().as_str().rsplit('/').next() {
+                                Some("VISION_POSITION_ESTIMATE") => {
+                                    if let Ok(env) = serde_json5::from_slice::<Envelope<VISION_POSITION_ESTIMATE_DATA>>(&payload) {
+                                        sources.vision_position = Some(env.message);
+                                    }
+                                }
+                                Some("VISION_SPEED_ESTIMATE") => {
+                                    if let Ok(env) = serde_json5::from_slice::<Envelope<VISION_SPEED_ESTIMATE_DATA>>(&payload) {
+                                        sources.vision_speed = Some(env.message);
+                                    }
+                                }
+                                // VISION_POSITION_DELTA and the others are for the autopilot EKF
+                                _ => continue,
+                            }
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh DVL recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = system_time_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if let Ok(env) = serde_json5::from_slice::<Envelope<SYSTEM_TIME_DATA>>(&sample.payload().to_bytes()) {
+                                clock::observe_system_time(&env.message);
+                            }
+                            // Nothing of the pose changed
+                            continue;
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh SYSTEM_TIME recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
                 res = attitude_sub.recv_async() => {
                     match res {
                         Ok(sample) => {
                             if let Ok(env) = serde_json5::from_slice::<Envelope<ATTITUDE_DATA>>(&sample.payload().to_bytes()) {
-                                latest_attitude = Some(env.message);
+                                sources.set_attitude(env.message);
+                            }
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh ATTITUDE recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = vfr_hud_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if let Ok(env) = serde_json5::from_slice::<Envelope<VFR_HUD_DATA>>(&sample.payload().to_bytes()) {
+                                sources.vfr_hud = Some(env.message);
+                            }
+                        },
+                        Err(e) => {
+                            failure = Some(format!("Zenoh VFR_HUD recv error: {e}"));
+                            break;
+                        }
+                    }
+                }
+                res = pressure_sub.recv_async() => {
+                    match res {
+                        Ok(sample) => {
+                            if let Ok(env) = serde_json5::from_slice::<Envelope<SCALED_PRESSURE2_DATA>>(&sample.payload().to_bytes()) {
+                                sources.pressure = Some(env.message);
                             }
                         },
                         Err(e) => {
-                            error!("Zenoh ATTITUDE recv error: {e}, reconnecting in {reconnect_delay_secs}s");
+                            failure = Some(format!("Zenoh SCALED_PRESSURE2 recv error: {e}"));
                             break;
                         }
                     }
@@ -111,31 +458,37 @@ pub async fn zenoh_client_bridge(latest_pose: Arc<RwLock<Option<VehicleData>>>)
                     match res {
                         Ok(sample) => {
                             if let Ok(env) = serde_json5::from_slice::<Envelope<GLOBAL_POSITION_INT_DATA>>(&sample.payload().to_bytes()) {
-                                latest_position = Some(env.message);
+                                sources.position = Some(env.message);
                             }
                         },
                         Err(e) => {
-                            error!("Zenoh POSITION recv error: {e}, reconnecting in {reconnect_delay_secs}s");
+                            failure = Some(format!("Zenoh POSITION recv error: {e}"));
                             break;
                         }
                     }
                 }
             }
 
-            if let (Some(att), Some(pos)) = (&latest_attitude, &latest_position) {
-                let pose = VehicleData {
-                    roll: att.roll,
-                    pitch: att.pitch,
-                    yaw: att.yaw,
-                    alt: pos.alt as f64 / 1000.0,
-                    lat: pos.lat as f64 / 1e7,
-                    lon: pos.lon as f64 / 1e7,
-                };
-                let mut pose_guard = latest_pose.write().await;
-                *pose_guard = Some(pose);
-            }
+            sources.publish();
+        }
+
+        // Endpoint changes and reconnect requests connect again right away
+        if let Some(failure) = failure {
+            bridge.retry(failure).await;
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        error!("Zenoh client bridge disconnected, retrying in {reconnect_delay_secs}s");
+    #[test]
+    fn test_pressure_depth() {
+        assert_eq!(pressure_depth(SURFACE_PRESSURE_HPA, 1025.0), 0.0);
+        // Above the surface the sensor reads less than sea level
+        assert_eq!(pressure_depth(1000.0, 1025.0), 0.0);
+        let depth = pressure_depth(SURFACE_PRESSURE_HPA + 1005.17, 1025.0);
+        assert!((depth - 10.0).abs() < 0.01, "{depth}");
     }
 }
@@ -0,0 +1,92 @@
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+// A few seconds of the messages of a typical vehicle, recorders that lag behind skip the rest
+const STREAM_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref STREAM: Sender<MavlinkSample> = broadcast::channel(STREAM_CAPACITY).0;
+}
+
+/// A MAVLink message as received by the vehicle bridge, kept as JSON so any message can be recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct MavlinkSample {
+    pub system_id: u8,
+    pub component_id: u8,
+    /// MAVLink message name, e.g. `ATTITUDE`
+    pub name: String,
+    /// Unix time in milliseconds of the reception
+    pub timestamp_ms: i64,
+    pub message: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    message: serde_json::Value,
+}
+
+/// Every message the bridge receives from now on from the vehicle selected by --vehicle-system-id
+pub fn subscribe() -> Receiver<MavlinkSample> {
+    STREAM.subscribe()
+}
+
+/// Nothing is parsed for the stream while no one listens
+pub(super) fn has_subscribers() -> bool {
+    STREAM.receiver_count() > 0
+}
+
+pub(super) fn publish(system_id: u8, component_id: u8, name: &str, message: serde_json::Value) {
+    // Only fails without receivers
+    let _ = STREAM.send(MavlinkSample {
+        system_id,
+        component_id,
+        name: name.to_string(),
+        timestamp_ms: chrono::Utc::now().timestamp_millis(),
+        message,
+    });
+}
+
+/// Sample of a Zenoh bridge message, published as mavlink/<system>/<component>/<message>
+pub(super) fn from_zenoh(key: &str, payload: &[u8]) -> Option<(u8, u8, String, serde_json::Value)> {
+    let (system_id, component_id) = super::systems::parse_key(key)?;
+    let name = key.rsplit('/').next()?.to_string();
+    let envelope: Envelope = serde_json5::from_slice(payload).ok()?;
+    Some((system_id, component_id, name, envelope.message))
+}
+
+/// Topic filter of the recordings, names are matched case-insensitively and `*` keeps everything
+pub fn matches(topics: &[String], name: &str) -> bool {
+    topics
+        .iter()
+        .any(|topic| topic == "*" || topic.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zenoh_sample() {
+        let (system_id, component_id, name, message) = from_zenoh(
+            "mavlink/1/1/BATTERY_STATUS",
+            br#"{header: {system_id: 1, component_id: 1, sequence: 0}, message: {type: "BATTERY_STATUS", current_battery: 120}}"#,
+        )
+        .unwrap();
+        assert_eq!((system_id, component_id), (1, 1));
+        assert_eq!(name, "BATTERY_STATUS");
+        assert_eq!(message["current_battery"], 120);
+
+        assert!(from_zenoh("mavlink/1/1/ATTITUDE", b"not json").is_none());
+    }
+
+    #[test]
+    fn test_topic_filter() {
+        let topics = vec!["ATTITUDE".to_string(), "rc_channels".to_string()];
+        assert!(matches(&topics, "ATTITUDE"));
+        assert!(matches(&topics, "RC_CHANNELS"));
+        assert!(!matches(&topics, "HEARTBEAT"));
+        assert!(matches(&["*".to_string()], "HEARTBEAT"));
+        assert!(!matches(&[], "ATTITUDE"));
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref SYSTEMS: std::sync::RwLock<BTreeMap<(u8, u8), (DateTime<Utc>, Instant)>> =
+        std::sync::RwLock::new(BTreeMap::new());
+}
+
+/// A MAVLink component that sent a HEARTBEAT, to find the ids of a vehicle
+#[derive(Debug, Clone, Serialize, Deserialize, Apiv2Schema)]
+pub struct MavlinkSystem {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub last_seen: String,
+    pub age_ms: u64,
+    /// Whether the vehicle data is read from this component
+    pub selected: bool,
+}
+
+/// Which system and component the attitude and position are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VehicleFilter {
+    /// Any system when unset
+    pub system_id: Option<u8>,
+    pub component_id: u8,
+}
+
+impl VehicleFilter {
+    pub fn from_cli() -> Self {
+        Self {
+            system_id: crate::cli::manager::vehicle_system_id(),
+            component_id: crate::cli::manager::vehicle_component_id(),
+        }
+    }
+
+    pub fn matches(&self, system_id: u8, component_id: u8) -> bool {
+        self.system_id.is_none_or(|id| id == system_id) && self.component_id == component_id
+    }
+
+    /// Zenoh key of a message, the bridge publishes them as mavlink/<system>/<component>/<message>
+    pub fn key_expr(&self, message: &str) -> String {
+        let system = self
+            .system_id
+            .map_or_else(|| "*".to_string(), |id| id.to_string());
+        format!("mavlink/{system}/{}/{message}", self.component_id)
+    }
+}
+
+/// System and component ids of a Zenoh MAVLink key
+pub fn parse_key(key: &str) -> Option<(u8, u8)> {
+    let mut parts = key.strip_prefix("mavlink/")?.split('/');
+    let system_id = parts.next()?.parse().ok()?;
+    let component_id = parts.next()?.parse().ok()?;
+    Some((system_id, component_id))
+}
+
+pub fn seen(system_id: u8, component_id: u8) {
+    if let Ok(mut systems) = SYSTEMS.write() {
+        systems.insert((system_id, component_id), (Utc::now(), Instant::now()));
+    }
+}
+
+pub fn list() -> Vec<MavlinkSystem> {
+    let filter = VehicleFilter::from_cli();
+    let Ok(systems) = SYSTEMS.read() else {
+        return Vec::new();
+    };
+    systems
+        .iter()
+        .map(
+            |(&(system_id, component_id), (last_seen, instant))| MavlinkSystem {
+                system_id,
+                component_id,
+                last_seen: last_seen.to_rfc3339(),
+                age_ms: instant.elapsed().as_millis() as u64,
+                selected: filter.matches(system_id, component_id),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_keys() {
+        let any_system = VehicleFilter {
+            system_id: None,
+            component_id: 1,
+        };
+        assert_eq!(any_system.key_expr("ATTITUDE"), "mavlink/*/1/ATTITUDE");
+        assert!(any_system.matches(42, 1));
+        assert!(!any_system.matches(42, 191));
+
+        let boat = VehicleFilter {
+            system_id: Some(2),
+            component_id: 1,
+        };
+        assert_eq!(boat.key_expr("ATTITUDE"), "mavlink/2/1/ATTITUDE");
+        assert!(!boat.matches(1, 1));
+
+        assert_eq!(parse_key("mavlink/2/1/HEARTBEAT"), Some((2, 1)));
+        assert_eq!(parse_key("mavlink/out/HEARTBEAT"), None);
+    }
+}